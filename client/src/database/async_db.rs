@@ -12,21 +12,24 @@ use uuid::Uuid;
 use wasm_bindgen_futures::spawn_local;
 
 use crate::{
-    CommandToServer, Container, ContainerScreen, DataType, LoginResult, UIPages, Visualoc,
+    BackupState, CommandToServer, DataType, LoginResult, ModalType, SearchMap, UIPages, Visualoc,
     gui::login::{initial_authentication, login_user_request, register_user_request},
 };
 
 use super::{
     containers::{add_container, delete_container, get_all_slaves, update_container},
+    csv_import::{import_items_csv, pick_csv_file},
     data_helpers::{
-        add_column_to_items, add_image, delete_column_from_items,
-        get_all_item_ids_not_in_container, get_backup_from_server, get_image_from_server,
-        pick_dump_file, pick_image_folder, update_items_column, upload_backup,
+        add_column_to_items, add_gallery_image, add_image, delete_column_from_items,
+        gallery_entries, get_all_item_ids_not_in_container, get_backup_from_server,
+        get_image_from_server, get_labels, get_unit_totals, get_value_totals, merge_backup,
+        pick_backup_file, resolve_code, restore_backup, scan_code_image, update_items_column,
     },
     items::{
-        delete_item, get_item_location_container, get_multiple_items, insert_item, search_items,
-        update_item,
+        delete_item, export_items, get_item_ancestors, get_item_map, get_multiple_items,
+        insert_item, move_items, search_items, update_item,
     },
+    stock::{get_low_stock, get_low_stock_csv, get_stock_threshold, set_stock_threshold},
 };
 
 pub fn get_item_column_types(host: &str, tx: &Sender<CommandToServer>, id: &str, token: &str) {
@@ -70,9 +73,17 @@ pub fn send_command_to_database(
                 id_vec.insert(id.clone());
                 get_item_column_types(host, sender, id, token);
             }
-            CommandToServer::SearchItems(id, value, column_name, _) => {
+            CommandToServer::SearchItems(id, query, _) => {
                 id_vec.insert(id.clone());
-                search_items(host, sender, id, token, value, column_name);
+                search_items(host, sender, id, token, query);
+            }
+            CommandToServer::GetItemMap(id, query, _) => {
+                id_vec.insert(id.clone());
+                get_item_map(host, sender, id, token, query);
+            }
+            CommandToServer::ExportItems(id, query, format, _) => {
+                id_vec.insert(id.clone());
+                export_items(host, sender, id, token, query, *format);
             }
             CommandToServer::AddContainer(id, container) => {
                 id_vec.insert(id.clone());
@@ -94,9 +105,9 @@ pub fn send_command_to_database(
                 id_vec.insert(id.clone());
                 add_column_to_items(host, sender, id, token, (name.clone(), data_type.clone()))
             }
-            CommandToServer::GetItemLocationContainer(id, item_id, _) => {
+            CommandToServer::GetItemAncestors(id, item_id, _) => {
                 id_vec.insert(id.clone());
-                get_item_location_container(host, sender, id, token, item_id);
+                get_item_ancestors(host, sender, id, token, item_id);
             }
             CommandToServer::DeleteColumnFromItems(id, column_name) => {
                 id_vec.insert(id.clone());
@@ -106,9 +117,9 @@ pub fn send_command_to_database(
                 id_vec.insert(id.clone());
                 update_items_column(host, sender, id, token, old, new);
             }
-            CommandToServer::DeleteContainer(id, container) => {
+            CommandToServer::DeleteContainer(id, container, item_policy, _) => {
                 id_vec.insert(id.clone());
-                delete_container(host, sender, id, token, container);
+                delete_container(host, sender, id, token, container, *item_policy);
             }
             CommandToServer::UpdateContainer(id, container) => {
                 id_vec.insert(id.clone());
@@ -126,6 +137,18 @@ pub fn send_command_to_database(
                     delete_from_database,
                 );
             }
+            CommandToServer::MoveItems(id, item_ids, from_container_id, container_id, _) => {
+                id_vec.insert(id.clone());
+                move_items(
+                    host,
+                    sender,
+                    id,
+                    token,
+                    item_ids,
+                    from_container_id,
+                    container_id,
+                );
+            }
             CommandToServer::GetMultipleItems(id, item_ids, _) => {
                 id_vec.insert(id.clone());
                 get_multiple_items(host, sender, id, token, item_ids);
@@ -134,6 +157,30 @@ pub fn send_command_to_database(
                 id_vec.insert(id.clone());
                 get_all_item_ids_not_in_container(host, sender, id, token);
             }
+            CommandToServer::GetValueTotals(id, _) => {
+                id_vec.insert(id.clone());
+                get_value_totals(host, sender, id, token);
+            }
+            CommandToServer::GetUnitTotals(id, _) => {
+                id_vec.insert(id.clone());
+                get_unit_totals(host, sender, id, token);
+            }
+            CommandToServer::GetStockThreshold(cmd_id, item_id, _) => {
+                id_vec.insert(cmd_id.clone());
+                get_stock_threshold(host, sender, cmd_id, token, item_id);
+            }
+            CommandToServer::SetStockThreshold(cmd_id, item_id, threshold) => {
+                id_vec.insert(cmd_id.clone());
+                set_stock_threshold(host, sender, cmd_id, token, item_id, threshold);
+            }
+            CommandToServer::GetLowStock(cmd_id, _) => {
+                id_vec.insert(cmd_id.clone());
+                get_low_stock(host, sender, cmd_id, token);
+            }
+            CommandToServer::GetLowStockCsv(cmd_id) => {
+                id_vec.insert(cmd_id.clone());
+                get_low_stock_csv(host, sender, cmd_id, token);
+            }
             CommandToServer::RegisterUser(id, _, username, password, email) => {
                 id_vec.insert(id.clone());
                 register_user_request(host, sender, id, username, password, email);
@@ -150,24 +197,56 @@ pub fn send_command_to_database(
                 id_vec.insert(cmd_id.clone());
                 add_image(host, sender, cmd_id, token, item_id);
             }
+            CommandToServer::AddGalleryImage(cmd_id, item_id, field_name, _) => {
+                id_vec.insert(cmd_id.clone());
+                add_gallery_image(host, sender, cmd_id, token, item_id, field_name);
+            }
             CommandToServer::GetBackup(cmd_id) => {
                 id_vec.insert(cmd_id.clone());
                 get_backup_from_server(host, sender, cmd_id, token);
             }
-            CommandToServer::PickBackupDumpFile(cmd_id, _) => {
+            CommandToServer::GetLabels(cmd_id, location_id, location_name, include_items) => {
                 id_vec.insert(cmd_id.clone());
-                pick_dump_file(sender, cmd_id);
+                get_labels(
+                    host,
+                    sender,
+                    cmd_id,
+                    token,
+                    location_id,
+                    location_name,
+                    *include_items,
+                );
             }
-            CommandToServer::PickBackupImageFolder(cmd_id, _) => {
+            CommandToServer::ResolveCode(cmd_id, code, _) => {
                 id_vec.insert(cmd_id.clone());
-                pick_image_folder(sender, cmd_id);
+                resolve_code(host, sender, cmd_id, token, code);
             }
-            CommandToServer::UploadBackup(cmd_id, file_handle, file_list) => {
-                if let Some(dump_file) = file_handle {
-                    if let Some(image_folder) = file_list {
-                        id_vec.insert(cmd_id.clone());
-                        upload_backup(host, sender, cmd_id, token, dump_file, image_folder);
-                    }
+            CommandToServer::ScanCodeImage(cmd_id, _) => {
+                id_vec.insert(cmd_id.clone());
+                scan_code_image(host, sender, cmd_id, token);
+            }
+            CommandToServer::PickBackupFile(cmd_id, _) => {
+                id_vec.insert(cmd_id.clone());
+                pick_backup_file(sender, cmd_id);
+            }
+            CommandToServer::PickCsvFile(cmd_id, _) => {
+                id_vec.insert(cmd_id.clone());
+                pick_csv_file(sender, cmd_id);
+            }
+            CommandToServer::ImportItemsCsv(cmd_id, request, _) => {
+                id_vec.insert(cmd_id.clone());
+                import_items_csv(host, sender, cmd_id, token, request);
+            }
+            CommandToServer::RestoreBackup(cmd_id, backup_file, _) => {
+                if let Some(backup_file) = backup_file {
+                    id_vec.insert(cmd_id.clone());
+                    restore_backup(host, sender, cmd_id, token, backup_file);
+                }
+            }
+            CommandToServer::MergeBackup(cmd_id, backup_file, policy, dry_run, _) => {
+                if let Some(backup_file) = backup_file {
+                    id_vec.insert(cmd_id.clone());
+                    merge_backup(host, sender, cmd_id, token, backup_file, *policy, *dry_run);
                 }
             }
             CommandToServer::Authenticate(cmd_id, persistent_token, _) => {
@@ -191,56 +270,88 @@ impl Visualoc {
                     self.item_field_types = vec.clone();
                     println!("Made it to parse_async_tasks get_item_column_types")
                 }
-                CommandToServer::SearchItems(id, _, _, vec) => {
+                CommandToServer::SearchItems(id, _, result) => {
                     self.async_tasks_sent_ids.remove(id);
                     println!("Made it to parse_async_tasks search_items");
-                    self.item_vec = vec.clone();
-                    self.item_vec.sort_by(|a, b| a.name.cmp(&b.name));
+                    //Keep the previous results while the filter is being typed
+                    match result {
+                        Ok((vec, total, matched_fields)) => {
+                            self.home_page.search_error = String::new();
+                            //Already sorted by the server
+                            self.item_vec = vec.clone();
+                            self.home_page.total_items = *total;
+                            self.home_page.matched_fields = matched_fields.clone();
+                        }
+                        Err(e) => self.home_page.search_error = e.clone(),
+                    }
+                }
+                CommandToServer::GetItemMap(id, query, result) => {
+                    self.async_tasks_sent_ids.remove(id);
+                    //A refresh after an edit keeps the photo that is shown, a new map opens its first location
+                    let refresh = self.search_map.as_ref().is_some_and(|x| x.query == *query);
+                    match result {
+                        Ok(locations) if refresh => {
+                            self.search_map = Some(SearchMap {
+                                query: query.clone(),
+                                locations: locations.clone(),
+                            })
+                            .filter(|x| !x.locations.is_empty());
+                        }
+                        Ok(locations) if locations.is_empty() => {
+                            self.home_page.search_error =
+                                "None of the matches are placed in a location".to_string();
+                        }
+                        Ok(locations) => {
+                            self.search_map = Some(SearchMap {
+                                query: query.clone(),
+                                locations: locations.clone(),
+                            });
+                            self.open_search_map_location(0);
+                        }
+                        Err(e) => self.home_page.search_error = e.clone(),
+                    }
+                    println!("Made it to parse_async_tasks get_item_map");
+                }
+                CommandToServer::ExportItems(id, _, _, result) => {
+                    self.async_tasks_sent_ids.remove(id);
+                    if let Err(e) = result {
+                        self.home_page.search_error = e.clone();
+                    }
                 }
                 CommandToServer::AddContainer(id, _) => {
                     println!("Made it to parse_async_tasks add_container");
                     self.async_tasks_sent_ids.remove(id);
+                    self.refresh_search_map();
                 }
                 CommandToServer::GetAllSlaves(id, _, containers) => {
                     self.async_tasks_sent_ids.remove(id);
-                    self.container_vec = containers.clone();
+                    //The move picker browses the containers without touching the canvas
+                    if *id == self.modal_vars.move_browse_id {
+                        self.modal_vars.move_children = containers.clone();
+                    } else {
+                        self.container_vec = containers.clone();
+                    }
                     println!("Made it to parse_async_tasks get_all_first_level_slaves")
                 }
                 CommandToServer::InsertItem(id, _, _, _) => {
                     self.async_tasks_sent_ids.remove(id);
+                    self.refresh_search_map();
                     println!("Made it to parse_async_tasks insert_or_update_item")
                 }
                 CommandToServer::UpdateItem(id, _, _) => {
                     self.async_tasks_sent_ids.remove(id);
+                    self.refresh_low_stock();
+                    self.refresh_search_map();
                     println!("Made it to parse_async_tasks update_item")
                 }
                 CommandToServer::AddField(id, _, _) => {
                     self.async_tasks_sent_ids.remove(id);
                     println!("Made it to parse_async_tasks add_field")
                 }
-                CommandToServer::GetItemLocationContainer(id, _, cont_loc) => {
+                CommandToServer::GetItemAncestors(id, _, path) => {
                     self.async_tasks_sent_ids.remove(id);
-                    match cont_loc {
-                        Some((cont, loc)) => {
-                            self.selected_location = loc.clone();
-                            self.selected_container = cont.clone();
-                            self.container_screen = ContainerScreen::SelectedItem;
-                            self.current_ui = UIPages::LocationContainers;
-                        }
-                        None => {
-                            self.selected_location = Container::default();
-                            self.selected_container = Container::default();
-                            self.container_screen = ContainerScreen::ItemNotInContainer;
-                            self.current_ui = UIPages::LocationContainers;
-                            self.container_vec = Vec::new();
-                        }
-                    }
-                    self.async_tasks_to_send.push(CommandToServer::GetAllSlaves(
-                        Uuid::new_v4().to_string(),
-                        self.selected_location.id.clone(),
-                        Vec::new(),
-                    ));
-                    println!("Made it to parse_async_tasks get_item_location_container")
+                    self.open_container_path(path.clone(), Some(self.selected_item.clone()));
+                    println!("Made it to parse_async_tasks get_item_ancestors")
                 }
                 CommandToServer::DeleteColumnFromItems(id, _) => {
                     self.async_tasks_sent_ids.remove(id);
@@ -250,18 +361,48 @@ impl Visualoc {
                     self.async_tasks_sent_ids.remove(id);
                     println!("Made it to parse_async_tasks rename_column")
                 }
-                CommandToServer::DeleteContainer(id, _) => {
+                CommandToServer::DeleteContainer(id, _, _, report) => {
                     println!("Made it to parse_async_tasks delete_container");
                     self.async_tasks_sent_ids.remove(id);
+                    self.delete_container_report = report.clone();
+                    self.refresh_search_map();
                 }
                 CommandToServer::UpdateContainer(id, _) => {
                     println!("Made it to parse_async_tasks update_container");
                     self.async_tasks_sent_ids.remove(id);
+                    //Quantities are saved with the container
+                    self.refresh_low_stock();
+                    self.refresh_search_map();
                 }
                 CommandToServer::DeleteItem(id, _, _, _) => {
                     self.async_tasks_sent_ids.remove(id);
+                    self.refresh_low_stock();
+                    self.refresh_search_map();
                     println!("Made it to parse_async_tasks delete_item")
                 }
+                CommandToServer::MoveItems(
+                    id,
+                    item_ids,
+                    from_container_id,
+                    container_id,
+                    moved,
+                ) => {
+                    self.async_tasks_sent_ids.remove(id);
+                    match moved {
+                        Some(_) => {
+                            self.items_moved(item_ids, from_container_id, container_id);
+                            self.refresh_search_map();
+                            if self.modal_vars.modal_type == ModalType::MoveItems {
+                                self.modal_vars.modal_type = ModalType::None;
+                                self.modal_vars.modal_id = Uuid::new_v4().to_string();
+                            }
+                        }
+                        None => {
+                            self.modal_vars.move_message = "The items couldn't be moved".to_string()
+                        }
+                    }
+                    println!("Made it to parse_async_tasks move_items")
+                }
                 CommandToServer::GetMultipleItems(id, _, vec) => {
                     self.async_tasks_sent_ids.remove(id);
                     self.item_vec = vec.clone();
@@ -273,6 +414,32 @@ impl Visualoc {
                     self.containerless_items_ids = vec.clone().into_iter().collect();
                     println!("Made it to parse_async_tasks get_all_item_ids_not_in_container")
                 }
+                CommandToServer::GetValueTotals(id, totals) => {
+                    self.async_tasks_sent_ids.remove(id);
+                    self.value_totals = totals.clone();
+                }
+                CommandToServer::GetUnitTotals(id, totals) => {
+                    self.async_tasks_sent_ids.remove(id);
+                    self.unit_totals = totals.clone();
+                }
+                CommandToServer::GetStockThreshold(cmd_id, item_id, threshold) => {
+                    self.async_tasks_sent_ids.remove(cmd_id);
+                    //Another item could have been opened meanwhile
+                    if *item_id == self.stock_threshold_item {
+                        self.stock_threshold = threshold.clone();
+                    }
+                }
+                CommandToServer::SetStockThreshold(cmd_id, _, _) => {
+                    self.async_tasks_sent_ids.remove(cmd_id);
+                    self.refresh_low_stock();
+                }
+                CommandToServer::GetLowStock(cmd_id, locations) => {
+                    self.async_tasks_sent_ids.remove(cmd_id);
+                    self.low_stock = locations.clone();
+                }
+                CommandToServer::GetLowStockCsv(cmd_id) => {
+                    self.async_tasks_sent_ids.remove(cmd_id);
+                }
                 CommandToServer::RegisterUser(id, register_result, _, _, _) => {
                     self.async_tasks_sent_ids.remove(id);
                     self.login.register_result = *register_result;
@@ -308,20 +475,101 @@ impl Visualoc {
                     } else if self.selected_location.id == *item_id {
                         self.selected_location.image_type = image_type.to_string();
                     }
+                    //Opened containers are drawn from these copies
+                    for container in self
+                        .container_path
+                        .iter_mut()
+                        .chain(self.container_vec.iter_mut())
+                    {
+                        if container.id == *item_id {
+                            container.image_type = image_type.to_string();
+                        }
+                    }
+                }
+                CommandToServer::AddGalleryImage(cmd_id, item_id, field_name, entry) => {
+                    self.async_tasks_sent_ids.remove(cmd_id);
+                    //Append to the gallery being edited, saving the item keeps the new image
+                    if self.selected_item.id == *item_id && !entry.is_empty() {
+                        let mut string_index = 0;
+                        for (name, data_type) in &self.item_field_types {
+                            match data_type {
+                                DataType::String
+                                | DataType::List(_)
+                                | DataType::Text
+                                | DataType::Gallery
+                                | DataType::Date
+                                | DataType::DateTime
+                                | DataType::Money { .. } => {
+                                    if name == field_name {
+                                        let mut entries = gallery_entries(
+                                            &self.selected_item.string_vars[string_index],
+                                        );
+                                        entries.push(entry.clone());
+                                        self.selected_item.string_vars[string_index] =
+                                            entries.join("@");
+                                        break;
+                                    }
+                                    string_index += 1;
+                                }
+                                _ => (),
+                            }
+                        }
+                    }
                 }
                 CommandToServer::GetBackup(cmd_id) => {
                     self.async_tasks_sent_ids.remove(cmd_id);
                 }
-                CommandToServer::PickBackupDumpFile(cmd_id, filehandle) => {
+                CommandToServer::GetLabels(cmd_id, _, _, _) => {
+                    self.async_tasks_sent_ids.remove(cmd_id);
+                }
+                CommandToServer::ResolveCode(cmd_id, _, result)
+                | CommandToServer::ScanCodeImage(cmd_id, result) => {
                     self.async_tasks_sent_ids.remove(cmd_id);
-                    self.backup.dump_filehandle = filehandle.clone();
+                    match result {
+                        Ok(Some(resolved)) => {
+                            self.open_container_path(resolved.path.clone(), resolved.item.clone());
+                            self.modal_vars.modal_type = ModalType::None;
+                            self.modal_vars.modal_id = Uuid::new_v4().to_string();
+                        }
+                        Ok(None) => {
+                            self.modal_vars.scan_message =
+                                "No item or container has this code".to_string();
+                        }
+                        Err(message) => self.modal_vars.scan_message = message.clone(),
+                    }
+                }
+                CommandToServer::PickCsvFile(cmd_id, file) => {
+                    self.async_tasks_sent_ids.remove(cmd_id);
+                    if let Some((file_name, csv)) = file {
+                        self.csv_file_picked(file_name.clone(), csv.clone());
+                    }
                 }
-                CommandToServer::PickBackupImageFolder(cmd_id, filelist) => {
+                CommandToServer::ImportItemsCsv(cmd_id, request, result) => {
                     self.async_tasks_sent_ids.remove(cmd_id);
-                    self.backup.images_filehandle = filelist.clone();
+                    self.csv_import_result(request, result.clone());
+                    self.refresh_search_map();
+                    println!("Made it to parse_async_tasks import_items_csv")
+                }
+                CommandToServer::PickBackupFile(cmd_id, backup_file) => {
+                    self.async_tasks_sent_ids.remove(cmd_id);
+                    if backup_file.is_some() {
+                        self.backup.backup_file = backup_file.clone();
+                        self.backup.merge_preview = None;
+                        self.backup.preview_id = String::new();
+                    }
+                }
+                CommandToServer::RestoreBackup(cmd_id, _, result) => {
+                    self.async_tasks_sent_ids.remove(cmd_id);
+                    //Everything shown comes from the replaced data, start over from the home page
+                    if result.is_ok() {
+                        self.prepare_page(UIPages::Home);
+                        self.refresh_low_stock();
+                    }
+                    self.backup.state = BackupState::Done(result.clone());
                 }
-                CommandToServer::UploadBackup(cmd_id, _, _) => {
+                CommandToServer::MergeBackup(cmd_id, _, _, dry_run, result) => {
                     self.async_tasks_sent_ids.remove(cmd_id);
+                    self.merge_backup_result(cmd_id, *dry_run, result.clone());
                 }
                 CommandToServer::Authenticate(cmd_id, _, login_result) => {
                     self.async_tasks_sent_ids.remove(cmd_id);
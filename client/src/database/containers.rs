@@ -3,13 +3,18 @@ use serde::Serialize;
 use tokio::sync::mpsc::Sender;
 use wasm_bindgen_futures::spawn_local;
 
-use crate::{CommandToServer, Container};
+use crate::{CommandToServer, Container, DeleteContainerReport, ItemPolicy};
 
 #[derive(Serialize)]
 struct ContainerRequest {
     container: Container,
 }
 #[derive(Serialize)]
+struct DeleteContainerRequest {
+    container: Container,
+    item_policy: ItemPolicy,
+}
+#[derive(Serialize)]
 struct IdRequest {
     id: String,
 }
@@ -57,15 +62,19 @@ pub fn delete_container(
     id: &str,
     token: &str,
     container: &Container,
+    item_policy: ItemPolicy,
 ) {
-    //Add the container on the database
+    //Delete the container and every container below it, the policy decides what happens to their items
     let tx = tx.clone();
     let id = id.to_owned();
     let host = host.to_owned();
     let container = container.clone();
     let token = token.to_owned();
     spawn_local(async move {
-        let request_data = ContainerRequest { container };
+        let request_data = DeleteContainerRequest {
+            container,
+            item_policy,
+        };
         let response = Client::new()
             .post(host + "delete_container")
             .header("Authorization", format!("Bearer {}", token))
@@ -73,16 +82,29 @@ pub fn delete_container(
             .send()
             .await;
 
-        match response {
-            Ok(_) => {
-                if let Err(e) = tx
-                    .send(CommandToServer::DeleteContainer(id, Container::default()))
-                    .await
-                {
-                    println!("Error when sending delete container back: {}", e);
+        let report = match response {
+            Ok(resp) => match resp.json::<DeleteContainerReport>().await {
+                Ok(report) => Some(report),
+                Err(e) => {
+                    println!("Error while deserializing json delete_container: {}", e);
+                    None
                 }
+            },
+            Err(e) => {
+                println!("Delete container error {}", e);
+                None
             }
-            Err(e) => println!("Delete container error {}", e),
+        };
+        if let Err(e) = tx
+            .send(CommandToServer::DeleteContainer(
+                id,
+                Container::default(),
+                item_policy,
+                report,
+            ))
+            .await
+        {
+            println!("Error when sending delete container back: {}", e);
         }
     });
 }
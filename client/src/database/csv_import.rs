@@ -0,0 +1,68 @@
+use reqwest::Client;
+use rfd::AsyncFileDialog;
+use tokio::sync::mpsc::Sender;
+use wasm_bindgen_futures::spawn_local;
+
+use crate::{CommandToServer, CsvImportReport, CsvImportRequest};
+
+//Reads the picked file as text, None is sent back when the dialog is closed
+pub fn pick_csv_file(tx: &Sender<CommandToServer>, cmd_id: &str) {
+    let cmd_id = cmd_id.to_owned();
+    let tx = tx.clone();
+    spawn_local(async move {
+        let file = match AsyncFileDialog::new()
+            .add_filter("CSV file", &["csv", "txt"])
+            .pick_file()
+            .await
+        {
+            Some(file) => Some((
+                file.file_name(),
+                String::from_utf8_lossy(&file.read().await).into_owned(),
+            )),
+            None => None,
+        };
+        if let Err(e) = tx.send(CommandToServer::PickCsvFile(cmd_id, file)).await {
+            println!("Error when sending the csv file back: {}", e);
+        }
+    });
+}
+
+pub fn import_items_csv(
+    host: &str,
+    tx: &Sender<CommandToServer>,
+    cmd_id: &str,
+    token: &str,
+    request: &CsvImportRequest,
+) {
+    let cmd_id = cmd_id.to_owned();
+    let host = host.to_owned();
+    let tx = tx.clone();
+    let token = token.to_owned();
+    let request = request.clone();
+    spawn_local(async move {
+        let response = Client::new()
+            .post(host + "import_items_csv")
+            .header("Authorization", format!("Bearer {}", token))
+            .json(&request)
+            .send()
+            .await;
+        //A bad request means the file or the mapping can't be used, the body says why
+        let result = match response {
+            Ok(resp) if resp.status().is_success() => match resp.json::<CsvImportReport>().await {
+                Ok(report) => Ok(report),
+                Err(e) => Err(format!("The server's answer couldn't be read: {}", e)),
+            },
+            Ok(resp) if resp.status() == reqwest::StatusCode::BAD_REQUEST => {
+                Err(resp.text().await.unwrap_or_default())
+            }
+            Ok(resp) => Err(format!("The import failed ({})", resp.status())),
+            Err(e) => Err(format!("The import failed: {}", e)),
+        };
+        if let Err(e) = tx
+            .send(CommandToServer::ImportItemsCsv(cmd_id, request, result))
+            .await
+        {
+            println!("Error when sending the csv import back: {}", e);
+        }
+    });
+}
@@ -1,19 +1,18 @@
-use chrono::Utc;
+use chrono::{DateTime, Local, NaiveDate, NaiveTime, SecondsFormat, TimeZone, Utc};
 use egui::ColorImage;
-use js_sys::Uint8Array;
 use log::{Level, log};
 use reqwest::{Client, multipart};
 use rfd::{AsyncFileDialog, FileHandle};
 use serde::Serialize;
 use tokio::sync::mpsc::Sender;
-use wasm_bindgen::{JsCast, JsValue, prelude::Closure};
-use wasm_bindgen_futures::{JsFuture, spawn_local};
-use web_sys::{
-    Blob, BlobPropertyBag, Document, FileList, FileReader, HtmlAnchorElement, HtmlInputElement,
-    Url, Window,
-};
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::spawn_local;
+use web_sys::{Blob, BlobPropertyBag, Document, HtmlAnchorElement, Url, Window};
 
-use crate::{CommandToServer, DataType};
+use crate::{
+    CommandToServer, DataType, MergePolicy, MergeReport, ResolvedCode, RestoreReport, UnitTotal,
+    ValueTotal,
+};
 
 #[derive(Serialize)]
 struct ColumnRequest {
@@ -67,6 +66,61 @@ pub fn get_all_item_ids_not_in_container(
     });
 }
 
+pub fn get_value_totals(host: &str, tx: &Sender<CommandToServer>, id: &str, token: &str) {
+    let id = id.to_owned();
+    let host = host.to_owned();
+    let tx = tx.clone();
+    let token = token.to_owned();
+    spawn_local(async move {
+        let response = Client::new()
+            .post(host + "get_value_totals")
+            .header("Authorization", format!("Bearer {}", token))
+            .send()
+            .await;
+
+        match response {
+            Ok(resp) => match resp.json::<Vec<ValueTotal>>().await {
+                Ok(totals) => {
+                    if let Err(e) = tx
+                        .send(CommandToServer::GetValueTotals(id, totals))
+                        .await
+                    {
+                        println!("Error when sending value totals back: {}", e);
+                    }
+                }
+                Err(e) => println!("Error while decoding json in get value totals {}", e),
+            },
+            Err(e) => println!("Get value totals error {}", e),
+        }
+    });
+}
+
+pub fn get_unit_totals(host: &str, tx: &Sender<CommandToServer>, id: &str, token: &str) {
+    let id = id.to_owned();
+    let host = host.to_owned();
+    let tx = tx.clone();
+    let token = token.to_owned();
+    spawn_local(async move {
+        let response = Client::new()
+            .post(host + "get_unit_totals")
+            .header("Authorization", format!("Bearer {}", token))
+            .send()
+            .await;
+
+        match response {
+            Ok(resp) => match resp.json::<Vec<UnitTotal>>().await {
+                Ok(totals) => {
+                    if let Err(e) = tx.send(CommandToServer::GetUnitTotals(id, totals)).await {
+                        println!("Error when sending unit totals back: {}", e);
+                    }
+                }
+                Err(e) => println!("Error while decoding json in get unit totals {}", e),
+            },
+            Err(e) => println!("Get unit totals error {}", e),
+        }
+    });
+}
+
 pub fn add_column_to_items(
     host: &str,
     tx: &Sender<CommandToServer>,
@@ -207,6 +261,17 @@ pub enum ImageSize {
     Large,
 }
 
+#[derive(Serialize)]
+struct LabelsRequest {
+    location_id: String,
+    include_items: bool,
+}
+
+#[derive(Serialize)]
+struct CodeRequest {
+    code: String,
+}
+
 #[derive(Serialize)]
 struct ImageRequest {
     image_id: String,
@@ -355,6 +420,266 @@ pub fn add_image(
     });
 }
 
+//A gallery value holds its images as "{image_id}.{extension}" entries joined with '@'
+pub fn gallery_entries(value: &str) -> Vec<String> {
+    value
+        .split('@')
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_string())
+        .collect()
+}
+
+pub fn split_gallery_entry(entry: &str) -> (String, String) {
+    match entry.rsplit_once('.') {
+        Some((image_id, image_type)) => (image_id.to_string(), image_type.to_string()),
+        None => (entry.to_string(), String::new()),
+    }
+}
+
+//Dates are sent as YYYY-MM-DD, date-times as RFC 3339 in UTC and shown in local time
+//An empty string means the field isn't set
+pub fn parse_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()
+}
+
+pub fn parse_date_time(value: &str) -> Option<DateTime<Local>> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|x| x.with_timezone(&Local))
+}
+
+pub fn date_time_to_string(date_time: &DateTime<Local>) -> String {
+    date_time
+        .with_timezone(&Utc)
+        .to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+pub fn format_date_time(value: &str) -> String {
+    match parse_date_time(value) {
+        Some(date_time) => date_time.format("%Y-%m-%d %H:%M").to_string(),
+        None => String::new(),
+    }
+}
+
+//Amounts are kept as text with 2 decimals, returns None if the input isn't an amount
+pub fn normalize_money(value: &str) -> Option<String> {
+    let value = value.trim().replace(',', "");
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, value.as_str()),
+    };
+    let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+    if (whole.is_empty() && fraction.is_empty())
+        || !whole.chars().all(|x| x.is_ascii_digit())
+        || !fraction.chars().all(|x| x.is_ascii_digit())
+        || fraction.len() > 2
+    {
+        return None;
+    }
+    let whole = whole.trim_start_matches('0');
+    Some(format!(
+        "{}{}.{:0<2}",
+        if negative { "-" } else { "" },
+        if whole.is_empty() { "0" } else { whole },
+        fraction
+    ))
+}
+
+//Same check as the server, 3 uppercase letters like EUR
+pub fn is_currency_code(currency: &str) -> bool {
+    currency.len() == 3 && currency.chars().all(|x| x.is_ascii_uppercase())
+}
+
+//Shown like 1,234.50 EUR, an empty amount stays empty
+pub fn format_money(value: &str, currency: &str) -> String {
+    let Some(value) = normalize_money(value) else {
+        return String::new();
+    };
+    let (sign, digits) = match value.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", value.as_str()),
+    };
+    let (whole, fraction) = digits.split_once('.').unwrap_or((digits, "00"));
+    let mut grouped = String::new();
+    for (index, digit) in whole.chars().enumerate() {
+        if index > 0 && (whole.len() - index) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    format!("{}{}.{} {}", sign, grouped, fraction, currency)
+}
+
+//Start of the day in local time, for comparing date-time fields to a date
+pub fn local_day_start(date: NaiveDate) -> Option<DateTime<Local>> {
+    Local
+        .from_local_datetime(&date.and_time(NaiveTime::MIN))
+        .earliest()
+}
+
+pub fn add_gallery_image(
+    host: &str,
+    tx: &Sender<CommandToServer>,
+    cmd_id: &str,
+    token: &str,
+    item_id: &str,
+    field_name: &str,
+) {
+    let item_id = item_id.to_owned();
+    let field_name = field_name.to_owned();
+    let cmd_id = cmd_id.to_owned();
+    let host = host.to_owned();
+    let token = token.to_owned();
+    let tx = tx.clone();
+    spawn_local(async move {
+        let image_option = AsyncFileDialog::new()
+            .add_filter("Supported image formats", &["jpg", "jpeg", "png", "webp"])
+            .pick_file()
+            .await;
+        let mut entry = String::new();
+        if let Some(image_file) = image_option {
+            let image_data = image_file.read().await;
+            let file_name = image_file.file_name();
+            let mime_string = match file_name.rsplit('.').next().map(|x| x.to_lowercase()) {
+                Some(ext) if ext == "jpeg" || ext == "jpg" => "image/jpeg",
+                Some(ext) if ext == "png" => "image/png",
+                Some(ext) if ext == "webp" => "image/webp",
+                _ => "",
+            };
+            if !mime_string.is_empty() {
+                if let Ok(image_field) = multipart::Part::bytes(image_data)
+                    .file_name(file_name.clone())
+                    .mime_str(mime_string)
+                {
+                    let form = multipart::Form::new()
+                        .part("item_id", multipart::Part::text(item_id.clone()))
+                        .part("field_name", multipart::Part::text(field_name.clone()))
+                        .part("image", image_field);
+                    match Client::new()
+                        .post(host + "upload_gallery_image")
+                        .header("Authorization", format!("Bearer {}", token))
+                        .multipart(form)
+                        .send()
+                        .await
+                    {
+                        Ok(resp) if resp.status().is_success() => {
+                            match resp.json::<String>().await {
+                                Ok(new_entry) => entry = new_entry,
+                                Err(e) => log!(Level::Error, "Gallery image json error: {}", e),
+                            }
+                        }
+                        Ok(resp) => {
+                            log!(Level::Error, "Gallery image rejected: {}", resp.status())
+                        }
+                        Err(e) => log!(Level::Error, "Add gallery image error: {}", e),
+                    }
+                }
+            }
+        }
+        //An empty entry still gets sent back so the command isn't left waiting
+        if let Err(e) = tx
+            .send(CommandToServer::AddGalleryImage(
+                cmd_id, item_id, field_name, entry,
+            ))
+            .await
+        {
+            log!(Level::Info, "Sending gallery image back err: {}", e);
+        }
+    });
+}
+
+//Saves the data as a file through a temporary link, returns false if the download couldn't be started
+pub fn download_file(data: &[u8], mime_type: &str, file_name: &str) -> bool {
+    // Create a Blob with the binary data
+    let blob_parts = js_sys::Uint8Array::from(data); // Convert &[u8] to Uint8Array
+    let blob_property_bag = BlobPropertyBag::new();
+    blob_property_bag.set_type(mime_type);
+    let blob = match Blob::new_with_u8_array_sequence_and_options(
+        &js_sys::Array::of1(&blob_parts).into(),
+        &blob_property_bag,
+    ) {
+        Ok(blob) => blob,
+        Err(e) => {
+            log::log!(Level::Error, "New blob error: {:?}", e);
+            return false;
+        }
+    };
+    // Create a temporary URL for the Blob
+    let url = match Url::create_object_url_with_blob(&blob) {
+        Ok(url) => url,
+        Err(e) => {
+            log::log!(Level::Error, "Create object url error: {:?}", e);
+            return false;
+        }
+    };
+
+    // Create an anchor element to trigger the download
+    let window: Window = match web_sys::window() {
+        Some(win) => win,
+        None => {
+            log::log!(Level::Error, "Window is none");
+            return false;
+        }
+    };
+    let document: Document = match window.document() {
+        Some(doc) => doc,
+        None => {
+            log::log!(Level::Error, "Documnet is none");
+            return false;
+        }
+    };
+
+    let anchor: HtmlAnchorElement = match document.create_element("a") {
+        Ok(element) => match element.dyn_into() {
+            Ok(anchor) => anchor,
+            Err(e) => {
+                log::log!(Level::Error, "Element dyn into error: {:?}", e);
+                return false;
+            }
+        },
+        Err(e) => {
+            log::log!(Level::Error, "Document create element error: {:?}", e);
+            return false;
+        }
+    };
+    anchor.set_href(&url);
+    anchor.set_download(file_name);
+    match document.body() {
+        Some(body) => {
+            if let Err(e) = body.append_child(&anchor) {
+                log::log!(Level::Error, "Append child error: {:?}", e);
+                return false;
+            }
+        }
+        None => {
+            log::log!(Level::Error, "No document body");
+            return false;
+        }
+    }
+
+    // Programmatically click the anchor
+    anchor.click();
+
+    // Clean up
+    match document.body() {
+        Some(body) => {
+            if let Err(e) = body.remove_child(&anchor) {
+                log::log!(Level::Error, "Remove child error: {:?}", e);
+                return false;
+            }
+        }
+        None => {
+            log::log!(Level::Error, "No document body");
+            return false;
+        }
+    }
+    if let Err(_) = Url::revoke_object_url(&url) {
+        log::log!(Level::Error, "Revoke object url error");
+        return false;
+    }
+    true
+}
+
 pub fn get_backup_from_server(host: &str, tx: &Sender<CommandToServer>, id: &str, token: &str) {
     let id = id.to_owned();
     let tx = tx.clone();
@@ -369,95 +694,9 @@ pub fn get_backup_from_server(host: &str, tx: &Sender<CommandToServer>, id: &str
         match response {
             Ok(resp) => match resp.bytes().await {
                 Ok(bytes) => {
-                    let zip_data = bytes.to_vec();
-
-                    // Create a Blob with the ZIP binary data
-                    let blob_parts = js_sys::Uint8Array::from(&zip_data[..]); // Convert Vec<u8> to Uint8Array
-                    let blob_property_bag = BlobPropertyBag::new();
-                    blob_property_bag.set_type("application/zip");
-                    let blob = match Blob::new_with_u8_array_sequence_and_options(
-                        &js_sys::Array::of1(&blob_parts).into(),
-                        &blob_property_bag,
-                    ) {
-                        Ok(blob) => blob,
-                        Err(e) => {
-                            log::log!(Level::Error, "New blob error: {:?}", e);
-                            return;
-                        }
-                    };
                     let timestamp = Utc::now().format("%Y-%m-%d_%H:%M:%S").to_string();
-                    let file_name = "Visualoc_Backup_".to_owned() + &timestamp;
-                    // Create a temporary URL for the Blob
-                    let url = match Url::create_object_url_with_blob(&blob) {
-                        Ok(url) => url,
-                        Err(e) => {
-                            log::log!(Level::Error, "Create object url error: {:?}", e);
-                            return;
-                        }
-                    };
-
-                    // Create an anchor element to trigger the download
-                    let window: Window = match web_sys::window() {
-                        Some(win) => win,
-                        None => {
-                            log::log!(Level::Error, "Window is none");
-                            return;
-                        }
-                    };
-                    let document: Document = match window.document() {
-                        Some(doc) => doc,
-                        None => {
-                            log::log!(Level::Error, "Documnet is none");
-                            return;
-                        }
-                    };
-
-                    let anchor: HtmlAnchorElement = match document.create_element("a") {
-                        Ok(element) => match element.dyn_into() {
-                            Ok(anchor) => anchor,
-                            Err(e) => {
-                                log::log!(Level::Error, "Element dyn into error: {:?}", e);
-                                return;
-                            }
-                        },
-                        Err(e) => {
-                            log::log!(Level::Error, "Document create element error: {:?}", e);
-                            return;
-                        }
-                    };
-                    anchor.set_href(&url);
-                    anchor.set_download(&file_name);
-                    match document.body() {
-                        Some(body) => {
-                            if let Err(e) = body.append_child(&anchor) {
-                                log::log!(Level::Error, "Append child error: {:?}", e);
-                                return;
-                            }
-                        }
-                        None => {
-                            log::log!(Level::Error, "No document body");
-                            return;
-                        }
-                    }
-
-                    // Programmatically click the anchor
-                    anchor.click();
-
-                    // Clean up
-                    match document.body() {
-                        Some(body) => {
-                            if let Err(e) = body.remove_child(&anchor) {
-                                log::log!(Level::Error, "Remove child error: {:?}", e);
-                                return;
-                            }
-                        }
-                        None => {
-                            log::log!(Level::Error, "No document body");
-                            return;
-                        }
-                    }
-                    if let Err(_) = Url::revoke_object_url(&url) {
-                        log::log!(Level::Error, "Revoke object url error");
+                    let file_name = "Visualoc_Backup_".to_owned() + &timestamp + ".zip";
+                    if !download_file(&bytes, "application/zip", &file_name) {
                         return;
                     }
                     if let Err(e) = tx.send(CommandToServer::GetBackup(id)).await {
@@ -475,197 +714,285 @@ pub fn get_backup_from_server(host: &str, tx: &Sender<CommandToServer>, id: &str
     });
 }
 
-pub fn pick_dump_file(tx: &Sender<CommandToServer>, cmd_id: &str) {
+//Downloads the QR label sheet of a location as an SVG, printed from the browser
+pub fn get_labels(
+    host: &str,
+    tx: &Sender<CommandToServer>,
+    cmd_id: &str,
+    token: &str,
+    location_id: &str,
+    location_name: &str,
+    include_items: bool,
+) {
     let cmd_id = cmd_id.to_owned();
     let tx = tx.clone();
+    let token = token.to_owned();
+    let host = host.to_owned();
+    let location_id = location_id.to_owned();
+    let location_name = location_name.to_owned();
     spawn_local(async move {
-        let dump_option = AsyncFileDialog::new()
-            .add_filter("Supported file format", &["sql"])
-            .pick_file()
+        let response = Client::new()
+            .post(host + "get_labels")
+            .header("Authorization", format!("Bearer {}", token))
+            .json(&LabelsRequest {
+                location_id: location_id.clone(),
+                include_items,
+            })
+            .send()
             .await;
-        if let Some(dump_file) = dump_option {
-            if let Err(e) = tx
-                .send(CommandToServer::PickBackupDumpFile(
-                    cmd_id.to_string(),
-                    Some(dump_file),
-                ))
-                .await
-            {
-                log::log!(Level::Info, "Sending dump file back err: {}", e);
-                println!("Error when sending dump file back: {}", e);
-            }
+        match response {
+            Ok(resp) if resp.status().is_success() => match resp.bytes().await {
+                Ok(bytes) => {
+                    let file_name = format!("Visualoc_Labels_{}.svg", location_name);
+                    download_file(&bytes, "image/svg+xml", &file_name);
+                }
+                Err(e) => log::log!(Level::Error, "Labels bytes error: {}", e),
+            },
+            Ok(resp) => log::log!(Level::Error, "Get labels failed: {}", resp.status()),
+            Err(e) => log::log!(Level::Error, "Get labels error: {}", e),
+        }
+        if let Err(e) = tx
+            .send(CommandToServer::GetLabels(
+                cmd_id,
+                location_id,
+                location_name,
+                include_items,
+            ))
+            .await
+        {
+            log::log!(Level::Info, "Sending labels back err: {}", e);
         }
     });
 }
 
-pub fn pick_image_folder(tx: &Sender<CommandToServer>, cmd_id: &str) {
+//Looks up the item or container a typed code belongs to
+pub fn resolve_code(
+    host: &str,
+    tx: &Sender<CommandToServer>,
+    cmd_id: &str,
+    token: &str,
+    code: &str,
+) {
     let cmd_id = cmd_id.to_owned();
     let tx = tx.clone();
-    let window: Window = match web_sys::window() {
-        Some(win) => win,
-        None => {
-            log::log!(Level::Error, "Window is none");
-            return;
-        }
-    };
-    let document: Document = match window.document() {
-        Some(doc) => doc,
-        None => {
-            log::log!(Level::Error, "Documnet is none");
-            return;
-        }
-    };
-
-    let input: HtmlInputElement = match document.get_element_by_id("folder_input") {
-        Some(element) => match element.dyn_into::<HtmlInputElement>() {
-            Ok(input) => {
-                input.set_value("");
-                input
-            }
-            Err(e) => {
-                log::log!(Level::Error, "Element dyn into error: {:?}", e);
-                return;
-            }
-        },
-        None => {
-            log::log!(Level::Error, "Document get element folder_input error");
-            return;
+    let token = token.to_owned();
+    let host = host.to_owned();
+    let code = code.to_owned();
+    spawn_local(async move {
+        let response = Client::new()
+            .post(host + "resolve_code")
+            .header("Authorization", format!("Bearer {}", token))
+            .json(&CodeRequest { code: code.clone() })
+            .send()
+            .await;
+        let result = match response {
+            Ok(resp) => match resp.json::<Option<ResolvedCode>>().await {
+                Ok(resolved) => Ok(resolved),
+                Err(e) => Err(format!("Couldn't read the server response: {}", e)),
+            },
+            Err(e) => Err(format!("Couldn't reach the server: {}", e)),
+        };
+        if let Err(e) = tx
+            .send(CommandToServer::ResolveCode(cmd_id, code, result))
+            .await
+        {
+            log::log!(Level::Info, "Sending resolved code back err: {}", e);
         }
-    };
+    });
+}
 
-    let cloned_input = input.clone();
-    // Add event listener to handle file selection
-    let closure = Closure::wrap(Box::new(move || {
-        if let Some(files) = cloned_input.files() {
-            let cmd_id = cmd_id.to_owned();
-            let tx = tx.clone();
-            spawn_local(async move {
-                if let Err(e) = tx
-                    .send(CommandToServer::PickBackupImageFolder(
-                        cmd_id.to_string(),
-                        Some(files),
-                    ))
-                    .await
+//Lets the user pick a photo of a label, the server decodes the QR code in it and resolves it
+//An empty error means no photo was picked
+pub fn scan_code_image(host: &str, tx: &Sender<CommandToServer>, cmd_id: &str, token: &str) {
+    let cmd_id = cmd_id.to_owned();
+    let tx = tx.clone();
+    let token = token.to_owned();
+    let host = host.to_owned();
+    spawn_local(async move {
+        let result = match AsyncFileDialog::new()
+            .add_filter("Supported image formats", &["jpg", "jpeg", "png", "webp"])
+            .pick_file()
+            .await
+        {
+            Some(image_file) => {
+                let file_name = image_file.file_name();
+                let mime_string = match file_name.rsplit('.').next().map(|x| x.to_lowercase()) {
+                    Some(ext) if ext == "jpg" || ext == "jpeg" => "image/jpeg",
+                    Some(ext) if ext == "png" => "image/png",
+                    _ => "image/webp",
+                };
+                match multipart::Part::bytes(image_file.read().await)
+                    .file_name(file_name)
+                    .mime_str(mime_string)
                 {
-                    log::log!(Level::Info, "Sending dump file back err: {}", e);
-                    println!("Error when sending dump file back: {}", e);
+                    Ok(image_field) => {
+                        let form = multipart::Form::new().part("image", image_field);
+                        match Client::new()
+                            .post(host + "scan_code_image")
+                            .header("Authorization", format!("Bearer {}", token))
+                            .multipart(form)
+                            .send()
+                            .await
+                        {
+                            Ok(resp) if resp.status().is_success() => {
+                                match resp.json::<Option<ResolvedCode>>().await {
+                                    Ok(resolved) => Ok(resolved),
+                                    Err(e) => {
+                                        Err(format!("Couldn't read the server response: {}", e))
+                                    }
+                                }
+                            }
+                            //The body says why, like no code found in the photo
+                            Ok(resp) => Err(resp.text().await.unwrap_or_default()),
+                            Err(e) => Err(format!("Couldn't reach the server: {}", e)),
+                        }
+                    }
+                    Err(e) => Err(format!("Couldn't read the photo: {}", e)),
                 }
-            });
+            }
+            None => Err(String::new()),
+        };
+        if let Err(e) = tx
+            .send(CommandToServer::ScanCodeImage(cmd_id, result))
+            .await
+        {
+            log::log!(Level::Info, "Sending scanned code back err: {}", e);
         }
-    }) as Box<dyn FnMut()>);
-    input.set_onchange(Some(closure.as_ref().unchecked_ref()));
-    closure.forget(); // Leak it to keep it alive
+    });
+}
 
-    // Trigger the file picker
-    input.click();
+//The backup.zip downloaded with get_backup_from_server
+pub fn pick_backup_file(tx: &Sender<CommandToServer>, cmd_id: &str) {
+    let cmd_id = cmd_id.to_owned();
+    let tx = tx.clone();
+    spawn_local(async move {
+        let backup_file = AsyncFileDialog::new()
+            .add_filter("Visualoc backup", &["zip"])
+            .pick_file()
+            .await;
+        if let Err(e) = tx
+            .send(CommandToServer::PickBackupFile(cmd_id, backup_file))
+            .await
+        {
+            log::log!(Level::Info, "Sending backup file back err: {}", e);
+            println!("Error when sending backup file back: {}", e);
+        }
+    });
 }
 
-pub fn upload_backup(
+pub fn restore_backup(
     host: &str,
     tx: &Sender<CommandToServer>,
     cmd_id: &str,
     token: &str,
-    dump_file: &FileHandle,
-    image_folder: &FileList,
+    backup_file: &FileHandle,
 ) {
     let cmd_id = cmd_id.to_owned();
     let tx = tx.clone();
     let token = token.to_owned();
     let host = host.to_owned();
-    let dump_file = dump_file.clone();
-    let image_folder = image_folder.clone();
+    let backup_file = backup_file.clone();
     spawn_local(async move {
-        // Read the dump file
-        let dump_file_bytes = dump_file.read().await;
-        let file_part = match reqwest::multipart::Part::bytes(dump_file_bytes)
-            .file_name(dump_file.file_name())
-            .mime_str("application/octet-stream")
+        let backup_part = match multipart::Part::bytes(backup_file.read().await)
+            .file_name(backup_file.file_name())
+            .mime_str("application/zip")
         {
-            Ok(file_part) => file_part,
+            Ok(backup_part) => backup_part,
             Err(e) => {
-                log::log!(Level::Info, "File part from dump err: {}", e);
+                log::log!(Level::Info, "File part from backup err: {}", e);
                 return;
             }
         };
-
-        // Create multipart form
-        let mut form = reqwest::multipart::Form::new().part("dump_file.sql", file_part);
-
-        // Read and add each file from the folder
-        for i in 0..image_folder.length() {
-            if let Some(image_file) = image_folder.item(i) {
-                let image_bytes = match read_file_to_bytes(&image_file).await {
-                    Ok(bytes) => bytes,
-                    Err(e) => {
-                        log::log!(Level::Info, "Read image to bytes err: {:?}", e);
-                        return;
-                    }
-                };
-
-                let image_part = match reqwest::multipart::Part::bytes(image_bytes)
-                    .file_name(image_file.name())
-                    .mime_str("image/*")
-                {
-                    Ok(image_part) => image_part,
-                    Err(e) => {
-                        log::log!(Level::Info, "Image part from bytes err: {}", e);
-                        return;
-                    }
-                };
-                form = form.part(image_file.name(), image_part);
-            }
-        }
-
-        match Client::new()
+        let form = multipart::Form::new().part("backup", backup_part);
+        let response = Client::new()
             .post(host + "upload_backup")
             .header("Authorization", format!("Bearer {}", token))
             .multipart(form)
             .send()
+            .await;
+        //A bad request means the backup was rejected before anything was changed, the body says why
+        let result = match response {
+            Ok(resp) if resp.status().is_success() => match resp.json::<RestoreReport>().await {
+                Ok(report) => Ok(report),
+                Err(e) => Err(format!("The server's answer couldn't be read: {}", e)),
+            },
+            Ok(resp) if resp.status() == reqwest::StatusCode::BAD_REQUEST => {
+                Err(resp.text().await.unwrap_or_default())
+            }
+            Ok(resp) => Err(format!(
+                "The restore failed ({}): {}",
+                resp.status(),
+                resp.text().await.unwrap_or_default()
+            )),
+            Err(e) => Err(format!("The restore failed: {}", e)),
+        };
+        if let Err(e) = tx
+            .send(CommandToServer::RestoreBackup(cmd_id, None, result))
             .await
         {
-            Ok(_) => {
-                if let Err(e) = tx
-                    .send(CommandToServer::UploadBackup(
-                        cmd_id.to_string(),
-                        None,
-                        None,
-                    ))
-                    .await
-                {
-                    log::log!(Level::Info, "Sending image back err: {}", e);
-                    println!("Error when sending the get_image back: {}", e);
-                }
-            }
-            Err(e) => {
-                log!(Level::Error, "Error at the end");
-                println!("Error add image: {}", e)
-            }
+            println!("Error when sending the restore back: {}", e);
         }
     });
 }
 
-async fn read_file_to_bytes(file: &web_sys::File) -> Result<Vec<u8>, JsValue> {
-    let reader = FileReader::new()?;
-    let blob = file.slice()?;
-    reader.read_as_array_buffer(&blob)?;
-    let reader_clone = reader.clone();
-    let promise = js_sys::Promise::new(&mut |resolve, reject| {
-        let reader = reader.clone();
-        let reader_clone = reader_clone.clone();
-        let onload = Closure::wrap(Box::new(move || {
-            if let Ok(result) = reader_clone.result() {
-                resolve.call1(&JsValue::NULL, &result).unwrap();
-            } else {
-                reject
-                    .call1(&JsValue::NULL, &JsValue::from_str("Failed to read file"))
-                    .unwrap();
+//The policy and dry_run go before the backup, a dry run only reports what the merge would change
+pub fn merge_backup(
+    host: &str,
+    tx: &Sender<CommandToServer>,
+    cmd_id: &str,
+    token: &str,
+    backup_file: &FileHandle,
+    policy: MergePolicy,
+    dry_run: bool,
+) {
+    let cmd_id = cmd_id.to_owned();
+    let tx = tx.clone();
+    let token = token.to_owned();
+    let host = host.to_owned();
+    let backup_file = backup_file.clone();
+    spawn_local(async move {
+        let backup_part = match multipart::Part::bytes(backup_file.read().await)
+            .file_name(backup_file.file_name())
+            .mime_str("application/zip")
+        {
+            Ok(backup_part) => backup_part,
+            Err(e) => {
+                log::log!(Level::Info, "File part from backup err: {}", e);
+                return;
             }
-        }) as Box<dyn FnMut()>);
-        reader.set_onload(Some(onload.as_ref().unchecked_ref()));
-        onload.forget();
+        };
+        let form = multipart::Form::new()
+            .part("policy", multipart::Part::text(format!("{:?}", policy)))
+            .part("dry_run", multipart::Part::text(dry_run.to_string()))
+            .part("backup", backup_part);
+        let response = Client::new()
+            .post(host + "merge_backup")
+            .header("Authorization", format!("Bearer {}", token))
+            .multipart(form)
+            .send()
+            .await;
+        let result = match response {
+            Ok(resp) if resp.status().is_success() => match resp.json::<MergeReport>().await {
+                Ok(report) => Ok(report),
+                Err(e) => Err(format!("The server's answer couldn't be read: {}", e)),
+            },
+            Ok(resp) if resp.status() == reqwest::StatusCode::BAD_REQUEST => {
+                Err(resp.text().await.unwrap_or_default())
+            }
+            Ok(resp) => Err(format!(
+                "The merge failed ({}): {}",
+                resp.status(),
+                resp.text().await.unwrap_or_default()
+            )),
+            Err(e) => Err(format!("The merge failed: {}", e)),
+        };
+        if let Err(e) = tx
+            .send(CommandToServer::MergeBackup(
+                cmd_id, None, policy, dry_run, result,
+            ))
+            .await
+        {
+            println!("Error when sending the merge back: {}", e);
+        }
     });
-
-    let result = JsFuture::from(promise).await?;
-    let array = Uint8Array::new(&result);
-    Ok(array.to_vec())
 }
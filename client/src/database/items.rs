@@ -12,11 +12,14 @@
 use std::collections::BTreeSet;
 
 use reqwest::Client;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::Sender;
 use wasm_bindgen_futures::spawn_local;
 
-use crate::{CommandToServer, ContainedItem, Container, DataType};
+use super::data_helpers::download_file;
+use crate::{
+    CommandToServer, ContainedItem, Container, DataType, ExportFormat, ItemQuery, LocationMatches,
+};
 
 // Struct for the request payload (matches MultipleItemRequest on the server)
 #[derive(Serialize)]
@@ -24,10 +27,19 @@ struct IdVectorRequest {
     id_vec: Vec<String>,
 }
 
+//Matches ExportItemsRequest on the server, the offset and the limit are ignored there
 #[derive(Serialize)]
-struct SearchItemsRequest {
-    search_string: String,
-    column_name: String,
+struct ExportItemsRequest {
+    #[serde(flatten)]
+    query: ItemQuery,
+    format: ExportFormat,
+}
+
+#[derive(Deserialize)]
+struct SearchItemsResponse {
+    items: Vec<ContainedItem>,
+    total: usize,
+    matched_fields: Vec<Vec<String>>,
 }
 
 #[derive(Serialize)]
@@ -49,6 +61,18 @@ struct DeleteItemRequest {
     delete_from_items: bool,
 }
 
+#[derive(Serialize)]
+struct MoveItemsRequest {
+    id_vec: Vec<String>,
+    from_container_id: String,
+    container_id: String,
+}
+
+#[derive(Deserialize)]
+struct MoveItemsResponse {
+    items_moved: u64,
+}
+
 pub fn get_multiple_items(
     host: &str,
     tx: &Sender<CommandToServer>,
@@ -92,40 +116,43 @@ pub fn search_items(
     tx: &Sender<CommandToServer>,
     id: &str,
     token: &str,
-    value: &str,
-    column_name: &str,
+    query: &ItemQuery,
 ) {
     let id = id.to_owned();
     let host = host.to_owned();
     let tx = tx.clone();
-    let value = value.to_owned();
+    let query = query.clone();
     let token = token.to_owned();
-    let column_name = column_name.to_owned();
     spawn_local(async move {
-        let request_data = SearchItemsRequest {
-            column_name: column_name.clone(),
-            search_string: value.clone(),
-        };
         let response = Client::new()
             .post(host + "search_items")
             .header("Authorization", format!("Bearer {}", token))
-            .json(&request_data)
+            .json(&query)
             .send()
             .await;
 
         match response {
             Ok(resp) => {
                 println!("{:?}", resp);
-                match resp.json().await {
-                    Ok(vec) => {
-                        if let Err(e) = tx
-                            .send(CommandToServer::SearchItems(id, value, column_name, vec))
-                            .await
-                        {
-                            println!("Error when sending search items back: {}", e);
+                //A bad request means the filter didn't parse, the body is the message for the user
+                let result = if resp.status() == reqwest::StatusCode::BAD_REQUEST {
+                    Err(resp.text().await.unwrap_or_default())
+                } else {
+                    match resp.json::<SearchItemsResponse>().await {
+                        Ok(response) => {
+                            Ok((response.items, response.total, response.matched_fields))
+                        }
+                        Err(e) => {
+                            println!("Error while deserializing json search_items: {}", e);
+                            return;
                         }
                     }
-                    Err(e) => println!("Error while deserializing json search_items: {}", e),
+                };
+                if let Err(e) = tx
+                    .send(CommandToServer::SearchItems(id, query, result))
+                    .await
+                {
+                    println!("Error when sending search items back: {}", e);
                 }
             }
             Err(e) => println!("Search items error {}", e),
@@ -133,7 +160,123 @@ pub fn search_items(
     });
 }
 
-pub fn get_item_location_container(
+//Where every match of the search is, the query's page is ignored so the whole result set is placed
+//Every match of the search downloaded as a file with a column per field and the location of each item
+pub fn export_items(
+    host: &str,
+    tx: &Sender<CommandToServer>,
+    id: &str,
+    token: &str,
+    query: &ItemQuery,
+    format: ExportFormat,
+) {
+    let id = id.to_owned();
+    let host = host.to_owned();
+    let tx = tx.clone();
+    let query = query.clone();
+    let token = token.to_owned();
+    spawn_local(async move {
+        let (mime_type, file_name) = match format {
+            ExportFormat::Csv => ("text/csv", "Visualoc_Items.csv"),
+            ExportFormat::Xlsx => (
+                "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+                "Visualoc_Items.xlsx",
+            ),
+        };
+        let response = Client::new()
+            .post(host + "export_items")
+            .header("Authorization", format!("Bearer {}", token))
+            .json(&ExportItemsRequest {
+                query: query.clone(),
+                format,
+            })
+            .send()
+            .await;
+        let result = match response {
+            Ok(resp) if resp.status().is_success() => match resp.bytes().await {
+                Ok(bytes) if download_file(&bytes, mime_type, file_name) => Ok(()),
+                Ok(_) => Err("The export couldn't be downloaded".to_string()),
+                Err(e) => Err(format!("Failed to read the export: {}", e)),
+            },
+            Ok(resp) if resp.status() == reqwest::StatusCode::BAD_REQUEST => {
+                Err(resp.text().await.unwrap_or_default())
+            }
+            Ok(resp) => Err(format!("Export failed ({})", resp.status())),
+            Err(e) => Err(format!("Export failed: {}", e)),
+        };
+        if let Err(e) = tx
+            .send(CommandToServer::ExportItems(id, query, format, result))
+            .await
+        {
+            println!("Error when sending the export back: {}", e);
+        }
+    });
+}
+
+pub fn get_item_map(
+    host: &str,
+    tx: &Sender<CommandToServer>,
+    id: &str,
+    token: &str,
+    query: &ItemQuery,
+) {
+    let id = id.to_owned();
+    let host = host.to_owned();
+    let tx = tx.clone();
+    let query = query.clone();
+    let token = token.to_owned();
+    spawn_local(async move {
+        let all_matches = ItemQuery {
+            offset: 0,
+            limit: None,
+            ..query.clone()
+        };
+        let client = Client::new();
+        let result = match client
+            .post(host.clone() + "search_items")
+            .header("Authorization", format!("Bearer {}", token))
+            .json(&all_matches)
+            .send()
+            .await
+        {
+            Ok(resp) if resp.status() == reqwest::StatusCode::BAD_REQUEST => {
+                Err(resp.text().await.unwrap_or_default())
+            }
+            Ok(resp) => match resp.json::<SearchItemsResponse>().await {
+                Ok(response) => Ok(response.items.into_iter().map(|x| x.id).collect()),
+                Err(e) => Err(format!("Failed to read the search results: {}", e)),
+            },
+            Err(e) => Err(format!("Search failed: {}", e)),
+        };
+        let result = match result {
+            Ok(id_vec) => {
+                match client
+                    .post(host + "get_item_map")
+                    .header("Authorization", format!("Bearer {}", token))
+                    .json(&IdVectorRequest { id_vec })
+                    .send()
+                    .await
+                {
+                    Ok(resp) => resp
+                        .json::<Vec<LocationMatches>>()
+                        .await
+                        .map_err(|e| format!("Failed to read where the items are: {}", e)),
+                    Err(e) => Err(format!("Failed to find where the items are: {}", e)),
+                }
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = tx
+            .send(CommandToServer::GetItemMap(id, query, result))
+            .await
+        {
+            println!("Error when sending the item map back: {}", e);
+        }
+    });
+}
+
+//Every container from the root down to the one the item is in, empty if it isn't in one
+pub fn get_item_ancestors(
     host: &str,
     tx: &Sender<CommandToServer>,
     cmd_id: &str,
@@ -146,48 +289,29 @@ pub fn get_item_location_container(
     let token = token.to_owned();
     let tx = tx.clone();
     spawn_local(async move {
-        let request_data = IdRequest { id: item_id };
+        let request_data = IdRequest {
+            id: item_id.clone(),
+        };
         let response = Client::new()
-            .post(host + "get_item_container_location")
+            .post(host + "get_item_ancestors")
             .header("Authorization", format!("Bearer {}", token))
             .json(&request_data)
             .send()
             .await;
 
         match response {
-            Ok(resp) => match resp.json::<Option<(Container, Container)>>().await {
-                Ok(cont1) => match cont1.clone() {
-                    Some((container, location)) => {
-                        if let Err(e) = tx
-                            .send(CommandToServer::GetItemLocationContainer(
-                                cmd_id,
-                                String::default(),
-                                Some((container, location)),
-                            ))
-                            .await
-                        {
-                            println!("Error when sending the get_item location back 1: {}", e);
-                        }
-                    }
-                    None => {
-                        if let Err(e) = tx
-                            .send(CommandToServer::GetItemLocationContainer(
-                                cmd_id,
-                                String::default(),
-                                None,
-                            ))
-                            .await
-                        {
-                            println!("Error when sending the get_item location back 2 {}", e);
-                        }
+            Ok(resp) => match resp.json::<Vec<Container>>().await {
+                Ok(path) => {
+                    if let Err(e) = tx
+                        .send(CommandToServer::GetItemAncestors(cmd_id, item_id, path))
+                        .await
+                    {
+                        println!("Error when sending the item ancestors back: {}", e);
                     }
-                },
-                Err(e) => println!(
-                    "Error while deserializing json get_item_container_location: {}",
-                    e
-                ),
+                }
+                Err(e) => println!("Error while deserializing json get_item_ancestors: {}", e),
             },
-            Err(e) => println!("Get item container location error {}", e),
+            Err(e) => println!("Get item ancestors error {}", e),
         }
     });
 }
@@ -332,3 +456,64 @@ pub fn delete_item(
         }
     });
 }
+
+//Moves the items from one container to another in a single request, None when the move failed
+pub fn move_items(
+    host: &str,
+    tx: &Sender<CommandToServer>,
+    cmd_id: &str,
+    token: &str,
+    item_ids: &[String],
+    from_container_id: &str,
+    container_id: &str,
+) {
+    let cmd_id = cmd_id.to_owned();
+    let host = host.to_owned();
+    let token = token.to_owned();
+    let tx = tx.clone();
+    let request_data = MoveItemsRequest {
+        id_vec: item_ids.to_vec(),
+        from_container_id: from_container_id.to_owned(),
+        container_id: container_id.to_owned(),
+    };
+    spawn_local(async move {
+        let response = Client::new()
+            .post(host + "move_items")
+            .header("Authorization", format!("Bearer {}", token))
+            .json(&request_data)
+            .send()
+            .await;
+
+        let items_moved = match response {
+            Ok(resp) if resp.status().is_success() => {
+                match resp.json::<MoveItemsResponse>().await {
+                    Ok(response) => Some(response.items_moved),
+                    Err(e) => {
+                        println!("Error while deserializing json move_items: {}", e);
+                        None
+                    }
+                }
+            }
+            Ok(resp) => {
+                println!("Move items failed {}", resp.status());
+                None
+            }
+            Err(e) => {
+                println!("Move items error {}", e);
+                None
+            }
+        };
+        if let Err(e) = tx
+            .send(CommandToServer::MoveItems(
+                cmd_id,
+                request_data.id_vec,
+                request_data.from_container_id,
+                request_data.container_id,
+                items_moved,
+            ))
+            .await
+        {
+            println!("Error when sending the move_items back: {}", e);
+        }
+    });
+}
@@ -0,0 +1,155 @@
+use log::{Level, log};
+use reqwest::Client;
+use serde::Serialize;
+use tokio::sync::mpsc::Sender;
+use wasm_bindgen_futures::spawn_local;
+
+use super::data_helpers::download_file;
+use crate::{CommandToServer, LowStockLocation, StockThreshold};
+
+#[derive(Serialize)]
+struct IdRequest {
+    id: String,
+}
+
+#[derive(Serialize)]
+struct SetThresholdRequest {
+    item_id: String,
+    threshold: Option<StockThreshold>,
+}
+
+pub fn get_stock_threshold(
+    host: &str,
+    tx: &Sender<CommandToServer>,
+    cmd_id: &str,
+    token: &str,
+    item_id: &str,
+) {
+    let cmd_id = cmd_id.to_owned();
+    let tx = tx.clone();
+    let token = token.to_owned();
+    let host = host.to_owned();
+    let item_id = item_id.to_owned();
+    spawn_local(async move {
+        let response = Client::new()
+            .post(host + "get_stock_threshold")
+            .header("Authorization", format!("Bearer {}", token))
+            .json(&IdRequest {
+                id: item_id.clone(),
+            })
+            .send()
+            .await;
+        match response {
+            Ok(resp) => match resp.json::<Option<StockThreshold>>().await {
+                Ok(threshold) => {
+                    if let Err(e) = tx
+                        .send(CommandToServer::GetStockThreshold(
+                            cmd_id, item_id, threshold,
+                        ))
+                        .await
+                    {
+                        println!("Error when sending the stock threshold back: {}", e);
+                    }
+                }
+                Err(e) => println!("Error while decoding json in get stock threshold {}", e),
+            },
+            Err(e) => println!("Get stock threshold error {}", e),
+        }
+    });
+}
+
+pub fn set_stock_threshold(
+    host: &str,
+    tx: &Sender<CommandToServer>,
+    cmd_id: &str,
+    token: &str,
+    item_id: &str,
+    threshold: &Option<StockThreshold>,
+) {
+    let cmd_id = cmd_id.to_owned();
+    let tx = tx.clone();
+    let token = token.to_owned();
+    let host = host.to_owned();
+    let item_id = item_id.to_owned();
+    let threshold = threshold.clone();
+    spawn_local(async move {
+        let response = Client::new()
+            .post(host + "set_stock_threshold")
+            .header("Authorization", format!("Bearer {}", token))
+            .json(&SetThresholdRequest {
+                item_id: item_id.clone(),
+                threshold: threshold.clone(),
+            })
+            .send()
+            .await;
+        match response {
+            Ok(resp) if resp.status().is_success() => (),
+            Ok(resp) => println!("Set stock threshold failed: {}", resp.status()),
+            Err(e) => println!("Set stock threshold error {}", e),
+        }
+        if let Err(e) = tx
+            .send(CommandToServer::SetStockThreshold(
+                cmd_id, item_id, threshold,
+            ))
+            .await
+        {
+            println!("Error when sending the set stock threshold back: {}", e);
+        }
+    });
+}
+
+pub fn get_low_stock(host: &str, tx: &Sender<CommandToServer>, cmd_id: &str, token: &str) {
+    let cmd_id = cmd_id.to_owned();
+    let tx = tx.clone();
+    let token = token.to_owned();
+    let host = host.to_owned();
+    spawn_local(async move {
+        let response = Client::new()
+            .post(host + "get_low_stock")
+            .header("Authorization", format!("Bearer {}", token))
+            .send()
+            .await;
+        match response {
+            Ok(resp) => match resp.json::<Vec<LowStockLocation>>().await {
+                Ok(locations) => {
+                    if let Err(e) = tx
+                        .send(CommandToServer::GetLowStock(cmd_id, locations))
+                        .await
+                    {
+                        println!("Error when sending low stock back: {}", e);
+                    }
+                }
+                Err(e) => println!("Error while decoding json in get low stock {}", e),
+            },
+            Err(e) => println!("Get low stock error {}", e),
+        }
+    });
+}
+
+//Saves the low stock report as a CSV shopping list
+pub fn get_low_stock_csv(host: &str, tx: &Sender<CommandToServer>, cmd_id: &str, token: &str) {
+    let cmd_id = cmd_id.to_owned();
+    let tx = tx.clone();
+    let token = token.to_owned();
+    let host = host.to_owned();
+    spawn_local(async move {
+        let response = Client::new()
+            .post(host + "get_low_stock_csv")
+            .header("Authorization", format!("Bearer {}", token))
+            .send()
+            .await;
+        match response {
+            Ok(resp) if resp.status().is_success() => match resp.bytes().await {
+                Ok(bytes) => {
+                    download_file(&bytes, "text/csv", "Visualoc_Shopping_List.csv");
+                }
+                Err(e) => log!(Level::Error, "Shopping list bytes error: {}", e),
+            },
+            Ok(resp) => log!(Level::Error, "Get shopping list failed: {}", resp.status()),
+            Err(e) => log!(Level::Error, "Get shopping list error: {}", e),
+        }
+        if let Err(e) = tx.send(CommandToServer::GetLowStockCsv(cmd_id)).await {
+            log!(Level::Info, "Sending shopping list back err: {}", e);
+        }
+    });
+}
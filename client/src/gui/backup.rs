@@ -0,0 +1,166 @@
+use egui::{Color32, Label, RichText, ScrollArea};
+use uuid::Uuid;
+
+use crate::{
+    BackupState, CommandToServer, MergeCounts, MergePolicy, MergeReport, UIPages, Visualoc,
+};
+
+impl MergePolicy {
+    fn name(&self) -> &'static str {
+        match self {
+            MergePolicy::Skip => "Keep mine",
+            MergePolicy::Overwrite => "Use the backup's",
+            MergePolicy::Duplicate => "Keep both",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            MergePolicy::Skip => {
+                "Fields, locations, containers and items you already have stay as they are, the backup's are left out."
+            }
+            MergePolicy::Overwrite => {
+                "The backup's replace the ones you have. A field that changes type keeps the values it can and is emptied otherwise."
+            }
+            MergePolicy::Duplicate => {
+                "The backup's are added next to yours with new ids, fields get a free name like Price2."
+            }
+        }
+    }
+}
+
+fn counts_text(kind: &str, counts: &MergeCounts) -> String {
+    format!(
+        "{}: {} new, {} overwritten, {} duplicated, {} skipped",
+        kind, counts.added, counts.overwritten, counts.duplicated, counts.skipped
+    )
+}
+
+impl Visualoc {
+    pub fn send_merge_backup(&mut self, dry_run: bool) {
+        let cmd_id = Uuid::new_v4().to_string();
+        if dry_run {
+            self.backup.merge_preview = None;
+            self.backup.preview_id = cmd_id.clone();
+        } else {
+            self.backup.state = BackupState::Merging;
+        }
+        self.async_tasks_to_send.push(CommandToServer::MergeBackup(
+            cmd_id,
+            self.backup.backup_file.clone(),
+            self.backup.merge_policy,
+            dry_run,
+            Ok(MergeReport::default()),
+        ));
+    }
+
+    pub fn merge_backup_result(
+        &mut self,
+        cmd_id: &str,
+        dry_run: bool,
+        result: Result<MergeReport, String>,
+    ) {
+        //A preview for another file or policy is dropped
+        if dry_run {
+            if cmd_id == self.backup.preview_id {
+                self.backup.merge_preview = Some(result);
+            }
+            return;
+        }
+        //The merge can add fields, locations and items anywhere
+        if result.is_ok() {
+            self.prepare_page(UIPages::Home);
+            self.refresh_low_stock();
+        }
+        self.backup.state = BackupState::Merged(result);
+    }
+
+    pub fn merge_report(ui: &mut egui::Ui, report: &MergeReport) {
+        ui.label(counts_text("Fields", &report.fields));
+        ui.label(counts_text("Locations and containers", &report.containers));
+        ui.label(counts_text("Items", &report.items));
+        if report.dry_run {
+            ui.label(format!("{} images would be added.", report.images));
+        } else {
+            ui.label(format!("{} images were added.", report.images));
+        }
+        if report.from_dump {
+            ui.add(
+                Label::new("The backup was made by an older version of Visualoc and is converted.")
+                    .wrap(),
+            );
+        }
+        for note in &report.notes {
+            ui.add(Label::new(format!("• {}", note)).wrap());
+        }
+        if !report.failed_images.is_empty() {
+            ui.colored_label(
+                Color32::RED,
+                format!("{} images couldn't be added:", report.failed_images.len()),
+            );
+            ScrollArea::vertical()
+                .id_salt("merge_failed_images")
+                .max_height(120.0)
+                .show(ui, |ui| {
+                    for file_name in &report.failed_images {
+                        ui.label(file_name);
+                    }
+                });
+        }
+    }
+
+    //The merge part of the Backup modal, the file is the one chosen for the restore above
+    pub fn merge_backup_section(&mut self, ui: &mut egui::Ui) {
+        ui.add(
+            Label::new(
+                "Merging adds the backup to your current data. Pick what happens to what is in both:",
+            )
+            .wrap(),
+        );
+        let previous_policy = self.backup.merge_policy;
+        for policy in [
+            MergePolicy::Skip,
+            MergePolicy::Overwrite,
+            MergePolicy::Duplicate,
+        ] {
+            ui.radio_value(&mut self.backup.merge_policy, policy, policy.name());
+        }
+        ui.add(Label::new(RichText::new(self.backup.merge_policy.description()).weak()).wrap());
+        //A preview is only valid for the policy it was made with
+        if self.backup.merge_policy != previous_policy {
+            self.backup.merge_preview = None;
+            self.backup.preview_id = String::new();
+        }
+        let previewing = self.async_tasks_sent_ids.contains(&self.backup.preview_id);
+        let can_merge = matches!(self.backup.merge_preview, Some(Ok(_)));
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(
+                    self.backup.backup_file.is_some() && !previewing,
+                    egui::Button::new("🔍 Preview Merge"),
+                )
+                .clicked()
+            {
+                self.send_merge_backup(true);
+            }
+            if ui
+                .add_enabled(can_merge, egui::Button::new("⤵ Merge into my data"))
+                .clicked()
+            {
+                self.send_merge_backup(false);
+            }
+            if previewing {
+                ui.spinner();
+            }
+        });
+        match &self.backup.merge_preview {
+            Some(Ok(report)) => Visualoc::merge_report(ui, report),
+            Some(Err(message)) => {
+                ui.colored_label(Color32::RED, message);
+            }
+            None => {
+                ui.weak("Preview the merge to see what it changes before merging.");
+            }
+        }
+    }
+}
@@ -1,19 +1,32 @@
-use crate::{CommandToServer, ContainerScreen, Visualoc, database::data_helpers::ImageSize};
-use egui::{Color32, FontFamily, Frame, Painter, Pos2, Rect, Response, Sense, Vec2};
+use crate::{
+    CanvasView, CommandToServer, ContainerScreen, DraggedItem, RectHandle, RegionMode, Visualoc,
+    database::data_helpers::ImageSize,
+    gui::region::{self, RegionSnapshot},
+};
+use egui::{
+    Align2, Color32, Context, CursorIcon, DragAndDrop, FontFamily, FontId, Frame, Key, Painter,
+    PointerButton, Pos2, Rect, Response, Sense, Stroke, StrokeKind, TextureHandle, Vec2,
+};
 use uuid::Uuid;
 
+const VERTEX_RADIUS: f32 = 5.0; //Size of the polygon points while editing
+const HANDLE_SIZE: f32 = 10.0; //Size of the squares on the corners and edges of an edited rectangle
+const SNAP_DISTANCE: f32 = 6.0; //Pixels from another container's edge where a dragged edge sticks to it
+const MAX_ZOOM: f32 = 20.0;
+const ZOOM_SPEED: f32 = 0.002; //Zoom per scrolled point, a wheel step is around 50 points
+const MINIMAP_SIZE: f32 = 150.0; //Longest side of the minimap
+const BADGE_RADIUS: f32 = 10.0; //Smallest size of the search match counts
+
 impl Visualoc {
-    fn percent_to_screen(&self, x: &f32, y: &f32, response: &Response, image_size: &Vec2) -> Pos2 {
-        //response.rect.min is there to offset the frame (from the top left corner of the window, to the top left corner of the canvas)
-        //(response.rect.max.x-response.rect.min.x-image_size.x)/2 is there to center the image in the canvas
-        return Pos2 {
-            x: (response.rect.max.x - response.rect.min.x - image_size.x) / 2.0
-                + response.rect.min.x
-                + x * image_size.x,
-            y: (response.rect.max.y - response.rect.min.y - image_size.y) / 2.0
-                + response.rect.min.y
-                + y * image_size.y,
-        };
+    //image_rect is where the photo is drawn after zooming and panning, it can reach past the canvas
+    fn percent_to_screen(&self, x: &f32, y: &f32, image_rect: &Rect) -> Pos2 {
+        image_rect.min + Vec2::new(x * image_rect.width(), y * image_rect.height())
+    }
+
+    //Inverse of percent_to_screen, kept inside the image
+    fn screen_to_percent(&self, point: Pos2, image_rect: &Rect) -> [f32; 2] {
+        let fraction = (point - image_rect.min) / image_rect.size();
+        [fraction.x.clamp(0.0, 1.0), fraction.y.clamp(0.0, 1.0)]
     }
 
     fn draw_all_slave_containers(
@@ -21,51 +34,43 @@ impl Visualoc {
         ui: &mut egui::Ui,
         painter: &mut Painter,
         response: &Response,
-        image_size: &Vec2,
+        image_rect: &Rect,
     ) {
-        for container in &self.container_vec {
-            let rect = Rect::from_two_pos(
-                self.percent_to_screen(
-                    &container.corners[0],
-                    &container.corners[1],
-                    response,
-                    image_size,
-                ),
-                self.percent_to_screen(
-                    &container.corners[2],
-                    &container.corners[3],
-                    response,
-                    image_size,
-                ),
-            );
-            let local_response = ui.interact(
-                rect,
-                container.id.clone().into(),
-                Sense::click() | Sense::hover(),
-            );
-            if local_response.hovered() {
-                painter.rect_stroke(
-                    rect,
-                    0.0,
-                    (2.0, self.settings.border_colour),
-                    egui::StrokeKind::Outside,
-                );
-            }
-            if local_response.clicked() {
-                self.selected_container = container.clone();
-                self.async_tasks_to_send
-                    .push(CommandToServer::GetMultipleItems(
-                        Uuid::new_v4().to_string(),
-                        self.selected_container.contained_items.clone(),
-                        Vec::new(),
-                    ));
-
-                self.search_string = "".into();
-                self.item_page_search_vec = self.item_vec.clone();
-                self.container_screen = ContainerScreen::SelectedContainer;
-            }
-            painter.rect_filled(rect, 0.0, self.settings.rectangle_colour);
+        let outlines: Vec<Vec<Pos2>> = self
+            .container_vec
+            .iter()
+            .map(|container| {
+                region::container_outline(container, |x, y| {
+                    self.percent_to_screen(&x, &y, image_rect)
+                })
+            })
+            .collect();
+        //Clicks go to the container drawn last under the pointer, the same one that is painted on top
+        //While a region is being drawn the canvas belongs to the editor
+        let hovered = if self.container_screen == ContainerScreen::AddingContainer
+            || self.container_screen == ContainerScreen::EditingContainer
+        {
+            None
+        } else {
+            ui.input(|i| i.pointer.hover_pos())
+                .filter(|pointer| response.rect.contains(*pointer))
+                .and_then(|pointer| {
+                    outlines
+                        .iter()
+                        .rposition(|outline| region::contains(outline, pointer))
+                })
+        };
+        for (container, outline) in self.container_vec.iter().zip(&outlines) {
+            //Containers with search matches stand out while the search map is shown
+            let matches = self.search_matches(&container.id);
+            let colour = if matches > 0 {
+                self.settings.selected_rectangle_colour
+            } else {
+                self.settings.rectangle_colour
+            };
+            painter.add(region::fill(outline, colour));
             //Paint the container name
+            let rect = Rect::from_points(outline);
             if self.settings.show_container_names && rect.height() > 0.0 && rect.width() > 0.0 {
                 let mut painted_text_size = 100.0;
                 let mut galley = painter.layout_no_wrap(
@@ -87,6 +92,495 @@ impl Visualoc {
                     self.settings.container_name_colour,
                 );
             }
+            //Count badge on the top right corner, painted without the rectangle opacity but clipped like the canvas
+            if matches > 0 {
+                let badge = ui.painter().with_clip_rect(painter.clip_rect());
+                let galley = badge.layout_no_wrap(
+                    matches.to_string(),
+                    FontId::proportional(12.0),
+                    Color32::WHITE,
+                );
+                let center = rect.right_top();
+                badge.circle(
+                    center,
+                    (galley.size().x / 2.0 + 5.0).max(BADGE_RADIUS),
+                    Color32::RED,
+                    Stroke::new(1.0, Color32::WHITE),
+                );
+                badge.galley(center - galley.size() / 2.0, galley, Color32::WHITE);
+            }
+        }
+        if let Some(index) = hovered {
+            //An item dragged from the list shows where it will be dropped
+            let dragging = DragAndDrop::has_payload_of_type::<DraggedItem>(ui.ctx());
+            painter.add(region::outline(
+                &outlines[index],
+                Stroke::new(
+                    if dragging { 4.0 } else { 2.0 },
+                    self.settings.border_colour,
+                ),
+            ));
+            let container = self.container_vec[index].clone();
+            if let Some(dragged) = response
+                .dnd_release_payload::<DraggedItem>()
+                .filter(|x| x.from_container_id != container.id)
+            {
+                //Out of the container the item was dragged from, the selection can change during the drag
+                self.move_items_to(
+                    vec![dragged.item_id.clone()],
+                    dragged.from_container_id.clone(),
+                    container.id,
+                );
+            //Double click shows the container's own photo with the containers inside it
+            } else if response.double_clicked() {
+                self.open_child_container(container);
+            } else if response.clicked() {
+                self.select_container(container);
+            }
+        }
+    }
+
+    //Click to add a point, drag a point to move it, right click a point to remove it
+    fn edit_polygon(&mut self, response: &Response, image_rect: &Rect) {
+        let vertices: Vec<Pos2> = self
+            .selected_container
+            .polygon
+            .iter()
+            .map(|vertex| self.percent_to_screen(&vertex[0], &vertex[1], image_rect))
+            .collect();
+        let near_vertex = |point: Pos2| {
+            vertices
+                .iter()
+                .position(|vertex| vertex.distance(point) < VERTEX_RADIUS * 2.0)
+        };
+        if response.drag_started() {
+            self.dragged_vertex = response
+                .ctx
+                .input(|i| i.pointer.press_origin())
+                .and_then(near_vertex);
+            if self.dragged_vertex.is_some() {
+                self.push_region_undo();
+            }
+        }
+        if response.dragged() {
+            let moved_to = response
+                .interact_pointer_pos
+                .map(|point| self.screen_to_percent(point, image_rect));
+            let vertex = self
+                .dragged_vertex
+                .and_then(|index| self.selected_container.polygon.get_mut(index));
+            if let (Some(vertex), Some(moved_to)) = (vertex, moved_to) {
+                *vertex = moved_to;
+            }
+        } else if response.drag_stopped() {
+            self.dragged_vertex = None;
+        }
+        if let Some(point) = response.interact_pointer_pos {
+            let clicked_vertex = near_vertex(point);
+            if response.clicked() && clicked_vertex.is_none() {
+                self.push_region_undo();
+                let vertex = self.screen_to_percent(point, image_rect);
+                self.selected_container.polygon.push(vertex);
+            }
+            if let Some(index) = clicked_vertex.filter(|_| response.secondary_clicked()) {
+                self.push_region_undo();
+                self.selected_container.polygon.remove(index);
+            }
+        }
+        self.selected_container.corners = region::bounding_box(&self.selected_container.polygon);
+    }
+
+    pub fn push_region_undo(&mut self) {
+        self.region_undo
+            .push(RegionSnapshot::of(&self.selected_container));
+        self.region_redo.clear();
+    }
+
+    pub fn undo_region(&mut self) {
+        if let Some(snapshot) = self.region_undo.pop() {
+            self.region_redo
+                .push(RegionSnapshot::of(&self.selected_container));
+            snapshot.restore(&mut self.selected_container);
+        }
+    }
+
+    pub fn redo_region(&mut self) {
+        if let Some(snapshot) = self.region_redo.pop() {
+            self.region_undo
+                .push(RegionSnapshot::of(&self.selected_container));
+            snapshot.restore(&mut self.selected_container);
+        }
+    }
+
+    //The rectangle only turns in the rotated mode, a leftover rotation is dropped on OK otherwise
+    fn shown_rotation(&self) -> f32 {
+        if self.region_mode == RegionMode::RotatedRectangle {
+            self.selected_container.rotation
+        } else {
+            0.0
+        }
+    }
+
+    //Edges of the photo and of the other containers on it, axis 0 for x and 1 for y
+    fn snap_targets(&self, axis: usize) -> Vec<f32> {
+        let mut targets = vec![0.0, 1.0];
+        for container in &self.container_vec {
+            if container.id != self.selected_container.id {
+                targets.push(container.corners[axis]);
+                targets.push(container.corners[axis + 2]);
+            }
+        }
+        targets
+    }
+
+    //Screen positions of the corner and edge handles of the edited rectangle
+    fn rect_handles(&self, image_rect: &Rect) -> Vec<(RectHandle, Pos2)> {
+        let corners = &self.selected_container.corners;
+        let first = self.percent_to_screen(&corners[0], &corners[1], image_rect);
+        let second = self.percent_to_screen(&corners[2], &corners[3], image_rect);
+        let center = first + (second - first) / 2.0;
+        let mut handles = Vec::new();
+        for (x, screen_x) in [(Some(0), first.x), (None, center.x), (Some(2), second.x)] {
+            for (y, screen_y) in [(Some(1), first.y), (None, center.y), (Some(3), second.y)] {
+                if x.is_some() || y.is_some() {
+                    handles.push((
+                        RectHandle::Edges(x, y),
+                        region::rotate_point(
+                            Pos2::new(screen_x, screen_y),
+                            center,
+                            self.shown_rotation(),
+                        ),
+                    ));
+                }
+            }
+        }
+        handles
+    }
+
+    fn rect_handle_at(&self, point: Pos2, image_rect: &Rect) -> RectHandle {
+        let corners = &self.selected_container.corners;
+        let first = self.percent_to_screen(&corners[0], &corners[1], image_rect);
+        let second = self.percent_to_screen(&corners[2], &corners[3], image_rect);
+        //A rectangle that was not drawn yet has nothing to grab
+        if (second.x - first.x).abs() < 1.0 || (second.y - first.y).abs() < 1.0 {
+            return RectHandle::New;
+        }
+        let handle = self
+            .rect_handles(image_rect)
+            .into_iter()
+            .filter(|(_, position)| position.distance(point) <= HANDLE_SIZE)
+            .min_by(|a, b| a.1.distance(point).total_cmp(&b.1.distance(point)));
+        if let Some((handle, _)) = handle {
+            handle
+        } else if region::contains(
+            &region::rotated_rect(first, second, self.shown_rotation()),
+            point,
+        ) {
+            RectHandle::Body
+        } else {
+            RectHandle::New
+        }
+    }
+
+    //Pressing outside the rectangle draws a new one, the body moves it and the handles resize it
+    //Edges stick to the edges of the photo and of the other containers unless Alt is held
+    fn edit_rectangle(&mut self, response: &Response, image_rect: &Rect) {
+        if !response.is_pointer_button_down_on() {
+            //Only a press that changed the rectangle can be undone, it's undone to the normalized start
+            let changed = self
+                .rect_drag
+                .take()
+                .filter(|(_, start)| *start != self.selected_container.corners);
+            if let Some((_, start)) = changed {
+                let corners = self.selected_container.corners;
+                self.selected_container.corners = start;
+                self.push_region_undo();
+                self.selected_container.corners = corners;
+            }
+            return;
+        }
+        let press_origin = response.ctx.input(|i| i.pointer.press_origin());
+        let (Some(origin), Some(pointer)) = (press_origin, response.interact_pointer_pos) else {
+            return;
+        };
+        if self.rect_drag.is_none() {
+            //Handles are found on the normalized rectangle, the first corner is the top left one
+            let corners = &mut self.selected_container.corners;
+            *corners = [
+                corners[0].min(corners[2]),
+                corners[1].min(corners[3]),
+                corners[0].max(corners[2]),
+                corners[1].max(corners[3]),
+            ];
+            let handle = self.rect_handle_at(origin, image_rect);
+            self.rect_drag = Some((handle, self.selected_container.corners));
+        }
+        let Some((handle, start)) = self.rect_drag else {
+            return;
+        };
+        //Snapping to straight edges only makes sense while the rectangle is straight too
+        let snapping = self.shown_rotation() == 0.0 && !response.ctx.input(|i| i.modifiers.alt);
+        let targets = if snapping {
+            [self.snap_targets(0), self.snap_targets(1)]
+        } else {
+            [Vec::new(), Vec::new()]
+        };
+        let distance = [
+            SNAP_DISTANCE / image_rect.width(),
+            SNAP_DISTANCE / image_rect.height(),
+        ];
+        let snapped = |value: f32, axis: usize| {
+            region::snap(value, &targets[axis], distance[axis]).unwrap_or(value)
+        };
+        //Pointer movement on the photo and along the sides of the rectangle, as a fraction of the photo
+        let moved = (pointer - origin) / image_rect.size();
+        let moved_along_sides = region::rotate_point(
+            Pos2::ZERO + (pointer - origin),
+            Pos2::ZERO,
+            -self.shown_rotation(),
+        )
+        .to_vec2()
+            / image_rect.size();
+        let first = self.screen_to_percent(origin, image_rect);
+        let second = self.screen_to_percent(pointer, image_rect);
+        let corners = &mut self.selected_container.corners;
+        match handle {
+            RectHandle::New => {
+                *corners = [
+                    first[0],
+                    first[1],
+                    snapped(second[0], 0),
+                    snapped(second[1], 1),
+                ];
+            }
+            RectHandle::Body => {
+                for axis in 0..2 {
+                    let low = start[axis];
+                    let high = start[axis + 2];
+                    let mut offset = moved[axis].clamp(-low, 1.0 - high);
+                    //The side closest to an edge decides where the rectangle sticks
+                    offset += [low + offset, high + offset]
+                        .iter()
+                        .filter_map(|side| {
+                            region::snap(*side, &targets[axis], distance[axis])
+                                .map(|target| target - side)
+                        })
+                        .min_by(|a, b| a.abs().total_cmp(&b.abs()))
+                        .unwrap_or(0.0);
+                    corners[axis] = low + offset;
+                    corners[axis + 2] = high + offset;
+                }
+            }
+            RectHandle::Edges(x, y) => {
+                *corners = start;
+                for (index, axis) in [(x, 0), (y, 1)] {
+                    if let Some(index) = index {
+                        corners[index] = snapped(
+                            (start[index] + moved_along_sides[axis]).clamp(0.0, 1.0),
+                            axis,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    //Arrow keys nudge the region by a pixel, Shift by 10 and Ctrl to the next edge of another container
+    //Ctrl+Z undoes and Ctrl+Y or Ctrl+Shift+Z redoes, a focused text field keeps the keys for itself
+    fn region_keys(&mut self, ctx: &Context, image_rect: &Rect) {
+        if ctx.memory(|m| m.focused().is_some()) {
+            return;
+        }
+        let (direction, modifiers, undo, redo) = ctx.input(|i| {
+            let direction = if i.key_pressed(Key::ArrowLeft) {
+                Vec2::new(-1.0, 0.0)
+            } else if i.key_pressed(Key::ArrowRight) {
+                Vec2::new(1.0, 0.0)
+            } else if i.key_pressed(Key::ArrowUp) {
+                Vec2::new(0.0, -1.0)
+            } else if i.key_pressed(Key::ArrowDown) {
+                Vec2::new(0.0, 1.0)
+            } else {
+                Vec2::ZERO
+            };
+            let z = i.key_pressed(Key::Z);
+            (
+                direction,
+                i.modifiers,
+                i.modifiers.command && z && !i.modifiers.shift,
+                i.modifiers.command && (i.key_pressed(Key::Y) || (z && i.modifiers.shift)),
+            )
+        });
+        if undo {
+            self.undo_region();
+        }
+        if redo {
+            self.redo_region();
+        }
+        if direction == Vec2::ZERO {
+            return;
+        }
+        //Polygons keep their bounding box in corners, so both shapes move the same way
+        let corners = self.selected_container.corners;
+        let low = [corners[0].min(corners[2]), corners[1].min(corners[3])];
+        let high = [corners[0].max(corners[2]), corners[1].max(corners[3])];
+        let mut offset = if modifiers.command {
+            let axis = if direction.x != 0.0 { 0 } else { 1 };
+            let sign = direction[axis];
+            let next = self
+                .snap_targets(axis)
+                .iter()
+                .flat_map(|target| [target - low[axis], target - high[axis]])
+                .filter(|step| step * sign > f32::EPSILON)
+                .min_by(|a, b| a.abs().total_cmp(&b.abs()));
+            let Some(next) = next else {
+                return;
+            };
+            let mut offset = Vec2::ZERO;
+            offset[axis] = next;
+            offset
+        } else if modifiers.shift {
+            direction * 10.0 / image_rect.size()
+        } else {
+            direction / image_rect.size()
+        };
+        for axis in 0..2 {
+            offset[axis] = offset[axis].clamp(-low[axis], 1.0 - high[axis]);
+        }
+        self.push_region_undo();
+        let corners = &mut self.selected_container.corners;
+        corners[0] += offset.x;
+        corners[1] += offset.y;
+        corners[2] += offset.x;
+        corners[3] += offset.y;
+        for point in &mut self.selected_container.polygon {
+            point[0] += offset.x;
+            point[1] += offset.y;
+        }
+    }
+
+    //Zooms the canvas on the selected container the next time the photo is drawn
+    pub fn zoom_to_selected_container(&mut self) {
+        self.canvas_view.zoom_to_selected = true;
+    }
+
+    //Applies this frame's zooming and panning, fit_size is the photo fitted in the canvas
+    //Returns where the photo is drawn
+    fn view_image_rect(
+        &mut self,
+        ui: &egui::Ui,
+        response: &Response,
+        parent_id: &str,
+        fit_size: Vec2,
+    ) -> Rect {
+        let canvas = response.rect;
+        let editing = self.container_screen == ContainerScreen::AddingContainer
+            || self.container_screen == ContainerScreen::EditingContainer;
+        let view = &mut self.canvas_view;
+        if view.parent_id != parent_id {
+            *view = CanvasView {
+                parent_id: parent_id.to_string(),
+                zoom_to_selected: view.zoom_to_selected,
+                ..CanvasView::default()
+            };
+        }
+        if view.zoom_to_selected {
+            view.zoom_to_selected = false;
+            if self.selected_container.master == parent_id {
+                let corners = self.selected_container.corners;
+                let low = Vec2::new(corners[0].min(corners[2]), corners[1].min(corners[3]));
+                let high = Vec2::new(corners[0].max(corners[2]), corners[1].max(corners[3]));
+                //The container takes about half of the canvas, leaving what's around it visible
+                let fill = canvas.size() / ((high - low) * fit_size).max(Vec2::splat(1.0)) * 0.5;
+                view.zoom = fill.min_elem().clamp(1.0, MAX_ZOOM);
+                view.center = (low + high) / 2.0;
+            }
+        }
+        let hover = ui
+            .input(|i| i.pointer.hover_pos())
+            .filter(|pointer| canvas.contains(*pointer));
+        //Scroll wheel and pinch zoom around the pointer, the spot under it stays put
+        if let Some(pointer) = hover {
+            let (scroll, pinch) = ui.input(|i| (i.smooth_scroll_delta.y, i.zoom_delta()));
+            let factor = pinch * (scroll * ZOOM_SPEED).exp();
+            if factor != 1.0 {
+                let from_center = pointer - canvas.center();
+                let under_pointer = view.center + from_center / (fit_size * view.zoom);
+                view.zoom = (view.zoom * factor).clamp(1.0, MAX_ZOOM);
+                view.center = under_pointer - from_center / (fit_size * view.zoom);
+            }
+        }
+        //The middle button pans everywhere, the left one only while no region is being drawn
+        let mut pan = Vec2::ZERO;
+        if !editing && response.dragged_by(PointerButton::Primary) {
+            pan = response.drag_delta();
+        }
+        if hover.is_some() && ui.input(|i| i.pointer.middle_down()) {
+            pan = ui.input(|i| i.pointer.delta());
+        }
+        if pan != Vec2::ZERO {
+            ui.ctx().set_cursor_icon(CursorIcon::Grabbing);
+            view.center -= pan / (fit_size * view.zoom);
+        }
+        //Keep the photo covering the canvas, a side smaller than the canvas stays centered
+        let size = fit_size * view.zoom;
+        let half_visible = canvas.size() / size / 2.0;
+        for axis in 0..2 {
+            view.center[axis] = if half_visible[axis] >= 0.5 {
+                0.5
+            } else {
+                view.center[axis].clamp(half_visible[axis], 1.0 - half_visible[axis])
+            };
+        }
+        Rect::from_min_size(canvas.center() - view.center * size, size)
+    }
+
+    //The whole photo in a corner of the canvas with the visible part outlined, clicking it moves the view
+    fn draw_minimap(
+        &mut self,
+        ui: &mut egui::Ui,
+        texture: &TextureHandle,
+        canvas_rect: &Rect,
+        image_rect: &Rect,
+    ) {
+        let size = image_rect.size() / image_rect.size().max_elem() * MINIMAP_SIZE;
+        let minimap =
+            Rect::from_min_size(canvas_rect.right_bottom() - size - Vec2::splat(8.0), size);
+        let response = ui.interact(minimap, "minimap".into(), Sense::click_and_drag());
+        let painter = ui.painter_at(*canvas_rect);
+        painter.rect_filled(minimap.expand(2.0), 2.0, Color32::from_black_alpha(160));
+        painter.image(
+            texture.id(),
+            minimap,
+            Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0)),
+            Color32::WHITE,
+        );
+        let visible = canvas_rect.intersect(*image_rect);
+        let to_minimap = |point: Pos2| {
+            minimap.min + (point - image_rect.min) / image_rect.size() * minimap.size()
+        };
+        painter.rect_stroke(
+            Rect::from_two_pos(to_minimap(visible.min), to_minimap(visible.max)),
+            0.0,
+            Stroke::new(2.0, Color32::YELLOW),
+            StrokeKind::Inside,
+        );
+        let pressed = response
+            .interact_pointer_pos
+            .filter(|_| response.is_pointer_button_down_on());
+        if let Some(pointer) = pressed {
+            self.canvas_view.center = (pointer - minimap.min) / minimap.size();
+            ui.ctx().request_repaint();
+        }
+        let fit = ui.put(
+            Rect::from_min_size(
+                canvas_rect.right_top() + Vec2::new(-130.0, 8.0),
+                Vec2::new(122.0, 24.0),
+            ),
+            egui::Button::new(format!("⛶ Fit ({:.0}%)", self.canvas_view.zoom * 100.0)),
+        );
+        if fit.clicked() {
+            self.canvas_view.zoom = 1.0;
         }
     }
 
@@ -103,17 +597,25 @@ impl Visualoc {
             //***********************************
             //Paint the image
             //***********************************
+            //The location, or the last container opened inside it
+            let canvas_parent = self.canvas_parent().clone();
             if self.redraw_canvas_image {
                 //If the image was changed, remove the old one from memory
-                self.loaded_images.remove(
-                    &(self.selected_location.id.to_string()
-                        + "."
-                        + &self.selected_location.image_type),
-                );
+                self.loaded_images
+                    .remove(&(canvas_parent.id.to_string() + "." + &canvas_parent.image_type));
                 self.redraw_canvas_image = false;
             }
-            let image_name =
-                &(self.selected_location.id.clone() + "." + &self.selected_location.image_type);
+            if canvas_parent.image_type.is_empty() {
+                ui.painter().text(
+                    response.rect.center(),
+                    Align2::CENTER_CENTER,
+                    "No photo yet, add one to draw the containers inside it",
+                    FontId::proportional(16.0),
+                    ui.visuals().text_color(),
+                );
+                return;
+            }
+            let image_name = &(canvas_parent.id.clone() + "." + &canvas_parent.image_type);
             //When if let chains are available this can be simplified
             if self.loaded_images.contains_key(image_name) {
                 if let Some((texture, image_size)) = self.loaded_images[image_name].clone() {
@@ -139,24 +641,9 @@ impl Visualoc {
                             image_size.y = canvas_size.y;
                             image_size.x = canvas_size.y * image_ratio;
                         }
-                        //Offset to center the image in the canvas
-                        let offset = if response.rect.max.x - response.rect.min.x - image_size.x
-                            > 0.1
-                        {
-                            Vec2 {
-                                x: (response.rect.max.x - response.rect.min.x - image_size.x) / 2.0,
-                                y: 0.0,
-                            }
-                        } else {
-                            Vec2 {
-                                x: 0.0,
-                                y: (response.rect.max.y - response.rect.min.y - image_size.y) / 2.0,
-                            }
-                        };
-                        let image_rect = Rect::from_two_pos(
-                            response.rect.min + offset,
-                            response.rect.min + offset + image_size,
-                        );
+                        let canvas_rect = response.rect;
+                        let image_rect =
+                            self.view_image_rect(ui, &response, &canvas_parent.id, image_size);
                         image.paint_at(ui, image_rect);
                         //***********************************
                         //Paint all the rectangles of the containers
@@ -166,110 +653,114 @@ impl Visualoc {
                                 ui,
                                 &mut painter,
                                 &response,
-                                &image_size,
+                                &image_rect,
                             );
                         }
                         //***********************************
                         //Paint selected container
                         //***********************************
-                        if self.selected_container.id != String::new()
+                        //Only containers drawn on this photo, an opened container is the photo itself
+                        if self.selected_container.master == canvas_parent.id
                             && self.settings.show_rectangles
                         {
-                            painter.rect_filled(
-                                Rect::from_two_pos(
-                                    self.percent_to_screen(
-                                        &self.selected_container.corners[0],
-                                        &self.selected_container.corners[1],
-                                        &response,
-                                        &image_size,
-                                    ),
-                                    self.percent_to_screen(
-                                        &self.selected_container.corners[2],
-                                        &self.selected_container.corners[3],
-                                        &response,
-                                        &image_size,
-                                    ),
-                                ),
-                                0.0,
+                            let outline =
+                                region::container_outline(&self.selected_container, |x, y| {
+                                    self.percent_to_screen(&x, &y, &image_rect)
+                                });
+                            painter.add(region::fill(
+                                &outline,
                                 self.settings.selected_rectangle_colour,
-                            );
+                            ));
                         }
                         //***********************************
                         //Paint the rectangle to be added
                         //***********************************
                         if self.container_screen == ContainerScreen::AddingContainer
-                            || self.container_screen == ContainerScreen::EditingContainer
+                            || (self.container_screen == ContainerScreen::EditingContainer
+                                && self.selected_container.master == canvas_parent.id)
                         {
-                            //New response to contain the initial interaction within the image_rect
+                            //New response to contain the initial interaction within the visible part of the image
                             response = ui.interact(
-                                image_rect,
+                                image_rect.intersect(canvas_rect),
                                 "image_rect".into(),
                                 Sense::click_and_drag(),
                             );
-                            if response.dragged() {
-                                self.selected_container.corners[2] +=
-                                    response.drag_delta().x / image_size.x;
-                                self.selected_container.corners[3] +=
-                                    response.drag_delta().y / image_size.y;
-                                //Constraining the second point to be inside the image
-                                self.selected_container.corners[2] =
-                                    self.selected_container.corners[2].clamp(0.0, 1.0);
-                                self.selected_container.corners[3] =
-                                    self.selected_container.corners[3].clamp(0.0, 1.0);
-                            } else if response.drag_started()
-                                || response.clicked()
-                                || response.is_pointer_button_down_on()
-                            {
-                                //This runs a few extra times when the item is not being actively dragged
-                                //But it feels more responsive
-                                if let Some(point) = response.interact_pointer_pos {
-                                    //Initial point
-                                    (
-                                        self.selected_container.corners[0],
-                                        self.selected_container.corners[1],
-                                    ) = point.into();
+                            if self.region_mode == RegionMode::Polygon {
+                                self.edit_polygon(&response, &image_rect);
+                                let points: Vec<Pos2> = self
+                                    .selected_container
+                                    .polygon
+                                    .iter()
+                                    .map(|point| {
+                                        self.percent_to_screen(&point[0], &point[1], &image_rect)
+                                    })
+                                    .collect();
+                                if points.len() >= 3 {
+                                    painter.add(region::fill(&points, Color32::WHITE));
+                                    painter.add(region::outline(
+                                        &points,
+                                        Stroke::new(2.0, Color32::WHITE),
+                                    ));
+                                } else {
+                                    painter.add(egui::Shape::line(
+                                        points.clone(),
+                                        Stroke::new(2.0, Color32::WHITE),
+                                    ));
                                 }
-                                self.selected_container.corners[2] =
-                                    self.selected_container.corners[0];
-                                self.selected_container.corners[3] =
-                                    self.selected_container.corners[1];
-                                //Offset from the window border
-                                self.selected_container.corners[0] -= response.rect.min.x;
-                                self.selected_container.corners[1] -= response.rect.min.y;
-                                self.selected_container.corners[2] -= response.rect.min.x;
-                                self.selected_container.corners[3] -= response.rect.min.y;
-                                //Scale to image percentage
-                                self.selected_container.corners[0] /= image_size.x;
-                                self.selected_container.corners[1] /= image_size.y;
-                                self.selected_container.corners[2] /= image_size.x;
-                                self.selected_container.corners[3] /= image_size.y;
-                            }
-                            painter.rect_filled(
-                                Rect::from_two_pos(
-                                    self.percent_to_screen(
-                                        &self.selected_container.corners[0],
-                                        &self.selected_container.corners[1],
-                                        &response,
-                                        &image_size,
-                                    ),
-                                    self.percent_to_screen(
-                                        &self.selected_container.corners[2],
-                                        &self.selected_container.corners[3],
-                                        &response,
-                                        &image_size,
+                                for point in points {
+                                    painter.circle(
+                                        point,
+                                        VERTEX_RADIUS,
+                                        Color32::WHITE,
+                                        Stroke::new(1.0, Color32::BLACK),
+                                    );
+                                }
+                            } else {
+                                self.edit_rectangle(&response, &image_rect);
+                                //The rectangle turns around its center, the polygon is ignored until OK clears it
+                                painter.add(region::fill(
+                                    &region::rotated_rect(
+                                        self.percent_to_screen(
+                                            &self.selected_container.corners[0],
+                                            &self.selected_container.corners[1],
+                                            &image_rect,
+                                        ),
+                                        self.percent_to_screen(
+                                            &self.selected_container.corners[2],
+                                            &self.selected_container.corners[3],
+                                            &image_rect,
+                                        ),
+                                        self.shown_rotation(),
                                     ),
-                                ),
-                                0.0,
-                                Color32::WHITE,
-                            );
+                                    Color32::WHITE,
+                                ));
+                                for (_, position) in self.rect_handles(&image_rect) {
+                                    let handle =
+                                        Rect::from_center_size(position, Vec2::splat(HANDLE_SIZE));
+                                    painter.rect_filled(handle, 0.0, Color32::WHITE);
+                                    painter.rect_stroke(
+                                        handle,
+                                        0.0,
+                                        Stroke::new(1.0, Color32::BLACK),
+                                        StrokeKind::Inside,
+                                    );
+                                }
+                            }
+                            self.region_keys(ui.ctx(), &image_rect);
+                        }
+                        //***********************************
+                        //Minimap and fit button while zoomed in
+                        //***********************************
+                        if self.canvas_view.zoom > 1.0 {
+                            self.draw_minimap(ui, &texture, &canvas_rect, &image_rect);
                         }
                     } else {
                         self.loaded_images.insert(image_name.to_owned(), None);
                         self.async_tasks_to_send
                             .push(CommandToServer::GetImageFromServer(
                                 Uuid::new_v4().to_string(),
-                                self.selected_location.id.clone(),
-                                self.selected_location.image_type.clone(),
+                                canvas_parent.id.clone(),
+                                canvas_parent.image_type.clone(),
                                 crate::database::data_helpers::ImageSize::Large,
                                 egui::ColorImage::default(),
                             ));
@@ -280,8 +771,8 @@ impl Visualoc {
                 self.async_tasks_to_send
                     .push(CommandToServer::GetImageFromServer(
                         Uuid::new_v4().to_string(),
-                        self.selected_location.id.clone(),
-                        self.selected_location.image_type.clone(),
+                        canvas_parent.id.clone(),
+                        canvas_parent.image_type.clone(),
                         crate::database::data_helpers::ImageSize::Large,
                         egui::ColorImage::default(),
                     ));
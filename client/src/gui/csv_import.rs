@@ -0,0 +1,365 @@
+use egui::{Color32, ComboBox, Grid, Label, RichText, ScrollArea, TextEdit};
+use uuid::Uuid;
+
+use crate::{
+    ColumnTarget, CommandToServer, Container, CsvImport, CsvImportReport, CsvImportRequest,
+    DataType, ModalType, Visualoc,
+};
+
+//Types a field can be created with while importing, lists and galleries need more than a column of text
+fn new_field_types() -> [(DataType, &'static str); 8] {
+    [
+        (DataType::String, "Text"),
+        (DataType::Text, "Paragraph"),
+        (DataType::Integer, "Integer"),
+        (DataType::Float, "Decimal"),
+        (DataType::Bool, "Checkbox"),
+        (DataType::Percentage, "Percentage"),
+        (DataType::Date, "Date"),
+        (DataType::DateTime, "Date and Time"),
+    ]
+}
+
+fn data_type_name(data_type: &DataType) -> &'static str {
+    match data_type {
+        DataType::Money { .. } => "Money",
+        _ => new_field_types()
+            .into_iter()
+            .find(|(x, _)| x == data_type)
+            .map_or("Text", |(_, name)| name),
+    }
+}
+
+fn target_name(target: &ColumnTarget) -> String {
+    match target {
+        ColumnTarget::Skip => "Don't import".to_string(),
+        ColumnTarget::Name => "Name".to_string(),
+        ColumnTarget::Quantity => "Quantity".to_string(),
+        ColumnTarget::Field(name) => name.clone(),
+        ColumnTarget::NewField(name, _) => format!("New field {}", name),
+    }
+}
+
+impl Visualoc {
+    //Opens the import modal, the items go into the container or stay without one
+    pub fn open_csv_import(&mut self, container: Option<Container>) {
+        self.csv_import = CsvImport::default();
+        if let Some(container) = container {
+            self.csv_import.container_id = container.id;
+            self.csv_import.container_name = container.name;
+        }
+        self.modal_vars.modal_type = ModalType::ImportCsv;
+    }
+
+    pub fn send_csv_import(&mut self, dry_run: bool) {
+        self.csv_import.error = String::new();
+        self.csv_import.request_id = Uuid::new_v4().to_string();
+        self.async_tasks_to_send
+            .push(CommandToServer::ImportItemsCsv(
+                self.csv_import.request_id.clone(),
+                CsvImportRequest {
+                    csv: self.csv_import.csv.clone(),
+                    mapping: self.csv_import.mapping.clone(),
+                    container_id: self.csv_import.container_id.clone(),
+                    dry_run,
+                },
+                Ok(CsvImportReport::default()),
+            ));
+    }
+
+    //A new file starts over, the server reads its headers and first rows without a mapping
+    pub fn csv_file_picked(&mut self, file_name: String, csv: String) {
+        self.csv_import = CsvImport {
+            file_name,
+            csv,
+            container_id: std::mem::take(&mut self.csv_import.container_id),
+            container_name: std::mem::take(&mut self.csv_import.container_name),
+            ..Default::default()
+        };
+        self.send_csv_import(true);
+    }
+
+    //The name, the quantity and the fields are matched by header, the other columns are left for the user
+    fn default_csv_mapping(&self, headers: &[String]) -> Vec<ColumnTarget> {
+        let mut mapping: Vec<ColumnTarget> = Vec::new();
+        for header in headers {
+            let lowercase = header.to_lowercase();
+            let field = self
+                .item_field_types
+                .iter()
+                .find(|(name, data_type)| {
+                    name.eq_ignore_ascii_case(header) && *data_type != DataType::Gallery
+                })
+                .map(|(name, _)| ColumnTarget::Field(name.clone()));
+            let target = if lowercase == "name" && !mapping.contains(&ColumnTarget::Name) {
+                ColumnTarget::Name
+            } else if (lowercase == "quantity" || lowercase == "qty")
+                && !self.csv_import.container_id.is_empty()
+                && !mapping.contains(&ColumnTarget::Quantity)
+            {
+                ColumnTarget::Quantity
+            } else {
+                field
+                    .filter(|x| !mapping.contains(x))
+                    .unwrap_or(ColumnTarget::Skip)
+            };
+            mapping.push(target);
+        }
+        mapping
+    }
+
+    pub fn csv_import_result(
+        &mut self,
+        request: &CsvImportRequest,
+        result: Result<CsvImportReport, String>,
+    ) {
+        //Answers about another file or an older mapping are dropped
+        if request.csv != self.csv_import.csv
+            || (!request.mapping.is_empty() && request.mapping != self.csv_import.mapping)
+        {
+            return;
+        }
+        let report = match result {
+            Ok(report) => report,
+            Err(message) => {
+                self.csv_import.error = message;
+                self.csv_import.checked = false;
+                return;
+            }
+        };
+        if request.mapping.is_empty() {
+            self.csv_import.mapping = self.default_csv_mapping(&report.headers);
+        } else if request.dry_run {
+            self.csv_import.checked = true;
+        } else {
+            self.csv_import.imported = true;
+            self.csv_imported(&report.placed);
+        }
+        self.csv_import.report = Some(report);
+    }
+
+    //Puts the imported items in the copies of the container and fetches again what lists items
+    fn csv_imported(&mut self, placed: &[(String, i32)]) {
+        let container_id = self.csv_import.container_id.clone();
+        let update = |container: &mut Container| {
+            if container.id == container_id {
+                for (item_id, quantity) in placed {
+                    container.contained_items.insert(item_id.clone());
+                    container.item_quantities.insert(item_id.clone(), *quantity);
+                }
+            }
+        };
+        update(&mut self.selected_container);
+        self.container_vec.iter_mut().for_each(update);
+        self.container_path.iter_mut().for_each(update);
+        if !container_id.is_empty() && self.selected_container.id == container_id {
+            self.async_tasks_to_send
+                .push(CommandToServer::GetMultipleItems(
+                    Uuid::new_v4().to_string(),
+                    self.selected_container.contained_items.clone(),
+                    Vec::new(),
+                ));
+        }
+        //The import can create fields
+        self.async_tasks_to_send
+            .push(CommandToServer::GetItemColumnTypes(
+                Uuid::new_v4().to_string(),
+                Vec::new(),
+            ));
+        self.home_page.previous_query = None;
+        self.refresh_low_stock();
+    }
+
+    fn csv_row_errors(ui: &mut egui::Ui, report: &CsvImportReport) {
+        if report.errors.is_empty() {
+            return;
+        }
+        ui.colored_label(
+            Color32::RED,
+            format!(
+                "{} rows have errors and aren't imported:",
+                report.errors.len()
+            ),
+        );
+        ScrollArea::vertical()
+            .id_salt("csv_errors")
+            .max_height(120.0)
+            .show(ui, |ui| {
+                for error in &report.errors {
+                    ui.add(Label::new(format!("Row {}: {}", error.row, error.message)).wrap());
+                }
+            });
+    }
+
+    pub fn csv_import_modal(&mut self, ui: &mut egui::Ui) {
+        Visualoc::themed_heading(ui, self.settings.light_mode, "Import Items from CSV");
+        if self.csv_import.container_id.is_empty() {
+            ui.label("The items won't be in a container.");
+        } else {
+            ui.label(format!(
+                "The items go into {}.",
+                self.csv_import.container_name
+            ));
+        }
+        ui.horizontal(|ui| {
+            if ui.button("📂 Choose File").clicked() {
+                self.async_tasks_to_send.push(CommandToServer::PickCsvFile(
+                    Uuid::new_v4().to_string(),
+                    None,
+                ));
+            }
+            if self.csv_import.file_name.is_empty() {
+                ui.weak("No file chosen");
+            } else {
+                ui.label(&self.csv_import.file_name);
+            }
+            if self
+                .async_tasks_sent_ids
+                .contains(&self.csv_import.request_id)
+            {
+                ui.spinner();
+            }
+        });
+        if !self.csv_import.error.is_empty() {
+            ui.colored_label(Color32::RED, &self.csv_import.error);
+        }
+        let Some(report) = self.csv_import.report.clone() else {
+            ui.add_space(15.0);
+            return;
+        };
+        ui.separator();
+        if self.csv_import.imported {
+            ui.label(format!(
+                "Imported {} of {} rows.",
+                report.imported, report.rows
+            ));
+            Visualoc::csv_row_errors(ui, &report);
+            ui.add_space(15.0);
+            return;
+        }
+
+        //Where each column goes, with its value on the first row to recognize it
+        ui.label(format!(
+            "{} rows. Pick where the values of each column go:",
+            report.rows
+        ));
+        self.csv_import
+            .mapping
+            .resize(report.headers.len(), ColumnTarget::Skip);
+        let previous_mapping = self.csv_import.mapping.clone();
+        let fields: Vec<String> = self
+            .item_field_types
+            .iter()
+            .filter(|(_, data_type)| *data_type != DataType::Gallery)
+            .map(|(name, _)| name.clone())
+            .collect();
+        let placed = !self.csv_import.container_id.is_empty();
+        ScrollArea::vertical()
+            .id_salt("csv_mapping")
+            .max_height(250.0)
+            .show(ui, |ui| {
+                Grid::new("csv_mapping").striped(true).show(ui, |ui| {
+                    for (index, header) in report.headers.iter().enumerate() {
+                        ui.strong(header);
+                        let sample = report
+                            .preview
+                            .first()
+                            .and_then(|row| row.get(index))
+                            .map_or("", |x| x.as_str());
+                        ui.add(Label::new(RichText::new(sample).weak()).truncate());
+                        let target = &mut self.csv_import.mapping[index];
+                        ComboBox::from_id_salt(("csv_target", index))
+                            .selected_text(target_name(target))
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(target, ColumnTarget::Skip, "Don't import");
+                                ui.selectable_value(target, ColumnTarget::Name, "Name");
+                                if placed {
+                                    ui.selectable_value(target, ColumnTarget::Quantity, "Quantity");
+                                }
+                                for field in &fields {
+                                    ui.selectable_value(
+                                        target,
+                                        ColumnTarget::Field(field.clone()),
+                                        field,
+                                    );
+                                }
+                                let new_field = matches!(target, ColumnTarget::NewField(..));
+                                if ui.selectable_label(new_field, "New field").clicked()
+                                    && !new_field
+                                {
+                                    *target = ColumnTarget::NewField(
+                                        header.chars().filter(|x| x.is_alphanumeric()).collect(),
+                                        DataType::String,
+                                    );
+                                }
+                            });
+                        if let ColumnTarget::NewField(name, data_type) = target {
+                            ui.add(TextEdit::singleline(name).desired_width(100.0));
+                            ComboBox::from_id_salt(("csv_type", index))
+                                .selected_text(data_type_name(data_type))
+                                .show_ui(ui, |ui| {
+                                    for (new_type, type_name) in new_field_types() {
+                                        ui.selectable_value(data_type, new_type, type_name);
+                                    }
+                                    let money = matches!(data_type, DataType::Money { .. });
+                                    if ui.selectable_label(money, "Money").clicked() && !money {
+                                        *data_type = DataType::Money {
+                                            currency: "EUR".to_string(),
+                                        };
+                                    }
+                                });
+                            if let DataType::Money { currency } = data_type {
+                                ui.add(TextEdit::singleline(currency).desired_width(40.0));
+                            }
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+        //A check is only valid for the mapping it was made with
+        if self.csv_import.mapping != previous_mapping {
+            self.csv_import.checked = false;
+        }
+        ui.separator();
+
+        ui.label("First rows of the file");
+        ScrollArea::both()
+            .id_salt("csv_preview")
+            .max_height(150.0)
+            .show(ui, |ui| {
+                Grid::new("csv_preview").striped(true).show(ui, |ui| {
+                    for header in &report.headers {
+                        ui.strong(header);
+                    }
+                    ui.end_row();
+                    for target in &self.csv_import.mapping {
+                        ui.weak(format!("→ {}", target_name(target)));
+                    }
+                    ui.end_row();
+                    for row in &report.preview {
+                        for value in row {
+                            ui.label(value);
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+        ui.separator();
+        ui.horizontal(|ui| {
+            if ui.button("✔ Check Rows").clicked() {
+                self.send_csv_import(true);
+            }
+            if self.csv_import.checked {
+                ui.label(format!(
+                    "{} of {} rows can be imported.",
+                    report.imported, report.rows
+                ));
+            }
+        });
+        if self.csv_import.checked {
+            Visualoc::csv_row_errors(ui, &report);
+        }
+        ui.weak("OK imports the rows without errors.");
+        ui.add_space(15.0);
+    }
+}
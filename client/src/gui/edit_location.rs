@@ -1,13 +1,18 @@
+use chrono::{Local, TimeZone, Timelike};
 use egui::{
-    Align, Color32, ColorImage, ComboBox, Context, DragValue, Label, Layout, RichText, ScrollArea,
-    Slider, TextEdit,
+    Align, Color32, ColorImage, ComboBox, Context, DragValue, Id, Label, Layout, RichText,
+    ScrollArea, Slider, TextEdit,
 };
-use egui_extras::Column;
+use egui_extras::{Column, DatePickerButton};
 use uuid::Uuid;
 
 use crate::{
-    CommandToServer, ContainedItem, Container, ContainerScreen, DataType, ModalType, UIPages,
-    Visualoc, WaitingFunctionKind,
+    CommandToServer, ContainedItem, Container, ContainerScreen, DataType, DraggedItem, ItemPolicy,
+    ModalType, RegionMode, StockThreshold, UIPages, Visualoc, WaitingFunctionKind,
+    database::data_helpers::{
+        ImageSize, date_time_to_string, format_date_time, format_money, gallery_entries,
+        normalize_money, parse_date, parse_date_time, split_gallery_entry,
+    },
 };
 
 impl Visualoc {
@@ -20,17 +25,48 @@ impl Visualoc {
             |ui| {
                 if Visualoc::add_button(ui, "Add Container").clicked() {
                     self.selected_container = Container::default();
-                    self.selected_container.master = self.selected_location.id.clone();
+                    self.selected_container.master = self.canvas_parent().id.clone();
                     self.container_screen = ContainerScreen::AddingContainer;
+                    self.region_mode = RegionMode::Rectangle;
+                    self.region_undo.clear();
+                    self.region_redo.clear();
                 }
                 if ui.button("✏ Edit Location").clicked() {
                     self.container_screen = ContainerScreen::EditingLocation;
                 }
                 if Visualoc::delete_button(ui, "Delete Location").clicked() {
+                    self.modal_vars.delete_item_policy = ItemPolicy::LeaveUnplaced;
                     self.modal_vars.modal_type = ModalType::DeleteLocation;
                 }
             },
         );
+        //QR labels for the location and every container below it
+        ui.horizontal(|ui| {
+            if ui.button("🖨 Print labels").clicked() {
+                self.async_tasks_to_send.push(CommandToServer::GetLabels(
+                    Uuid::new_v4().to_string(),
+                    self.selected_location.id.clone(),
+                    self.selected_location.name.clone(),
+                    self.labels_include_items,
+                ));
+            }
+            ui.checkbox(&mut self.labels_include_items, "Include items");
+        });
+        //Summary of the last container deletion
+        if let Some(report) = &self.delete_container_report {
+            ui.add_space(5.0);
+            ui.add(
+                Label::new(format!(
+                    "Deleted {} container(s) and {} image(s). Items: {} deleted, {} moved to the parent, {} left without a container.",
+                    report.containers_deleted,
+                    report.images_removed,
+                    report.items_deleted,
+                    report.items_moved,
+                    report.items_unplaced
+                ))
+                .wrap(),
+            );
+        }
     }
 
     pub fn editing_location_screen(&mut self, ui: &mut egui::Ui) {
@@ -120,7 +156,21 @@ impl Visualoc {
             .auto_shrink(false)
             .show(ui, |ui| {
                 for item in &self.item_page_search_vec {
-                    if ui.button(&item.name).clicked() {
+                    let quantity = self
+                        .selected_container
+                        .item_quantities
+                        .get(&item.id)
+                        .copied()
+                        .unwrap_or(1);
+                    //Dragging the item onto a container on the canvas moves it there
+                    let dragged = DraggedItem {
+                        item_id: item.id.clone(),
+                        from_container_id: self.selected_container.id.clone(),
+                    };
+                    let response = ui.dnd_drag_source(Id::new(("item", &item.id)), dragged, |ui| {
+                        ui.button(format!("{}  ×{}", item.name, quantity))
+                    });
+                    if response.inner.clicked() {
                         self.selected_item = item.clone();
                         self.container_screen = ContainerScreen::SelectedItem;
                     }
@@ -136,6 +186,8 @@ impl Visualoc {
         ui.horizontal(|ui| {
             if Visualoc::add_button(ui, "Add Item").clicked() {
                 self.selected_item = ContainedItem::default();
+                self.selected_item.quantity = 1;
+                self.item_quantity = 1;
                 self.dynamic_fields_initialization();
                 self.async_tasks_to_send.push(CommandToServer::InsertItem(
                     Uuid::new_v4().to_string(),
@@ -157,19 +209,75 @@ impl Visualoc {
                     kind: WaitingFunctionKind::AddExistingItemClicked1,
                 });
             }
+            if ui.button("⬆ Import CSV").clicked() {
+                self.open_csv_import(Some(self.selected_container.clone()));
+            }
+        });
+        ui.add_space(4.0);
+        //The opened container is the canvas itself, the others can be opened to show their own photo
+        let opened = self
+            .container_path
+            .last()
+            .is_some_and(|x| x.id == self.selected_container.id);
+        ui.horizontal(|ui| {
+            if !opened && ui.button("🔍 Open").clicked() {
+                self.open_child_container(self.selected_container.clone());
+            }
+            if !opened && ui.button("🔎 Zoom to").clicked() {
+                self.zoom_to_selected_container();
+            }
+            let image_text = if self.selected_container.image_type.is_empty() {
+                "➕ Add Image"
+            } else {
+                "Change Image"
+            };
+            if ui.button(image_text).clicked() {
+                self.loaded_images.remove(
+                    &(self.selected_container.id.to_string()
+                        + "."
+                        + &self.selected_container.image_type),
+                );
+                self.async_tasks_to_send.push(CommandToServer::AddImage(
+                    Uuid::new_v4().to_string(),
+                    self.selected_container.id.clone(),
+                    String::new(),
+                ));
+                self.redraw_canvas_image = true;
+            }
         });
         ui.add_space(4.0);
         ui.horizontal(|ui| {
             if ui.button("✏ Edit Container").clicked() {
                 self.container_screen = ContainerScreen::EditingContainer;
+                self.region_mode = if self.selected_container.polygon.len() >= 3 {
+                    RegionMode::Polygon
+                } else if self.selected_container.rotation != 0.0 {
+                    RegionMode::RotatedRectangle
+                } else {
+                    RegionMode::Rectangle
+                };
+                self.region_undo.clear();
+                self.region_redo.clear();
             }
             if Visualoc::delete_button(ui, "Delete Container").clicked() {
+                self.modal_vars.delete_item_policy = ItemPolicy::MoveToParent;
                 self.modal_vars.modal_type = ModalType::DeleteContainer;
             }
         });
         ui.add_space(4.0);
-        if ui.button("⮪ Back to Location").clicked() {
-            self.container_screen = ContainerScreen::None;
+        if opened {
+            if ui.button("⮪ Back").clicked() {
+                self.open_breadcrumb(self.container_path.len() - 1);
+            }
+        } else {
+            let back_text = if self.container_path.is_empty() {
+                "⮪ Back to Location"
+            } else {
+                "⮪ Back"
+            };
+            if ui.button(back_text).clicked() {
+                self.container_screen = ContainerScreen::None;
+            }
         }
         ui.separator();
         ui.add_space(5.0);
@@ -188,7 +296,70 @@ impl Visualoc {
             }
         });
         ui.add_space(5.0);
-        ui.label("1. Click and drag on the main image to create a container");
+        ui.horizontal(|ui| {
+            ui.label("Shape ");
+            ui.radio_value(&mut self.region_mode, RegionMode::Rectangle, "Rectangle");
+            ui.radio_value(
+                &mut self.region_mode,
+                RegionMode::RotatedRectangle,
+                "Rotated rectangle",
+            );
+            ui.radio_value(&mut self.region_mode, RegionMode::Polygon, "Polygon");
+        });
+        ui.add_space(5.0);
+        match self.region_mode {
+            RegionMode::Rectangle => {
+                ui.label("1. Click and drag on the main image to create a container");
+                ui.weak(
+                    "Drag the rectangle or its handles to adjust it, hold Alt to stop snapping",
+                );
+            }
+            RegionMode::RotatedRectangle => {
+                ui.label("1. Click and drag on the main image, then turn the rectangle");
+                let mut rotation = self.selected_container.rotation;
+                let slider = ui.add(
+                    Slider::new(&mut rotation, -180.0..=180.0)
+                        .suffix("°")
+                        .text("Rotation"),
+                );
+                if slider.changed() {
+                    //One undo step for the whole slide
+                    if !slider.dragged() || slider.drag_started() {
+                        self.push_region_undo();
+                    }
+                    self.selected_container.rotation = rotation;
+                }
+            }
+            RegionMode::Polygon => {
+                ui.label(
+                    "1. Click on the main image to add points, drag a point to move it, right click a point to remove it",
+                );
+                ui.horizontal(|ui| {
+                    ui.label(format!("{} points", self.selected_container.polygon.len()));
+                    if ui.button("Clear points").clicked() {
+                        self.push_region_undo();
+                        self.selected_container.polygon.clear();
+                    }
+                });
+            }
+        }
+        ui.weak("Arrow keys nudge it, Shift moves further, Ctrl jumps to the next edge");
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(!self.region_undo.is_empty(), egui::Button::new("⟲ Undo"))
+                .on_hover_text("Ctrl+Z")
+                .clicked()
+            {
+                self.undo_region();
+            }
+            if ui
+                .add_enabled(!self.region_redo.is_empty(), egui::Button::new("⟳ Redo"))
+                .on_hover_text("Ctrl+Y")
+                .clicked()
+            {
+                self.redo_region();
+            }
+        });
         ui.separator();
         ui.add_space(5.0);
         ui.label("2. Container details");
@@ -224,8 +395,27 @@ impl Visualoc {
             );
         });
         ui.add_space(5.0);
+        //A polygon needs an area, fewer points would save an invisible container
+        let missing_points =
+            self.region_mode == RegionMode::Polygon && self.selected_container.polygon.len() < 3;
+        if missing_points {
+            ui.colored_label(Color32::RED, "A polygon needs at least 3 points");
+        }
         ui.horizontal(|ui| {
-            if Visualoc::ok_button(ui).clicked() {
+            if ui
+                .add_enabled_ui(!missing_points, Visualoc::ok_button)
+                .inner
+                .clicked()
+            {
+                //Only keep what the chosen shape uses
+                match self.region_mode {
+                    RegionMode::Rectangle => {
+                        self.selected_container.polygon.clear();
+                        self.selected_container.rotation = 0.0;
+                    }
+                    RegionMode::RotatedRectangle => self.selected_container.polygon.clear(),
+                    RegionMode::Polygon => self.selected_container.rotation = 0.0,
+                }
                 if self.container_screen == ContainerScreen::AddingContainer {
                     self.async_tasks_to_send.push(CommandToServer::AddContainer(
                         Uuid::new_v4().to_string(),
@@ -237,10 +427,16 @@ impl Visualoc {
                             Uuid::new_v4().to_string(),
                             self.selected_container.clone(),
                         ));
+                    //Keep the breadcrumb's copy up to date when editing the opened container
+                    for container in &mut self.container_path {
+                        if container.id == self.selected_container.id {
+                            *container = self.selected_container.clone();
+                        }
+                    }
                 }
                 self.async_tasks_to_send.push(CommandToServer::GetAllSlaves(
                     Uuid::new_v4().to_string(),
-                    self.selected_location.id.clone(),
+                    self.canvas_parent().id.clone(),
                     Vec::new(),
                 ));
                 self.container_screen = ContainerScreen::None;
@@ -438,8 +634,255 @@ impl Visualoc {
                     );
                 }
             }
+            DataType::Date => {
+                if self.container_screen == ContainerScreen::EditingItem
+                    || self.container_screen == ContainerScreen::AddingItem
+                {
+                    ui.horizontal(|ui| {
+                        match parse_date(&self.selected_item.string_vars[index]) {
+                            Some(mut date) => {
+                                if ui
+                                    .add(DatePickerButton::new(&mut date).id_salt(&column_name))
+                                    .changed()
+                                {
+                                    self.selected_item.string_vars[index] =
+                                        date.format("%Y-%m-%d").to_string();
+                                }
+                                if ui.small_button("✖").clicked() {
+                                    self.selected_item.string_vars[index] = String::new();
+                                }
+                            }
+                            None => {
+                                if ui.button("Set Date").clicked() {
+                                    self.selected_item.string_vars[index] =
+                                        Local::now().format("%Y-%m-%d").to_string();
+                                }
+                            }
+                        }
+                    });
+                } else {
+                    ui.horizontal(|ui| {
+                        let clicked = Visualoc::interactive_label(
+                            ui,
+                            ctx,
+                            self.settings.light_mode,
+                            false,
+                            &self.selected_item.string_vars[index],
+                        );
+                        //Shows the items with the same date
+                        if clicked {
+                            if let Some(date) = parse_date(&self.selected_item.string_vars[index]) {
+                                self.prepare_page(UIPages::Home);
+                                self.home_page.column_search = (column_name, DataType::Date);
+                                self.home_page.date_from = Some(date);
+                                self.home_page.date_to = Some(date);
+                            }
+                        }
+                        Visualoc::copy_button(
+                            ui,
+                            self.settings.light_mode,
+                            self.selected_item.string_vars[index].clone(),
+                            ctx,
+                        )
+                    });
+                }
+            }
+            DataType::DateTime => {
+                if self.container_screen == ContainerScreen::EditingItem
+                    || self.container_screen == ContainerScreen::AddingItem
+                {
+                    ui.horizontal(|ui| {
+                        match parse_date_time(&self.selected_item.string_vars[index]) {
+                            Some(date_time) => {
+                                let mut date = date_time.date_naive();
+                                let mut hour = date_time.hour();
+                                let mut minute = date_time.minute();
+                                let mut changed = ui
+                                    .add(DatePickerButton::new(&mut date).id_salt(&column_name))
+                                    .changed();
+                                changed |= ui
+                                    .add(DragValue::new(&mut hour).range(0..=23))
+                                    .changed();
+                                ui.label(":");
+                                changed |= ui
+                                    .add(DragValue::new(&mut minute).range(0..=59))
+                                    .changed();
+                                if changed {
+                                    if let Some(date_time) = date
+                                        .and_hms_opt(hour, minute, 0)
+                                        .and_then(|x| Local.from_local_datetime(&x).earliest())
+                                    {
+                                        self.selected_item.string_vars[index] =
+                                            date_time_to_string(&date_time);
+                                    }
+                                }
+                                if ui.small_button("✖").clicked() {
+                                    self.selected_item.string_vars[index] = String::new();
+                                }
+                            }
+                            None => {
+                                if ui.button("Set Date").clicked() {
+                                    self.selected_item.string_vars[index] =
+                                        date_time_to_string(&Local::now());
+                                }
+                            }
+                        }
+                    });
+                } else {
+                    let text = format_date_time(&self.selected_item.string_vars[index]);
+                    ui.horizontal(|ui| {
+                        let clicked = Visualoc::interactive_label(
+                            ui,
+                            ctx,
+                            self.settings.light_mode,
+                            false,
+                            &text,
+                        );
+                        //Shows the items from the same day
+                        if clicked {
+                            if let Some(date_time) =
+                                parse_date_time(&self.selected_item.string_vars[index])
+                            {
+                                self.prepare_page(UIPages::Home);
+                                self.home_page.column_search = (column_name, DataType::DateTime);
+                                self.home_page.date_from = Some(date_time.date_naive());
+                                self.home_page.date_to = Some(date_time.date_naive());
+                            }
+                        }
+                        Visualoc::copy_button(ui, self.settings.light_mode, text.clone(), ctx)
+                    });
+                }
+            }
+            DataType::Money { currency } => {
+                if self.container_screen == ContainerScreen::EditingItem
+                    || self.container_screen == ContainerScreen::AddingItem
+                {
+                    ui.horizontal(|ui| {
+                        let response = ui.add(
+                            TextEdit::singleline(&mut self.selected_item.string_vars[index])
+                                .hint_text("0.00")
+                                .desired_width(120.0),
+                        );
+                        ui.label(&currency);
+                        let value = &self.selected_item.string_vars[index];
+                        match normalize_money(value) {
+                            //Always shown with 2 decimals once the user is done typing
+                            Some(normalized) => {
+                                if response.lost_focus() {
+                                    self.selected_item.string_vars[index] = normalized;
+                                }
+                            }
+                            None => {
+                                if !value.trim().is_empty() {
+                                    ui.colored_label(Color32::RED, "Not an amount");
+                                }
+                            }
+                        }
+                    });
+                } else {
+                    ui.horizontal(|ui| {
+                        let clicked = Visualoc::interactive_label(
+                            ui,
+                            ctx,
+                            self.settings.light_mode,
+                            false,
+                            &format_money(&self.selected_item.string_vars[index], &currency),
+                        );
+                        if clicked {
+                            self.prepare_page(UIPages::Home);
+                            self.home_page.column_search =
+                                (column_name, DataType::Money { currency });
+                            self.search_string = self.selected_item.string_vars[index].clone();
+                        }
+                        Visualoc::copy_button(
+                            ui,
+                            self.settings.light_mode,
+                            self.selected_item.string_vars[index].clone(),
+                            ctx,
+                        )
+                    });
+                }
+            }
             DataType::Gallery => {
-                ui.label("todo");
+                let editing = self.container_screen == ContainerScreen::EditingItem
+                    || self.container_screen == ContainerScreen::AddingItem;
+                let mut entries = gallery_entries(&self.selected_item.string_vars[index]);
+                let mut clicked_index: Option<usize> = None;
+                let mut moved: Option<(usize, usize)> = None;
+                let mut removed: Option<usize> = None;
+                ScrollArea::horizontal()
+                    .id_salt(&column_name)
+                    .show(ui, |ui| {
+                        ui.horizontal(|ui| {
+                            for (entry_index, entry) in entries.iter().enumerate() {
+                                ui.vertical(|ui| {
+                                    if let Some(Some((texture, _))) = self.loaded_images.get(entry)
+                                    {
+                                        let response = ui.add(
+                                            egui::Image::new(texture)
+                                                .fit_to_original_size(2.0)
+                                                .max_height(60.0)
+                                                .sense(egui::Sense::click()),
+                                        );
+                                        if response.clicked() {
+                                            clicked_index = Some(entry_index);
+                                        }
+                                    } else if !self.loaded_images.contains_key(entry) {
+                                        let (image_id, image_type) = split_gallery_entry(entry);
+                                        self.loaded_images.insert(entry.to_owned(), None);
+                                        self.async_tasks_to_send.push(
+                                            CommandToServer::GetImageFromServer(
+                                                Uuid::new_v4().to_string(),
+                                                image_id,
+                                                image_type,
+                                                ImageSize::Small,
+                                                ColorImage::default(),
+                                            ),
+                                        );
+                                    } else {
+                                        ui.spinner();
+                                    }
+                                    if editing {
+                                        ui.horizontal(|ui| {
+                                            if ui.small_button("◀").clicked() && entry_index > 0 {
+                                                moved = Some((entry_index, entry_index - 1));
+                                            }
+                                            if ui.small_button("▶").clicked()
+                                                && entry_index + 1 < entries.len()
+                                            {
+                                                moved = Some((entry_index, entry_index + 1));
+                                            }
+                                            if ui.small_button("🗑").clicked() {
+                                                removed = Some(entry_index);
+                                            }
+                                        });
+                                    }
+                                });
+                            }
+                            if editing && ui.button("➕ Add Image").clicked() {
+                                self.async_tasks_to_send
+                                    .push(CommandToServer::AddGalleryImage(
+                                        Uuid::new_v4().to_string(),
+                                        self.selected_item.id.clone(),
+                                        column_name.clone(),
+                                        String::new(),
+                                    ));
+                            }
+                        });
+                    });
+                if let Some(entry_index) = clicked_index {
+                    self.modal_vars.gallery_entries = entries.clone();
+                    self.modal_vars.gallery_index = entry_index;
+                    self.modal_vars.modal_type = ModalType::GalleryImage;
+                }
+                //A removed image is deleted from the server once the item is saved
+                if let Some((from, to)) = moved {
+                    entries.swap(from, to);
+                    self.selected_item.string_vars[index] = entries.join("@");
+                } else if let Some(entry_index) = removed {
+                    entries.remove(entry_index);
+                    self.selected_item.string_vars[index] = entries.join("@");
+                }
             }
             DataType::List(string_vec) => {
                 if self.container_screen == ContainerScreen::EditingItem
@@ -489,6 +932,17 @@ impl Visualoc {
     }
 
     pub fn show_item_fields(&mut self, ui: &mut egui::Ui, ctx: &Context) {
+        //The low stock warning is kept apart from the item, it's fetched once per item shown
+        if self.stock_threshold_item != self.selected_item.id {
+            self.stock_threshold_item = self.selected_item.id.clone();
+            self.stock_threshold = None;
+            self.async_tasks_to_send
+                .push(CommandToServer::GetStockThreshold(
+                    Uuid::new_v4().to_string(),
+                    self.selected_item.id.clone(),
+                    None,
+                ));
+        }
         ui.vertical_centered(|ui| {
             ui.horizontal(|ui| {
                 if self.selected_item.image_type != String::new() {
@@ -573,11 +1027,64 @@ impl Visualoc {
                         }
                     });
                 });
+                //Items not in a container have no placement to count
+                if self.container_screen != ContainerScreen::ItemNotInContainer {
+                    body.row(30.0, |mut row| {
+                        row.col(|ui| {
+                            ui.label("Quantity ");
+                        });
+                        row.col(|ui| {
+                            if self.container_screen == ContainerScreen::EditingItem
+                                || self.container_screen == ContainerScreen::AddingItem
+                            {
+                                ui.horizontal(|ui| {
+                                    ui.add(
+                                        DragValue::new(&mut self.item_quantity).range(0..=i32::MAX),
+                                    );
+                                    ui.weak("in this container");
+                                });
+                            } else {
+                                ui.label(format!(
+                                    "{} in this container, {} in total",
+                                    self.quantity_in_selected_container(),
+                                    self.selected_item.quantity
+                                ));
+                            }
+                        });
+                    });
+                }
+                body.row(30.0, |mut row| {
+                    row.col(|ui| {
+                        ui.label("Low stock warning ");
+                    });
+                    row.col(|ui| {
+                        if self.container_screen == ContainerScreen::EditingItem
+                            || self.container_screen == ContainerScreen::AddingItem
+                        {
+                            self.edit_stock_threshold(ui);
+                        } else {
+                            match &self.stock_threshold {
+                                Some(threshold) => ui.label(format!(
+                                    "{} at or below {}",
+                                    threshold.field_name.as_deref().unwrap_or("Quantity"),
+                                    threshold.minimum
+                                )),
+                                None => ui.weak("Off"),
+                            };
+                        }
+                    });
+                });
                 let mut i64_index: usize = 0;
                 let mut f64_index: usize = 0;
                 let mut string_index: usize = 0;
                 for (field_name, field_type) in self.item_field_types.clone() {
-                    body.row(30.0, |mut row| {
+                    //Galleries need room for the thumbnails and their buttons
+                    let row_height = if field_type == DataType::Gallery {
+                        100.0
+                    } else {
+                        30.0
+                    };
+                    body.row(row_height, |mut row| {
                         row.col(|ui| {
                             ui.label(field_name.to_owned() + " ");
                         });
@@ -596,6 +1103,9 @@ impl Visualoc {
                             }
                             DataType::String
                             | DataType::Gallery
+                            | DataType::Date
+                            | DataType::DateTime
+                            | DataType::Money { .. }
                             | DataType::Text
                             | DataType::List(_) => {
                                 row.col(|ui| {
@@ -635,6 +1145,39 @@ impl Visualoc {
         }
     }
 
+    //Checkbox to turn the warning on, then the field compared and its minimum
+    fn edit_stock_threshold(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let mut enabled = self.stock_threshold.is_some();
+            if ui.checkbox(&mut enabled, "").changed() {
+                self.stock_threshold = enabled.then(StockThreshold::default);
+            }
+            let Some(threshold) = &mut self.stock_threshold else {
+                ui.weak("Off");
+                return;
+            };
+            ComboBox::from_id_salt("stock threshold field")
+                .selected_text(threshold.field_name.as_deref().unwrap_or("Quantity"))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut threshold.field_name, None, "Quantity");
+                    for (field_name, field_type) in &self.item_field_types {
+                        if matches!(
+                            field_type,
+                            DataType::Integer | DataType::Float | DataType::Percentage
+                        ) {
+                            ui.selectable_value(
+                                &mut threshold.field_name,
+                                Some(field_name.clone()),
+                                field_name,
+                            );
+                        }
+                    }
+                });
+            ui.label("at or below");
+            ui.add(DragValue::new(&mut threshold.minimum).range(0.0..=f64::MAX));
+        });
+    }
+
     pub fn item_selected_screen(&mut self, ui: &mut egui::Ui, ctx: &Context) {
         ScrollArea::vertical()
             .stick_to_right(true)
@@ -647,6 +1190,7 @@ impl Visualoc {
                     ui.vertical_centered(|ui| ui.heading("Item Actions"));
                     ui.horizontal(|ui| {
                         if ui.button("✏ Edit Item").clicked() {
+                            self.item_quantity = self.quantity_in_selected_container();
                             self.container_screen = ContainerScreen::EditingItem;
                         }
                         if Visualoc::delete_button(ui, "Delete Item").clicked() {
@@ -655,6 +1199,12 @@ impl Visualoc {
                         if ui.button("⬆ Remove from Container").clicked() {
                             self.modal_vars.modal_type = ModalType::RemoveFromContainer;
                         }
+                        if ui.button("➡ Move to…").clicked() {
+                            self.modal_vars.move_path = Vec::new();
+                            self.modal_vars.move_message = String::new();
+                            self.browse_move_picker();
+                            self.modal_vars.modal_type = ModalType::MoveItems;
+                        }
                     });
                 }
                 if self.container_screen == ContainerScreen::EditingItem {
@@ -670,6 +1220,8 @@ impl Visualoc {
                                 self.selected_item.clone(),
                                 self.item_field_types.clone(),
                             ));
+                            self.save_item_quantity();
+                            self.save_stock_threshold();
                             self.async_tasks_to_send
                                 .push(CommandToServer::GetMultipleItems(
                                     Uuid::new_v4().to_string(),
@@ -686,6 +1238,8 @@ impl Visualoc {
                                     + "."
                                     + &self.selected_item.image_type),
                             );
+                            //Fetched again so the edits are dropped
+                            self.stock_threshold_item = String::new();
                             self.async_tasks_to_send
                                 .push(CommandToServer::GetMultipleItems(
                                     Uuid::new_v4().to_string(),
@@ -716,6 +1270,10 @@ impl Visualoc {
                         self.selected_container
                             .contained_items
                             .insert(self.selected_item.id.clone());
+                        self.save_item_quantity();
+                        if self.stock_threshold.is_some() {
+                            self.save_stock_threshold();
+                        }
                         self.item_vec.push(self.selected_item.clone());
                         self.item_vec.sort_by(|a, b| a.name.cmp(&b.name));
                         //Update the Container in the ContainerVec
@@ -770,7 +1328,82 @@ impl Visualoc {
             });
     }
 
+    //Location › opened containers › selected container › selected item, clicking a part goes back to it
+    fn breadcrumb_bar(&mut self, ui: &mut egui::Ui) {
+        if self.container_screen == ContainerScreen::ItemNotInContainer {
+            return;
+        }
+        let mut open_depth = None;
+        ui.horizontal_wrapped(|ui| {
+            if ui.link(format!("📍 {}", self.selected_location.name)).clicked() {
+                open_depth = Some(0);
+            }
+            for (depth, container) in self.container_path.iter().enumerate() {
+                ui.label("›");
+                if ui.link(&container.name).clicked() {
+                    open_depth = Some(depth + 1);
+                }
+            }
+            //Selected on the canvas, not opened
+            if self.selected_container.master == self.canvas_parent().id
+                && self.container_screen != ContainerScreen::None
+                && self.container_screen != ContainerScreen::AddingContainer
+            {
+                ui.label("›");
+                ui.label(&self.selected_container.name);
+            }
+            if self.container_screen == ContainerScreen::SelectedItem
+                || self.container_screen == ContainerScreen::EditingItem
+            {
+                ui.label("›");
+                ui.label(&self.selected_item.name);
+            }
+        });
+        if let Some(depth) = open_depth {
+            self.open_breadcrumb(depth);
+        }
+    }
+
+    //Locations with matches of the search shown on the map, and the way back to the plain photos
+    fn search_map_bar(&mut self, ui: &mut egui::Ui) {
+        let Some(map) = &self.search_map else {
+            return;
+        };
+        let mut open_index = None;
+        let mut close = false;
+        ui.horizontal_wrapped(|ui| {
+            if map.query.search_string.is_empty() {
+                ui.label("🗺 All items in");
+            } else {
+                ui.label(format!("🗺 Matches of \"{}\" in", map.query.search_string));
+            }
+            for (index, matches) in map.locations.iter().enumerate() {
+                let selected = matches.location.id == self.selected_location.id;
+                let text = format!("{} ({})", matches.location.name, matches.item_count);
+                if ui.selectable_label(selected, text).clicked() && !selected {
+                    open_index = Some(index);
+                }
+            }
+            if ui.button("✖ Close map").clicked() {
+                close = true;
+            }
+        });
+        if let Some(index) = open_index {
+            self.open_search_map_location(index);
+        }
+        if close {
+            self.search_map = None;
+        }
+        ui.separator();
+    }
+
     pub fn location_containers_screen(&mut self, ui: &mut egui::Ui, ctx: &Context) {
+        self.search_map_bar(ui);
+        self.breadcrumb_bar(ui);
+        //Inside an opened container there's no location screen, its actions are the container's
+        if self.container_screen == ContainerScreen::None && !self.container_path.is_empty() {
+            self.open_breadcrumb(self.container_path.len());
+        }
         ui.with_layout(Layout::left_to_right(Align::LEFT), |ui| {
             self.draw_canvas(ui);
             ui.vertical(|ui| {
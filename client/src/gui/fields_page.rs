@@ -3,7 +3,7 @@ use uuid::Uuid;
 
 use crate::{
     CommandToServer, DataType, FieldModal, ModalType, Visualoc, WaitingFunction,
-    WaitingFunctionKind,
+    WaitingFunctionKind, database::data_helpers::is_currency_code,
 };
 
 impl Visualoc {
@@ -81,6 +81,15 @@ impl Visualoc {
                                     DataType::Percentage => {
                                         ui.label("Percentage");
                                     }
+                                    DataType::Date => {
+                                        ui.label("Date");
+                                    }
+                                    DataType::DateTime => {
+                                        ui.label("Date and Time");
+                                    }
+                                    DataType::Money { currency } => {
+                                        ui.label(format!("Money ({})", currency));
+                                    }
                                 });
                             });
                         }
@@ -195,6 +204,9 @@ impl Visualoc {
                                     //The existence of string_vec[0] is guaranteed by the checkbox click
                                     ui.text_edit_multiline(&mut string_vec[0]);
                                 }
+                                DataType::Money { currency } => {
+                                    Visualoc::currency_edit(ui, currency);
+                                }
                                 _ => (),
                             }
                         });
@@ -211,6 +223,7 @@ impl Visualoc {
                                 && self.modal_vars.new_field_name != "id"
                                 && self.modal_vars.new_field_name != "name"
                                 && self.modal_vars.new_field_name != "image_type"
+                                && !Visualoc::invalid_currency(&self.modal_vars.new_field_type)
                             {
                                 for (index, selected) in self
                                     .modal_vars
@@ -300,6 +313,9 @@ impl Visualoc {
                                 DataType::Text => "Paragraph",
                                 DataType::Gallery => "Image Gallery",
                                 DataType::Percentage => "Percentage",
+                                DataType::Date => "Date",
+                                DataType::DateTime => "Date and Time",
+                                DataType::Money { .. } => "Money",
                             })
                             .show_ui(ui, |ui| {
                                 ui.selectable_value(
@@ -342,6 +358,34 @@ impl Visualoc {
                                     DataType::Gallery,
                                     "Image Gallery",
                                 );
+                                ui.selectable_value(
+                                    &mut self.modal_vars.new_field_type,
+                                    DataType::Date,
+                                    "Date",
+                                );
+                                ui.selectable_value(
+                                    &mut self.modal_vars.new_field_type,
+                                    DataType::DateTime,
+                                    "Date and Time",
+                                );
+                                if ui
+                                    .selectable_label(
+                                        matches!(
+                                            self.modal_vars.new_field_type,
+                                            DataType::Money { .. }
+                                        ),
+                                        "Money",
+                                    )
+                                    .clicked()
+                                    && !matches!(
+                                        self.modal_vars.new_field_type,
+                                        DataType::Money { .. }
+                                    )
+                                {
+                                    self.modal_vars.new_field_type = DataType::Money {
+                                        currency: "EUR".to_string(),
+                                    };
+                                }
                             });
                     });
                     ui.separator();
@@ -355,6 +399,9 @@ impl Visualoc {
                             ui.label("Add the list values separated by a comma (,)");
                             ui.text_edit_multiline(&mut string_vec[0]);
                         }
+                        DataType::Money { currency } => {
+                            Visualoc::currency_edit(ui, currency);
+                        }
                         _ => (),
                     }
                     ui.separator();
@@ -371,6 +418,7 @@ impl Visualoc {
                             && self.modal_vars.new_field_name != "id"
                             && self.modal_vars.new_field_name != "name"
                             && self.modal_vars.new_field_name != "image_type"
+                            && !Visualoc::invalid_currency(&self.modal_vars.new_field_type)
                         {
                             let cmd_id = Uuid::new_v4().to_string();
                             self.async_tasks_to_send.push(CommandToServer::AddField(
@@ -390,7 +438,12 @@ impl Visualoc {
                                         item.float_vars.push(0.0);
                                     }
                                 }
-                                DataType::String | DataType::Text | DataType::Gallery => {
+                                DataType::String
+                                | DataType::Text
+                                | DataType::Gallery
+                                | DataType::Date
+                                | DataType::DateTime
+                                | DataType::Money { .. } => {
                                     for item in self.item_vec.iter_mut() {
                                         item.string_vars.push(String::new());
                                     }
@@ -435,4 +488,23 @@ impl Visualoc {
             }
         });
     }
+
+    //Currencies are 3 letter codes like EUR or USD
+    fn currency_edit(ui: &mut egui::Ui, currency: &mut String) {
+        ui.horizontal(|ui| {
+            ui.label("Currency");
+            ui.add(egui::TextEdit::singleline(currency).desired_width(60.0));
+        });
+        *currency = currency.trim().to_uppercase();
+        if !is_currency_code(currency) {
+            ui.colored_label(Color32::RED, "Currency must be a 3 letter code like EUR");
+        }
+    }
+
+    fn invalid_currency(data_type: &DataType) -> bool {
+        match data_type {
+            DataType::Money { currency } => !is_currency_code(currency),
+            _ => false,
+        }
+    }
 }
@@ -1,34 +1,93 @@
+use std::collections::HashSet;
+
 use egui::{Button, Color32, Context, Label, Rect, Response, RichText, Sense, Stroke, Ui, Vec2};
 use uuid::Uuid;
 
 use crate::{
-    CommandToServer, Container, ContainerScreen, DataType, FieldModal, ModalType, UIPages,
-    Visualoc, WaitingFunction, WaitingFunctionKind, toggle_light_mode,
+    ALL_FIELDS, CommandToServer, ContainedItem, Container, ContainerScreen, DataType, FieldModal,
+    ItemQuery, ModalType, SortDirection, UIPages, Visualoc, WaitingFunction, WaitingFunctionKind,
+    database::data_helpers::{date_time_to_string, local_day_start},
+    toggle_light_mode,
 };
 
 impl Visualoc {
     pub fn calculate_item_page_indexes(&mut self) -> (usize, usize, usize) {
-        let num_of_pages: usize = if self.item_vec.len() % self.settings.items_per_page == 0 {
-            if self.item_vec.is_empty() {
-                1
-            } else {
-                self.item_vec.len() / self.settings.items_per_page
-            }
+        //The item_vec only holds the page shown, the total comes from the server
+        let num_of_pages: usize = if self.home_page.total_items == 0 {
+            1
         } else {
-            self.item_vec.len() / self.settings.items_per_page + 1
+            self.home_page.total_items.div_ceil(self.settings.items_per_page)
         };
+        let last_shown_item = self.item_vec.len().min(self.settings.items_per_page);
+        return (num_of_pages, 0, last_shown_item);
+    }
 
-        let first_shown_item = self.home_page.page_number * self.settings.items_per_page;
-
-        let last_shown_item: usize =
-            if first_shown_item + self.settings.items_per_page - 1 < self.item_vec.len() {
-                first_shown_item + self.settings.items_per_page - 1
-            } else if !self.item_vec.is_empty() {
-                self.item_vec.len()
+    //The page of items the home page should be showing
+    pub fn home_page_query(&self) -> ItemQuery {
+        let all_fields = self.home_page.column_search.0 == ALL_FIELDS;
+        let search_string = match self.home_page.column_search.1 {
+            DataType::Date | DataType::DateTime => self.date_range_filter(),
+            _ => self.search_string.clone(),
+        };
+        ItemQuery {
+            search_string,
+            column_name: if all_fields {
+                "Name".to_string()
             } else {
-                first_shown_item
+                self.home_page.column_search.0.clone()
+            },
+            offset: self.home_page.page_number * self.settings.items_per_page,
+            limit: Some(self.settings.items_per_page),
+            sort_column: self.home_page.sort_column.clone(),
+            sort_direction: self.home_page.sort_direction,
+            all_fields,
+        }
+    }
+
+    //Date fields are searched by a range, sent as a filter like `Bought >= 2024-01-01 AND Bought <= 2024-01-31`
+    fn date_range_filter(&self) -> String {
+        let (column_name, column_type) = &self.home_page.column_search;
+        let mut conditions = Vec::new();
+        if *column_type == DataType::Date {
+            if let Some(from) = self.home_page.date_from {
+                conditions.push(format!("{} >= {}", column_name, from.format("%Y-%m-%d")));
+            }
+            if let Some(to) = self.home_page.date_to {
+                conditions.push(format!("{} <= {}", column_name, to.format("%Y-%m-%d")));
+            }
+        } else {
+            //Date-times are compared to the start of the days in local time, the end is exclusive
+            if let Some(from) = self.home_page.date_from.and_then(local_day_start) {
+                conditions.push(format!(
+                    "{} >= {}",
+                    column_name,
+                    date_time_to_string(&from)
+                ));
+            }
+            if let Some(to) = self
+                .home_page
+                .date_to
+                .and_then(|x| x.succ_opt())
+                .and_then(local_day_start)
+            {
+                conditions.push(format!("{} < {}", column_name, date_time_to_string(&to)));
+            }
+        }
+        conditions.join(" AND ")
+    }
+
+    //Clicking a column header sorts by it, clicking it again reverses the order
+    pub fn toggle_sort_column(&mut self, column_name: &str) {
+        if self.home_page.sort_column.as_deref() == Some(column_name) {
+            self.home_page.sort_direction = match self.home_page.sort_direction {
+                SortDirection::Ascending => SortDirection::Descending,
+                SortDirection::Descending => SortDirection::Ascending,
             };
-        return (num_of_pages, first_shown_item, last_shown_item);
+        } else {
+            self.home_page.sort_column = Some(column_name.to_string());
+            self.home_page.sort_direction = SortDirection::Ascending;
+        }
+        self.home_page.page_number = 0;
     }
 
     pub fn calculate_label_size_with_wrap(ui: &Ui, text: &str, max_width: f32) -> egui::Rect {
@@ -216,6 +275,7 @@ impl Visualoc {
                 Uuid::new_v4().to_string(),
                 Vec::new(),
             ));
+        self.refresh_low_stock();
         toggle_light_mode(ctx, self.settings.light_mode);
     }
 
@@ -224,9 +284,13 @@ impl Visualoc {
         //for items so that their size and initial values are ok to be used for item creation
         for (_, column_type) in &self.item_field_types {
             match column_type {
-                DataType::String | DataType::List(_) | DataType::Text | DataType::Gallery => {
-                    self.selected_item.string_vars.push(String::new())
-                }
+                DataType::String
+                | DataType::List(_)
+                | DataType::Text
+                | DataType::Gallery
+                | DataType::Date
+                | DataType::DateTime
+                | DataType::Money { .. } => self.selected_item.string_vars.push(String::new()),
                 DataType::Integer | DataType::Bool => self.selected_item.int_vars.push(0),
                 DataType::Float | DataType::Percentage => self.selected_item.float_vars.push(0.0),
             }
@@ -242,6 +306,8 @@ impl Visualoc {
                         Vec::new(),
                     ));
                 self.home_page.page_number = 0;
+                //Other pages overwrite the item_vec, fetch the page again
+                self.home_page.previous_query = None;
                 self.current_ui = UIPages::Home;
             }
             UIPages::LocationGrid => {
@@ -263,6 +329,8 @@ impl Visualoc {
                     Vec::new(),
                 ));
                 self.search_string = String::new();
+                self.delete_container_report = None;
+                self.container_path = Vec::new();
                 self.current_ui = UIPages::LocationContainers;
             }
             UIPages::Statistics => {
@@ -271,22 +339,322 @@ impl Visualoc {
                     self.source_node_id.clone(),
                     Vec::new(),
                 ));
+                //Statistics need every item
                 self.async_tasks_to_send.push(CommandToServer::SearchItems(
                     Uuid::new_v4().to_string(),
-                    "".to_string(),
-                    "name".to_string(),
-                    Vec::new(),
+                    ItemQuery::default(),
+                    Ok((Vec::new(), 0, Vec::new())),
                 ));
+                self.async_tasks_to_send
+                    .push(CommandToServer::GetValueTotals(
+                        Uuid::new_v4().to_string(),
+                        Vec::new(),
+                    ));
+                self.async_tasks_to_send
+                    .push(CommandToServer::GetUnitTotals(
+                        Uuid::new_v4().to_string(),
+                        Vec::new(),
+                    ));
                 self.calculate_statistics();
 
                 self.current_ui = UIPages::Statistics;
             }
+            UIPages::LowStock => {
+                self.refresh_low_stock();
+                self.current_ui = UIPages::LowStock;
+            }
             UIPages::Account => {
                 self.current_ui = UIPages::Account;
             }
         }
     }
 
+    //The top row badge and the low stock page both read the report
+    pub fn refresh_low_stock(&mut self) {
+        self.async_tasks_to_send.push(CommandToServer::GetLowStock(
+            Uuid::new_v4().to_string(),
+            Vec::new(),
+        ));
+    }
+
+    //Items at or below their minimum, an item low in several locations counts once
+    pub fn low_stock_count(&self) -> usize {
+        self.low_stock
+            .iter()
+            .flat_map(|location| location.items.iter().map(|item| &item.item_id))
+            .collect::<HashSet<_>>()
+            .len()
+    }
+
+    //Sends the low stock warning edited in the item view
+    pub fn save_stock_threshold(&mut self) {
+        self.async_tasks_to_send
+            .push(CommandToServer::SetStockThreshold(
+                Uuid::new_v4().to_string(),
+                self.selected_item.id.clone(),
+                self.stock_threshold.clone(),
+            ));
+    }
+
+    //The container whose photo the canvas shows, the location until a container is opened
+    pub fn canvas_parent(&self) -> &Container {
+        self.container_path.last().unwrap_or(&self.selected_location)
+    }
+
+    //Same as clicking the container on the canvas
+    pub fn select_container(&mut self, container: Container) {
+        self.selected_container = container;
+        self.async_tasks_to_send
+            .push(CommandToServer::GetMultipleItems(
+                Uuid::new_v4().to_string(),
+                self.selected_container.contained_items.clone(),
+                Vec::new(),
+            ));
+        self.search_string = String::new();
+        self.item_page_search_vec = self.item_vec.clone();
+        self.container_screen = ContainerScreen::SelectedContainer;
+    }
+
+    //Shows the container's photo with the containers inside it, the container stays selected
+    pub fn open_child_container(&mut self, container: Container) {
+        self.container_path.push(container.clone());
+        self.select_container(container);
+        self.async_tasks_to_send.push(CommandToServer::GetAllSlaves(
+            Uuid::new_v4().to_string(),
+            self.canvas_parent().id.clone(),
+            Vec::new(),
+        ));
+    }
+
+    //Goes back up the breadcrumb, keeping the first depth opened containers, 0 is the location
+    pub fn open_breadcrumb(&mut self, depth: usize) {
+        self.container_path.truncate(depth);
+        match self.container_path.last().cloned() {
+            Some(container) => self.select_container(container),
+            None => {
+                self.selected_container = Container::default();
+                self.container_screen = ContainerScreen::None;
+            }
+        }
+        self.async_tasks_to_send.push(CommandToServer::GetAllSlaves(
+            Uuid::new_v4().to_string(),
+            self.canvas_parent().id.clone(),
+            Vec::new(),
+        ));
+    }
+
+    //Units of the selected item in the selected container, placements from before quantities count as 1
+    pub fn quantity_in_selected_container(&self) -> i32 {
+        self.selected_container
+            .item_quantities
+            .get(&self.selected_item.id)
+            .copied()
+            .unwrap_or(1)
+    }
+
+    //Stores the quantity edited in the item view on the selected container and sends it to the database
+    pub fn save_item_quantity(&mut self) {
+        let previous = self.quantity_in_selected_container();
+        self.selected_item.quantity += (self.item_quantity - previous) as i64;
+        self.selected_container
+            .item_quantities
+            .insert(self.selected_item.id.clone(), self.item_quantity);
+        for cont in &mut self.container_vec {
+            if cont.id == self.selected_container.id {
+                cont.item_quantities
+                    .insert(self.selected_item.id.clone(), self.item_quantity);
+            }
+        }
+        self.async_tasks_to_send
+            .push(CommandToServer::UpdateContainer(
+                Uuid::new_v4().to_string(),
+                self.selected_container.clone(),
+            ));
+    }
+
+    //Moves the items from one container to another, the copies are updated once the server confirms
+    pub fn move_items_to(
+        &mut self,
+        item_ids: Vec<String>,
+        from_container_id: String,
+        container_id: String,
+    ) {
+        self.modal_vars.move_message = String::new();
+        self.async_tasks_to_send.push(CommandToServer::MoveItems(
+            Uuid::new_v4().to_string(),
+            item_ids,
+            from_container_id,
+            container_id,
+            None,
+        ));
+    }
+
+    //Updates every copy of the two containers, quantities are added up the same way the server does
+    pub fn items_moved(
+        &mut self,
+        item_ids: &[String],
+        from_container_id: &str,
+        container_id: &str,
+    ) {
+        let source = std::iter::once(&self.selected_container)
+            .chain(&self.container_vec)
+            .chain(&self.container_path)
+            .find(|x| x.id == from_container_id);
+        let quantities: Vec<i32> = item_ids
+            .iter()
+            .map(|id| {
+                source
+                    .and_then(|x| x.item_quantities.get(id).copied())
+                    .unwrap_or(1)
+            })
+            .collect();
+        let update = |container: &mut Container| {
+            for (item_id, quantity) in item_ids.iter().zip(&quantities) {
+                if container.id == from_container_id {
+                    container.contained_items.remove(item_id);
+                    container.item_quantities.remove(item_id);
+                } else if container.id == container_id {
+                    let held = if container.contained_items.contains(item_id) {
+                        container.item_quantities.get(item_id).copied().unwrap_or(1)
+                    } else {
+                        0
+                    };
+                    container.contained_items.insert(item_id.clone());
+                    container
+                        .item_quantities
+                        .insert(item_id.clone(), held + quantity);
+                }
+            }
+        };
+        update(&mut self.selected_container);
+        self.container_vec.iter_mut().for_each(update);
+        self.container_path.iter_mut().for_each(update);
+        if self.selected_container.id == from_container_id {
+            self.item_vec.retain(|x| !item_ids.contains(&x.id));
+            self.item_page_search_vec = self.item_vec.clone();
+            if item_ids.contains(&self.selected_item.id)
+                && self.container_screen == ContainerScreen::SelectedItem
+            {
+                self.container_screen = ContainerScreen::SelectedContainer;
+            }
+        } else if self.selected_container.id == container_id {
+            self.async_tasks_to_send
+                .push(CommandToServer::GetMultipleItems(
+                    Uuid::new_v4().to_string(),
+                    self.selected_container.contained_items.clone(),
+                    Vec::new(),
+                ));
+        }
+    }
+
+    //Lists what's inside the last container browsed in the MoveItems modal, the locations at the start
+    pub fn browse_move_picker(&mut self) {
+        let parent_id = match self.modal_vars.move_path.last() {
+            Some(container) => container.id.clone(),
+            None => self.source_node_id.clone(),
+        };
+        self.modal_vars.move_browse_id = Uuid::new_v4().to_string();
+        self.modal_vars.move_children = Vec::new();
+        self.async_tasks_to_send.push(CommandToServer::GetAllSlaves(
+            self.modal_vars.move_browse_id.clone(),
+            parent_id,
+            Vec::new(),
+        ));
+    }
+
+    //Matches of the search map inside the container, 0 when no map is shown
+    pub fn search_matches(&self, container_id: &str) -> usize {
+        self.search_map.as_ref().map_or(0, |map| {
+            map.locations
+                .iter()
+                .filter_map(|matches| matches.container_counts.get(container_id))
+                .sum()
+        })
+    }
+
+    //Edits change which containers hold matches, the map is fetched again and stays on the shown photo
+    pub fn refresh_search_map(&mut self) {
+        if let Some(map) = &self.search_map {
+            self.async_tasks_to_send.push(CommandToServer::GetItemMap(
+                Uuid::new_v4().to_string(),
+                map.query.clone(),
+                Ok(Vec::new()),
+            ));
+        }
+    }
+
+    //Opens one of the locations of the search map, its photo shows the containers with matches highlighted
+    pub fn open_search_map_location(&mut self, index: usize) {
+        let Some(location) = self
+            .search_map
+            .as_ref()
+            .and_then(|map| map.locations.get(index))
+            .map(|matches| matches.location.clone())
+        else {
+            return;
+        };
+        self.loaded_images
+            .remove(&(location.id.clone() + "." + &location.image_type));
+        self.selected_location = location;
+        self.item_vec = Vec::new();
+        self.container_screen = ContainerScreen::None;
+        self.selected_container = Container::default();
+        self.prepare_page(UIPages::LocationContainers);
+    }
+
+    //Opens a path from the root down to a container, used by the home page and scanned codes
+    //The container is shown selected on its parent's photo, with the item selected if there is one
+    pub fn open_container_path(&mut self, path: Vec<Container>, item: Option<ContainedItem>) {
+        let mut path: Vec<Container> = path
+            .into_iter()
+            .filter(|x| x.id != self.source_node_id)
+            .collect();
+        let in_location = !path.is_empty();
+        self.selected_location = if in_location {
+            path.remove(0)
+        } else {
+            Container::default()
+        };
+        let target = path.pop();
+        self.container_path = path;
+        //Small drawers are hard to spot on a large photo
+        if target.is_some() {
+            self.zoom_to_selected_container();
+        }
+        match (target, item) {
+            (Some(container), Some(item)) => {
+                self.selected_container = container;
+                self.selected_item = item;
+                self.container_screen = ContainerScreen::SelectedItem;
+            }
+            (Some(container), None) => self.select_container(container),
+            //Placed in the location itself
+            (None, Some(item)) if in_location => {
+                self.selected_container = self.selected_location.clone();
+                self.selected_item = item;
+                self.container_screen = ContainerScreen::SelectedItem;
+            }
+            (None, Some(item)) => {
+                self.selected_container = Container::default();
+                self.selected_item = item;
+                self.container_screen = ContainerScreen::ItemNotInContainer;
+                self.container_vec = Vec::new();
+            }
+            (None, None) => {
+                self.selected_container = Container::default();
+                self.container_screen = ContainerScreen::None;
+            }
+        }
+        self.async_tasks_to_send.push(CommandToServer::GetAllSlaves(
+            Uuid::new_v4().to_string(),
+            self.canvas_parent().id.clone(),
+            Vec::new(),
+        ));
+        self.search_string = String::new();
+        self.delete_container_report = None;
+        self.current_ui = UIPages::LocationContainers;
+    }
+
     pub fn sort_locations(&mut self, order: &Vec<String>) {
         //Sorts the container_vec based on the order in ordered_locations_vec
         let mut result = Vec::new();
@@ -304,6 +672,15 @@ impl Visualoc {
         }
     }
 
+    //Background behind a table cell whose value matched the search
+    pub fn highlight_cell(ui: &Ui) {
+        ui.painter().rect_filled(
+            ui.max_rect(),
+            4.0,
+            Color32::from_rgba_unmultiplied(255, 200, 0, 40),
+        );
+    }
+
     pub fn themed_heading(ui: &mut egui::Ui, light_mode: bool, text: &str) -> Response {
         let resp: Response = if light_mode {
             ui.label(egui::RichText::new(text).heading().color(Color32::BLACK))
@@ -349,11 +726,19 @@ impl Visualoc {
                         self.prepare_page(UIPages::Home);
                     }
                     WaitingFunctionKind::DeleteContainerOk1 => {
+                        //Deleting the opened container goes back to its parent
+                        if self
+                            .container_path
+                            .last()
+                            .is_some_and(|x| x.id == self.selected_container.id)
+                        {
+                            self.container_path.pop();
+                        }
                         self.selected_container = Container::default();
                         let cmd_id = Uuid::new_v4().to_string();
                         self.async_tasks_to_send.push(CommandToServer::GetAllSlaves(
                             cmd_id.clone(),
-                            self.selected_location.id.clone(),
+                            self.canvas_parent().id.clone(),
                             Vec::new(),
                         ));
 
@@ -385,6 +770,8 @@ impl Visualoc {
                         self.containerless_items = self.item_vec.clone();
                         self.containerless_items_bools =
                             vec![false; self.containerless_items.len()];
+                        self.containerless_items_quantities =
+                            vec![1; self.containerless_items.len()];
                         self.async_tasks_to_send
                             .push(CommandToServer::GetMultipleItems(
                                 Uuid::new_v4().to_string(),
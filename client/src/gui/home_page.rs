@@ -1,23 +1,33 @@
-use std::cmp::Ordering;
-
+use chrono::Local;
 use egui::{Color32, ColorImage, Margin, ScrollArea, Stroke};
-use egui_extras::Column;
+use egui_extras::{Column, DatePickerButton};
 use uuid::Uuid;
 
-use crate::{CommandToServer, DataType, ModalType, Visualoc};
+use crate::{
+    ALL_FIELDS, CommandToServer, DataType, ExportFormat, ModalType, SortDirection, Visualoc,
+    database::data_helpers::{format_date_time, format_money, gallery_entries},
+};
 
 impl Visualoc {
     pub fn home_page(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
-        //Search when the string is different
-        if self.search_string != self.home_page.previous_search {
+        //Go back to the first page when the search or the searched column changes
+        if self.search_string != self.home_page.previous_search
+            || self.home_page.column_search.0 != self.home_page.previous_column_search
+        {
             self.home_page.previous_column_search = self.home_page.column_search.0.clone();
             self.home_page.previous_search = self.search_string.clone();
             self.home_page.page_number = 0;
+            //The map shows the matches of the search it was opened for
+            self.search_map = None;
+        }
+        //Fetch the page when the search, the page or the sorting changed
+        let query = self.home_page_query();
+        if self.home_page.previous_query.as_ref() != Some(&query) {
+            self.home_page.previous_query = Some(query.clone());
             self.async_tasks_to_send.push(CommandToServer::SearchItems(
                 Uuid::new_v4().to_string(),
-                self.search_string.clone(),
-                self.home_page.column_search.0.clone(),
-                Vec::new(),
+                query,
+                Ok((Vec::new(), 0, Vec::new())),
             ));
         }
         //If the vec holding the columns shown bools doesnt have the same len as the extra column, show all columns
@@ -32,10 +42,45 @@ impl Visualoc {
         );
         let (num_of_pages, first_shown_item, last_shown_item) = self.calculate_item_page_indexes();
         ui.horizontal(|ui| {
-            ui.add(egui::TextEdit::singleline(&mut self.search_string).hint_text("Search Items"));
+            if matches!(
+                self.home_page.column_search.1,
+                DataType::Date | DataType::DateTime
+            ) {
+                //Each side of the range is optional, unchecking it leaves that side open
+                let today = Local::now().date_naive();
+                let mut range_changed = false;
+                for (label, bound) in [
+                    ("From", &mut self.home_page.date_from),
+                    ("To", &mut self.home_page.date_to),
+                ] {
+                    let mut enabled = bound.is_some();
+                    if ui.checkbox(&mut enabled, label).changed() {
+                        *bound = if enabled { Some(today) } else { None };
+                        range_changed = true;
+                    }
+                    if let Some(date) = bound {
+                        if ui
+                            .add(DatePickerButton::new(date).id_salt(label))
+                            .changed()
+                        {
+                            range_changed = true;
+                        }
+                    }
+                }
+                if range_changed {
+                    self.home_page.page_number = 0;
+                }
+            } else {
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.search_string)
+                        .hint_text("Search Items, or filter: Weight > 2.5 AND Checked"),
+                );
+            }
             //Create a temp vec with the name added in front of item fields so the user can search each column
-            let mut item_fields_types_plus_name: Vec<(String, DataType)> =
-                vec![("Name".to_string(), DataType::String)];
+            let mut item_fields_types_plus_name: Vec<(String, DataType)> = vec![
+                (ALL_FIELDS.to_string(), DataType::String),
+                ("Name".to_string(), DataType::String),
+            ];
             item_fields_types_plus_name.extend(self.item_field_types.clone());
             egui::ComboBox::from_id_salt("datatypesearchfield")
                 .selected_text(&self.home_page.column_search.0)
@@ -48,18 +93,38 @@ impl Visualoc {
                         );
                     }
                 });
-            //If the column was changed search immediately
-            if self.home_page.previous_column_search != self.home_page.column_search.0 {
-                self.home_page.previous_search = self.search_string.clone();
-                self.home_page.page_number = 0;
-                self.home_page.previous_column_search = self.home_page.column_search.0.clone();
-                self.async_tasks_to_send.push(CommandToServer::SearchItems(
+            //Every match of the search, not only this page, highlighted on the location photos
+            if ui
+                .add_enabled(
+                    self.home_page.total_items > 0,
+                    egui::Button::new("🗺 Show on map"),
+                )
+                .clicked()
+            {
+                self.async_tasks_to_send.push(CommandToServer::GetItemMap(
                     Uuid::new_v4().to_string(),
-                    self.search_string.clone(),
-                    self.home_page.column_search.0.clone(),
-                    Vec::new(),
+                    self.home_page_query(),
+                    Ok(Vec::new()),
                 ));
             }
+            //Every match of the search as a file, with the same order as the list
+            ui.add_enabled_ui(self.home_page.total_items > 0, |ui| {
+                ui.menu_button("⬇ Export these results", |ui| {
+                    for (format, text) in [
+                        (ExportFormat::Csv, "CSV"),
+                        (ExportFormat::Xlsx, "Excel (XLSX)"),
+                    ] {
+                        if ui.button(text).clicked() {
+                            self.async_tasks_to_send.push(CommandToServer::ExportItems(
+                                Uuid::new_v4().to_string(),
+                                self.home_page_query(),
+                                format,
+                                Ok(()),
+                            ));
+                        }
+                    }
+                });
+            });
 
             ui.separator();
             ui.checkbox(&mut self.show_all_fields, "Show all fields");
@@ -76,6 +141,9 @@ impl Visualoc {
             } else {
                 self.item_fields_shown = vec![true; self.item_field_types.len()];
             }
+            if ui.button("⬆ Import CSV").clicked() {
+                self.open_csv_import(None);
+            }
             ui.separator();
             //Navigation Buttons
             if ui.button("⏪").clicked() && self.home_page.page_number != 0 {
@@ -110,6 +178,9 @@ impl Visualoc {
                 self.home_page.page_number = num_of_pages - 1;
             }
         });
+        if !self.home_page.search_error.is_empty() {
+            ui.colored_label(Color32::RED, &self.home_page.search_error);
+        }
         //Calculate the page indexes again in case they have changed form the search being called
         let (_, first_shown_item, last_shown_item) = self.calculate_item_page_indexes();
         egui::frame::Frame::default()
@@ -140,186 +211,65 @@ impl Visualoc {
                         .column(Column::initial(180.0).resizable(true))//Image column has default width so it's defined separately
                         .columns(
                             egui_extras::Column::remainder().resizable(true),
-                            // Name + Dynamic fields shown
-                            self.item_fields_shown.iter().filter(|x| **x).count() + 1,
+                            // Name + Quantity + Dynamic fields shown
+                            self.item_fields_shown.iter().filter(|x| **x).count() + 2,
                         )
                         .header(20.0, |mut header| {
                             header.col(|ui| {
                                 Visualoc::themed_heading(ui, self.settings.light_mode, "Image");
                             });
-                            header.col(|ui| {
-                                ui.horizontal(|ui| {
-                                    let name_response = Visualoc::themed_heading(
-                                        ui,
-                                        self.settings.light_mode,
-                                        "Name",
-                                    );
-                                    if self.home_page.search_results_sorted && name_response.hovered() {
-                                        Visualoc::themed_heading(ui, self.settings.light_mode, "⏶");
-                                    } else if !self.home_page.search_results_sorted && name_response.hovered()
-                                    {
-                                        Visualoc::themed_heading(ui, self.settings.light_mode, "⏷");
-                                    }
-                                    if name_response.clicked() {
-                                        //Sort the results by name, reverse if already sorted
-                                        if self.home_page.search_results_sorted {
-                                            self.item_vec.sort_unstable_by_key(|x| x.name.clone());
-                                            self.item_vec.reverse();
-                                            self.home_page.search_results_sorted = false;
-                                        } else {
-                                            self.item_vec.sort_unstable_by_key(|x| x.name.clone());
-                                            self.home_page.search_results_sorted = true;
+                            let mut sort_clicked = None;
+                            //Name + Dynamic fields shown, clicking a title sorts by it on the server
+                            let mut shown_columns = vec![("Name".to_string(), DataType::String)];
+                            for (index, column) in self.item_field_types.iter().enumerate() {
+                                if self.item_fields_shown[index] {
+                                    shown_columns.push(column.clone());
+                                }
+                            }
+                            for (index, (column_name, column_type)) in shown_columns.into_iter().enumerate() {
+                                header.col(|ui| {
+                                    ui.horizontal(|ui| {
+                                        let name_response = Visualoc::themed_heading(
+                                            ui,
+                                            self.settings.light_mode,
+                                            &column_name,
+                                        );
+                                        if self.home_page.sort_column.as_ref() == Some(&column_name) {
+                                            let arrow = match self.home_page.sort_direction {
+                                                SortDirection::Ascending => "⏶",
+                                                SortDirection::Descending => "⏷",
+                                            };
+                                            Visualoc::themed_heading(ui, self.settings.light_mode, arrow);
                                         }
-                                    }
+                                        //Galleries have no meaningful order
+                                        if name_response.clicked() && column_type != DataType::Gallery {
+                                            sort_clicked = Some(column_name.clone());
+                                        }
+                                    });
                                 });
-                            });
-                            for (index, (column_name, _)) in
-                                self.item_field_types.iter().enumerate()
-                            {
-                                if self.item_fields_shown[index] {
+                                //Units over every container, right after the name
+                                if index == 0 {
                                     header.col(|ui| {
-                                        ui.horizontal(|ui| {
-                                            //If the column title is clicked sort the data
-                                            let name_response = Visualoc::themed_heading(
-                                                ui,
-                                                self.settings.light_mode,
-                                                column_name,
-                                            );
-                                            if self.home_page.search_results_sorted && name_response.hovered()
-                                            {
-                                                Visualoc::themed_heading(
-                                                    ui,
-                                                    self.settings.light_mode,
-                                                    "⏶",
-                                                );
-                                            } else if !self.home_page.search_results_sorted
-                                                && name_response.hovered()
-                                            {
-                                                Visualoc::themed_heading(
-                                                    ui,
-                                                    self.settings.light_mode,
-                                                    "⏷",
-                                                );
-                                            }
-                                            if name_response.clicked() {
-                                                //Get the proper vector and the position from the column name
-                                                let mut column_data_type: DataType =
-                                                    DataType::String;
-                                                let mut column_index = 0;
-                                                let mut string_index = 0;
-                                                let mut f64_index = 0;
-                                                let mut i64_index = 0;
-                                                for (current_column_name, column_type) in
-                                                    self.item_field_types.clone()
-                                                {
-                                                    match column_type {
-                                                        DataType::String => {
-                                                            if *column_name == current_column_name {
-                                                                column_data_type = DataType::String;
-                                                                column_index = string_index;
-                                                                break;
-                                                            }
-                                                            string_index += 1;
-                                                        }
-                                                        DataType::Integer => {
-                                                            if *column_name == current_column_name {
-                                                                column_data_type =
-                                                                    DataType::Integer;
-                                                                column_index = i64_index;
-                                                                break;
-                                                            }
-                                                            i64_index += 1;
-                                                        }
-                                                        DataType::Float => {
-                                                            if *column_name == current_column_name {
-                                                                column_data_type = DataType::Float;
-                                                                column_index = f64_index;
-                                                                break;
-                                                            }
-                                                            f64_index += 1;
-                                                        }
-                                                        DataType::Bool=>{
-                                                            if *column_name == current_column_name {
-                                                                column_data_type = DataType::Bool;
-                                                                column_index = i64_index;
-                                                                break;
-                                                            }
-                                                            i64_index += 1;
-                                                        }
-                                                        DataType::Text=>{
-                                                            if *column_name == current_column_name {
-                                                                column_data_type = DataType::Text;
-                                                                column_index = string_index;
-                                                                break;
-                                                            }
-                                                            string_index += 1;
-                                                        }
-                                                        DataType::List(val)=>{
-                                                            if *column_name == current_column_name {
-                                                                column_data_type = DataType::List(val);
-                                                                column_index = string_index;
-                                                                break;
-                                                            }
-                                                            string_index += 1;
-                                                        }
-                                                        DataType::Gallery=>{
-                                                            if *column_name == current_column_name {
-                                                                column_data_type = DataType::Gallery;
-                                                                column_index = string_index;
-                                                                break;
-                                                            }
-                                                            string_index += 1;
-                                                        }
-                                                        DataType::Percentage=>{
-                                                            if *column_name == current_column_name {
-                                                                column_data_type = DataType::Percentage;
-                                                                column_index = f64_index;
-                                                                break;
-                                                            }
-                                                            f64_index += 1;
-                                                        }
-                                                    }
-                                                }
-                                                //Sort the results, reverse if already sorted
-                                                match column_data_type {
-                                                    DataType::String | DataType::List(_) | DataType::Text=> {
-                                                        self.item_vec.sort_unstable_by_key(|x| {
-                                                            x.string_vars[column_index].clone()
-                                                        })
-                                                    }
-                                                    DataType::Integer | DataType::Bool => {
-                                                        self.item_vec.sort_unstable_by_key(|x| {
-                                                            x.int_vars[column_index]
-                                                        })
-                                                    }
-                                                    DataType::Float | DataType::Percentage => {
-                                                        self.item_vec.sort_by(|a, b| {
-                                                            match a.float_vars[column_index]
-                                                                .partial_cmp(
-                                                                    &b.float_vars[column_index],
-                                                                ) {
-                                                                Some(ordering) => ordering,
-                                                                None => Ordering::Equal,
-                                                            }
-                                                        });
-                                                    }
-                                                    DataType::Gallery=>()
-                                                }
-                                                if self.home_page.search_results_sorted {
-                                                    self.item_vec.reverse();
-                                                    self.home_page.search_results_sorted = false;
-                                                } else {
-                                                    self.home_page.search_results_sorted = true;
-                                                }
-                                            }
-                                        });
+                                        Visualoc::themed_heading(ui, self.settings.light_mode, "Quantity");
                                     });
                                 }
                             }
+                            if let Some(column_name) = sort_clicked {
+                                self.toggle_sort_column(&column_name);
+                            }
                         })
                         .body(|mut body| {
                             //Show the rows
-                            for item in &self.item_vec[first_shown_item..last_shown_item] {
+                            for (row_index, item) in
+                                self.item_vec[first_shown_item..last_shown_item].iter().enumerate()
+                            {
+                                //Fields an all fields search matched get highlighted
+                                let matched_fields = self
+                                    .home_page
+                                    .matched_fields
+                                    .get(first_shown_item + row_index)
+                                    .cloned()
+                                    .unwrap_or_default();
                                 let mut line_height = 30.0;
                                 if item.image_type != String::default() {
                                     line_height = 70.0;
@@ -362,6 +312,9 @@ impl Visualoc {
                                         });
                                     });
                                     row.col(|ui| {
+                                        if matched_fields.iter().any(|x| x == "Name") {
+                                            Visualoc::highlight_cell(ui);
+                                        }
                                         ui.add_space(ui.available_size().y / 3.0);
                                         ui.horizontal(|ui| {
                                             ui.add_space(ui.available_size().x / 5.0);
@@ -377,16 +330,41 @@ impl Visualoc {
                                             }
                                         });
                                     });
+                                    row.col(|ui| {
+                                        ui.add_space(ui.available_size().y / 3.0);
+                                        ui.horizontal(|ui| {
+                                            ui.add_space(ui.available_size().x / 5.0);
+                                            let label_clicked = Visualoc::interactive_label(
+                                                ui,
+                                                ctx,
+                                                self.settings.light_mode,
+                                                false,
+                                                &item.quantity.to_string(),
+                                            );
+                                            if label_clicked {
+                                                clicked = true;
+                                            }
+                                        });
+                                    });
                                     let mut string_index = 0;
                                     let mut f64_index = 0;
                                     let mut i64_index = 0;
-                                    for (index, (_, column_type)) in
+                                    for (index, (column_name, column_type)) in
                                         self.item_field_types.iter().enumerate()
                                     {
                                         if self.item_fields_shown[index] {
                                             row.col(|ui| {
+                                                if matched_fields.contains(column_name) {
+                                                    Visualoc::highlight_cell(ui);
+                                                }
                                                 ui.centered_and_justified(|ui| match column_type {
-                                                    DataType::String |DataType::List(_) | DataType::Text => {
+                                                    DataType::String |DataType::List(_) | DataType::Text | DataType::Date | DataType::DateTime | DataType::Money { .. } => {
+                                                        //Date-times come in UTC, they're shown in local time
+                                                        let text = match column_type {
+                                                            DataType::DateTime => format_date_time(&item.string_vars[string_index]),
+                                                            DataType::Money { currency } => format_money(&item.string_vars[string_index], currency),
+                                                            _ => item.string_vars[string_index].clone(),
+                                                        };
                                                         ui.add_space(ui.available_size().y / 3.0);
                                                         ui.horizontal(|ui| {
                                                             ui.add_space(
@@ -398,7 +376,7 @@ impl Visualoc {
                                                                     ctx,
                                                                     self.settings.light_mode,
                                                                     false,
-                                                                    &item.string_vars[string_index]
+                                                                    &text
                                                                 );
 
                                                             if label_clicked {
@@ -419,7 +397,14 @@ impl Visualoc {
                                                                     ctx,
                                                                     self.settings.light_mode,
                                                                     false,
-                                                                    &"todo".to_string()
+                                                                    &format!(
+                                                                        "🖼 {}",
+                                                                        gallery_entries(
+                                                                            &item.string_vars
+                                                                                [string_index]
+                                                                        )
+                                                                        .len()
+                                                                    )
                                                                 );
 
                                                             if label_clicked {
@@ -522,10 +507,10 @@ impl Visualoc {
                                     }
                                     if clicked {
                                         self.async_tasks_to_send.push(
-                                            CommandToServer::GetItemLocationContainer(
+                                            CommandToServer::GetItemAncestors(
                                                 Uuid::new_v4().to_string(),
                                                 item.id.clone(),
-                                                None,
+                                                Vec::new(),
                                             ),
                                         );
                                         self.selected_item = item.clone();
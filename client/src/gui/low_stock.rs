@@ -0,0 +1,83 @@
+use egui::ScrollArea;
+use uuid::Uuid;
+
+use crate::{CommandToServer, Visualoc};
+
+impl Visualoc {
+    //Items at or below their minimum grouped by location, clicking one opens it
+    pub fn low_stock_screen(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            Visualoc::themed_heading(ui, self.settings.light_mode, "Low stock");
+            if ui
+                .add_enabled(
+                    !self.low_stock.is_empty(),
+                    egui::Button::new("⬇ Shopping list (CSV)"),
+                )
+                .clicked()
+            {
+                self.async_tasks_to_send
+                    .push(CommandToServer::GetLowStockCsv(Uuid::new_v4().to_string()));
+            }
+            if ui.button("⟳ Refresh").clicked() {
+                self.refresh_low_stock();
+            }
+        });
+        ui.separator();
+        if self.low_stock.is_empty() {
+            ui.weak("Nothing is at or below its minimum.");
+            return;
+        }
+        let mut opened_item = None;
+        ScrollArea::vertical().auto_shrink(false).show(ui, |ui| {
+            for location in &self.low_stock {
+                let location_name: &str = if location.location_id.is_empty() {
+                    "Not in a location"
+                } else {
+                    &location.location_name
+                };
+                Visualoc::themed_heading(ui, self.settings.light_mode, location_name);
+                egui_extras::TableBuilder::new(ui)
+                    .id_salt(&location.location_id)
+                    .striped(true)
+                    .columns(egui_extras::Column::remainder(), 4)
+                    .header(20.0, |mut header| {
+                        for title in ["Item", "Field", "Current", "Minimum"] {
+                            header.col(|ui| {
+                                ui.label(title);
+                            });
+                        }
+                    })
+                    .body(|mut body| {
+                        for item in &location.items {
+                            body.row(30.0, |mut row| {
+                                row.col(|ui| {
+                                    if ui.link(&item.item_name).clicked() {
+                                        opened_item = Some(item.item_id.clone());
+                                    }
+                                });
+                                row.col(|ui| {
+                                    ui.label(item.field_name.as_deref().unwrap_or("Quantity"));
+                                });
+                                row.col(|ui| {
+                                    ui.colored_label(egui::Color32::RED, item.value.to_string());
+                                });
+                                row.col(|ui| {
+                                    ui.label(item.minimum.to_string());
+                                });
+                            });
+                        }
+                    });
+                ui.separator();
+                ui.add_space(20.0);
+            }
+        });
+        //Same as scanning the item's label
+        if let Some(item_id) = opened_item {
+            self.async_tasks_to_send.push(CommandToServer::ResolveCode(
+                Uuid::new_v4().to_string(),
+                item_id,
+                Ok(None),
+            ));
+        }
+    }
+}
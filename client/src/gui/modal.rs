@@ -1,8 +1,8 @@
 use crate::{
-    BackupState, CommandToServer, ContainerScreen, ModalType, UIPages, Visualoc, WaitingFunction,
-    WaitingFunctionKind, database::data_helpers::ImageSize, toggle_light_mode,
+    BackupState, CommandToServer, ContainerScreen, ItemPolicy, ModalType, UIPages, Visualoc,
+    WaitingFunction, WaitingFunctionKind, database::data_helpers::{ImageSize, split_gallery_entry}, toggle_light_mode,
 };
-use egui::{Color32, ColorImage, Label, Layout, Modal, Slider};
+use egui::{Color32, ColorImage, DragValue, Label, Layout, Modal, ScrollArea, Slider};
 use uuid::Uuid;
 
 impl Visualoc {
@@ -13,12 +13,21 @@ impl Visualoc {
                 ModalType::DeleteLocation => {
                     ui.label("Confirm Deletion");
                     ui.add_space(10.0);
-                    ui.add(Label::new("Are you sure you want to permanently delete this location?").wrap());
+                    ui.add(Label::new("Are you sure you want to permanently delete this location? All of its containers will be deleted too.").wrap());
+                    ui.add_space(10.0);
+                    ui.label("Items inside the location");
+                    ui.radio_value(&mut self.modal_vars.delete_item_policy, ItemPolicy::LeaveUnplaced, "Keep them, not in a container");
+                    ui.radio_value(&mut self.modal_vars.delete_item_policy, ItemPolicy::DeleteItems, "Delete them");
                 }
                 ModalType::DeleteContainer => {
                     ui.label("Confirm Deletion");
                     ui.add_space(10.0);
-                    ui.add(Label::new("Are you sure you want to permanently delete this container? This will not delete the items inside this container.").wrap());
+                    ui.add(Label::new("Are you sure you want to permanently delete this container? All the containers inside it will be deleted too.").wrap());
+                    ui.add_space(10.0);
+                    ui.label("Items inside the container");
+                    ui.radio_value(&mut self.modal_vars.delete_item_policy, ItemPolicy::MoveToParent, "Move them to the parent");
+                    ui.radio_value(&mut self.modal_vars.delete_item_policy, ItemPolicy::LeaveUnplaced, "Keep them, not in a container");
+                    ui.radio_value(&mut self.modal_vars.delete_item_policy, ItemPolicy::DeleteItems, "Delete them");
                 }
                 ModalType::DeleteItem => {
                     ui.label("Confirm Deletion");
@@ -39,9 +48,15 @@ impl Visualoc {
                     if self.containerless_items.is_empty(){
                         ui.add(Label::new("There are no existing items not in a container.").wrap());
                     }else{
-                        ui.add(Label::new("Select the items to add to this container.").wrap());
+                        ui.add(Label::new("Select the items and how many of each to add to this container.").wrap());
                         for (index,item) in self.containerless_items.iter().enumerate(){
-                            ui.checkbox(&mut self.containerless_items_bools[index], &item.name);
+                            ui.horizontal(|ui|{
+                                ui.checkbox(&mut self.containerless_items_bools[index], &item.name);
+                                ui.add_enabled(
+                                    self.containerless_items_bools[index],
+                                    DragValue::new(&mut self.containerless_items_quantities[index]).range(1..=i32::MAX).prefix("×"),
+                                );
+                            });
                         }
                     }
                 }
@@ -83,47 +98,117 @@ impl Visualoc {
                         ));
                     }
                 }
+                ModalType::GalleryImage=>{
+                    let entries=self.modal_vars.gallery_entries.clone();
+                    if let Some(entry)=entries.get(self.modal_vars.gallery_index){
+                        match self.loaded_images.get(entry){
+                            Some(Some((texture, ImageSize::Large)))=>{
+                                ui.add(
+                                    egui::Image::new(texture)
+                                        .fit_to_original_size(2.0)
+                                        .max_height(500.0),
+                                );
+                            }
+                            //Nothing loaded yet or only the thumbnail, ask for the full size image
+                            Some(Some(_))|None=>{
+                                let (image_id, image_type)=split_gallery_entry(entry);
+                                self.loaded_images.insert(entry.to_owned(), None);
+                                self.async_tasks_to_send.push(CommandToServer::GetImageFromServer(Uuid::new_v4().to_string(),
+                                    image_id,
+                                    image_type,
+                                    ImageSize::Large,
+                                    ColorImage::default()
+                                ));
+                            }
+                            Some(None)=>{
+                                ui.spinner();
+                            }
+                        }
+                        ui.horizontal(|ui|{
+                            if ui.add_enabled(self.modal_vars.gallery_index>0, egui::Button::new("◀")).clicked(){
+                                self.modal_vars.gallery_index-=1;
+                            }
+                            ui.label(format!("{} / {}", self.modal_vars.gallery_index+1, entries.len()));
+                            if ui.add_enabled(self.modal_vars.gallery_index+1<entries.len(), egui::Button::new("▶")).clicked(){
+                                self.modal_vars.gallery_index+=1;
+                            }
+                        });
+                    }
+                }
                 ModalType::Backup=>{
-                    match self.backup.state{
+                    match self.backup.state.clone(){
                         BackupState::Start=>{
                             Visualoc::themed_heading(ui, self.settings.light_mode, "Download Backup");
-                            ui.add(Label::new("This will download a copy of your data.").wrap());
+                            ui.add(Label::new("This will download a copy of your data as a single backup.zip file.").wrap());
                             if ui.button("Download Backup").clicked(){
                                 self.async_tasks_to_send.push(CommandToServer::GetBackup(Uuid::new_v4().to_string()));
                             }
                             ui.add_space(20.0);
                             ui.separator();
                             Visualoc::themed_heading(ui, self.settings.light_mode, "Restore from Backup");
-                            ui.add(Label::new("You can upload a backup to restore your data to that previous state").wrap());
-                            if ui.button("Upload backup").clicked(){
-                                self.backup.state=BackupState::Upload;
-                            }
-                        }
-                        BackupState::Upload=>{
-                            ui.add(Label::new("Uploading a backup will overwrite the current data. It is recommended to download a backup of the current state before reverting to an older one.").wrap());
-                            if ui.button("Download Backup").clicked(){
-                                self.async_tasks_to_send.push(CommandToServer::GetBackup(Uuid::new_v4().to_string()));
+                            ui.add(Label::new("Restoring a backup will overwrite the current data. It is recommended to download a backup of the current state before reverting to an older one.").wrap());
+                            ui.horizontal(|ui|{
+                                if ui.button("📂 Choose Backup File").clicked(){
+                                    self.async_tasks_to_send.push(CommandToServer::PickBackupFile(Uuid::new_v4().to_string(), None));
+                                }
+                                match &self.backup.backup_file{
+                                    Some(backup_file)=>{ui.label(backup_file.file_name());}
+                                    None=>{ui.weak("No file chosen");}
+                                }
+                            });
+                            if ui.add_enabled(self.backup.backup_file.is_some(), egui::Button::new("⟲ Restore from backup file")).clicked(){
+                                self.backup.state=BackupState::Restoring;
+                                self.async_tasks_to_send.push(CommandToServer::RestoreBackup(Uuid::new_v4().to_string(), self.backup.backup_file.clone(), Ok(Default::default())));
                             }
-                            ui.separator();
                             ui.add_space(20.0);
-                            ui.add(Label::new("Select the dump file you want to restore from:").wrap());
-                            if self.backup.dump_filehandle.is_none(){
-                                ui.colored_label(Color32::RED, "Please select a valid sql dump file");
-                            }
-                            if ui.button("Upload Dump File").clicked(){
-                                self.async_tasks_to_send.push(CommandToServer::PickBackupDumpFile(Uuid::new_v4().to_string(), None));
-                            }
-                            ui.add(Label::new("Select the images folder you want to restore from:").wrap());
-                            if self.backup.images_filehandle.is_none(){
-                                ui.colored_label(Color32::RED, "Please select a valid images folder");
+                            ui.separator();
+                            Visualoc::themed_heading(ui, self.settings.light_mode, "Merge into Current Data");
+                            self.merge_backup_section(ui);
+                        }
+                        BackupState::Restoring=>{
+                            Visualoc::themed_heading(ui, self.settings.light_mode, "Restoring Backup");
+                            ui.horizontal(|ui|{
+                                ui.spinner();
+                                ui.label(format!("Uploading and restoring {}…", self.backup.backup_file.as_ref().map(|x| x.file_name()).unwrap_or_default()));
+                            });
+                            ui.weak("Backups with many images can take a few minutes, keep this page open.");
+                        }
+                        BackupState::Done(Ok(report))=>{
+                            Visualoc::themed_heading(ui, self.settings.light_mode, "Backup Restored");
+                            ui.add(Label::new(format!("Restored {} fields, {} locations and containers, {} items and {} images.", report.fields, report.containers, report.items, report.images)).wrap());
+                            if report.from_dump{
+                                ui.add(Label::new("The backup was made by an older version of Visualoc and was converted while restoring.").wrap());
                             }
-                            if ui.button("Upload Images Folder").clicked(){
-                                self.async_tasks_to_send.push(CommandToServer::PickBackupImageFolder(Uuid::new_v4().to_string(), None));
+                            if !report.failed_images.is_empty(){
+                                ui.colored_label(Color32::RED, format!("{} images couldn't be restored:", report.failed_images.len()));
+                                ScrollArea::vertical().id_salt("failed_images").max_height(120.0).show(ui, |ui|{
+                                    for file_name in &report.failed_images{
+                                        ui.label(file_name);
+                                    }
+                                });
                             }
-                            ui.add(Label::new("Once you have selected both the dump file and the images folder click ok to start uploading the files").wrap());
                         }
-                        BackupState::Waiting=>{
-                            ui.label(&self.backup.resulting_string);
+                        BackupState::Done(Err(message))=>{
+                            Visualoc::themed_heading(ui, self.settings.light_mode, "Restore Failed");
+                            ui.colored_label(Color32::RED, message);
+                            ui.add(Label::new("Your data wasn't changed.").wrap());
+                        }
+                        BackupState::Merging=>{
+                            Visualoc::themed_heading(ui, self.settings.light_mode, "Merging Backup");
+                            ui.horizontal(|ui|{
+                                ui.spinner();
+                                ui.label(format!("Uploading and merging {}…", self.backup.backup_file.as_ref().map(|x| x.file_name()).unwrap_or_default()));
+                            });
+                            ui.weak("Backups with many images can take a few minutes, keep this page open.");
+                        }
+                        BackupState::Merged(Ok(report))=>{
+                            Visualoc::themed_heading(ui, self.settings.light_mode, "Backup Merged");
+                            Visualoc::merge_report(ui, &report);
+                        }
+                        BackupState::Merged(Err(message))=>{
+                            Visualoc::themed_heading(ui, self.settings.light_mode, "Merge Failed");
+                            ui.colored_label(Color32::RED, message);
+                            ui.add(Label::new("Your data wasn't changed.").wrap());
                         }
                     }
                     ui.add_space(35.0);
@@ -212,6 +297,74 @@ impl Visualoc {
                     ui.add_space(15.0);
                     ui.separator();
                 }
+                ModalType::ScanCode=>{
+                    Visualoc::themed_heading(ui, self.settings.light_mode, "Scan / Enter Code");
+                    ui.add(Label::new("Type the code or ID printed on a label, or pick a photo of its QR code.").wrap());
+                    ui.add_space(10.0);
+                    let response=ui.add(egui::TextEdit::singleline(&mut self.modal_vars.scan_code).hint_text("Code or ID"));
+                    if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) && !self.modal_vars.scan_code.trim().is_empty(){
+                        self.modal_vars.scan_message=String::new();
+                        self.async_tasks_to_send.push(CommandToServer::ResolveCode(Uuid::new_v4().to_string(), self.modal_vars.scan_code.clone(), Ok(None)));
+                    }
+                    if ui.button("📷 Scan Photo").clicked(){
+                        self.modal_vars.scan_message=String::new();
+                        self.async_tasks_to_send.push(CommandToServer::ScanCodeImage(Uuid::new_v4().to_string(), Ok(None)));
+                    }
+                    if !self.modal_vars.scan_message.is_empty(){
+                        ui.colored_label(Color32::RED, &self.modal_vars.scan_message);
+                    }
+                    ui.add_space(15.0);
+                }
+                ModalType::ImportCsv=>self.csv_import_modal(ui),
+                ModalType::MoveItems=>{
+                    Visualoc::themed_heading(ui, self.settings.light_mode, "Move to…");
+                    ui.add(Label::new(format!("Open the location or container to move {} to, its quantity goes with it.", self.selected_item.name)).wrap());
+                    ui.add_space(10.0);
+                    //Clicking a container of the path goes back to it
+                    let mut browse_depth=None;
+                    ui.horizontal_wrapped(|ui|{
+                        if ui.link("📍 Locations").clicked(){
+                            browse_depth=Some(0);
+                        }
+                        for (depth,container) in self.modal_vars.move_path.iter().enumerate(){
+                            ui.label("›");
+                            if ui.link(&container.name).clicked(){
+                                browse_depth=Some(depth+1);
+                            }
+                        }
+                    });
+                    ui.separator();
+                    let mut opened=None;
+                    ScrollArea::vertical().max_height(300.0).show(ui,|ui|{
+                        if self.async_tasks_sent_ids.contains(&self.modal_vars.move_browse_id){
+                            ui.spinner();
+                        }else if self.modal_vars.move_children.is_empty(){
+                            ui.weak("There is nothing inside.");
+                        }
+                        for container in &self.modal_vars.move_children{
+                            if ui.button(format!("📂 {}",container.name)).clicked(){
+                                opened=Some(container.clone());
+                            }
+                        }
+                    });
+                    if let Some(depth)=browse_depth{
+                        self.modal_vars.move_path.truncate(depth);
+                        self.browse_move_picker();
+                    }
+                    if let Some(container)=opened{
+                        self.modal_vars.move_path.push(container);
+                        self.browse_move_picker();
+                    }
+                    ui.add_space(10.0);
+                    match self.modal_vars.move_path.last(){
+                        Some(target)=>ui.label(format!("Moving to {}", target.name)),
+                        None=>ui.weak("Open a location or a container to pick it"),
+                    };
+                    if !self.modal_vars.move_message.is_empty(){
+                        ui.colored_label(Color32::RED, &self.modal_vars.move_message);
+                    }
+                    ui.add_space(15.0);
+                }
             }
             ui.with_layout(Layout::left_to_right(egui::Align::Center),|ui|{
                 ui.add_space(ui.available_width()/2.0-85.0);
@@ -220,14 +373,14 @@ impl Visualoc {
                         ModalType::None=>(),
                         ModalType::DeleteContainer=>{
                             let cmd_id = Uuid::new_v4().to_string();
-                            self.async_tasks_to_send.push(CommandToServer::DeleteContainer(cmd_id.clone(),self.selected_container.clone()));
+                            self.async_tasks_to_send.push(CommandToServer::DeleteContainer(cmd_id.clone(),self.selected_container.clone(), self.modal_vars.delete_item_policy, None));
                             self.functions_waiting_data.push(WaitingFunction {
                                 id: cmd_id,
                                 kind: WaitingFunctionKind::DeleteContainerOk1,
                             });
                         }
                         ModalType::DeleteLocation=>{
-                            self.async_tasks_to_send.push(CommandToServer::DeleteContainer(Uuid::new_v4().to_string(),self.selected_location.clone()));
+                            self.async_tasks_to_send.push(CommandToServer::DeleteContainer(Uuid::new_v4().to_string(),self.selected_location.clone(), self.modal_vars.delete_item_policy, None));
                             self.ordered_locations_vec.retain(|x| *x!=self.selected_location.id);
                             self.prepare_page(UIPages::LocationGrid);
                         }
@@ -270,6 +423,9 @@ impl Visualoc {
                             self.selected_container
                                 .contained_items
                                 .remove(&self.selected_item.id);
+                            self.selected_container
+                                .item_quantities
+                                .remove(&self.selected_item.id);
                             self.async_tasks_to_send
                                 .push(CommandToServer::GetMultipleItems(Uuid::new_v4().to_string(),
                                     self.selected_container.contained_items.clone(),
@@ -279,6 +435,7 @@ impl Visualoc {
                             for cont in &mut self.container_vec {
                                 if cont.id == self.selected_container.id {
                                     cont.contained_items.remove(&self.selected_item.id.clone());
+                                    cont.item_quantities.remove(&self.selected_item.id);
                                 }
                             }
                             self.search_string = "".into();
@@ -288,18 +445,29 @@ impl Visualoc {
                         ModalType::SelectContainerlessItem=>{
                             for (index,val) in self.containerless_items_bools.iter().enumerate(){
                                 if *val{
+                                    //Adds N units, on top of the ones already in the container
+                                    let item_id=self.containerless_items[index].id.clone();
+                                    let added=self.containerless_items_quantities[index];
+                                    let mut quantity=added;
+                                    if self.selected_container.contained_items.contains(&item_id){
+                                        quantity+=self.selected_container.item_quantities.get(&item_id).copied().unwrap_or(1);
+                                    }
                                     //Update the containers contained_items in memory
-                                    self.selected_container.contained_items.insert(self.containerless_items[index].id.clone());
+                                    self.selected_container.contained_items.insert(item_id.clone());
+                                    self.selected_container.item_quantities.insert(item_id.clone(), quantity);
                                     //Update the containers contained_items on the database
                                     self.async_tasks_to_send.push(CommandToServer::UpdateContainer(Uuid::new_v4().to_string(),self.selected_container.clone()));
                                     //Update the Container in the ContainerVec
                                     for cont in &mut self.container_vec {
                                         if cont.id == self.selected_container.id {
-                                            cont.contained_items.insert(self.containerless_items[index].id.clone());
+                                            cont.contained_items.insert(item_id.clone());
+                                            cont.item_quantities.insert(item_id.clone(), quantity);
                                         }
                                     }
                                     //Update the item vec
-                                    self.item_vec.push(self.containerless_items[index].clone());
+                                    let mut item=self.containerless_items[index].clone();
+                                    item.quantity+=added as i64;
+                                    self.item_vec.push(item);
                                     //Update the search vec
                                     self.item_page_search_vec=self.item_vec.clone();
                                 }
@@ -307,20 +475,12 @@ impl Visualoc {
                         }
                         ModalType::SelectFieldsShown=>(),
                         ModalType::ItemImage=>(),
+                        ModalType::GalleryImage=>(),
                         ModalType::Backup=>{
-                            match self.backup.state{
-                                BackupState::Start=>{
-
-                                    self.modal_vars.modal_type=ModalType::None;
-                                    self.modal_vars.modal_id=Uuid::new_v4().to_string();
-                                }
-                                BackupState::Upload=>{
-                                    if self.backup.dump_filehandle.is_some() && self.backup.images_filehandle.is_some(){
-                                        self.backup.state=BackupState::Waiting;
-                                        self.async_tasks_to_send.push(CommandToServer::UploadBackup(Uuid::new_v4().to_string(), self.backup.dump_filehandle.clone(), self.backup.images_filehandle.clone()));
-                                    }
-                                }
-                                BackupState::Waiting=>()
+                            //The modal stays open while the restore or the merge runs
+                            if !matches!(self.backup.state, BackupState::Restoring | BackupState::Merging){
+                                self.modal_vars.modal_type=ModalType::None;
+                                self.modal_vars.modal_id=Uuid::new_v4().to_string();
                             }
                         }
                         ModalType::Settings=>(),
@@ -328,9 +488,34 @@ impl Visualoc {
                             self.async_tasks_to_send.push(CommandToServer::UpdateContainer(Uuid::new_v4().to_string(), self.selected_location.clone()));
                             self.prepare_page(UIPages::LocationGrid);
                         }
+                        ModalType::ImportCsv=>{
+                            if self.csv_import.imported{
+                                self.modal_vars.modal_type=ModalType::None;
+                                self.modal_vars.modal_id=Uuid::new_v4().to_string();
+                            }else if self.csv_import.csv.is_empty(){
+                                self.csv_import.error="Choose a CSV file first".to_string();
+                            }else if !self.async_tasks_sent_ids.contains(&self.csv_import.request_id){
+                                self.send_csv_import(false);
+                            }
+                        }
+                        ModalType::MoveItems=>{
+                            match self.modal_vars.move_path.last().map(|x| x.id.clone()){
+                                Some(target_id) if target_id==self.selected_container.id=>{
+                                    self.modal_vars.move_message="The item is already in this container".to_string();
+                                }
+                                Some(target_id)=>self.move_items_to(vec![self.selected_item.id.clone()], self.selected_container.id.clone(), target_id),
+                                None=>self.modal_vars.move_message="Pick a location or a container first".to_string(),
+                            }
+                        }
+                        ModalType::ScanCode=>{
+                            if !self.modal_vars.scan_code.trim().is_empty(){
+                                self.modal_vars.scan_message=String::new();
+                                self.async_tasks_to_send.push(CommandToServer::ResolveCode(Uuid::new_v4().to_string(), self.modal_vars.scan_code.clone(), Ok(None)));
+                            }
+                        }
                     }
-                    //Backup handles it's own state
-                    if self.modal_vars.modal_type!=ModalType::Backup{
+                    //Backup and the CSV import handle their own state, the scan and move modals close once the server is done
+                    if self.modal_vars.modal_type!=ModalType::Backup && self.modal_vars.modal_type!=ModalType::ScanCode && self.modal_vars.modal_type!=ModalType::MoveItems && self.modal_vars.modal_type!=ModalType::ImportCsv{
                         self.modal_vars.modal_type=ModalType::None;
                         self.modal_vars.modal_id=Uuid::new_v4().to_string();
                     }
@@ -342,7 +527,7 @@ impl Visualoc {
                         self.settings=self.temp_settings.clone();
                     }
                     if self.modal_vars.modal_type==ModalType::AddLocation{
-                        self.async_tasks_to_send.push(CommandToServer::DeleteContainer(Uuid::new_v4().to_string(), self.selected_location.clone()));
+                        self.async_tasks_to_send.push(CommandToServer::DeleteContainer(Uuid::new_v4().to_string(), self.selected_location.clone(), ItemPolicy::LeaveUnplaced, None));
                     }
                     self.modal_vars.modal_type=ModalType::None;
                     self.modal_vars.modal_id=Uuid::new_v4().to_string();
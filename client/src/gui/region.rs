@@ -0,0 +1,189 @@
+//Shapes of the container regions drawn on a photo
+//A region is a rectangle, a rectangle rotated around its center or a polygon
+//Everything here works on screen positions, the canvas converts the stored fractions of the photo first
+
+use egui::{Color32, Mesh, Pos2, Shape, Stroke};
+
+use crate::Container;
+
+//Turns the point clockwise by rotation degrees around center
+pub fn rotate_point(point: Pos2, center: Pos2, rotation: f32) -> Pos2 {
+    let (sin, cos) = rotation.to_radians().sin_cos();
+    let offset = point - center;
+    let x = offset.x * cos - offset.y * sin;
+    let y = offset.x * sin + offset.y * cos;
+    center + egui::vec2(x, y)
+}
+
+//The 4 corners of the rectangle turned clockwise by rotation degrees around its center
+pub fn rotated_rect(first: Pos2, second: Pos2, rotation: f32) -> Vec<Pos2> {
+    let center = first + (second - first) / 2.0;
+    [
+        Pos2::new(first.x, first.y),
+        Pos2::new(second.x, first.y),
+        Pos2::new(second.x, second.y),
+        Pos2::new(first.x, second.y),
+    ]
+    .iter()
+    .map(|point| rotate_point(*point, center, rotation))
+    .collect()
+}
+
+//Outline of the container, to_screen turns a fraction of the photo into a screen position
+pub fn container_outline(container: &Container, to_screen: impl Fn(f32, f32) -> Pos2) -> Vec<Pos2> {
+    if container.polygon.len() >= 3 {
+        container
+            .polygon
+            .iter()
+            .map(|point| to_screen(point[0], point[1]))
+            .collect()
+    } else {
+        rotated_rect(
+            to_screen(container.corners[0], container.corners[1]),
+            to_screen(container.corners[2], container.corners[3]),
+            container.rotation,
+        )
+    }
+}
+
+//Even-odd ray casting, works for concave polygons too
+pub fn contains(points: &[Pos2], point: Pos2) -> bool {
+    let mut inside = false;
+    let mut previous = match points.last() {
+        Some(last) => *last,
+        None => return false,
+    };
+    for current in points {
+        if (current.y > point.y) != (previous.y > point.y)
+            && point.x
+                < (previous.x - current.x) * (point.y - current.y) / (previous.y - current.y)
+                    + current.x
+        {
+            inside = !inside;
+        }
+        previous = *current;
+    }
+    inside
+}
+
+fn cross(a: Pos2, b: Pos2, c: Pos2) -> f32 {
+    (b.x - a.x) * (c.y - b.y) - (b.y - a.y) * (c.x - b.x)
+}
+
+fn in_triangle(point: Pos2, a: Pos2, b: Pos2, c: Pos2) -> bool {
+    let d1 = cross(a, b, point);
+    let d2 = cross(b, c, point);
+    let d3 = cross(c, a, point);
+    let negative = d1 < 0.0 || d2 < 0.0 || d3 < 0.0;
+    let positive = d1 > 0.0 || d2 > 0.0 || d3 > 0.0;
+    !(negative && positive)
+}
+
+//Ear clipping, egui only fills convex shapes on its own
+//A self intersecting polygon stops when no ear is left, the rest of it stays unfilled
+fn triangulate(points: &[Pos2]) -> Vec<[usize; 3]> {
+    let mut triangles = Vec::new();
+    if points.len() < 3 {
+        return triangles;
+    }
+    let mut area = 0.0;
+    for (i, point) in points.iter().enumerate() {
+        let next = points[(i + 1) % points.len()];
+        area += point.x * next.y - next.x * point.y;
+    }
+    let mut remaining: Vec<usize> = (0..points.len()).collect();
+    while remaining.len() > 3 {
+        let count = remaining.len();
+        let ear = (0..count).find(|&i| {
+            let (a, b, c) = (
+                remaining[(i + count - 1) % count],
+                remaining[i],
+                remaining[(i + 1) % count],
+            );
+            //The corner has to turn the same way as the whole polygon
+            let turn = cross(points[a], points[b], points[c]);
+            if turn * area <= 0.0 {
+                return false;
+            }
+            !remaining.iter().any(|&other| {
+                other != a
+                    && other != b
+                    && other != c
+                    && in_triangle(points[other], points[a], points[b], points[c])
+            })
+        });
+        match ear {
+            Some(i) => {
+                triangles.push([
+                    remaining[(i + count - 1) % count],
+                    remaining[i],
+                    remaining[(i + 1) % count],
+                ]);
+                remaining.remove(i);
+            }
+            None => return triangles,
+        }
+    }
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+    triangles
+}
+
+pub fn fill(points: &[Pos2], color: Color32) -> Shape {
+    let mut mesh = Mesh::default();
+    for point in points {
+        mesh.colored_vertex(*point, color);
+    }
+    for [a, b, c] in triangulate(points) {
+        mesh.add_triangle(a as u32, b as u32, c as u32);
+    }
+    Shape::mesh(mesh)
+}
+
+pub fn outline(points: &[Pos2], stroke: Stroke) -> Shape {
+    Shape::closed_line(points.to_vec(), stroke)
+}
+
+//Smallest rectangle around the points, kept in corners so the name and older clients still have a box
+pub fn bounding_box(points: &[[f32; 2]]) -> [f32; 4] {
+    let mut result = [f32::MAX, f32::MAX, f32::MIN, f32::MIN];
+    for point in points {
+        result[0] = result[0].min(point[0]);
+        result[1] = result[1].min(point[1]);
+        result[2] = result[2].max(point[0]);
+        result[3] = result[3].max(point[1]);
+    }
+    if points.is_empty() { [0.0; 4] } else { result }
+}
+
+//The part of a container the region editor changes, names typed meanwhile are left alone by undo
+#[derive(Clone)]
+pub struct RegionSnapshot {
+    corners: [f32; 4],
+    polygon: Vec<[f32; 2]>,
+    rotation: f32,
+}
+
+impl RegionSnapshot {
+    pub fn of(container: &Container) -> Self {
+        Self {
+            corners: container.corners,
+            polygon: container.polygon.clone(),
+            rotation: container.rotation,
+        }
+    }
+
+    pub fn restore(self, container: &mut Container) {
+        container.corners = self.corners;
+        container.polygon = self.polygon;
+        container.rotation = self.rotation;
+    }
+}
+
+//The closest target within distance of the value
+pub fn snap(value: f32, targets: &[f32], distance: f32) -> Option<f32> {
+    targets
+        .iter()
+        .filter(|target| (**target - value).abs() <= distance)
+        .min_by(|a, b| (**a - value).abs().total_cmp(&(**b - value).abs()))
+        .copied()
+}
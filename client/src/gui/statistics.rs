@@ -1,6 +1,9 @@
 use std::collections::HashMap;
 
-use crate::{DataType, Visualoc};
+use crate::{
+    DataType, UnitTotal, ValueTotal, Visualoc,
+    database::data_helpers::{format_money, parse_date, parse_date_time},
+};
 
 impl Visualoc {
     pub fn statistics_screen(&mut self, ui: &mut egui::Ui) {
@@ -13,7 +16,16 @@ impl Visualoc {
             ui.label(self.item_count.to_string());
         });
         Visualoc::themed_heading(ui, self.settings.light_mode, "Most common values");
-        for (index, (field_name, _)) in self.item_field_types.iter().enumerate() {
+        for (index, (field_name, field_type)) in self.item_field_types.iter().enumerate() {
+            //Date fields show every month, the other fields their 5 most common values
+            let (value_header, shown_rows) = match field_type {
+                DataType::Date | DataType::DateTime => {
+                    ("Month", self.max_min_field_values[index].len())
+                }
+                //Money fields are summed up below instead
+                DataType::Money { .. } => continue,
+                _ => ("Value", 5),
+            };
             ui.label(field_name);
             ui.separator();
             egui_extras::TableBuilder::new(ui)
@@ -22,14 +34,14 @@ impl Visualoc {
                 .columns(egui_extras::Column::remainder(), 2)
                 .header(20.0, |mut header| {
                     header.col(|ui| {
-                        ui.label("Value");
+                        ui.label(value_header);
                     });
                     header.col(|ui| {
                         ui.label("Count");
                     });
                 })
                 .body(|mut body| {
-                    for i in 0..std::cmp::min(5, self.max_min_field_values[index].len()) {
+                    for i in 0..std::cmp::min(shown_rows, self.max_min_field_values[index].len()) {
                         body.row(30.0, |mut row| {
                             row.col(|ui| {
                                 ui.label(&self.max_min_field_values[index][i].0);
@@ -43,6 +55,8 @@ impl Visualoc {
             ui.separator();
             ui.add_space(30.0);
         }
+        self.unit_totals_screen(ui);
+        self.value_totals_screen(ui);
         //Largest Container?
         //Container count
         //Location count
@@ -112,14 +126,147 @@ impl Visualoc {
                 DataType::Text => {
                     string_index += 1;
                 }
+                DataType::Money { .. } => {
+                    string_index += 1;
+                }
+                //Counted by month, date-times by the month in local time
+                DataType::Date | DataType::DateTime => {
+                    for item in &self.item_vec {
+                        let month = match field_type {
+                            DataType::Date => parse_date(&item.string_vars[string_index])
+                                .map(|x| x.format("%Y-%m").to_string()),
+                            _ => parse_date_time(&item.string_vars[string_index])
+                                .map(|x| x.format("%Y-%m").to_string()),
+                        };
+                        if let Some(key) = month {
+                            match map.get(&key) {
+                                Some(count) => map.insert(key, count + 1),
+                                None => map.insert(key, 1),
+                            };
+                        }
+                    }
+                    string_index += 1;
+                }
             }
             //Hashmap to Vec
             let mut str_count_vec = Vec::new();
             for item in map {
                 str_count_vec.push(item);
             }
-            str_count_vec.sort_by(|a, b| b.1.cmp(&a.1));
+            match field_type {
+                DataType::Date | DataType::DateTime => str_count_vec.sort_by(|a, b| a.0.cmp(&b.0)),
+                _ => str_count_vec.sort_by(|a, b| b.1.cmp(&a.1)),
+            }
             self.max_min_field_values.push(str_count_vec);
         }
     }
+
+    //Total value of each money field per location, with the containers inside it indented below
+    fn value_totals_screen(&self, ui: &mut egui::Ui) {
+        for (field_name, field_type) in &self.item_field_types {
+            if !matches!(field_type, DataType::Money { .. }) {
+                continue;
+            }
+            let totals: Vec<&ValueTotal> = self
+                .value_totals
+                .iter()
+                .filter(|x| &x.field_name == field_name)
+                .collect();
+            Visualoc::themed_heading(
+                ui,
+                self.settings.light_mode,
+                &format!("{} totals", field_name),
+            );
+            ui.separator();
+            let rows = tree_rows(&totals, |x| &x.container_id, |x| &x.parent_id);
+            egui_extras::TableBuilder::new(ui)
+                .id_salt(format!("{} totals", field_name))
+                .striped(true)
+                .columns(egui_extras::Column::remainder(), 2)
+                .header(20.0, |mut header| {
+                    header.col(|ui| {
+                        ui.label("Location / Container");
+                    });
+                    header.col(|ui| {
+                        ui.label("Total");
+                    });
+                })
+                .body(|mut body| {
+                    for (depth, total) in rows {
+                        body.row(30.0, |mut row| {
+                            row.col(|ui| {
+                                ui.add_space(depth as f32 * 20.0);
+                                ui.label(&total.container_name);
+                            });
+                            row.col(|ui| {
+                                ui.label(format_money(&total.total, &total.currency));
+                            });
+                        });
+                    }
+                });
+            ui.separator();
+            ui.add_space(30.0);
+        }
+    }
+
+    //Units of every item per location, with the containers inside it indented below
+    fn unit_totals_screen(&self, ui: &mut egui::Ui) {
+        let totals: Vec<&UnitTotal> = self.unit_totals.iter().collect();
+        Visualoc::themed_heading(ui, self.settings.light_mode, "Units per location");
+        ui.separator();
+        let rows = tree_rows(&totals, |x| &x.container_id, |x| &x.parent_id);
+        egui_extras::TableBuilder::new(ui)
+            .id_salt("Units per location")
+            .striped(true)
+            .columns(egui_extras::Column::remainder(), 2)
+            .header(20.0, |mut header| {
+                header.col(|ui| {
+                    ui.label("Location / Container");
+                });
+                header.col(|ui| {
+                    ui.label("Units");
+                });
+            })
+            .body(|mut body| {
+                for (depth, total) in rows {
+                    body.row(30.0, |mut row| {
+                        row.col(|ui| {
+                            ui.add_space(depth as f32 * 20.0);
+                            ui.label(&total.container_name);
+                        });
+                        row.col(|ui| {
+                            ui.label(total.units.to_string());
+                        });
+                    });
+                }
+            });
+        ui.separator();
+        ui.add_space(30.0);
+    }
+}
+
+//Locations first, depth first so every container comes right after its parent
+fn tree_rows<'a, T>(
+    totals: &[&'a T],
+    id: impl Fn(&T) -> &String,
+    parent_id: impl Fn(&T) -> &String,
+) -> Vec<(usize, &'a T)> {
+    let mut rows = Vec::new();
+    let mut stack: Vec<(usize, &'a T)> = totals
+        .iter()
+        .rev()
+        .filter(|x| parent_id(x).is_empty())
+        .map(|x| (0, *x))
+        .collect();
+    while let Some((depth, total)) = stack.pop() {
+        rows.push((depth, total));
+        stack.extend(
+            totals
+                .iter()
+                .rev()
+                .filter(|x| parent_id(x) == id(total))
+                .map(|x| (depth + 1, *x)),
+        );
+    }
+    rows
 }
@@ -1,6 +1,8 @@
 use uuid::Uuid;
 
-use crate::{Backup, CommandToServer, DataType, FieldModal, ModalType, UIPages, Visualoc};
+use crate::{
+    Backup, BackupState, CommandToServer, DataType, FieldModal, ModalType, UIPages, Visualoc,
+};
 
 impl Visualoc {
     pub fn top_row(&mut self, ui: &mut egui::Ui) {
@@ -9,12 +11,6 @@ impl Visualoc {
                 self.home_page.column_search = ("Name".to_string(), DataType::String);
                 self.search_string = String::new();
                 self.home_page.previous_search = String::new();
-                self.async_tasks_to_send.push(CommandToServer::SearchItems(
-                    Uuid::new_v4().to_string(),
-                    self.search_string.clone(),
-                    self.home_page.column_search.0.clone(),
-                    Vec::new(),
-                ));
                 self.prepare_page(UIPages::Home);
             }
             if ui.button("⛃ Locations").clicked() {
@@ -23,11 +19,36 @@ impl Visualoc {
             if Visualoc::add_button(ui, "Add/Delete Item Fields").clicked() {
                 self.modal_vars.field_modal = FieldModal::Start;
             }
+            if ui.button("📷 Scan / enter code").clicked() {
+                self.modal_vars.scan_code = String::new();
+                self.modal_vars.scan_message = String::new();
+                self.modal_vars.modal_type = ModalType::ScanCode;
+            }
             if ui.button("📊 Statistics").clicked() {
                 self.prepare_page(UIPages::Statistics);
             }
+            let low_count = self.low_stock_count();
+            if low_count > 0 {
+                let badge = egui::Button::new(
+                    egui::RichText::new(format!("⚠ {} low", low_count)).color(egui::Color32::WHITE),
+                )
+                .fill(egui::Color32::from_rgb(200, 40, 40));
+                if ui
+                    .add(badge)
+                    .on_hover_text("Items at or below their minimum")
+                    .clicked()
+                {
+                    self.prepare_page(UIPages::LowStock);
+                }
+            }
             if ui.button("💾 Backup").clicked() {
-                self.backup = Backup::default();
+                //A restore or a merge that is still running keeps its state to show the result
+                if !matches!(
+                    self.backup.state,
+                    BackupState::Restoring | BackupState::Merging
+                ) {
+                    self.backup = Backup::default();
+                }
                 self.modal_vars.modal_type = ModalType::Backup;
             }
 
@@ -1,17 +1,20 @@
+use chrono::NaiveDate;
 use database::{async_db::send_command_to_database, data_helpers::ImageSize};
 use egui::{
-    Color32, ColorImage, FontFamily, FontId, Style, TextStyle, TextWrapMode, TextureHandle,
+    Color32, ColorImage, FontFamily, FontId, Style, TextStyle, TextWrapMode, TextureHandle, Vec2,
     Visuals,
     ahash::{HashMap, HashMapExt},
 };
+use gui::region::RegionSnapshot;
 use rfd::FileHandle;
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeSet, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use uuid::Uuid;
-use web_sys::FileList;
 mod gui {
     pub mod account;
+    pub mod backup;
     pub mod canvas;
+    pub mod csv_import;
     pub mod edit_location;
     pub mod fields_page;
     pub mod gui_helpers;
@@ -19,6 +22,8 @@ mod gui {
     pub mod locations_page;
     pub mod login;
     pub mod modal;
+    pub mod low_stock;
+    pub mod region;
     pub mod statistics;
     pub mod top_row;
 }
@@ -26,8 +31,10 @@ mod gui {
 mod database {
     pub mod async_db;
     pub mod containers;
+    pub mod csv_import;
     pub mod data_helpers;
     pub mod items;
+    pub mod stock;
 }
 
 #[derive(PartialEq)]
@@ -41,9 +48,13 @@ enum ModalType {
     SelectContainerlessItem,
     SelectFieldsShown,
     ItemImage,
+    GalleryImage,
     Backup,
     Settings,
     AddLocation,
+    ScanCode,
+    MoveItems,
+    ImportCsv,
 }
 
 enum UIPages {
@@ -51,6 +62,7 @@ enum UIPages {
     LocationGrid,
     LocationContainers,
     Statistics,
+    LowStock,
     Account,
 }
 
@@ -67,6 +79,25 @@ enum ContainerScreen {
     EditingLocation,
 }
 
+//How the region of the container being added or edited is drawn
+#[derive(PartialEq)]
+enum RegionMode {
+    Rectangle,
+    RotatedRectangle,
+    Polygon,
+}
+
+//What a press on the canvas grabbed while a rectangle is edited
+#[derive(Clone, Copy, PartialEq)]
+enum RectHandle {
+    //Outside the rectangle, draws a new one
+    New,
+    //Moves the whole rectangle
+    Body,
+    //Index in corners of the x and y edge that follow the pointer
+    Edges(Option<usize>, Option<usize>),
+}
+
 #[derive(PartialEq)]
 enum FieldModal {
     None,
@@ -86,6 +117,218 @@ enum DataType {
     Text,
     List(Vec<String>),
     Gallery,
+    Date,
+    DateTime,
+    Money { currency: String },
+}
+
+#[derive(PartialEq, Clone, Copy, Debug, Serialize)]
+enum SortDirection {
+    Ascending,
+    Descending,
+}
+
+//Search mode shown in the column combo box, searches the name and every text field
+const ALL_FIELDS: &str = "All fields";
+
+//A page of the item search, matches SearchItemsRequest on the server
+#[derive(PartialEq, Clone, Debug, Serialize)]
+struct ItemQuery {
+    search_string: String,
+    column_name: String,
+    offset: usize,
+    limit: Option<usize>,        //None returns every match
+    sort_column: Option<String>, //None sorts by name, or by rank for all fields searches
+    sort_direction: SortDirection,
+    all_fields: bool,
+}
+
+impl Default for ItemQuery {
+    fn default() -> Self {
+        Self {
+            search_string: String::new(),
+            column_name: "Name".to_string(),
+            offset: 0,
+            limit: None,
+            sort_column: None,
+            sort_direction: SortDirection::Ascending,
+            all_fields: false,
+        }
+    }
+}
+
+//File types the matches of a search can be exported to, matches ExportFormat on the server
+#[derive(PartialEq, Clone, Copy, Debug, Serialize)]
+enum ExportFormat {
+    Csv,
+    Xlsx,
+}
+
+//A page of items, the total across all pages and the fields each item matched in an all fields search
+type SearchResult = (Vec<ContainedItem>, usize, Vec<Vec<String>>);
+
+//What happens to the items inside a container when it gets deleted
+#[derive(PartialEq, Clone, Copy, Debug, Serialize)]
+enum ItemPolicy {
+    DeleteItems,
+    MoveToParent,
+    LeaveUnplaced,
+}
+
+//Value of a money field inside a location or container, including the containers below it
+#[derive(Clone, Debug, Deserialize)]
+struct ValueTotal {
+    container_id: String,
+    container_name: String,
+    parent_id: String, //Empty for locations
+    field_name: String,
+    currency: String,
+    total: String,
+}
+
+//Units inside a location or container, including the containers below it
+#[derive(Clone, Debug, Deserialize)]
+struct UnitTotal {
+    container_id: String,
+    container_name: String,
+    parent_id: String, //Empty for locations
+    units: i64,
+}
+
+//Minimum of an item before it's reported as low stock
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+struct StockThreshold {
+    field_name: Option<String>, //None compares the quantity over all placements
+    minimum: f64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct LowStockItem {
+    item_id: String,
+    item_name: String,
+    field_name: Option<String>,
+    value: f64,
+    minimum: f64,
+}
+
+//Items at or below their minimum inside one location
+#[derive(Clone, Debug, Deserialize)]
+struct LowStockLocation {
+    location_id: String, //Empty for items not in a location
+    location_name: String,
+    items: Vec<LowStockItem>,
+}
+
+//Where a scanned or typed code points to, the path goes from the root down to the container
+#[derive(Clone, Debug, Deserialize)]
+struct ResolvedCode {
+    path: Vec<Container>,
+    item: Option<ContainedItem>, //Set when the code is an item's
+}
+
+//Search matches inside one location, container_counts goes by container id and counts the items below it too
+#[derive(Clone, Debug, Deserialize)]
+struct LocationMatches {
+    location: Container,
+    item_count: usize,
+    container_counts: HashMap<String, usize>,
+}
+
+//Home page search shown on the location photos
+struct SearchMap {
+    query: ItemQuery, //What was searched, shown above the photo and fetched again after edits
+    locations: Vec<LocationMatches>,
+}
+
+//What the server removed when deleting a container
+#[derive(Clone, Debug, Default, Deserialize)]
+struct DeleteContainerReport {
+    containers_deleted: u64,
+    items_deleted: u64,
+    items_moved: u64,
+    items_unplaced: u64,
+    images_removed: u64,
+}
+
+//Where the values of a CSV column go when importing items
+#[derive(PartialEq, Clone, Debug, Serialize)]
+enum ColumnTarget {
+    Skip,
+    Name,
+    Quantity,
+    Field(String),
+    NewField(String, DataType),
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct CsvImportRequest {
+    csv: String,
+    mapping: Vec<ColumnTarget>, //Empty only reads the headers and the first rows
+    container_id: String,       //Empty imports the items without a container
+    dry_run: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct CsvRowError {
+    row: usize,
+    message: String,
+}
+
+//What the server read from the file, and what it imported or would import
+#[derive(Clone, Debug, Default, Deserialize)]
+struct CsvImportReport {
+    headers: Vec<String>,
+    preview: Vec<Vec<String>>,
+    rows: usize,
+    imported: usize,
+    errors: Vec<CsvRowError>,
+    placed: Vec<(String, i32)>,
+}
+
+//What the server restored from a backup, images that couldn't be written are listed by file name
+#[derive(Clone, Debug, Default, Deserialize)]
+struct RestoreReport {
+    fields: usize,
+    containers: usize,
+    items: usize,
+    images: usize,
+    failed_images: Vec<String>,
+    from_dump: bool, //The backup was made before the archive and was converted
+}
+
+//How a merge resolves a field name or an id that is used in both, matches ConflictPolicy on the server
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MergePolicy {
+    Skip,
+    Overwrite,
+    Duplicate,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+struct MergeCounts {
+    added: usize,
+    overwritten: usize,
+    duplicated: usize,
+    skipped: usize,
+}
+
+//What a merge changed, or would change for a dry run
+#[derive(Clone, Debug, Default, Deserialize)]
+struct MergeReport {
+    dry_run: bool,
+    fields: MergeCounts,
+    containers: MergeCounts,
+    items: MergeCounts,
+    images: usize,
+    failed_images: Vec<String>,
+    notes: Vec<String>, //What happens to each conflicting field
+    from_dump: bool,
+}
+
+//Dragged from the item list onto a container on the canvas
+struct DraggedItem {
+    item_id: String,
+    from_container_id: String,
 }
 
 //On all commands the first string is the command id
@@ -97,18 +340,29 @@ enum CommandToServer {
 
     AddContainer(String, Container),
     UpdateContainer(String, Container),
-    DeleteContainer(String, Container),
+    DeleteContainer(String, Container, ItemPolicy, Option<DeleteContainerReport>),
 
     UpdateItem(String, ContainedItem, Vec<(String, DataType)>),
     InsertItem(String, ContainedItem, String, Vec<(String, DataType)>),
     DeleteItem(String, ContainedItem, String, bool),
-    SearchItems(String, String, String, Vec<ContainedItem>),
+    PickCsvFile(String, Option<(String, String)>), //Name and contents of the file
+    ImportItemsCsv(String, CsvImportRequest, Result<CsvImportReport, String>),
+    MoveItems(String, Vec<String>, String, String, Option<u64>), //Items, from container, to container, how many moved
+    SearchItems(String, ItemQuery, Result<SearchResult, String>),
+    GetItemMap(String, ItemQuery, Result<Vec<LocationMatches>, String>),
+    ExportItems(String, ItemQuery, ExportFormat, Result<(), String>),
 
     GetItemColumnTypes(String, Vec<(String, DataType)>),
     GetAllSlaves(String, String, Vec<Container>),
-    GetItemLocationContainer(String, String, Option<(Container, Container)>),
+    GetItemAncestors(String, String, Vec<Container>),
     GetMultipleItems(String, BTreeSet<String>, Vec<ContainedItem>),
     GetAllItemIdsNotInContainer(String, Vec<String>),
+    GetValueTotals(String, Vec<ValueTotal>),
+    GetUnitTotals(String, Vec<UnitTotal>),
+    GetStockThreshold(String, String, Option<StockThreshold>),
+    SetStockThreshold(String, String, Option<StockThreshold>),
+    GetLowStock(String, Vec<LowStockLocation>),
+    GetLowStockCsv(String),
 
     AddField(String, String, DataType),
     DeleteColumnFromItems(String, String),
@@ -116,11 +370,21 @@ enum CommandToServer {
 
     GetImageFromServer(String, String, String, ImageSize, ColorImage),
     AddImage(String, String, String),
+    AddGalleryImage(String, String, String, String),
     GetBackup(String),
+    GetLabels(String, String, String, bool),
+    ResolveCode(String, String, Result<Option<ResolvedCode>, String>),
+    ScanCodeImage(String, Result<Option<ResolvedCode>, String>),
 
-    PickBackupDumpFile(String, Option<FileHandle>),
-    PickBackupImageFolder(String, Option<FileList>),
-    UploadBackup(String, Option<FileHandle>, Option<FileList>),
+    PickBackupFile(String, Option<FileHandle>),
+    RestoreBackup(String, Option<FileHandle>, Result<RestoreReport, String>),
+    MergeBackup(
+        String,
+        Option<FileHandle>,
+        MergePolicy,
+        bool,
+        Result<MergeReport, String>,
+    ),
 }
 
 #[derive(Clone)]
@@ -154,10 +418,13 @@ enum LoginResult {
     WrongPassword,
 }
 
+#[derive(Clone)]
 enum BackupState {
     Start,
-    Upload,
-    Waiting,
+    Restoring,
+    Done(Result<RestoreReport, String>),
+    Merging,
+    Merged(Result<MergeReport, String>),
 }
 
 struct HomePage {
@@ -165,7 +432,14 @@ struct HomePage {
     previous_search: String, //String to compare to, to see if search has to be run again
     column_search: (String, DataType),
     previous_column_search: String,
-    search_results_sorted: bool,
+    sort_column: Option<String>,
+    sort_direction: SortDirection,
+    total_items: usize,                //Matches of the search across all pages
+    matched_fields: Vec<Vec<String>>,  //Fields to highlight for each item of the page
+    previous_query: Option<ItemQuery>, //Query of the page shown, a different one gets fetched
+    search_error: String,              //Parse error of the last filter, shown under the search box
+    date_from: Option<NaiveDate>,      //Range searched in a date field, None leaves that side open
+    date_to: Option<NaiveDate>,
 }
 
 impl Default for HomePage {
@@ -175,7 +449,14 @@ impl Default for HomePage {
             previous_search: String::new(),
             column_search: ("Name".to_string(), DataType::String),
             previous_column_search: String::new(),
-            search_results_sorted: false,
+            sort_column: None,
+            sort_direction: SortDirection::Ascending,
+            total_items: 0,
+            matched_fields: Vec::new(),
+            previous_query: None,
+            search_error: String::new(),
+            date_from: None,
+            date_to: None,
         }
     }
 }
@@ -188,6 +469,15 @@ struct ModalVars {
     modal_type: ModalType,
     modal_id: String, //Different id for every modal spawned
     field_modal_id: String,
+    delete_item_policy: ItemPolicy,
+    gallery_entries: Vec<String>, //Images of the gallery shown in the GalleryImage modal
+    gallery_index: usize,
+    scan_code: String,    //Code typed in the ScanCode modal
+    scan_message: String, //Why the last code couldn't be opened
+    move_path: Vec<Container>, //Containers browsed into by the MoveItems modal, the last one is the target
+    move_children: Vec<Container>, //What's inside the last browsed container, the locations when empty
+    move_browse_id: String,  //Command id of the last fetch of move_children
+    move_message: String,    //Why the last move failed
 }
 
 impl Default for ModalVars {
@@ -200,6 +490,15 @@ impl Default for ModalVars {
             new_field_type: DataType::String,
             modal_type: ModalType::None,
             field_modal_id: Uuid::new_v4().to_string(),
+            delete_item_policy: ItemPolicy::LeaveUnplaced,
+            gallery_entries: Vec::new(),
+            gallery_index: 0,
+            scan_code: String::new(),
+            scan_message: String::new(),
+            move_path: Vec::new(),
+            move_children: Vec::new(),
+            move_browse_id: String::new(),
+            move_message: String::new(),
         }
     }
 }
@@ -236,18 +535,54 @@ impl Default for Login {
 
 struct Backup {
     state: BackupState,
-    dump_filehandle: Option<FileHandle>,
-    images_filehandle: Option<FileList>,
-    resulting_string: String,
+    backup_file: Option<FileHandle>,
+    merge_policy: MergePolicy,
+    merge_preview: Option<Result<MergeReport, String>>, //The dry run for the chosen file and policy
+    preview_id: String,
 }
 
 impl Default for Backup {
     fn default() -> Self {
         Self {
             state: BackupState::Start,
-            dump_filehandle: None,
-            images_filehandle: None,
-            resulting_string: String::new(),
+            backup_file: None,
+            merge_policy: MergePolicy::Skip,
+            merge_preview: None,
+            preview_id: String::new(),
+        }
+    }
+}
+
+//Steps of the CSV import modal, a check with the mapping comes before the import
+#[derive(Default)]
+struct CsvImport {
+    file_name: String,
+    csv: String,
+    container_id: String, //Empty imports the items without a container
+    container_name: String,
+    mapping: Vec<ColumnTarget>,
+    report: Option<CsvImportReport>, //Headers and preview first, then the last check or the import
+    checked: bool,                   //The report is a check of the current mapping
+    imported: bool,
+    error: String,
+    request_id: String, //Command id of the last request, a spinner shows while it runs
+}
+
+//Zoom and pan of the location canvas
+struct CanvasView {
+    parent_id: String, //Photo the view belongs to, another photo starts fitted again
+    zoom: f32,         //1 fits the whole photo in the canvas
+    center: Vec2,      //Point of the photo in the middle of the canvas, as fractions of the photo
+    zoom_to_selected: bool, //Set when a container is opened from elsewhere, done when the photo is drawn
+}
+
+impl Default for CanvasView {
+    fn default() -> Self {
+        Self {
+            parent_id: String::new(),
+            zoom: 1.0,
+            center: Vec2::splat(0.5),
+            zoom_to_selected: false,
         }
     }
 }
@@ -301,6 +636,8 @@ struct ContainedItem {
     string_vars: Vec<String>,
     int_vars: Vec<i32>,
     float_vars: Vec<f32>,
+    #[serde(default)]
+    quantity: i64, //Units of the item over all of its placements, set by the server
 }
 
 impl Default for ContainedItem {
@@ -312,6 +649,7 @@ impl Default for ContainedItem {
             string_vars: Vec::new(),
             int_vars: Vec::new(),
             float_vars: Vec::new(),
+            quantity: 0,
         }
     }
 }
@@ -322,9 +660,15 @@ struct Container {
     master: String,
     slaves: BTreeSet<String>,
     name: String,
-    corners: [f32; 4],
+    corners: [f32; 4], //The rectangle before rotation, or the bounding box of the polygon
     image_type: String,
     contained_items: BTreeSet<String>,
+    #[serde(default)]
+    polygon: Vec<[f32; 2]>, //Points as fractions of the parent's photo, empty for a rectangle
+    #[serde(default)]
+    rotation: f32, //Degrees clockwise around the center of the corners rectangle
+    #[serde(default)]
+    item_quantities: BTreeMap<String, i32>, //Units of each contained item, a missing item counts as 1
 }
 
 impl Default for Container {
@@ -337,6 +681,9 @@ impl Default for Container {
             corners: [0.0, 0.0, 0.0, 0.0],
             image_type: String::new(),
             contained_items: BTreeSet::new(),
+            polygon: Vec::new(),
+            rotation: 0.0,
+            item_quantities: BTreeMap::new(),
         }
     }
 }
@@ -361,6 +708,12 @@ struct Visualoc {
     #[serde(skip)]
     selected_item: ContainedItem,
     #[serde(skip)]
+    item_quantity: i32, //Units of the selected item in the selected container while it's added or edited
+    #[serde(skip)]
+    stock_threshold: Option<StockThreshold>, //Low stock warning of the selected item, edited in the item view
+    #[serde(skip)]
+    stock_threshold_item: String, //Item the stock_threshold was fetched for
+    #[serde(skip)]
     container_vec: Vec<Container>,
     #[serde(skip)]
     item_vec: Vec<ContainedItem>,
@@ -380,6 +733,26 @@ struct Visualoc {
     item_field_types: Vec<(String, DataType)>,
     #[serde(skip)]
     search_string: String,
+    #[serde(skip)]
+    delete_container_report: Option<DeleteContainerReport>,
+    #[serde(skip)]
+    labels_include_items: bool, //Location page, print labels for the items too
+    #[serde(skip)]
+    container_path: Vec<Container>, //Containers opened below the location, the canvas shows the last one
+    #[serde(skip)]
+    region_mode: RegionMode, //Shape of the container being added or edited
+    #[serde(skip)]
+    dragged_vertex: Option<usize>, //Polygon point being moved on the canvas
+    #[serde(skip)]
+    rect_drag: Option<(RectHandle, [f32; 4])>, //Grabbed handle and the corners when the press started
+    #[serde(skip)]
+    region_undo: Vec<RegionSnapshot>, //Shapes of the container being added or edited, oldest first
+    #[serde(skip)]
+    region_redo: Vec<RegionSnapshot>,
+    #[serde(skip)]
+    canvas_view: CanvasView,
+    #[serde(skip)]
+    search_map: Option<SearchMap>, //Set while the matches of a search are highlighted on the photos
     //=========================================
     //Database
     //=========================================
@@ -406,6 +779,8 @@ struct Visualoc {
     #[serde(skip)]
     containerless_items_bools: Vec<bool>,
     #[serde(skip)]
+    containerless_items_quantities: Vec<i32>, //Units added for each checked item
+    #[serde(skip)]
     containerless_items_ids: BTreeSet<String>,
     //=========================================
     //Location Grid settings
@@ -421,6 +796,8 @@ struct Visualoc {
     temp_settings: Settings,
     #[serde(skip)]
     backup: Backup,
+    #[serde(skip)]
+    csv_import: CsvImport,
     //=========================================
     //Statistics
     //=========================================
@@ -430,6 +807,12 @@ struct Visualoc {
     location_count: usize,
     #[serde(skip)]
     max_min_field_values: Vec<Vec<(String, usize)>>,
+    #[serde(skip)]
+    value_totals: Vec<ValueTotal>,
+    #[serde(skip)]
+    unit_totals: Vec<UnitTotal>,
+    #[serde(skip)]
+    low_stock: Vec<LowStockLocation>,
     //=========================================
     //Persistent variables
     //=========================================
@@ -454,6 +837,9 @@ impl Default for Visualoc {
             selected_location: Container::default(),
             selected_container: Container::default(),
             selected_item: ContainedItem::default(),
+            item_quantity: 1,
+            stock_threshold: None,
+            stock_threshold_item: String::new(),
             loaded_images: HashMap::new(),
             login: Login::default(),
             remember_login: false,
@@ -462,6 +848,16 @@ impl Default for Visualoc {
             home_page: HomePage::default(),
             item_field_types: Vec::new(),
             search_string: String::new(),
+            delete_container_report: None,
+            labels_include_items: false,
+            container_path: Vec::new(),
+            region_mode: RegionMode::Rectangle,
+            dragged_vertex: None,
+            rect_drag: None,
+            region_undo: Vec::new(),
+            region_redo: Vec::new(),
+            canvas_view: CanvasView::default(),
+            search_map: None,
             tokio_sender: tx,
             tokio_receiver: rx,
             async_tasks_to_send: Vec::new(),
@@ -476,6 +872,7 @@ impl Default for Visualoc {
             item_fields_shown: Vec::new(),
             containerless_items: Vec::new(),
             containerless_items_bools: Vec::new(),
+            containerless_items_quantities: Vec::new(),
             containerless_items_ids: BTreeSet::new(),
             ordered_locations_vec: Vec::new(),
             new_ordered_locations_vec: Vec::new(),
@@ -483,9 +880,13 @@ impl Default for Visualoc {
             settings: Settings::default(),
             temp_settings: Settings::default(),
             backup: Backup::default(),
+            csv_import: CsvImport::default(),
             item_count: 0,
             location_count: 0,
             max_min_field_values: Vec::new(),
+            value_totals: Vec::new(),
+            unit_totals: Vec::new(),
+            low_stock: Vec::new(),
         }
     }
 }
@@ -666,6 +1067,7 @@ impl eframe::App for Visualoc {
                         UIPages::LocationGrid => self.location_grid_screen(ui),
                         UIPages::LocationContainers => self.location_containers_screen(ui, ctx),
                         UIPages::Statistics => self.statistics_screen(ui),
+                        UIPages::LowStock => self.low_stock_screen(ui),
                         UIPages::Account => self.account_page(ui),
                     });
                     if self.modal_vars.modal_type != ModalType::None {
@@ -0,0 +1,689 @@
+//Backups are a zip with archive.json next to the images folder, the archive holds the whole database as JSON
+//It doesn't depend on the Postgres version, and restoring it never runs SQL taken from the file
+//
+//Format, version 1:
+//  format      Always "visualoc-archive"
+//  version     1, archives from a newer server are refused
+//  created_at  RFC 3339 date-time
+//  fields      [{ name, type }] in column order, type is the DataType like the API sends it:
+//              "String", "Text", "Integer", "Float", "Bool", "Percentage", "Gallery", "Date", "DateTime",
+//              { "List": [options] } or { "Money": { "currency": "EUR" } }
+//  containers  [{ id, name, parent_id, corners, polygon, rotation, image_type }]
+//              Parents come before their children, the root has a null parent_id
+//  items       [{ id, name, image_type, values, placements: [{ container_id, quantity }], stock_threshold }]
+//              values has a key per field name: text, paragraphs and lists as strings, integers as numbers,
+//              checkboxes as true or false, decimals and percentages (0 to 100) as numbers,
+//              dates as "2024-12-31", date-times as RFC 3339, money as a string like "12.50"
+//              and galleries as an array of "{image_id}.{extension}"
+//              Missing values get the same defaults as a new item, empty dates and amounts are null
+//              stock_threshold is { field_name, minimum } or null, a null field_name is the quantity
+//  images      [{ file, sha256, size }], file is "{id}.{extension}" in the images folder of the zip
+//Changes an older server couldn't read get a new version, the restore keeps reading the older ones
+
+use chrono::{DateTime, NaiveDate, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Row, postgres::PgRow};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use tokio::fs;
+
+use crate::{
+    DataType, DynamicFieldValue, add_item_column, column_definition, corners_to_string, parse_date,
+    parse_date_time, parse_money, polygon_to_string, read_dynamic_fields, search,
+    stock::numeric_fields, string_to_corners, string_to_polygon,
+};
+
+pub const ARCHIVE_FORMAT: &str = "visualoc-archive";
+pub const ARCHIVE_VERSION: u32 = 1;
+pub const IMAGE_EXTENSIONS: [&str; 5] = ["png", "jpg", "jpeg", "gif", "webp"];
+
+#[derive(Serialize, Deserialize)]
+pub struct Archive {
+    pub format: String,
+    pub version: u32,
+    pub created_at: DateTime<Utc>,
+    pub fields: Vec<ArchiveField>,
+    pub containers: Vec<ArchiveContainer>,
+    pub items: Vec<ArchiveItem>,
+    #[serde(default)]
+    pub images: Vec<ArchiveImage>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ArchiveField {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub data_type: DataType,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ArchiveContainer {
+    pub id: String,
+    pub name: String,
+    pub parent_id: Option<String>,
+    pub corners: [f32; 4],
+    #[serde(default)]
+    pub polygon: Vec<[f32; 2]>,
+    #[serde(default)]
+    pub rotation: f32,
+    #[serde(default)]
+    pub image_type: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ArchiveItem {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub image_type: String,
+    #[serde(default)]
+    pub values: BTreeMap<String, Value>,
+    #[serde(default)]
+    pub placements: Vec<ArchivePlacement>,
+    #[serde(default)]
+    pub stock_threshold: Option<ArchiveThreshold>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ArchivePlacement {
+    pub container_id: String,
+    pub quantity: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ArchiveThreshold {
+    pub field_name: Option<String>,
+    pub minimum: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ArchiveImage {
+    pub file: String,
+    pub sha256: String,
+    pub size: u64,
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+//Ids and image types end up in image paths, anything else could point outside the folder
+pub fn is_record_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|x| x.is_ascii_alphanumeric() || x == '-')
+}
+
+//Older uploads kept the extension as the client sent it, so the case is ignored
+fn is_image_extension(extension: &str) -> bool {
+    IMAGE_EXTENSIONS
+        .iter()
+        .any(|x| x.eq_ignore_ascii_case(extension))
+}
+
+//A record without an image has an empty image type
+pub fn is_image_type(image_type: &str) -> bool {
+    image_type.is_empty() || is_image_extension(image_type)
+}
+
+//Image files are named after the id of what they show
+pub fn is_image_file_name(file: &str) -> bool {
+    match file.split_once('.') {
+        Some((id, extension)) => is_record_id(id) && is_image_extension(extension),
+        None => false,
+    }
+}
+
+//The full size images in the folder, the smaller copies are made again when restoring
+pub async fn image_manifest(images_dir: &str) -> std::io::Result<Vec<ArchiveImage>> {
+    let mut images = Vec::new();
+    let mut entries = fs::read_dir(images_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let file = entry.file_name().to_string_lossy().into_owned();
+        if !entry.path().is_file() || !is_image_file_name(&file) {
+            continue;
+        }
+        let data = fs::read(entry.path()).await?;
+        images.push(ArchiveImage {
+            sha256: sha256_hex(&data),
+            size: data.len() as u64,
+            file,
+        });
+    }
+    images.sort_by(|a, b| a.file.cmp(&b.file));
+    Ok(images)
+}
+
+//Floats are written with their shortest f32 form, 0.1 stays 0.1 instead of 0.10000000149011612
+fn float_value(value: f32) -> Value {
+    value
+        .to_string()
+        .parse::<f64>()
+        .map_or(Value::Null, Value::from)
+}
+
+fn read_value(row: &PgRow, column: &str, data_type: &DataType) -> Value {
+    match data_type {
+        DataType::String | DataType::Text | DataType::List(_) => Value::String(
+            row.try_get::<Option<String>, _>(column)
+                .ok()
+                .flatten()
+                .unwrap_or_default(),
+        ),
+        DataType::Gallery => row
+            .try_get::<Option<String>, _>(column)
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+            .split('@')
+            .filter(|x| !x.is_empty())
+            .map(|x| Value::String(x.to_string()))
+            .collect(),
+        DataType::Integer => Value::from(
+            row.try_get::<Option<i32>, _>(column)
+                .ok()
+                .flatten()
+                .unwrap_or(0),
+        ),
+        DataType::Bool => {
+            Value::Bool(row.try_get::<Option<i32>, _>(column).ok().flatten() == Some(1))
+        }
+        DataType::Float | DataType::Percentage => float_value(
+            row.try_get::<Option<f32>, _>(column)
+                .ok()
+                .flatten()
+                .unwrap_or(0.0),
+        ),
+        DataType::Date => row
+            .try_get::<Option<NaiveDate>, _>(column)
+            .ok()
+            .flatten()
+            .map_or(Value::Null, |x| {
+                Value::String(x.format("%Y-%m-%d").to_string())
+            }),
+        DataType::DateTime => row
+            .try_get::<Option<DateTime<Utc>>, _>(column)
+            .ok()
+            .flatten()
+            .map_or(Value::Null, |x| {
+                Value::String(x.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+            }),
+        DataType::Money { .. } => row
+            .try_get::<Option<Decimal>, _>(column)
+            .ok()
+            .flatten()
+            .map_or(Value::Null, |x| Value::String(format!("{:.2}", x))),
+    }
+}
+
+//A value of the archive as it's stored for the field's type, missing and null values get the defaults of a new item
+pub fn field_value(
+    value: Option<&Value>,
+    data_type: &DataType,
+) -> Result<DynamicFieldValue, String> {
+    let value = value.filter(|x| !x.is_null());
+    let text = |value: Option<&Value>| match value {
+        Some(Value::String(x)) => Ok(x.clone()),
+        None => Ok(String::new()),
+        Some(x) => Err(format!("{} isn't text", x)),
+    };
+    match data_type {
+        DataType::String | DataType::Text | DataType::List(_) => {
+            text(value).map(DynamicFieldValue::Text)
+        }
+        DataType::Gallery => match value {
+            Some(Value::Array(entries)) => {
+                let mut files = Vec::new();
+                for entry in entries {
+                    match entry.as_str().filter(|x| is_image_file_name(x)) {
+                        Some(file) => files.push(file),
+                        None => return Err(format!("{} isn't a gallery image", entry)),
+                    }
+                }
+                Ok(DynamicFieldValue::Text(files.join("@")))
+            }
+            None => Ok(DynamicFieldValue::Text(String::new())),
+            Some(x) => Err(format!("{} isn't a list of images", x)),
+        },
+        DataType::Integer => match value {
+            Some(x) => x
+                .as_i64()
+                .and_then(|x| i32::try_from(x).ok())
+                .map(DynamicFieldValue::Integer)
+                .ok_or_else(|| format!("{} isn't a whole number", x)),
+            None => Ok(DynamicFieldValue::Integer(0)),
+        },
+        DataType::Bool => match value {
+            Some(Value::Bool(x)) => Ok(DynamicFieldValue::Integer(*x as i32)),
+            None => Ok(DynamicFieldValue::Integer(0)),
+            Some(x) => Err(format!("{} isn't true or false", x)),
+        },
+        DataType::Float | DataType::Percentage => match value {
+            Some(x) => x
+                .as_f64()
+                .map(|x| DynamicFieldValue::Float(x as f32))
+                .ok_or_else(|| format!("{} isn't a number", x)),
+            None => Ok(DynamicFieldValue::Float(0.0)),
+        },
+        DataType::Date => {
+            let text = text(value)?;
+            parse_date(&text)
+                .map(DynamicFieldValue::Date)
+                .map_err(|_| format!("'{}' isn't a date like 2024-12-31", text))
+        }
+        DataType::DateTime => {
+            let text = text(value)?;
+            parse_date_time(&text)
+                .map(DynamicFieldValue::DateTime)
+                .map_err(|_| format!("'{}' isn't an RFC 3339 date-time", text))
+        }
+        DataType::Money { .. } => {
+            let text = text(value)?;
+            parse_money(&text)
+                .map(DynamicFieldValue::Money)
+                .map_err(|_| format!("'{}' isn't an amount", text))
+        }
+    }
+}
+
+//Reads the whole database of the user, the images are listed from the folder with their hashes
+pub async fn build_archive(pool: &PgPool, images_dir: &str) -> Result<Archive, String> {
+    let fields = read_dynamic_fields(pool)
+        .await
+        .map_err(|e| format!("Reading fields error: {}", e))?;
+    //Walked down from the roots so parents come first, every container has one parent so the walk ends at the leaves
+    let container_rows = sqlx::query(
+        r#"
+        WITH RECURSIVE tree AS (
+            SELECT id, 0 AS depth FROM containers WHERE parent_id IS NULL
+            UNION ALL
+            SELECT c.id, tree.depth + 1 FROM containers c JOIN tree ON c.parent_id = tree.id
+        )
+        SELECT c.id, c.name, c.parent_id, COALESCE(c.corners, '') AS corners,
+        COALESCE(c.image_type, '') AS image_type, c.polygon, c.rotation
+        FROM containers c JOIN tree ON tree.id = c.id
+        ORDER BY tree.depth, c.name, c.id
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Reading containers error: {}", e))?;
+    let containers = container_rows
+        .iter()
+        .map(|row| ArchiveContainer {
+            id: row.get("id"),
+            name: row.get("name"),
+            parent_id: row.get("parent_id"),
+            corners: string_to_corners(row.get("corners")),
+            polygon: string_to_polygon(row.get("polygon")),
+            rotation: row.get("rotation"),
+            image_type: row.get("image_type"),
+        })
+        .collect();
+
+    let mut placements: HashMap<String, Vec<ArchivePlacement>> = HashMap::new();
+    let placement_rows = sqlx::query(
+        r#"
+        SELECT item_id, container_id, quantity FROM item_placements ORDER BY item_id, container_id
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Reading placements error: {}", e))?;
+    for row in placement_rows {
+        placements
+            .entry(row.get("item_id"))
+            .or_default()
+            .push(ArchivePlacement {
+                container_id: row.get("container_id"),
+                quantity: row.get("quantity"),
+            });
+    }
+    let mut thresholds: HashMap<String, ArchiveThreshold> = HashMap::new();
+    let threshold_rows = sqlx::query("SELECT item_id, field_name, minimum FROM stock_thresholds")
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("Reading stock thresholds error: {}", e))?;
+    for row in threshold_rows {
+        thresholds.insert(
+            row.get("item_id"),
+            ArchiveThreshold {
+                field_name: row.get("field_name"),
+                minimum: row.get("minimum"),
+            },
+        );
+    }
+
+    let item_rows = sqlx::query("SELECT * FROM items ORDER BY name, id")
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("Reading items error: {}", e))?;
+    let items = item_rows
+        .iter()
+        .map(|row| {
+            let id: String = row.get("id");
+            ArchiveItem {
+                name: row.get("name"),
+                image_type: row
+                    .try_get::<Option<String>, _>("image_type")
+                    .ok()
+                    .flatten()
+                    .unwrap_or_default(),
+                values: fields
+                    .iter()
+                    .map(|(name, data_type)| (name.clone(), read_value(row, name, data_type)))
+                    .collect(),
+                placements: placements.remove(&id).unwrap_or_default(),
+                stock_threshold: thresholds.remove(&id),
+                id,
+            }
+        })
+        .collect();
+
+    let images = image_manifest(images_dir)
+        .await
+        .map_err(|e| format!("Reading images error: {}", e))?;
+    Ok(Archive {
+        format: ARCHIVE_FORMAT.to_string(),
+        version: ARCHIVE_VERSION,
+        created_at: Utc::now(),
+        fields: fields
+            .into_iter()
+            .map(|(name, data_type)| ArchiveField { name, data_type })
+            .collect(),
+        containers,
+        items,
+        images,
+    })
+}
+
+//Checks everything the restore relies on before the database is touched, the errors are meant for the user
+pub fn validate(archive: &Archive) -> Result<(), String> {
+    if archive.format != ARCHIVE_FORMAT {
+        return Err("The file isn't a Visualoc archive".to_string());
+    }
+    if archive.version == 0 || archive.version > ARCHIVE_VERSION {
+        return Err(format!(
+            "The archive is version {}, this server reads up to version {}",
+            archive.version, ARCHIVE_VERSION
+        ));
+    }
+
+    let mut field_names: HashSet<String> = HashSet::new();
+    for field in &archive.fields {
+        if field.name.is_empty() || field.name.chars().any(|x| !x.is_alphanumeric()) {
+            return Err(format!("Invalid field name '{}'", field.name));
+        }
+        //The name column and the field names are matched without case
+        let lowercase = field.name.to_lowercase();
+        if lowercase == "id" || lowercase == "name" {
+            return Err(format!("The field name '{}' is reserved", field.name));
+        }
+        if !field_names.insert(lowercase) {
+            return Err(format!("The field '{}' is there twice", field.name));
+        }
+        if column_definition(&field.data_type).is_none() {
+            return Err(format!(
+                "The field '{}' has an invalid currency",
+                field.name
+            ));
+        }
+    }
+
+    //A parent has to be listed before its children, that also rules out cycles
+    let mut container_ids: HashSet<&str> = HashSet::new();
+    for container in &archive.containers {
+        if !is_record_id(&container.id) {
+            return Err(format!(
+                "The container '{}' has a missing or invalid id",
+                container.name
+            ));
+        }
+        if !is_image_type(&container.image_type) {
+            return Err(format!(
+                "The container '{}' has an invalid image type",
+                container.name
+            ));
+        }
+        let parent_listed = match &container.parent_id {
+            Some(parent_id) => container_ids.contains(parent_id.as_str()),
+            None => true,
+        };
+        if !parent_listed {
+            return Err(format!(
+                "The parent of the container '{}' isn't listed before it",
+                container.name
+            ));
+        }
+        if !container_ids.insert(&container.id) {
+            return Err(format!("The container id {} is there twice", container.id));
+        }
+    }
+
+    let threshold_fields = numeric_fields(
+        archive
+            .fields
+            .iter()
+            .map(|x| (x.name.clone(), x.data_type.clone()))
+            .collect(),
+    );
+    let mut item_ids: HashSet<&str> = HashSet::new();
+    for item in &archive.items {
+        if !is_record_id(&item.id) || !item_ids.insert(&item.id) {
+            return Err(format!(
+                "The item '{}' has a missing, invalid or repeated id",
+                item.name
+            ));
+        }
+        if !is_image_type(&item.image_type) {
+            return Err(format!(
+                "The item '{}' has an invalid image type",
+                item.name
+            ));
+        }
+        for name in item.values.keys() {
+            if !archive.fields.iter().any(|x| &x.name == name) {
+                return Err(format!(
+                    "The item '{}' has a value for the unknown field '{}'",
+                    item.name, name
+                ));
+            }
+        }
+        for field in &archive.fields {
+            if let Err(e) = field_value(item.values.get(&field.name), &field.data_type) {
+                return Err(format!(
+                    "The item '{}', field '{}': {}",
+                    item.name, field.name, e
+                ));
+            }
+        }
+        let mut placed_in: HashSet<&str> = HashSet::new();
+        for placement in &item.placements {
+            if !container_ids.contains(placement.container_id.as_str())
+                || !placed_in.insert(&placement.container_id)
+            {
+                return Err(format!(
+                    "The item '{}' is placed in an unknown container",
+                    item.name
+                ));
+            }
+            if placement.quantity < 0 {
+                return Err(format!("The item '{}' has a negative quantity", item.name));
+            }
+        }
+        if let Some(threshold) = &item.stock_threshold {
+            let known_field = match &threshold.field_name {
+                Some(field_name) => threshold_fields.contains(field_name),
+                None => true,
+            };
+            if !known_field || !threshold.minimum.is_finite() {
+                return Err(format!(
+                    "The item '{}' has an invalid stock threshold",
+                    item.name
+                ));
+            }
+        }
+    }
+
+    let mut image_files: HashSet<&str> = HashSet::new();
+    for image in &archive.images {
+        if !is_image_file_name(&image.file) || !image_files.insert(&image.file) {
+            return Err(format!("Invalid or repeated image file '{}'", image.file));
+        }
+        if image.sha256.len() != 64 || !image.sha256.chars().all(|x| x.is_ascii_hexdigit()) {
+            return Err(format!("The image {} has an invalid hash", image.file));
+        }
+    }
+    Ok(())
+}
+
+//Every image of the manifest has to be in the folder with the same size and hash
+pub async fn check_images(archive: &Archive, images_dir: &str) -> Result<(), String> {
+    for image in &archive.images {
+        let data = match fs::read(format!("{}/{}", images_dir, image.file)).await {
+            Ok(data) => data,
+            Err(_) => {
+                return Err(format!(
+                    "The image {} is missing from the backup",
+                    image.file
+                ));
+            }
+        };
+        if data.len() as u64 != image.size || sha256_hex(&data) != image.sha256 {
+            return Err(format!(
+                "The image {} doesn't match the archive, it may be damaged",
+                image.file
+            ));
+        }
+    }
+    Ok(())
+}
+
+//Replaces the user's data with the archive in one transaction, the archive has to be validated first
+pub async fn restore_archive(pool: &PgPool, archive: &Archive) -> Result<(), String> {
+    let error = |context: &str, e: sqlx::Error| format!("{} error: {}", context, e);
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| error("Restore begin transaction", e))?;
+    //The search vector depends on the TEXT fields, it's created again once the fields changed
+    search::drop_search_vector(&mut tx)
+        .await
+        .map_err(|e| error("Drop search vector", e))?;
+    let old_fields = read_dynamic_fields(&mut *tx)
+        .await
+        .map_err(|e| error("Reading fields", e))?;
+    for (name, _) in old_fields {
+        sqlx::query(&format!(
+            r#"ALTER TABLE items DROP COLUMN IF EXISTS "{}""#,
+            name
+        ))
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| error("Drop field", e))?;
+    }
+    for table in [
+        "dynamic_fields",
+        "stock_thresholds",
+        "item_placements",
+        "items",
+        "containers",
+    ] {
+        sqlx::query(&format!("DELETE FROM {}", table))
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| error("Emptying the database", e))?;
+    }
+
+    for field in &archive.fields {
+        if let Some(definition) = column_definition(&field.data_type) {
+            add_item_column(&mut tx, &field.name, &definition)
+                .await
+                .map_err(|e| error("Add field", e))?;
+        }
+    }
+    for container in &archive.containers {
+        sqlx::query(
+            r#"
+            INSERT INTO containers (id, name, parent_id, corners, image_type, polygon, rotation)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+        )
+        .bind(&container.id)
+        .bind(&container.name)
+        .bind(&container.parent_id)
+        .bind(corners_to_string(&container.corners))
+        .bind(&container.image_type)
+        .bind(polygon_to_string(&container.polygon))
+        .bind(container.rotation)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| error("Insert container", e))?;
+    }
+
+    let mut column_clause = vec![
+        "id".to_string(),
+        "name".to_string(),
+        "image_type".to_string(),
+    ];
+    column_clause.extend(archive.fields.iter().map(|x| x.name.clone()));
+    let value_clause: Vec<String> = (1..=column_clause.len())
+        .map(|x| format!("${}", x))
+        .collect();
+    let items_query = format!(
+        r#"INSERT INTO items ("{}") VALUES ({})"#,
+        column_clause.join(r#"", ""#),
+        value_clause.join(", ")
+    );
+    for item in &archive.items {
+        let mut query = sqlx::query(&items_query)
+            .bind(&item.id)
+            .bind(&item.name)
+            .bind(&item.image_type);
+        for field in &archive.fields {
+            query = match field_value(item.values.get(&field.name), &field.data_type)? {
+                DynamicFieldValue::Text(val) => query.bind(val),
+                DynamicFieldValue::Integer(val) => query.bind(val),
+                DynamicFieldValue::Float(val) => query.bind(val),
+                DynamicFieldValue::Date(val) => query.bind(val),
+                DynamicFieldValue::DateTime(val) => query.bind(val),
+                DynamicFieldValue::Money(val) => query.bind(val),
+            };
+        }
+        query
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| error("Insert item", e))?;
+        for placement in &item.placements {
+            sqlx::query(
+                r#"
+                INSERT INTO item_placements (item_id, container_id, quantity) VALUES ($1, $2, $3)
+                "#,
+            )
+            .bind(&item.id)
+            .bind(&placement.container_id)
+            .bind(placement.quantity)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| error("Insert placement", e))?;
+        }
+        if let Some(threshold) = &item.stock_threshold {
+            sqlx::query(
+                r#"
+                INSERT INTO stock_thresholds (item_id, field_name, minimum) VALUES ($1, $2, $3)
+                "#,
+            )
+            .bind(&item.id)
+            .bind(&threshold.field_name)
+            .bind(threshold.minimum)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| error("Insert stock threshold", e))?;
+        }
+    }
+    search::create_search_vector(&mut tx)
+        .await
+        .map_err(|e| error("Create search vector", e))?;
+    tx.commit().await.map_err(|e| error("Restore commit", e))?;
+    Ok(())
+}
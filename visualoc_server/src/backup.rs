@@ -1,28 +1,32 @@
-use sqlx::Row;
-use std::{io::Write, path::Path};
-
-use axum::{
-    Extension,
-    extract::{Multipart, State},
-    http::StatusCode,
-    response::IntoResponse,
+use std::{
+    collections::HashMap,
+    io::{Cursor, Read, Write},
+    path::Path,
 };
-use sqlx::PgPool;
+
+use axum::{Extension, Json, extract::Multipart, http::StatusCode, response::IntoResponse};
+use serde::Serialize;
 use tokio::{
     fs::{self, File},
-    io::{AsyncReadExt, AsyncWriteExt},
-    process::Command,
+    io::AsyncReadExt,
 };
 use tokio_util::io::ReaderStream;
-use zip::{ZipWriter, write::FileOptions};
+use zip::{ZipArchive, ZipWriter, write::FileOptions};
 
-use crate::{AppState, add_image_to_database, users::UserContext};
+use crate::{
+    add_image_to_database,
+    archive::{self, Archive},
+    legacy_dump,
+    merge::{self, ConflictPolicy, MergeReport},
+    remove_image,
+    users::UserContext,
+};
 
 pub async fn serve_backup(
     Extension(user): Extension<UserContext>,
 ) -> Result<impl IntoResponse, StatusCode> {
     //Creates the backup and sends it to the user
-    //Pg_dump and images folder(without the smaller ones) in a zip sent to the user
+    //The archive and images folder(without the smaller ones) in a zip sent to the user
     let temp_path = "/app/users/".to_owned() + &user.user_id + "/temp";
     //Remove the backup folder in case there are leftover files from a previous operation
     let _ = fs::remove_dir_all(temp_path.clone()).await;
@@ -34,49 +38,47 @@ pub async fn serve_backup(
         )
             .into_response());
     }
-    //Dump the users database
-    let dump_file = temp_path.clone() + "/dump_file.sql";
-    match Command::new("pg_dump")
-        .arg("-h")
-        .arg("db")
-        .arg(format!("--dbname={}", &user.user_id))
-        .arg("--file")
-        .arg(&dump_file)
-        .arg("--format=plain")
-        .output()
-        .await
-    {
-        Ok(output) => {
-            println!("Output is : {:?}", output);
-            println!("output status: {:?}", output.status);
-            if !output.status.success() {
-                println!("Output failed");
-                return Ok((StatusCode::INTERNAL_SERVER_ERROR, "Failed output").into_response());
-            }
+    //Write the users database as the JSON archive
+    let images_path = "/app/users/".to_owned() + &user.user_id + "/images";
+    let archive = match archive::build_archive(&user.db_pool, &images_path).await {
+        Ok(archive) => archive,
+        Err(e) => {
+            println!("Build archive error: {}", e);
+            return Ok((StatusCode::INTERNAL_SERVER_ERROR, e).into_response());
         }
+    };
+    let archive_json = match serde_json::to_vec_pretty(&archive) {
+        Ok(archive_json) => archive_json,
         Err(e) => {
-            println!("Output error: {}", e);
+            println!("Serialize archive error: {}", e);
             return Ok((
                 StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Output error: {}", e),
+                format!("Serialize archive error: {}", e),
             )
                 .into_response());
         }
     };
+    if let Err(e) = fs::write(temp_path.clone() + "/archive.json", archive_json).await {
+        println!("Write archive error: {}", e);
+        return Ok((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Write archive error: {}", e),
+        )
+            .into_response());
+    }
 
     //Copy over the users images
-    let mut files_in_dir =
-        match fs::read_dir("/app/users/".to_owned() + &user.user_id + "/images").await {
-            Ok(files) => files,
-            Err(e) => {
-                println!("Reading files error: {}", e);
-                return Ok((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    format!("Reading files error: {}", e),
-                )
-                    .into_response());
-            }
-        };
+    let mut files_in_dir = match fs::read_dir(&images_path).await {
+        Ok(files) => files,
+        Err(e) => {
+            println!("Reading files error: {}", e);
+            return Ok((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Reading files error: {}", e),
+            )
+                .into_response());
+        }
+    };
 
     while let Ok(Some(file)) = files_in_dir.next_entry().await {
         let path = file.path();
@@ -209,219 +211,417 @@ async fn add_dir_to_zip<T: Write + std::io::Seek>(
     Ok(())
 }
 
-pub async fn restore_to_user_backup(
-    State(state): State<AppState>,
-    Extension(user): Extension<UserContext>,
-    mut multipart: Multipart,
-) -> Result<(), (StatusCode, String)> {
-    println!("In backup");
-    let temp_path = "/app/users/".to_owned() + &user.user_id + "/temp";
+//Limits against zip bombs, the sizes written in a zip can't be trusted so the unpacked bytes are counted
+const MAX_BACKUP_ENTRIES: usize = 100_000;
+const MAX_UNPACKED_SIZE: u64 = 2 * 1024 * 1024 * 1024;
+const MAX_COMPRESSION_RATIO: u64 = 100;
+
+//The most an entry may unpack to, the compressed size comes from the zip and can be anything
+fn ratio_limit(compressed_size: u64) -> u64 {
+    compressed_size
+        .saturating_mul(MAX_COMPRESSION_RATIO)
+        .max(1024 * 1024)
+}
+
+//Unpacks the zip made by serve_backup into the temp folder, only the files a backup has are accepted
+fn unpack_backup(data: &[u8], temp_path: &str) -> Result<(), String> {
+    let mut zip = ZipArchive::new(Cursor::new(data))
+        .map_err(|e| format!("The file isn't a valid zip: {}", e))?;
+    if zip.len() > MAX_BACKUP_ENTRIES {
+        return Err(format!(
+            "The backup has more than {} files",
+            MAX_BACKUP_ENTRIES
+        ));
+    }
+    let mut unpacked: u64 = 0;
+    for index in 0..zip.len() {
+        let mut entry = zip
+            .by_index(index)
+            .map_err(|e| format!("The backup couldn't be read: {}", e))?;
+        if entry.is_dir() {
+            continue;
+        }
+        //enclosed_name refuses absolute paths and any .. that would leave the folder
+        let path = match entry.enclosed_name() {
+            Some(path) if !entry.is_symlink() => path,
+            _ => return Err(format!("The backup has an unsafe path: {}", entry.name())),
+        };
+        let name = path.to_string_lossy().into_owned();
+        let expected = name == "archive.json"
+            || name == "dump_file.sql"
+            || name
+                .strip_prefix("images/")
+                .is_some_and(archive::is_image_file_name);
+        if !expected {
+            return Err(format!("The backup has an unexpected file: {}", name));
+        }
+        //An entry can't unpack past what is left of the total or past its own ratio, files up to 1 MiB are always allowed
+        let remaining = MAX_UNPACKED_SIZE - unpacked;
+        let ratio_limit = ratio_limit(entry.compressed_size());
+        let limit = remaining.min(ratio_limit);
+        let mut file = std::fs::File::create(Path::new(temp_path).join(&path))
+            .map_err(|e| format!("Unpacking {} error: {}", name, e))?;
+        let written = std::io::copy(&mut (&mut entry).take(limit + 1), &mut file)
+            .map_err(|e| format!("Unpacking {} error: {}", name, e))?;
+        if written > remaining {
+            return Err(format!(
+                "The backup unpacks to more than {} GB",
+                MAX_UNPACKED_SIZE / 1024 / 1024 / 1024
+            ));
+        }
+        if written > ratio_limit {
+            return Err(format!("The file {} in the backup is a zip bomb", name));
+        }
+        unpacked += written;
+    }
+    Ok(())
+}
+
+//What a restore brought back, shown to the user once it's done
+#[derive(Serialize, Default)]
+pub struct RestoreReport {
+    fields: usize,
+    containers: usize,
+    items: usize,
+    images: usize,
+    failed_images: Vec<String>, //Files that couldn't be added back, their items keep no image
+    from_dump: bool,            //The backup predates the archive and was converted
+}
+
+//Backups are read as an archive, older ones only have a dump that gets converted to one
+//The archive is validated and its images checked against their hashes
+async fn read_backup(temp_path: &str) -> Result<(Archive, bool), (StatusCode, String)> {
+    let (mut archive, from_dump) = match fs::read(temp_path.to_owned() + "/archive.json").await {
+        Ok(data) => match serde_json::from_slice::<Archive>(&data) {
+            Ok(archive) => (archive, false),
+            Err(e) => {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("The archive couldn't be read: {}", e),
+                ));
+            }
+        },
+        Err(_) => match fs::read(temp_path.to_owned() + "/dump_file.sql").await {
+            Ok(data) => (
+                legacy_dump::archive_from_dump(&String::from_utf8_lossy(&data))
+                    .map_err(|e| (StatusCode::BAD_REQUEST, e))?,
+                true,
+            ),
+            Err(_) => {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    "The backup has no archive.json or dump_file.sql".to_string(),
+                ));
+            }
+        },
+    };
+    let images_path = temp_path.to_owned() + "/images";
+    //Dumps have no manifest, the images in the zip are taken as they are
+    if from_dump {
+        archive.images = archive::image_manifest(&images_path)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    }
+    archive::validate(&archive).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    archive::check_images(&archive, &images_path)
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    Ok((archive, from_dump))
+}
+
+//Unpacks the backup.zip of the "backup" part into an empty temp folder, the other parts are returned as text
+async fn receive_backup(
+    temp_path: &str,
+    multipart: &mut Multipart,
+) -> Result<HashMap<String, String>, (StatusCode, String)> {
     //Remove the backup folder in case there are leftover files from a previous operation
-    let _ = fs::remove_dir_all(temp_path.clone()).await;
+    let _ = fs::remove_dir_all(temp_path).await;
     //Create the backup folder
-    if let Err(e) = fs::create_dir_all(temp_path.clone() + "/images").await {
+    if let Err(e) = fs::create_dir_all(temp_path.to_owned() + "/images").await {
         println!("Failed to create dir images: {:?}", e);
         return Err((
             StatusCode::INTERNAL_SERVER_ERROR,
             format!("Failed to create backup directory: {}", e),
         ));
     }
-    while let Some(field) = multipart.next_field().await.map_err(|e| {
-        println!("Upload image error {}", e);
-        (StatusCode::BAD_REQUEST, e.to_string())
-    })? {
-        if let Some(file_name) = field.name() {
-            let filename: Vec<&str> = file_name.split(".").collect();
-            let file_path = if filename.len() == 2 {
-                let file_extension = filename[1].to_owned();
-                if file_extension == "webp"
-                    || file_extension == "jpg"
-                    || file_extension == "png"
-                    || file_extension == "jpeg"
-                    || file_extension == "gif"
-                {
-                    temp_path.clone() + "/images/" + file_name
-                } else if file_extension == "sql" {
-                    temp_path.clone() + "/" + file_name
-                } else {
-                    println!("Error 1, File extension: {}", file_extension);
-                    return Err((
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        "Failed to read file name".to_string(),
-                    ));
-                }
-            } else {
-                println!("Error 2");
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Failed to read file name".to_string(),
-                ));
-            };
-            let data = match field.bytes().await {
-                Ok(data) => data,
+    let mut options = HashMap::new();
+    let mut data = None;
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => {
+                println!("Upload backup error {}", e);
+                return Err((StatusCode::BAD_REQUEST, e.to_string()));
+            }
+        };
+        let name = field.name().unwrap_or_default().to_string();
+        if name == "backup" {
+            match field.bytes().await {
+                Ok(bytes) => data = Some(bytes),
                 Err(e) => {
-                    println!("Error 3");
+                    println!("Upload backup bytes error {}", e);
                     return Err((StatusCode::BAD_REQUEST, e.to_string()));
                 }
-            };
-
-            // Handle potential folder structure from the filename
-            if let Some(parent_dir) = std::path::Path::new(&file_path).parent() {
-                if let Err(e) = fs::create_dir_all(parent_dir).await {
-                    println!("Error 4");
-                    return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
-                }
             }
-
-            // Write the file
-            let mut file = match fs::File::create(&file_path).await {
-                Ok(file) => file,
+        } else {
+            match field.text().await {
+                Ok(text) => {
+                    options.insert(name, text);
+                }
                 Err(e) => {
-                    println!("Error 5");
-                    return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+                    println!("Upload backup field error {}", e);
+                    return Err((StatusCode::BAD_REQUEST, e.to_string()));
                 }
-            };
-            if let Err(e) = file.write(&data).await {
-                println!("Error 6");
-                return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
             }
         }
     }
-    if let Err(e) = restore_database(
-        &user.user_id,
-        &(temp_path.clone() + "/dump_file.sql"),
-        &state.master_pool,
-        &user.db_pool,
-    )
-    .await
-    {
+    let Some(data) = data else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "No backup file in the request".to_string(),
+        ));
+    };
+    let unpack_path = temp_path.to_owned();
+    match tokio::task::spawn_blocking(move || unpack_backup(&data, &unpack_path)).await {
+        Ok(Ok(())) => Ok(options),
+        Ok(Err(e)) => Err((StatusCode::BAD_REQUEST, e)),
+        Err(e) => {
+            println!("Unpack backup task error {}", e);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to unpack the backup".to_string(),
+            ))
+        }
+    }
+}
+
+//Replaces all the data of the user with the backup.zip made by serve_backup
+pub async fn restore_to_user_backup(
+    Extension(user): Extension<UserContext>,
+    mut multipart: Multipart,
+) -> Result<Json<RestoreReport>, (StatusCode, String)> {
+    println!("In backup");
+    let temp_path = "/app/users/".to_owned() + &user.user_id + "/temp";
+    //The unpacked backup is removed however the restore ends
+    let result = restore_from_temp(&user, &temp_path, &mut multipart).await;
+    let _ = fs::remove_dir_all(temp_path).await;
+    result.map(Json)
+}
+
+async fn restore_from_temp(
+    user: &UserContext,
+    temp_path: &str,
+    multipart: &mut Multipart,
+) -> Result<RestoreReport, (StatusCode, String)> {
+    receive_backup(temp_path, multipart).await?;
+    let (archive, from_dump) = read_backup(temp_path).await?;
+    if let Err(e) = archive::restore_archive(&user.db_pool, &archive).await {
+        println!("Restore archive error: {}", e);
         return Err((
             StatusCode::INTERNAL_SERVER_ERROR,
             format!("Failed to restore from backup: {}", e),
         ));
     }
-    tokio::spawn(async move {
-        let _ = restore_images(&user).await;
-    });
-    Ok(())
+    //The images of the replaced data are removed, the backup's are added back
+    let images_path = "/app/users/".to_owned() + &user.user_id + "/images";
+    let _ = fs::remove_dir_all(images_path.clone()).await;
+    let _ = fs::create_dir_all(images_path.clone() + "/small").await;
+    let _ = fs::create_dir(images_path + "/medium").await;
+    let images = archive
+        .images
+        .iter()
+        .filter_map(|image| {
+            let (id, _) = image.file.split_once('.')?;
+            Some((image.file.clone(), id.to_string()))
+        })
+        .collect();
+    let failed_images = add_images(user, temp_path, images).await;
+    Ok(RestoreReport {
+        fields: archive.fields.len(),
+        containers: archive.containers.len(),
+        items: archive.items.len(),
+        images: archive.images.len() - failed_images.len(),
+        failed_images,
+        from_dump,
+    })
 }
 
-async fn restore_database(
-    db_name: &str,
-    dump_file_path: &str,
-    master_pool: &PgPool,
-    user_pool: &PgPool,
-) -> Result<(), String> {
-    println!("Gets in restore");
-    //Get the user's username
-    let username_row = match sqlx::query("SELECT username FROM users where id = $1")
-        .bind(&db_name)
-        .fetch_one(master_pool)
-        .await
-    {
-        Ok(username_row) => username_row,
-        Err(e) => {
-            return Err(format!(
-                "Couldn't find username when restoring database: {}",
-                e
-            ));
+//Adds the files of the temp folder as the images of the given ids, they were checked against the hashes
+//Returns the files that couldn't be added
+async fn add_images(
+    user: &UserContext,
+    temp_path: &str,
+    images: Vec<(String, String)>,
+) -> Vec<String> {
+    let mut failed_images = Vec::new();
+    for (file, id) in images {
+        let Some((_, ext)) = file.split_once('.') else {
+            continue;
+        };
+        let added = match fs::read(temp_path.to_owned() + "/images/" + &file).await {
+            Ok(data) => add_image_to_database(&data.into(), user, &id, ext).await,
+            Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+        };
+        if let Err((_, e)) = added {
+            println!("Restore image {} error: {}", file, e);
+            failed_images.push(file);
         }
-    };
-    let username: &str = username_row.get("username");
-    //Empty the database by dropping and recreating the public schema
-    if let Err(e) = sqlx::query("DROP SCHEMA public CASCADE;")
-        .execute(user_pool)
-        .await
-    {
-        println!("Drop schema error: {}", e);
-        return Err(format!("Drop schema error: {}", e));
-    }
-    if let Err(e) = sqlx::query("CREATE SCHEMA public;")
-        .execute(user_pool)
-        .await
-    {
-        println!("Create schema error: {}", e);
-        return Err(format!("Create schema error: {}", e));
     }
-
-    if let Err(e) = sqlx::query(&format!(r#"GRANT ALL ON SCHEMA public TO "{}";"#, username))
-        .execute(user_pool)
-        .await
-    {
-        println!("Grant schema error: {}", e);
-        return Err(format!("Grant schema error: {}", e));
-    }
-    // Run psql to restore the database
-    let output = Command::new("psql")
-        .arg("-h")
-        .arg("db")
-        .arg("-d")
-        .arg(db_name)
-        .arg("-f")
-        .arg(dump_file_path)
-        .output()
-        .await;
-    return match output {
-        Ok(output) => {
-            println!("{:?}", output);
-            if output.status.success() {
-                println!("Database restored successfully");
-                Ok(())
-            } else {
-                return Err(format!(
-                    "Restore database output error: {}",
-                    String::from_utf8_lossy(&output.stderr)
-                ));
-            }
-        }
-        Err(e) => {
-            println!("error in restore database: {}", e);
-            return Err(format!("Restore database error: {}", e));
-        }
-    };
+    failed_images
 }
 
-async fn restore_images(user: &UserContext) -> Result<(), String> {
+//Adds a backup.zip to the user's data, the parts policy and dry_run pick how conflicts are resolved
+//and whether anything is written, a dry run only reports what the merge would change
+pub async fn merge_backup(
+    Extension(user): Extension<UserContext>,
+    mut multipart: Multipart,
+) -> Result<Json<MergeReport>, (StatusCode, String)> {
+    println!("in merge backup");
     let temp_path = "/app/users/".to_owned() + &user.user_id + "/temp";
-    let images_path = "/app/users/".to_owned() + &user.user_id + "/images";
-    let image_extensions = vec!["png", "jpg", "jpeg", "gif", "webp"];
-    //Remove the backup folder in case there are leftover files from a previous operation
-    let _ = fs::remove_dir_all(images_path.clone()).await;
-    let _ = fs::create_dir_all(images_path.clone() + "/small").await;
-    let _ = fs::create_dir(images_path.clone() + "/medium").await;
-    let mut images = match fs::read_dir(temp_path.clone() + "/images").await {
-        Ok(images) => images,
+    //Like the restore, the unpacked backup is removed however the merge ends
+    let result = merge_from_temp(&user, &temp_path, &mut multipart).await;
+    let _ = fs::remove_dir_all(temp_path).await;
+    result.map(Json)
+}
+
+async fn merge_from_temp(
+    user: &UserContext,
+    temp_path: &str,
+    multipart: &mut Multipart,
+) -> Result<MergeReport, (StatusCode, String)> {
+    let options = receive_backup(temp_path, multipart).await?;
+    let policy = match options.get("policy").map(String::as_str) {
+        Some("Skip") => ConflictPolicy::Skip,
+        Some("Overwrite") => ConflictPolicy::Overwrite,
+        Some("Duplicate") => ConflictPolicy::Duplicate,
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "The policy has to be Skip, Overwrite or Duplicate".to_string(),
+            ));
+        }
+    };
+    let dry_run = options.get("dry_run").is_some_and(|x| x == "true");
+    let (archive, from_dump) = read_backup(temp_path).await?;
+    let mut plan = match merge::plan_merge(&user.db_pool, &archive, policy).await {
+        Ok(plan) => plan,
         Err(e) => {
-            println!("Error when reading images: {}", e);
-            return Err(format!("Error when reading images: {}", e));
+            println!("Plan merge error: {}", e);
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to compare the backup with your data".to_string(),
+            ));
         }
     };
-    while let Ok(Some(entry)) = images.next_entry().await {
-        let path = entry.path();
-        if path.is_file() {
-            if let Some(ext) = path.extension() {
-                let ext = ext.to_str().unwrap_or("").to_lowercase();
-                if image_extensions.contains(&ext.as_str()) {
-                    if let Some(name) = path.clone().file_name() {
-                        if let Some(name) = name.to_str() {
-                            let split_string: Vec<&str> = name.split(".").collect();
-                            let item_id = split_string[0].to_string();
-                            let data = match fs::read(path).await {
-                                Ok(data) => data,
-                                Err(e) => {
-                                    println!("Error when reading image: {}", e);
-                                    return Err(format!("Error when reading image: {}", e));
-                                }
-                            };
-                            let _ =
-                                add_image_to_database(&data.into(), &user, &item_id, &ext).await;
-                        }
-                    }
-                }
+    plan.report.dry_run = dry_run;
+    plan.report.from_dump = from_dump;
+    if !dry_run {
+        let unused_images = match merge::apply_merge(&user.db_pool, &archive, &plan).await {
+            Ok(unused_images) => unused_images,
+            Err(e) => {
+                println!("Apply merge error: {}", e);
+                return Err((
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to merge the backup: {}", e),
+                ));
             }
+        };
+        //The overwritten images are removed before the backup's are added, they can share ids
+        for image_id in unused_images {
+            remove_image(&user.user_id, &image_id).await;
         }
+        plan.report.failed_images = add_images(user, temp_path, plan.images(&archive)).await;
+        plan.report.images -= plan.report.failed_images.len();
+    }
+    Ok(plan.report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zip::{CompressionMethod, write::SimpleFileOptions};
+
+    //A temp folder of its own for each test, with the images folder receive_backup creates
+    fn temp_folder() -> String {
+        let path = std::env::temp_dir().join(format!("visualoc_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(path.join("images")).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn unpack(build: impl FnOnce(&mut ZipWriter<Cursor<Vec<u8>>>)) -> Result<(), String> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        build(&mut zip);
+        let data = zip.finish().unwrap().into_inner();
+        let temp_path = temp_folder();
+        let result = unpack_backup(&data, &temp_path);
+        std::fs::remove_dir_all(&temp_path).unwrap();
+        result
+    }
+
+    #[test]
+    fn unpacks_a_backup() {
+        let result = unpack(|zip| {
+            zip.start_file("archive.json", SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(b"{}").unwrap();
+            zip.start_file("images/item1.png", SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(b"png").unwrap();
+        });
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test]
+    fn unpacks_images_with_an_uppercase_extension() {
+        let result = unpack(|zip| {
+            zip.start_file("archive.json", SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(b"{}").unwrap();
+            zip.start_file("images/item1.JPG", SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(b"jpg").unwrap();
+        });
+        assert_eq!(result, Ok(()));
+        assert!(archive::is_image_type("JPG"));
+        assert!(!archive::is_image_type("exe"));
+    }
+
+    #[test]
+    fn rejects_path_traversal() {
+        let result = unpack(|zip| {
+            zip.start_file("../../other/archive.json", SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(b"{}").unwrap();
+        });
+        assert!(result.unwrap_err().contains("unsafe path"));
+    }
+
+    #[test]
+    fn rejects_symlinks() {
+        let result = unpack(|zip| {
+            zip.add_symlink("archive.json", "/etc/passwd", SimpleFileOptions::default())
+                .unwrap();
+        });
+        assert!(result.unwrap_err().contains("unsafe path"));
+    }
+
+    #[test]
+    fn rejects_zip_bombs() {
+        let result = unpack(|zip| {
+            let options =
+                SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+            zip.start_file("dump_file.sql", options).unwrap();
+            zip.write_all(&vec![0; 8 * 1024 * 1024]).unwrap();
+        });
+        assert!(result.unwrap_err().contains("zip bomb"));
+    }
+
+    #[test]
+    fn limits_entries_with_a_huge_compressed_size() {
+        assert_eq!(ratio_limit(0), 1024 * 1024);
+        assert_eq!(ratio_limit(1024 * 1024), 100 * 1024 * 1024);
+        assert_eq!(ratio_limit(u64::MAX), u64::MAX);
+        assert_eq!(ratio_limit(u64::MAX / 10), u64::MAX);
     }
-    //Remove the temp folder
-    let _ = fs::remove_dir_all(temp_path).await;
-    return Ok(());
 }
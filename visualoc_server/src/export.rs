@@ -0,0 +1,342 @@
+//Items exported as CSV or XLSX, with the same filters and order as the search on the home page
+//Every field except galleries gets a column, the location and the containers below it are resolved from the placements
+//An item placed in several containers lists all of them, separated by semicolons
+
+use axum::{
+    Extension, Json,
+    http::{StatusCode, header},
+    response::IntoResponse,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use rust_decimal::Decimal;
+use rust_xlsxwriter::{Format, Workbook, XlsxError};
+use serde::Deserialize;
+use sqlx::{Row, postgres::PgRow};
+use std::collections::HashMap;
+
+use crate::{
+    DataType, ITEM_QUANTITY_COLUMN, ItemSearch, SearchItemsRequest, item_search,
+    read_dynamic_fields, search, stock::formula_safe, users::UserContext,
+};
+
+#[derive(Deserialize)]
+enum ExportFormat {
+    Csv,
+    Xlsx,
+}
+
+#[derive(Deserialize)]
+pub struct ExportItemsRequest {
+    #[serde(flatten)]
+    query: SearchItemsRequest, //The offset and the limit are ignored, every match is exported
+    format: ExportFormat,
+}
+
+//A value as it is written to the file, the XLSX keeps the type and the CSV writes it as text
+enum Cell {
+    Empty,
+    Text(String),
+    Number(f64),
+    Bool(bool),
+    Percentage(f32), //From 0 to 100 like it's stored
+    Date(NaiveDate),
+    DateTime(DateTime<Utc>),
+    Money(Decimal),
+}
+
+fn read_cell(row: &PgRow, column: &str, data_type: &DataType) -> Cell {
+    match data_type {
+        DataType::String | DataType::Text | DataType::List(_) | DataType::Gallery => row
+            .try_get::<Option<String>, _>(column)
+            .ok()
+            .flatten()
+            .map_or(Cell::Empty, Cell::Text),
+        DataType::Integer => row
+            .try_get::<Option<i32>, _>(column)
+            .ok()
+            .flatten()
+            .map_or(Cell::Empty, |x| Cell::Number(x.into())),
+        DataType::Bool => {
+            Cell::Bool(row.try_get::<Option<i32>, _>(column).ok().flatten() == Some(1))
+        }
+        DataType::Float => row
+            .try_get::<Option<f32>, _>(column)
+            .ok()
+            .flatten()
+            .map_or(Cell::Empty, |x| Cell::Number(x.into())),
+        DataType::Percentage => row
+            .try_get::<Option<f32>, _>(column)
+            .ok()
+            .flatten()
+            .map_or(Cell::Empty, Cell::Percentage),
+        DataType::Date => row
+            .try_get::<Option<NaiveDate>, _>(column)
+            .ok()
+            .flatten()
+            .map_or(Cell::Empty, Cell::Date),
+        DataType::DateTime => row
+            .try_get::<Option<DateTime<Utc>>, _>(column)
+            .ok()
+            .flatten()
+            .map_or(Cell::Empty, Cell::DateTime),
+        DataType::Money { .. } => row
+            .try_get::<Option<Decimal>, _>(column)
+            .ok()
+            .flatten()
+            .map_or(Cell::Empty, Cell::Money),
+    }
+}
+
+fn cell_text(cell: &Cell) -> String {
+    match cell {
+        Cell::Empty => String::new(),
+        Cell::Text(x) => x.clone(),
+        Cell::Number(x) => x.to_string(),
+        Cell::Bool(x) => if *x { "TRUE" } else { "FALSE" }.to_string(),
+        Cell::Percentage(x) => x.to_string(),
+        Cell::Date(x) => x.format("%Y-%m-%d").to_string(),
+        Cell::DateTime(x) => x.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        Cell::Money(x) => format!("{:.2}", x),
+    }
+}
+
+//The headers and a row of cells per item
+struct Table {
+    headers: Vec<String>,
+    types: Vec<Option<DataType>>, //None for the columns that aren't fields
+    rows: Vec<Vec<Cell>>,
+}
+
+fn to_csv(table: &Table) -> Result<Vec<u8>, csv::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(&table.headers)?;
+    for row in &table.rows {
+        //Only text can start like a formula, a negative number stays a number
+        writer.write_record(row.iter().map(|cell| match cell {
+            Cell::Text(x) => formula_safe(x),
+            cell => cell_text(cell),
+        }))?;
+    }
+    writer
+        .into_inner()
+        .map_err(|e| csv::Error::from(e.into_error()))
+}
+
+fn to_xlsx(table: &Table) -> Result<Vec<u8>, XlsxError> {
+    let mut workbook = Workbook::new();
+    let worksheet = workbook.add_worksheet();
+    worksheet.set_name("Items")?;
+    let bold = Format::new().set_bold();
+    for (column, header) in table.headers.iter().enumerate() {
+        worksheet.write_string_with_format(0, column as u16, header, &bold)?;
+    }
+    //Money columns show their currency, the other formats are the same for every column
+    let formats: Vec<Format> = table
+        .types
+        .iter()
+        .map(|data_type| match data_type {
+            Some(DataType::Percentage) => Format::new().set_num_format("0.00%"),
+            Some(DataType::Date) => Format::new().set_num_format("yyyy-mm-dd"),
+            Some(DataType::DateTime) => Format::new().set_num_format("yyyy-mm-dd hh:mm:ss"),
+            Some(DataType::Money { currency }) => {
+                Format::new().set_num_format(format!("#,##0.00 \"{}\"", currency))
+            }
+            _ => Format::new(),
+        })
+        .collect();
+    for (index, row) in table.rows.iter().enumerate() {
+        let row_number = index as u32 + 1;
+        for (column, cell) in row.iter().enumerate() {
+            let format = &formats[column];
+            let column = column as u16;
+            match cell {
+                Cell::Empty => continue,
+                Cell::Text(x) => worksheet.write_string(row_number, column, x)?,
+                Cell::Number(x) => worksheet.write_number(row_number, column, *x)?,
+                Cell::Bool(x) => worksheet.write_boolean(row_number, column, *x)?,
+                //Excel percentages are fractions, 45 is written as 0.45 and shown as 45%
+                Cell::Percentage(x) => worksheet.write_number_with_format(
+                    row_number,
+                    column,
+                    f64::from(*x) / 100.0,
+                    format,
+                )?,
+                Cell::Date(x) => {
+                    worksheet.write_datetime_with_format(row_number, column, x, format)?
+                }
+                Cell::DateTime(x) => worksheet.write_datetime_with_format(
+                    row_number,
+                    column,
+                    x.naive_utc(),
+                    format,
+                )?,
+                Cell::Money(x) => worksheet.write_number_with_format(
+                    row_number,
+                    column,
+                    f64::try_from(*x).unwrap_or_default(),
+                    format,
+                )?,
+            };
+        }
+    }
+    worksheet.set_freeze_panes(1, 0)?;
+    worksheet.autofit();
+    workbook.save_to_buffer()
+}
+
+//For each item the location and the path below it of every container it's placed in
+async fn item_locations(
+    pool: &sqlx::PgPool,
+    item_ids: &[String],
+) -> Result<HashMap<String, Vec<(String, String)>>, sqlx::Error> {
+    //The names are collected from the container up to the root, the root's name is left out
+    let rows = sqlx::query(
+        r#"
+        WITH RECURSIVE up AS (
+            SELECT p.item_id, c.parent_id, ARRAY[c.name] AS names, 0 AS depth
+            FROM item_placements p JOIN containers c ON c.id = p.container_id
+            WHERE p.item_id = ANY($1)
+            UNION ALL
+            SELECT up.item_id, c.parent_id, c.name || up.names, up.depth + 1
+            FROM containers c JOIN up ON c.id = up.parent_id
+            WHERE up.depth < 100
+        )
+        SELECT item_id, COALESCE(names[2], '') AS location,
+        array_to_string(names[3:], ' / ') AS container
+        FROM up WHERE parent_id IS NULL
+        ORDER BY item_id, location, container
+        "#,
+    )
+    .bind(item_ids)
+    .fetch_all(pool)
+    .await?;
+    let mut locations: HashMap<String, Vec<(String, String)>> = HashMap::new();
+    for row in rows {
+        locations
+            .entry(row.get("item_id"))
+            .or_default()
+            .push((row.get("location"), row.get("container")));
+    }
+    Ok(locations)
+}
+
+pub async fn export_items(
+    Extension(user): Extension<UserContext>,
+    Json(payload): Json<ExportItemsRequest>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    println!("in export items");
+    let fields = match read_dynamic_fields(&*user.db_pool).await {
+        Ok(fields) => fields,
+        Err(e) => {
+            println!("Export items fields error {}", e);
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to read fields".to_string(),
+            ));
+        }
+    };
+    let ItemSearch {
+        where_clause,
+        params,
+        order_by,
+        ..
+    } = item_search(payload.query, &fields)?;
+    let query = format!(
+        r#"SELECT *, {} FROM items WHERE {} ORDER BY {}, id"#,
+        ITEM_QUANTITY_COLUMN, where_clause, order_by
+    );
+    let rows = match search::bind_params(sqlx::query(&query), &params)
+        .fetch_all(&*user.db_pool)
+        .await
+    {
+        Ok(rows) => rows,
+        Err(e) => {
+            println!("Export items error {}", e);
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to read the items".to_string(),
+            ));
+        }
+    };
+    let item_ids: Vec<String> = rows.iter().map(|row| row.get("id")).collect();
+    let locations = match item_locations(&user.db_pool, &item_ids).await {
+        Ok(locations) => locations,
+        Err(e) => {
+            println!("Export items locations error {}", e);
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to read the locations".to_string(),
+            ));
+        }
+    };
+
+    let fields: Vec<(String, DataType)> = fields
+        .into_iter()
+        .filter(|(_, data_type)| !matches!(data_type, DataType::Gallery))
+        .collect();
+    let mut table = Table {
+        headers: vec!["Name".to_string(), "Quantity".to_string()],
+        types: vec![None, None],
+        rows: Vec::new(),
+    };
+    for (name, data_type) in &fields {
+        table.headers.push(name.clone());
+        table.types.push(Some(data_type.clone()));
+    }
+    table.headers.push("Location".to_string());
+    table.headers.push("Container".to_string());
+    table.types.extend([None, None]);
+    for row in &rows {
+        let mut cells = vec![
+            Cell::Text(row.get("name")),
+            Cell::Number(row.get::<i64, _>("total_quantity") as f64),
+        ];
+        for (name, data_type) in &fields {
+            cells.push(read_cell(row, name, data_type));
+        }
+        let placements = locations
+            .get(&row.get::<String, _>("id"))
+            .map_or(&[][..], |x| x.as_slice());
+        //The placements stay in the same order in both columns, an item directly in its location has no container
+        let join = |names: Vec<&str>| match names.iter().all(|x| x.is_empty()) {
+            true => Cell::Empty,
+            false => Cell::Text(names.join("; ")),
+        };
+        cells.push(join(placements.iter().map(|(x, _)| x.as_str()).collect()));
+        cells.push(join(placements.iter().map(|(_, x)| x.as_str()).collect()));
+        table.rows.push(cells);
+    }
+
+    let (content_type, file_name, file) = match payload.format {
+        ExportFormat::Csv => (
+            "text/csv; charset=utf-8",
+            "items.csv",
+            to_csv(&table).map_err(|e| e.to_string()),
+        ),
+        ExportFormat::Xlsx => (
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            "items.xlsx",
+            to_xlsx(&table).map_err(|e| e.to_string()),
+        ),
+    };
+    match file {
+        Ok(file) => Ok((
+            [
+                (header::CONTENT_TYPE, content_type),
+                (
+                    header::CONTENT_DISPOSITION,
+                    &format!("attachment; filename=\"{}\"", file_name) as &str,
+                ),
+            ],
+            file,
+        )
+            .into_response()),
+        Err(e) => {
+            println!("Export items file error {}", e);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to write the file".to_string(),
+            ))
+        }
+    }
+}
@@ -0,0 +1,261 @@
+//Gallery fields hold an ordered list of images per item
+//The TEXT column stores the entries joined with '@', each entry is "{image_id}.{extension}"
+//The image files are stored and served like any other image, under their own id
+
+use axum::{Extension, Json, extract::Multipart, http::StatusCode};
+use sqlx::{PgConnection, Row};
+use tokio::fs;
+use uuid::Uuid;
+
+use crate::{
+    DataType, read_dynamic_fields, remove_image, save_resized_images, users::UserContext,
+};
+
+//Image ids of a gallery value, without the extensions
+pub fn entry_ids(value: &str) -> Vec<String> {
+    value
+        .split('@')
+        .filter(|x| !x.is_empty())
+        .map(|x| x.split('.').next().unwrap_or_default().to_string())
+        .collect()
+}
+
+async fn gallery_field_names(conn: &mut PgConnection) -> Result<Vec<String>, sqlx::Error> {
+    Ok(read_dynamic_fields(&mut *conn)
+        .await?
+        .into_iter()
+        .filter(|(_, data_type)| matches!(data_type, DataType::Gallery))
+        .map(|(name, _)| name)
+        .collect())
+}
+
+//Every gallery entry of the given items, across all gallery fields
+pub async fn item_gallery_entries(
+    conn: &mut PgConnection,
+    item_ids: &[String],
+) -> Result<Vec<String>, sqlx::Error> {
+    let mut entries = Vec::new();
+    for field in gallery_field_names(&mut *conn).await? {
+        let rows = sqlx::query(&format!(
+            r#"SELECT COALESCE("{}", '') AS gallery FROM items WHERE id = ANY($1)"#,
+            field
+        ))
+        .bind(item_ids)
+        .fetch_all(&mut *conn)
+        .await?;
+        for row in rows {
+            entries.extend(
+                row.get::<String, _>("gallery")
+                    .split('@')
+                    .filter(|x| !x.is_empty())
+                    .map(String::from),
+            );
+        }
+    }
+    Ok(entries)
+}
+
+//Every gallery image of the given items, across all gallery fields
+pub async fn item_gallery_images(
+    conn: &mut PgConnection,
+    item_ids: &[String],
+) -> Result<Vec<String>, sqlx::Error> {
+    Ok(item_gallery_entries(conn, item_ids)
+        .await?
+        .iter()
+        .flat_map(|entry| entry_ids(entry))
+        .collect())
+}
+
+//An edited gallery value with only the entries the item already has, in their new order
+//Images only get into a gallery by being uploaded to its item, an edit can reorder and remove them but not add others
+pub fn owned_entries(value: &str, item_entries: &[String]) -> String {
+    value
+        .split('@')
+        .filter(|x| item_entries.iter().any(|entry| entry == x))
+        .collect::<Vec<_>>()
+        .join("@")
+}
+
+//Every image of one gallery field, used before the field gets deleted
+pub async fn column_gallery_images(
+    conn: &mut PgConnection,
+    column_name: &str,
+) -> Result<Vec<String>, sqlx::Error> {
+    if !gallery_field_names(&mut *conn)
+        .await?
+        .iter()
+        .any(|x| x == column_name)
+    {
+        return Ok(Vec::new());
+    }
+    let rows = sqlx::query(&format!(
+        r#"SELECT COALESCE("{}", '') AS gallery FROM items"#,
+        column_name
+    ))
+    .fetch_all(&mut *conn)
+    .await?;
+    Ok(rows
+        .iter()
+        .flat_map(|row| entry_ids(&row.get::<String, _>("gallery")))
+        .collect())
+}
+
+//True if an item has this image in one of its galleries, used when restoring images from a backup
+pub async fn is_gallery_image(
+    conn: &mut PgConnection,
+    image_id: &str,
+) -> Result<bool, sqlx::Error> {
+    for field in gallery_field_names(&mut *conn).await? {
+        let found = sqlx::query(&format!(
+            r#"SELECT 1 FROM items WHERE '@' || COALESCE("{}", '') LIKE '%@' || $1 || '.%'"#,
+            field
+        ))
+        .bind(image_id)
+        .fetch_optional(&mut *conn)
+        .await?
+        .is_some();
+        if found {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+//Saves the image under a new id and appends it to the end of the item's gallery
+//Returns the new entry so the client can add it to the gallery it's editing
+pub async fn upload_gallery_image(
+    Extension(user): Extension<UserContext>,
+    mut multipart: Multipart,
+) -> Result<Json<String>, (StatusCode, String)> {
+    println!("In upload gallery image");
+    let mut data: Option<axum::body::Bytes> = None;
+    let mut file_extension: Option<String> = None;
+    let mut item_id: Option<String> = None;
+    let mut field_name: Option<String> = None;
+    while let Some(field) = multipart.next_field().await.map_err(|e| {
+        println!("Upload gallery image error {}", e);
+        (StatusCode::BAD_REQUEST, e.to_string())
+    })? {
+        match field.name() {
+            Some("image") => {
+                if let Some(filename) = field.file_name() {
+                    file_extension = filename.rsplit('.').next().map(|x| x.to_lowercase());
+                }
+                data = Some(field.bytes().await.map_err(|e| {
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("Failed to read file data : {}", e),
+                    )
+                })?);
+            }
+            Some("item_id") => {
+                item_id = Some(field.text().await.map_err(|e| {
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("Failed to read item id: {}", e),
+                    )
+                })?);
+            }
+            Some("field_name") => {
+                field_name = Some(field.text().await.map_err(|e| {
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("Failed to read field name: {}", e),
+                    )
+                })?);
+            }
+            _ => (),
+        }
+    }
+    let (Some(data), Some(file_extension), Some(item_id), Some(field_name)) =
+        (data, file_extension, item_id, field_name)
+    else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Missing image, item id or field name".to_string(),
+        ));
+    };
+    if !(infer::image::is_jpeg(&data) || infer::image::is_png(&data) || infer::image::is_webp(&data))
+        || !["jpg", "jpeg", "png", "webp"].contains(&file_extension.as_str())
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Unsupported image format".to_string(),
+        ));
+    }
+
+    let mut tx = user.db_pool.begin().await.map_err(|e| {
+        println!("Upload gallery image begin transaction error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to start transaction".to_string(),
+        )
+    })?;
+    //The field name is only used after it's found in dynamic_fields
+    match gallery_field_names(&mut tx).await {
+        Ok(fields) if fields.contains(&field_name) => (),
+        Ok(_) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("'{}' is not a gallery field", field_name),
+            ));
+        }
+        Err(e) => {
+            println!("Upload gallery image fields error: {}", e);
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to read fields".to_string(),
+            ));
+        }
+    }
+    let image_id = Uuid::new_v4().to_string();
+    let entry = format!("{}.{}", image_id, file_extension);
+    match sqlx::query(&format!(
+        r#"
+        UPDATE items SET "{0}" = CASE WHEN COALESCE("{0}", '') = '' THEN $1 ELSE "{0}" || '@' || $1 END
+        WHERE id = $2
+        "#,
+        field_name
+    ))
+    .bind(&entry)
+    .bind(&item_id)
+    .execute(&mut *tx)
+    .await
+    {
+        Ok(result) if result.rows_affected() == 1 => (),
+        Ok(_) => return Err((StatusCode::BAD_REQUEST, "Id not in items".to_string())),
+        Err(e) => {
+            println!("Upload gallery image update error: {}", e);
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to update the gallery".to_string(),
+            ));
+        }
+    }
+    //Same files as every other image, the full size one plus the small and medium copies
+    let path = format!(
+        "/app/users/{}/images/{}.{}",
+        user.user_id, image_id, file_extension
+    );
+    let saved = match fs::write(&path, &data).await {
+        Ok(_) => save_resized_images(&data, &user.user_id, &image_id, &file_extension),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to save file: {}", e),
+        )),
+    };
+    if let Err(e) = saved {
+        remove_image(&user.user_id, &image_id).await;
+        return Err(e);
+    }
+    if let Err(e) = tx.commit().await {
+        println!("Upload gallery image commit error: {}", e);
+        remove_image(&user.user_id, &image_id).await;
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to commit image".to_string(),
+        ));
+    }
+    Ok(Json(entry))
+}
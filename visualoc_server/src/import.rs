@@ -0,0 +1,399 @@
+//Items imported from a CSV file, each header is mapped to the name, the quantity, a field or a new field
+//Values are checked against the type of their field, a row with a bad value is reported and left out
+//A dry run checks everything without saving, the client shows it as the preview before importing
+
+use axum::{Extension, Json, http::StatusCode};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use uuid::Uuid;
+
+use crate::{
+    DataType, DynamicFieldValue, add_item_column, column_definition, parse_date, parse_date_time,
+    parse_money, read_dynamic_fields, search, users::UserContext,
+};
+
+const PREVIEW_ROWS: usize = 5;
+
+#[derive(Deserialize)]
+enum ColumnTarget {
+    Skip,
+    Name,
+    Quantity,
+    Field(String),
+    NewField(String, DataType),
+}
+
+#[derive(Deserialize)]
+pub struct ImportItemsRequest {
+    csv: String,
+    #[serde(default)]
+    mapping: Vec<ColumnTarget>, //One per header, an empty mapping only reads the headers and the preview
+    #[serde(default)]
+    container_id: String, //Empty leaves the items unplaced
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Serialize)]
+struct RowError {
+    row: usize, //Numbered like in a spreadsheet, the headers are row 1
+    message: String,
+}
+
+#[derive(Serialize, Default)]
+pub struct ImportItemsResponse {
+    headers: Vec<String>,
+    preview: Vec<Vec<String>>, //First rows as they were read
+    rows: usize,
+    imported: usize, //Rows without errors, the ones a dry run would import
+    errors: Vec<RowError>,
+    placed: Vec<(String, i32)>, //Ids of the imported items with their quantity, empty without a container
+}
+
+//Where the values of a column go once the mapping is checked
+enum Column {
+    Skip,
+    Name,
+    Quantity,
+    Field(String, DataType),
+}
+
+//A value read from the file as it is stored for the field's type, empty cells get the same defaults as a new item
+fn coerce(value: &str, data_type: &DataType) -> Result<DynamicFieldValue, String> {
+    let value = value.trim();
+    match data_type {
+        DataType::String | DataType::Text => Ok(DynamicFieldValue::Text(value.to_string())),
+        DataType::List(options) => {
+            if value.is_empty() || options.iter().any(|x| x == value) {
+                Ok(DynamicFieldValue::Text(value.to_string()))
+            } else {
+                Err(format!("'{}' isn't one of {}", value, options.join(", ")))
+            }
+        }
+        DataType::Gallery => Err("Gallery fields can't be imported".to_string()),
+        DataType::Integer => match value {
+            "" => Ok(DynamicFieldValue::Integer(0)),
+            _ => value
+                .parse()
+                .map(DynamicFieldValue::Integer)
+                .map_err(|_| format!("'{}' isn't a whole number", value)),
+        },
+        DataType::Bool => match value.to_lowercase().as_str() {
+            "true" | "yes" | "y" | "1" | "x" => Ok(DynamicFieldValue::Integer(1)),
+            "false" | "no" | "n" | "0" | "" => Ok(DynamicFieldValue::Integer(0)),
+            _ => Err(format!("'{}' isn't true or false", value)),
+        },
+        DataType::Float => match value {
+            "" => Ok(DynamicFieldValue::Float(0.0)),
+            _ => value
+                .parse()
+                .map(DynamicFieldValue::Float)
+                .map_err(|_| format!("'{}' isn't a number", value)),
+        },
+        //Stored from 0 to 100, the % sign is optional
+        DataType::Percentage => match value.trim_end_matches('%').trim() {
+            "" => Ok(DynamicFieldValue::Float(0.0)),
+            number => match number.parse::<f32>() {
+                Ok(x) if (0.0..=100.0).contains(&x) => Ok(DynamicFieldValue::Float(x)),
+                _ => Err(format!("'{}' isn't a percentage from 0 to 100", value)),
+            },
+        },
+        DataType::Date => parse_date(value)
+            .map(DynamicFieldValue::Date)
+            .map_err(|_| format!("'{}' isn't a date like 2024-12-31", value)),
+        DataType::DateTime => parse_date_time(value)
+            .map(DynamicFieldValue::DateTime)
+            .map_err(|_| {
+                format!(
+                    "'{}' isn't a date and time like 2024-12-31T18:30:00Z",
+                    value
+                )
+            }),
+        DataType::Money { .. } => parse_money(value)
+            .map(DynamicFieldValue::Money)
+            .map_err(|_| format!("'{}' isn't an amount", value)),
+    }
+}
+
+//Checks the mapping against the fields, the errors are meant for the user
+fn check_mapping(
+    mapping: Vec<ColumnTarget>,
+    fields: &[(String, DataType)],
+    placed: bool,
+) -> Result<Vec<Column>, String> {
+    let mut columns = Vec::new();
+    let mut used: Vec<String> = Vec::new();
+    for target in mapping {
+        let column = match target {
+            ColumnTarget::Skip => Column::Skip,
+            ColumnTarget::Name => Column::Name,
+            ColumnTarget::Quantity if !placed => {
+                return Err("Pick a container to import quantities".to_string());
+            }
+            ColumnTarget::Quantity => Column::Quantity,
+            //Field names are matched without case like in search, the column keeps the field's own name
+            ColumnTarget::Field(name) => {
+                match fields.iter().find(|(x, _)| x.eq_ignore_ascii_case(&name)) {
+                    Some((_, DataType::Gallery)) => {
+                        return Err(format!("{} is a gallery, it can't be imported", name));
+                    }
+                    Some((field_name, data_type)) => {
+                        Column::Field(field_name.clone(), data_type.clone())
+                    }
+                    None => return Err(format!("There is no field called {}", name)),
+                }
+            }
+            ColumnTarget::NewField(name, data_type) => {
+                if name.is_empty() || name.chars().any(|x| !x.is_alphanumeric()) {
+                    return Err(format!("{} can only have letters and numbers", name));
+                }
+                if ["id", "name"].iter().any(|x| x.eq_ignore_ascii_case(&name))
+                    || fields.iter().any(|(x, _)| x.eq_ignore_ascii_case(&name))
+                {
+                    return Err(format!("There is already a field called {}", name));
+                }
+                if matches!(data_type, DataType::Gallery) || column_definition(&data_type).is_none()
+                {
+                    return Err(format!("{} can't be created with that type", name));
+                }
+                Column::Field(name, data_type)
+            }
+        };
+        let key = match &column {
+            Column::Skip => None,
+            Column::Name => Some("name".to_string()),
+            Column::Quantity => Some("quantity".to_string()),
+            Column::Field(name, _) => Some(name.to_lowercase()),
+        };
+        if let Some(key) = key {
+            if used.contains(&key) {
+                return Err("Two columns are imported into the same field".to_string());
+            }
+            used.push(key);
+        }
+        columns.push(column);
+    }
+    if !columns.iter().any(|x| matches!(x, Column::Name)) {
+        return Err("One of the columns has to be the name".to_string());
+    }
+    Ok(columns)
+}
+
+pub async fn import_items_csv(
+    Extension(user): Extension<UserContext>,
+    Json(payload): Json<ImportItemsRequest>,
+) -> Result<Json<ImportItemsResponse>, (StatusCode, String)> {
+    println!("in import items csv");
+    let mut response = ImportItemsResponse::default();
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(payload.csv.as_bytes());
+    response.headers = match reader.headers() {
+        Ok(headers) => headers
+            .iter()
+            .map(|x| x.trim_start_matches('\u{feff}').trim().to_string())
+            .collect(),
+        Err(e) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Couldn't read the file: {}", e),
+            ));
+        }
+    };
+    let mut records = Vec::new();
+    for (index, record) in reader.records().enumerate() {
+        match record {
+            Ok(record) => {
+                let values: Vec<String> = record.iter().map(|x| x.to_string()).collect();
+                if values.iter().all(|x| x.trim().is_empty()) {
+                    continue;
+                }
+                if response.preview.len() < PREVIEW_ROWS {
+                    response.preview.push(values.clone());
+                }
+                records.push((index + 2, values));
+            }
+            Err(e) => response.errors.push(RowError {
+                row: index + 2,
+                message: format!("Couldn't read the row: {}", e),
+            }),
+        }
+    }
+    response.rows = records.len() + response.errors.len();
+    if payload.mapping.is_empty() {
+        return Ok(Json(response));
+    }
+
+    let fields = match read_dynamic_fields(&*user.db_pool).await {
+        Ok(fields) => fields,
+        Err(e) => {
+            println!("Import items fields error {}", e);
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, String::new()));
+        }
+    };
+    let placed = !payload.container_id.is_empty();
+    let columns = check_mapping(payload.mapping, &fields, placed)
+        .map_err(|message| (StatusCode::BAD_REQUEST, message))?;
+
+    //Each row becomes its name, the values of its fields and its quantity
+    let mut items = Vec::new();
+    for (row, values) in records {
+        let mut name = String::new();
+        let mut field_values = Vec::new();
+        let mut quantity = 1;
+        let mut error = None;
+        for (index, column) in columns.iter().enumerate() {
+            //Short rows are read as if the missing cells were empty
+            let value = values.get(index).map_or("", |x| x.as_str());
+            match column {
+                Column::Skip => (),
+                Column::Name => name = value.trim().to_string(),
+                Column::Quantity => match value.trim() {
+                    "" => (),
+                    x => match x.parse::<i32>() {
+                        Ok(x) if x >= 0 => quantity = x,
+                        _ => error = Some(format!("'{}' isn't a quantity", x)),
+                    },
+                },
+                Column::Field(field_name, data_type) => match coerce(value, data_type) {
+                    Ok(x) => field_values.push((field_name.clone(), x)),
+                    Err(message) => error = Some(format!("{}: {}", field_name, message)),
+                },
+            }
+            if error.is_some() {
+                break;
+            }
+        }
+        if error.is_none() && name.is_empty() {
+            error = Some("The name is empty".to_string());
+        }
+        match error {
+            Some(message) => response.errors.push(RowError { row, message }),
+            None => items.push((name, field_values, quantity)),
+        }
+    }
+    response.errors.sort_by_key(|x| x.row);
+    response.imported = items.len();
+    if payload.dry_run || items.is_empty() {
+        return Ok(Json(response));
+    }
+
+    let internal_error = |message: &str, e: sqlx::Error| {
+        println!("{} {}", message, e);
+        (StatusCode::INTERNAL_SERVER_ERROR, String::new())
+    };
+    let mut tx = user
+        .db_pool
+        .begin()
+        .await
+        .map_err(|e| internal_error("Import items begin transaction error", e))?;
+    if placed {
+        //Items can't be placed in the root, only in a location or a container inside one
+        match sqlx::query("SELECT parent_id FROM containers WHERE id = $1")
+            .bind(&payload.container_id)
+            .fetch_optional(&mut *tx)
+            .await
+        {
+            Ok(Some(row)) if row.get::<Option<String>, _>("parent_id").is_some() => {}
+            Ok(Some(_)) => {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    "Items can't be placed in the root, pick a location or a container".to_string(),
+                ));
+            }
+            Ok(None) => {
+                return Err((
+                    StatusCode::NOT_FOUND,
+                    "The container doesn't exist".to_string(),
+                ));
+            }
+            Err(e) => return Err(internal_error("Import items container error", e)),
+        }
+    }
+    let new_fields: Vec<(&String, &DataType)> = columns
+        .iter()
+        .filter_map(|column| match column {
+            Column::Field(name, data_type) if !fields.iter().any(|(x, _)| x == name) => {
+                Some((name, data_type))
+            }
+            _ => None,
+        })
+        .collect();
+    if !new_fields.is_empty() {
+        //The search vector depends on the TEXT fields, it's created again once the fields changed
+        search::drop_search_vector(&mut tx)
+            .await
+            .map_err(|e| internal_error("Import items drop search vector error", e))?;
+        for (name, data_type) in new_fields {
+            if let Some(definition) = column_definition(data_type) {
+                add_item_column(&mut tx, name, &definition)
+                    .await
+                    .map_err(|e| internal_error("Import items add field error", e))?;
+            }
+        }
+        search::create_search_vector(&mut tx)
+            .await
+            .map_err(|e| internal_error("Import items create search vector error", e))?;
+    }
+    //Fields without a column get what an empty cell gets, the same as a new item, galleries start empty
+    let unmapped: Vec<(String, DynamicFieldValue)> = fields
+        .iter()
+        .filter(|(name, _)| {
+            !columns
+                .iter()
+                .any(|x| matches!(x, Column::Field(field_name, _) if field_name == name))
+        })
+        .map(|(name, data_type)| {
+            let value =
+                coerce("", data_type).unwrap_or_else(|_| DynamicFieldValue::Text(String::new()));
+            (name.clone(), value)
+        })
+        .collect();
+    for (name, mut field_values, quantity) in items {
+        field_values.extend(unmapped.iter().cloned());
+        let id = Uuid::new_v4().to_string();
+        let mut column_clause = vec!["id".to_string(), "name".to_string()];
+        column_clause.extend(field_values.iter().map(|(x, _)| x.clone()));
+        let value_clause: Vec<String> = (1..=column_clause.len())
+            .map(|x| format!("${}", x))
+            .collect();
+        let items_query = format!(
+            r#"INSERT INTO items ("{}", image_type) VALUES ({}, '')"#,
+            column_clause.join(r#"", ""#),
+            value_clause.join(", ")
+        );
+        let mut query = sqlx::query(&items_query).bind(&id).bind(name);
+        for (_, value) in field_values {
+            query = match value {
+                DynamicFieldValue::Text(val) => query.bind(val),
+                DynamicFieldValue::Integer(val) => query.bind(val),
+                DynamicFieldValue::Float(val) => query.bind(val),
+                DynamicFieldValue::Date(val) => query.bind(val),
+                DynamicFieldValue::DateTime(val) => query.bind(val),
+                DynamicFieldValue::Money(val) => query.bind(val),
+            };
+        }
+        query
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| internal_error("Import items insert error", e))?;
+        if placed {
+            sqlx::query(
+                r#"
+                INSERT INTO item_placements (item_id, container_id, quantity) VALUES ($1, $2, $3)
+                "#,
+            )
+            .bind(&id)
+            .bind(&payload.container_id)
+            .bind(quantity)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| internal_error("Import items placement error", e))?;
+            response.placed.push((id, quantity));
+        }
+    }
+    tx.commit()
+        .await
+        .map_err(|e| internal_error("Import items commit error", e))?;
+    Ok(Json(response))
+}
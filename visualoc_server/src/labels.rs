@@ -0,0 +1,222 @@
+//Printable sheets of QR code labels for a location and everything below it
+//The sheet is an A4 SVG with 3 x 8 labels of 70 x 37mm per page, pages are stacked vertically
+//Each code holds "visualoc:container:{id}" or "visualoc:item:{id}" so a scan knows what it points to
+
+use axum::{
+    Extension, Json,
+    http::{StatusCode, header},
+    response::IntoResponse,
+};
+use qrcode::{Color, QrCode};
+use serde::Deserialize;
+use sqlx::Row;
+
+use crate::users::UserContext;
+
+const PAGE_WIDTH: f32 = 210.0;
+const PAGE_HEIGHT: f32 = 297.0;
+const COLUMNS: usize = 3;
+const ROWS: usize = 8;
+const LABEL_WIDTH: f32 = 70.0;
+const LABEL_HEIGHT: f32 = 37.0;
+const QR_SIZE: f32 = 29.0;
+const QUIET_ZONE: usize = 2; //Modules of white around the code
+const LINE_LENGTH: usize = 16; //Characters per line of the name
+const MAX_LINES: usize = 4;
+
+#[derive(Deserialize)]
+pub struct LabelsRequest {
+    location_id: String,
+    #[serde(default)]
+    include_items: bool,
+}
+
+struct Label {
+    content: String,
+    name: String,
+    kind: &'static str,
+}
+
+pub async fn get_labels(
+    Extension(user): Extension<UserContext>,
+    Json(payload): Json<LabelsRequest>,
+) -> Result<impl IntoResponse, StatusCode> {
+    println!("in get labels");
+    let tree = r#"
+        WITH RECURSIVE tree AS (
+            SELECT id, name, 0 AS depth FROM containers WHERE id = $1
+            UNION ALL
+            SELECT c.id, c.name, t.depth + 1 FROM containers c JOIN tree t ON c.parent_id = t.id
+        )
+        "#;
+    let mut labels = match sqlx::query(&format!(
+        "{} SELECT id, name FROM tree ORDER BY depth, name, id",
+        tree
+    ))
+    .bind(&payload.location_id)
+    .fetch_all(&*user.db_pool)
+    .await
+    {
+        Ok(rows) => rows
+            .iter()
+            .map(|row| Label {
+                content: format!("visualoc:container:{}", row.get::<String, _>("id")),
+                name: row.get("name"),
+                kind: "Container",
+            })
+            .collect::<Vec<Label>>(),
+        Err(e) => {
+            println!("Get labels containers error {}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    if labels.is_empty() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    if payload.include_items {
+        match sqlx::query(&format!(
+            r#"
+            {}
+            SELECT i.id, i.name FROM items i
+            WHERE EXISTS (
+                SELECT 1 FROM item_placements p JOIN tree t ON t.id = p.container_id
+                WHERE p.item_id = i.id
+            )
+            ORDER BY i.name, i.id
+            "#,
+            tree
+        ))
+        .bind(&payload.location_id)
+        .fetch_all(&*user.db_pool)
+        .await
+        {
+            Ok(rows) => labels.extend(rows.iter().map(|row| Label {
+                content: format!("visualoc:item:{}", row.get::<String, _>("id")),
+                name: row.get("name"),
+                kind: "Item",
+            })),
+            Err(e) => {
+                println!("Get labels items error {}", e);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        }
+    }
+
+    let svg = match labels_svg(&labels) {
+        Ok(svg) => svg,
+        Err(e) => {
+            println!("Get labels qr code error {}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    Ok(([(header::CONTENT_TYPE, "image/svg+xml")], svg))
+}
+
+fn labels_svg(labels: &[Label]) -> Result<String, qrcode::types::QrError> {
+    let per_page = COLUMNS * ROWS;
+    let pages = labels.len().div_ceil(per_page).max(1);
+    let height = PAGE_HEIGHT * pages as f32;
+    //Centers the grid on the page
+    let margin_x = (PAGE_WIDTH - LABEL_WIDTH * COLUMNS as f32) / 2.0;
+    let margin_y = (PAGE_HEIGHT - LABEL_HEIGHT * ROWS as f32) / 2.0;
+    let mut svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}mm" height="{h}mm" viewBox="0 0 {w} {h}" font-family="sans-serif">"#,
+        w = PAGE_WIDTH,
+        h = height
+    );
+    svg.push_str(&format!(
+        r#"<rect width="{}" height="{}" fill="white"/>"#,
+        PAGE_WIDTH, height
+    ));
+    for (i, label) in labels.iter().enumerate() {
+        let page = i / per_page;
+        let slot = i % per_page;
+        let x = margin_x + (slot % COLUMNS) as f32 * LABEL_WIDTH;
+        let y = PAGE_HEIGHT * page as f32 + margin_y + (slot / COLUMNS) as f32 * LABEL_HEIGHT;
+        //Thin outline to cut along when printing on plain paper
+        svg.push_str(&format!(
+            r##"<rect x="{}" y="{}" width="{}" height="{}" fill="none" stroke="#cccccc" stroke-width="0.1"/>"##,
+            x, y, LABEL_WIDTH, LABEL_HEIGHT
+        ));
+        let padding = (LABEL_HEIGHT - QR_SIZE) / 2.0;
+        svg.push_str(&qr_svg(&label.content, x + padding, y + padding)?);
+        let text_x = x + padding + QR_SIZE + 2.0;
+        let mut text_y = y + padding + 4.0;
+        for line in wrap_name(&label.name) {
+            svg.push_str(&format!(
+                r#"<text x="{}" y="{}" font-size="3.5">{}</text>"#,
+                text_x,
+                text_y,
+                escape_xml(&line)
+            ));
+            text_y += 4.5;
+        }
+        svg.push_str(&format!(
+            r##"<text x="{}" y="{}" font-size="2.5" fill="#666666">{}</text>"##,
+            text_x,
+            y + padding + QR_SIZE - 1.0,
+            label.kind
+        ));
+    }
+    svg.push_str("</svg>");
+    Ok(svg)
+}
+
+//One path for the whole code, a square per dark module, scaled to QR_SIZE
+fn qr_svg(content: &str, x: f32, y: f32) -> Result<String, qrcode::types::QrError> {
+    let code = QrCode::new(content.as_bytes())?;
+    let width = code.width();
+    let scale = QR_SIZE / (width + QUIET_ZONE * 2) as f32;
+    let mut path = String::new();
+    for (i, color) in code.to_colors().iter().enumerate() {
+        if *color == Color::Dark {
+            path.push_str(&format!(
+                "M{} {}h1v1h-1z",
+                i % width + QUIET_ZONE,
+                i / width + QUIET_ZONE
+            ));
+        }
+    }
+    Ok(format!(
+        r#"<g transform="translate({} {}) scale({})"><path d="{}" fill="black" shape-rendering="crispEdges"/></g>"#,
+        x, y, scale, path
+    ))
+}
+
+//Splits the name on spaces into the lines next to the code, long words are cut
+//Anything past MAX_LINES is replaced by an ellipsis
+fn wrap_name(name: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut current = String::new();
+    for word in name.split_whitespace() {
+        let word: String = word.chars().take(LINE_LENGTH).collect();
+        if current.is_empty() {
+            current = word;
+        } else if current.chars().count() + 1 + word.chars().count() <= LINE_LENGTH {
+            current.push(' ');
+            current.push_str(&word);
+        } else {
+            lines.push(std::mem::take(&mut current));
+            current = word;
+        }
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
+    if lines.len() > MAX_LINES {
+        lines.truncate(MAX_LINES);
+        if let Some(last) = lines.last_mut() {
+            let kept: String = last.chars().take(LINE_LENGTH - 1).collect();
+            *last = kept + "…";
+        }
+    }
+    lines
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
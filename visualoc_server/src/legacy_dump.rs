@@ -0,0 +1,454 @@
+//Backups made before the JSON archive hold a plain pg_dump of the user's database
+//The SQL is never run, only the COPY data of the tables Visualoc knows is read and turned into an archive
+//Dumps from before the schema migrations keep the hierarchy in containers.master and containers.contained_items
+
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    DataType,
+    archive::{
+        ARCHIVE_FORMAT, ARCHIVE_VERSION, Archive, ArchiveContainer, ArchiveField, ArchiveItem,
+        ArchivePlacement, ArchiveThreshold,
+    },
+    parse_data_type, string_to_corners, string_to_polygon,
+};
+
+//The rows of a COPY block, None is a NULL
+#[derive(Default)]
+struct Table {
+    columns: Vec<String>,
+    rows: Vec<Vec<Option<String>>>,
+}
+
+impl Table {
+    fn get<'a>(&self, row: &'a [Option<String>], column: &str) -> Option<&'a str> {
+        let index = self.columns.iter().position(|x| x == column)?;
+        row.get(index)?.as_deref()
+    }
+}
+
+//Undoes the escapes of the COPY text format, \N is handled by the caller
+fn unescape(value: &str) -> String {
+    let mut bytes: Vec<u8> = Vec::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+    while let Some(x) = chars.next() {
+        if x != '\\' {
+            let mut buffer = [0; 4];
+            bytes.extend_from_slice(x.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('b') => bytes.push(0x08),
+            Some('f') => bytes.push(0x0c),
+            Some('n') => bytes.push(b'\n'),
+            Some('r') => bytes.push(b'\r'),
+            Some('t') => bytes.push(b'\t'),
+            Some('v') => bytes.push(0x0b),
+            //Up to three octal digits or two hex digits make one byte
+            Some(digit @ '0'..='7') => {
+                let mut byte = digit.to_digit(8).unwrap_or(0);
+                for _ in 0..2 {
+                    match chars.peek().and_then(|x| x.to_digit(8)) {
+                        Some(next) => {
+                            byte = byte * 8 + next;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                bytes.push(byte as u8);
+            }
+            Some('x') => {
+                let mut byte = 0;
+                for _ in 0..2 {
+                    match chars.peek().and_then(|x| x.to_digit(16)) {
+                        Some(next) => {
+                            byte = byte * 16 + next;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                bytes.push(byte as u8);
+            }
+            Some(other) => {
+                let mut buffer = [0; 4];
+                bytes.extend_from_slice(other.encode_utf8(&mut buffer).as_bytes());
+            }
+            None => bytes.push(b'\\'),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+//Reads `COPY public.items (id, name, ...) FROM stdin;` blocks up to their `\.` line
+fn read_copy_blocks(sql: &str) -> HashMap<String, Table> {
+    let mut tables: HashMap<String, Table> = HashMap::new();
+    let mut lines = sql.lines();
+    while let Some(line) = lines.next() {
+        let Some(statement) = line
+            .strip_prefix("COPY ")
+            .and_then(|x| x.strip_suffix(" FROM stdin;"))
+        else {
+            continue;
+        };
+        let Some((table_name, columns)) = statement.split_once(" (") else {
+            continue;
+        };
+        let table_name = table_name
+            .trim_start_matches("public.")
+            .trim_matches('"')
+            .to_string();
+        let columns = columns
+            .trim_end_matches(')')
+            .split(", ")
+            .map(|x| x.trim_matches('"').to_string())
+            .collect();
+        let mut table = Table {
+            columns,
+            rows: Vec::new(),
+        };
+        for line in lines.by_ref() {
+            if line == "\\." {
+                break;
+            }
+            table.rows.push(
+                line.split('\t')
+                    .map(|x| match x {
+                        "\\N" => None,
+                        _ => Some(unescape(x)),
+                    })
+                    .collect(),
+            );
+        }
+        tables.insert(table_name, table);
+    }
+    tables
+}
+
+//A value of the dump in the archive's format, values that can't be read are kept as text so validation reports them
+fn dump_value(value: Option<&str>, data_type: &DataType) -> Option<Value> {
+    match data_type {
+        DataType::String | DataType::Text | DataType::List(_) => {
+            Some(Value::String(value.unwrap_or_default().to_string()))
+        }
+        DataType::Gallery => Some(
+            value
+                .unwrap_or_default()
+                .split('@')
+                .filter(|x| !x.is_empty())
+                .map(|x| Value::String(x.to_string()))
+                .collect(),
+        ),
+        DataType::Integer => value?.parse::<i64>().ok().map(Value::from),
+        DataType::Bool => Some(Value::Bool(value == Some("1"))),
+        DataType::Float | DataType::Percentage => value?.parse::<f64>().ok().map(Value::from),
+        DataType::Date | DataType::Money { .. } => {
+            Some(value.map_or(Value::Null, |x| Value::String(x.to_string())))
+        }
+        //pg_dump writes timestamptz like 2024-12-31 18:30:00+00
+        DataType::DateTime => Some(value.map_or(Value::Null, |x| {
+            Value::String(
+                DateTime::parse_from_str(x, "%Y-%m-%d %H:%M:%S%.f%#z")
+                    .map(|x| {
+                        x.with_timezone(&Utc)
+                            .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+                    })
+                    .unwrap_or_else(|_| x.to_string()),
+            )
+        })),
+    }
+}
+
+pub fn archive_from_dump(sql: &str) -> Result<Archive, String> {
+    let tables = read_copy_blocks(sql);
+    let (Some(container_table), Some(item_table)) = (tables.get("containers"), tables.get("items"))
+    else {
+        return Err("The file isn't a database dump of Visualoc".to_string());
+    };
+    let empty = Table::default();
+
+    let field_table = tables.get("dynamic_fields").unwrap_or(&empty);
+    let fields: Vec<ArchiveField> = field_table
+        .rows
+        .iter()
+        .filter_map(|row| {
+            Some(ArchiveField {
+                name: field_table.get(row, "name")?.to_string(),
+                data_type: parse_data_type(field_table.get(row, "type")?),
+            })
+        })
+        .collect();
+
+    let container_ids: HashSet<&str> = container_table
+        .rows
+        .iter()
+        .filter_map(|row| container_table.get(row, "id"))
+        .collect();
+    let item_ids: HashSet<&str> = item_table
+        .rows
+        .iter()
+        .filter_map(|row| item_table.get(row, "id"))
+        .collect();
+    //Older dumps have master instead of parent_id, a master that doesn't exist makes a root like the migration does
+    let old_hierarchy = !container_table.columns.iter().any(|x| x == "parent_id");
+    let mut containers: Vec<ArchiveContainer> = Vec::new();
+    let mut placements: HashMap<String, Vec<ArchivePlacement>> = HashMap::new();
+    for row in &container_table.rows {
+        let Some(id) = container_table.get(row, "id") else {
+            continue;
+        };
+        let parent_id = if old_hierarchy {
+            container_table.get(row, "master")
+        } else {
+            container_table.get(row, "parent_id")
+        }
+        .filter(|x| *x != id && container_ids.contains(x));
+        if old_hierarchy {
+            let contained_items = container_table
+                .get(row, "contained_items")
+                .unwrap_or_default();
+            for item_id in contained_items.split('@').collect::<HashSet<_>>() {
+                if item_ids.contains(item_id) {
+                    placements
+                        .entry(item_id.to_string())
+                        .or_default()
+                        .push(ArchivePlacement {
+                            container_id: id.to_string(),
+                            quantity: 1,
+                        });
+                }
+            }
+        }
+        containers.push(ArchiveContainer {
+            id: id.to_string(),
+            name: container_table
+                .get(row, "name")
+                .unwrap_or_default()
+                .to_string(),
+            parent_id: parent_id.map(String::from),
+            corners: string_to_corners(container_table.get(row, "corners").unwrap_or_default()),
+            polygon: string_to_polygon(container_table.get(row, "polygon").unwrap_or_default()),
+            rotation: container_table
+                .get(row, "rotation")
+                .and_then(|x| x.parse().ok())
+                .unwrap_or(0.0),
+            image_type: container_table
+                .get(row, "image_type")
+                .unwrap_or_default()
+                .to_string(),
+        });
+    }
+    //The archive lists parents before their children, containers left in a cycle become roots
+    let mut ordered: Vec<ArchiveContainer> = Vec::new();
+    let mut listed: HashSet<String> = HashSet::new();
+    while !containers.is_empty() {
+        let (ready, waiting): (Vec<_>, Vec<_>) = containers.into_iter().partition(|x| {
+            x.parent_id
+                .as_ref()
+                .is_none_or(|parent_id| listed.contains(parent_id))
+        });
+        containers = waiting;
+        if ready.is_empty() {
+            for container in &mut containers {
+                container.parent_id = None;
+            }
+            continue;
+        }
+        listed.extend(ready.iter().map(|x| x.id.clone()));
+        ordered.extend(ready);
+    }
+
+    if let Some(placement_table) = tables.get("item_placements") {
+        for row in &placement_table.rows {
+            let (Some(item_id), Some(container_id)) = (
+                placement_table.get(row, "item_id"),
+                placement_table.get(row, "container_id"),
+            ) else {
+                continue;
+            };
+            //Placements of rows that aren't in the dump are dropped, a placement is only listed once
+            let item_placements = placements.entry(item_id.to_string()).or_default();
+            if !item_ids.contains(item_id)
+                || !container_ids.contains(container_id)
+                || item_placements
+                    .iter()
+                    .any(|x| x.container_id == container_id)
+            {
+                continue;
+            }
+            item_placements.push(ArchivePlacement {
+                container_id: container_id.to_string(),
+                quantity: placement_table
+                    .get(row, "quantity")
+                    .and_then(|x| x.parse().ok())
+                    .unwrap_or(1),
+            });
+        }
+    }
+    let mut thresholds: HashMap<String, ArchiveThreshold> = HashMap::new();
+    if let Some(threshold_table) = tables.get("stock_thresholds") {
+        for row in &threshold_table.rows {
+            let (Some(item_id), Some(minimum)) = (
+                threshold_table.get(row, "item_id"),
+                threshold_table
+                    .get(row, "minimum")
+                    .and_then(|x| x.parse().ok()),
+            ) else {
+                continue;
+            };
+            thresholds.insert(
+                item_id.to_string(),
+                ArchiveThreshold {
+                    field_name: threshold_table.get(row, "field_name").map(String::from),
+                    minimum,
+                },
+            );
+        }
+    }
+
+    let items = item_table
+        .rows
+        .iter()
+        .filter_map(|row| {
+            let id = item_table.get(row, "id")?.to_string();
+            Some(ArchiveItem {
+                name: item_table.get(row, "name").unwrap_or_default().to_string(),
+                image_type: item_table
+                    .get(row, "image_type")
+                    .unwrap_or_default()
+                    .to_string(),
+                values: fields
+                    .iter()
+                    .filter_map(|field| {
+                        dump_value(item_table.get(row, &field.name), &field.data_type)
+                            .map(|x| (field.name.clone(), x))
+                    })
+                    .collect(),
+                placements: placements.remove(&id).unwrap_or_default(),
+                stock_threshold: thresholds.remove(&id),
+                id,
+            })
+        })
+        .collect();
+    Ok(Archive {
+        format: ARCHIVE_FORMAT.to_string(),
+        version: ARCHIVE_VERSION,
+        created_at: Utc::now(),
+        fields,
+        containers: ordered,
+        items,
+        images: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    //A dump with the current hierarchy, the shelf is dumped before the house it is in
+    const DUMP: &str = "\
+SET client_encoding = 'UTF8';
+COPY public.dynamic_fields (name, type) FROM stdin;
+Notes\tparagraph
+Photos\tgallery
+Count\tinteger
+\\.
+COPY public.containers (id, name, parent_id, corners, polygon, rotation, image_type) FROM stdin;
+shelf\tShelf\thouse\t0.1@0.2@0.3@0.4\t\\N\t90\tpng
+house\tHouse\t\\N\t\\N\t\\N\t\\N\t\\N
+\\.
+COPY public.items (id, name, image_type, \"Notes\", \"Photos\", \"Count\") FROM stdin;
+screw\tM4\\tscrew\t\\N\tC:\\\\parts\\nbox 2\tscrew-1.png@screw-2.jpg\t12
+nut\tNut\tpng\t\\N\t\\N\t\\N
+\\.
+COPY public.item_placements (item_id, container_id, quantity) FROM stdin;
+screw\tshelf\t5
+screw\tshelf\t7
+nut\tgone\t1
+\\.
+";
+
+    #[test]
+    fn unescapes_copy_values() {
+        assert_eq!(unescape("a\\tb\\\\c"), "a\tb\\c");
+        assert_eq!(unescape("\\101\\x42\\n"), "AB\n");
+        assert_eq!(unescape("plain é"), "plain é");
+        let tables = read_copy_blocks(DUMP);
+        let items = &tables["items"];
+        assert_eq!(items.columns[3], "Notes");
+        assert_eq!(items.get(&items.rows[0], "name"), Some("M4\tscrew"));
+        assert_eq!(items.get(&items.rows[0], "image_type"), None);
+        assert_eq!(items.get(&items.rows[0], "Notes"), Some("C:\\parts\nbox 2"));
+    }
+
+    #[test]
+    fn lists_parents_first() {
+        let archive = archive_from_dump(DUMP).unwrap();
+        let containers: Vec<(&str, Option<&str>)> = archive
+            .containers
+            .iter()
+            .map(|x| (x.id.as_str(), x.parent_id.as_deref()))
+            .collect();
+        assert_eq!(containers, [("house", None), ("shelf", Some("house"))]);
+        assert_eq!(archive.containers[0].image_type, "");
+        assert_eq!(archive.containers[1].image_type, "png");
+        assert_eq!(archive.containers[1].corners, [0.1, 0.2, 0.3, 0.4]);
+        assert_eq!(archive.containers[1].rotation, 90.0);
+        assert!(crate::archive::validate(&archive).is_ok());
+    }
+
+    #[test]
+    fn reads_item_values() {
+        let archive = archive_from_dump(DUMP).unwrap();
+        let screw = &archive.items[0];
+        assert_eq!(screw.name, "M4\tscrew");
+        assert_eq!(screw.image_type, "");
+        assert_eq!(screw.values["Notes"], json!("C:\\parts\nbox 2"));
+        assert_eq!(
+            screw.values["Photos"],
+            json!(["screw-1.png", "screw-2.jpg"])
+        );
+        assert_eq!(screw.values["Count"], json!(12));
+        //A placement is only listed once
+        assert_eq!(screw.placements.len(), 1);
+        assert_eq!(screw.placements[0].quantity, 5);
+
+        let nut = &archive.items[1];
+        assert_eq!(nut.values["Notes"], json!(""));
+        assert_eq!(nut.values["Photos"], json!([]));
+        assert!(!nut.values.contains_key("Count"));
+        assert!(nut.placements.is_empty());
+    }
+
+    #[test]
+    fn reads_the_old_hierarchy() {
+        let dump = "\
+COPY public.containers (id, name, master, corners, contained_items) FROM stdin;
+box\tBox\tattic\t\\N\tscrew@screw@gone
+attic\tAttic\tlost\t\\N\t\\N
+\\.
+COPY public.items (id, name) FROM stdin;
+screw\tScrew
+\\.
+";
+        let archive = archive_from_dump(dump).unwrap();
+        let containers: Vec<(&str, Option<&str>)> = archive
+            .containers
+            .iter()
+            .map(|x| (x.id.as_str(), x.parent_id.as_deref()))
+            .collect();
+        assert_eq!(containers, [("attic", None), ("box", Some("attic"))]);
+        let placements = &archive.items[0].placements;
+        assert_eq!(placements.len(), 1);
+        assert_eq!(placements[0].container_id, "box");
+    }
+
+    #[test]
+    fn rejects_other_sql() {
+        assert!(archive_from_dump("SELECT 1;\n").is_err());
+    }
+}
@@ -1,4 +1,8 @@
-use std::{collections::BTreeSet, sync::Arc, time::Duration};
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Arc,
+    time::Duration,
+};
 
 use axum::{
     Extension, Json, Router,
@@ -9,9 +13,11 @@ use axum::{
     response::IntoResponse,
     routing::post,
 };
-use dashmap::DashMap;
+use chrono::{DateTime, NaiveDate, Utc};
+use dashmap::{DashMap, DashSet};
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
-use sqlx::{Column, PgPool, Row, postgres::PgRow};
+use sqlx::{Column, PgConnection, PgExecutor, PgPool, Row, postgres::PgRow};
 use tokio::{
     fs::{self, File},
     time::sleep,
@@ -26,10 +32,23 @@ use tracing::{Level, info};
 use users::{UserContext, auth_middleware, cleanup_inactive_pools};
 use uuid::Uuid;
 
+mod archive;
 mod backup;
+mod export;
+mod gallery;
+mod import;
+mod labels;
+mod legacy_dump;
+mod map;
+mod merge;
+mod migrations;
+mod scan;
+mod search;
+mod statistics;
+mod stock;
 mod users;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 enum DataType {
     String,
     Integer,
@@ -39,6 +58,9 @@ enum DataType {
     Text,
     List(Vec<String>),
     Gallery,
+    Date,
+    DateTime,
+    Money { currency: String },
 }
 
 #[derive(Serialize, Deserialize)]
@@ -51,6 +73,8 @@ struct ContainedItem {
     string_vars: Vec<String>,
     int_vars: Vec<i32>,
     float_vars: Vec<f32>,
+    #[serde(default)]
+    quantity: i64, //Units of the item over all of its placements
 }
 impl Default for ContainedItem {
     fn default() -> Self {
@@ -61,19 +85,26 @@ impl Default for ContainedItem {
             string_vars: Vec::new(),
             int_vars: Vec::new(),
             float_vars: Vec::new(),
+            quantity: 0,
         }
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 struct Container {
     id: String,
     master: String,
     slaves: BTreeSet<String>,
     name: String,
-    corners: [f32; 4],
+    corners: [f32; 4], //The rectangle before rotation, or the bounding box of the polygon
     image_type: String,
     contained_items: BTreeSet<String>,
+    #[serde(default)]
+    polygon: Vec<[f32; 2]>, //Points as fractions of the parent's photo, empty for a rectangle
+    #[serde(default)]
+    rotation: f32, //Degrees clockwise around the center of the corners rectangle
+    #[serde(default)]
+    item_quantities: HashMap<String, i32>, //Units of each contained item, items missing here keep their quantity or get 1
 }
 
 #[derive(Deserialize)]
@@ -81,10 +112,34 @@ struct IdVectorRequest {
     id_vec: Vec<String>,
 }
 
+#[derive(Deserialize, Default)]
+enum SortDirection {
+    #[default]
+    Ascending,
+    Descending,
+}
+
 #[derive(Deserialize)]
 struct SearchItemsRequest {
     column_name: String,
     search_string: String,
+    #[serde(default)]
+    offset: i64,
+    #[serde(default)]
+    limit: Option<i64>, //None returns every match
+    #[serde(default)]
+    sort_column: Option<String>, //Name when missing, or the rank for all fields searches
+    #[serde(default)]
+    sort_direction: SortDirection,
+    #[serde(default)]
+    all_fields: bool, //Full text search over the name and every TEXT field instead of column_name
+}
+
+#[derive(Serialize)]
+struct SearchItemsResponse {
+    items: Vec<ContainedItem>,
+    total: i64,                      //Matches before offset and limit
+    matched_fields: Vec<Vec<String>>, //For each item the fields an all fields search matched
 }
 
 #[derive(Deserialize)]
@@ -106,11 +161,50 @@ struct DeleteItemRequest {
     delete_from_items: bool,
 }
 
+#[derive(Deserialize)]
+struct MoveItemsRequest {
+    id_vec: Vec<String>,
+    #[serde(default)]
+    from_container_id: String, //Empty moves every placement of the items
+    container_id: String,
+}
+
+#[derive(Serialize)]
+struct MoveItemsResponse {
+    items_moved: u64,
+}
+
 #[derive(Deserialize)]
 struct ContainerRequest {
     container: Container,
 }
 
+//What happens to the items placed in a deleted container or any of its descendants
+//Items of a location can't move to its parent, the root
+#[derive(Deserialize, Default, PartialEq, Clone, Copy)]
+enum ItemPolicy {
+    DeleteItems,
+    MoveToParent,
+    #[default]
+    LeaveUnplaced,
+}
+
+#[derive(Deserialize)]
+struct DeleteContainerRequest {
+    container: Container,
+    #[serde(default)]
+    item_policy: ItemPolicy,
+}
+
+#[derive(Serialize, Default)]
+struct DeleteContainerResponse {
+    containers_deleted: u64,
+    items_deleted: u64,
+    items_moved: u64,
+    items_unplaced: u64,
+    images_removed: u64,
+}
+
 #[derive(Deserialize)]
 struct ColumnRequest {
     column_name: String,
@@ -132,6 +226,25 @@ pub fn corners_to_string(corners: &[f32; 4]) -> String {
     return result;
 }
 
+//Points are stored like the corners, "x,y@x,y@"
+pub fn polygon_to_string(polygon: &[[f32; 2]]) -> String {
+    let mut result = String::new();
+    for point in polygon {
+        result += &format!("{},{}@", point[0], point[1]);
+    }
+    result
+}
+
+pub fn string_to_polygon(input: &str) -> Vec<[f32; 2]> {
+    input
+        .split('@')
+        .filter_map(|point| {
+            let (x, y) = point.split_once(',')?;
+            Some([x.parse().ok()?, y.parse().ok()?])
+        })
+        .collect()
+}
+
 pub fn string_to_corners(input: &str) -> [f32; 4] {
     let mut result = [0.0; 4];
     for (index, string) in input.split('@').enumerate() {
@@ -144,25 +257,6 @@ pub fn string_to_corners(input: &str) -> [f32; 4] {
     return result;
 }
 
-pub fn set_to_string(set: &BTreeSet<String>) -> String {
-    let mut result = String::new();
-    for item in set {
-        result += item;
-        result.push('@');
-    }
-    return result;
-}
-
-pub fn string_to_set(input: &str) -> BTreeSet<String> {
-    let mut result = BTreeSet::new();
-    for string in input.split('@') {
-        if !string.is_empty() {
-            result.insert(string.to_owned());
-        }
-    }
-    return result;
-}
-
 #[derive(Clone)]
 struct AppState {
     master_pool: PgPool,
@@ -170,6 +264,7 @@ struct AppState {
     master_db_string: String,
     user_pools: Arc<DashMap<String, Arc<PgPool>>>,
     token_belonging_to_user: Arc<DashMap<String, String>>,
+    migrated_users: Arc<DashSet<String>>,
 }
 
 // const DOMAIN: &str = "https://visualoc.com";
@@ -184,13 +279,21 @@ async fn main() -> Result<(), sqlx::Error> {
     let secret = std::env::var("SECRET").unwrap_or("".into());
     // let master_db_string = "postgres://postgres@localhost:5432".to_string();
     let master_db_string = format!("postgres://{}:{}@db:5432", pg_user, pg_password);
+    //List the pending migrations of every database and exit without applying them
+    if std::env::args().any(|x| x == "--migrations-dry-run") {
+        let pool = connect_with_retry(&(master_db_string.clone() + "/master")).await?;
+        migrations::dry_run(&pool).await;
+        return Ok(());
+    }
     let pool = setup_master_database(&(master_db_string.clone() + "/master")).await;
+    migrations::migrate_all_user_databases(&pool).await;
     let state = AppState {
         master_pool: pool,
         secret,
         master_db_string,
         user_pools: Arc::new(DashMap::new()),
         token_belonging_to_user: Arc::new(DashMap::new()),
+        migrated_users: Arc::new(DashSet::new()),
     };
     tokio::spawn(cleanup_inactive_pools(state.clone()));
     println!("Connected to the database!");
@@ -213,9 +316,14 @@ async fn main() -> Result<(), sqlx::Error> {
             "/get_item_container_location",
             post(get_item_container_location),
         )
+        .route("/get_item_ancestors", post(get_item_ancestors))
+        .route("/get_item_map", post(map::get_item_map))
         .route("/insert_item", post(insert_item))
+        .route("/import_items_csv", post(import::import_items_csv))
+        .route("/export_items", post(export::export_items))
         .route("/update_item", post(update_item))
         .route("/delete_item", post(delete_item))
+        .route("/move_items", post(move_items))
         .route("/add_container", post(add_container))
         .route("/delete_container", post(delete_container))
         .route("/update_container", post(update_container))
@@ -228,9 +336,23 @@ async fn main() -> Result<(), sqlx::Error> {
         .route("/update_items_column", post(update_items_column))
         .route("/get_item_column_types", post(get_dynamic_fields))
         .route("/get_all_slaves", post(get_all_slaves))
+        .route("/get_value_totals", post(statistics::get_value_totals))
+        .route("/get_unit_totals", post(statistics::get_unit_totals))
+        .route("/get_stock_threshold", post(stock::get_stock_threshold))
+        .route("/set_stock_threshold", post(stock::set_stock_threshold))
+        .route("/get_low_stock", post(stock::get_low_stock))
+        .route("/get_low_stock_csv", post(stock::get_low_stock_csv))
+        .route("/get_labels", post(labels::get_labels))
+        .route("/resolve_code", post(scan::resolve_code))
+        .route("/scan_code_image", post(scan::scan_code_image))
         .route("/upload_image", post(upload_image))
+        .route("/upload_gallery_image", post(gallery::upload_gallery_image))
         .route("/images", post(serve_image))
         .route("/get_backup", post(backup::serve_backup))
+        .layer(ServiceBuilder::new().layer(middleware::from_fn_with_state(
+            state.clone(),
+            migrations::migrate_user_database,
+        )))
         .layer(ServiceBuilder::new().layer(middleware::from_fn_with_state(
             state.clone(),
             auth_middleware,
@@ -245,6 +367,7 @@ async fn main() -> Result<(), sqlx::Error> {
 
     let different_rate_routes = Router::new()
         .route("/upload_backup", post(backup::restore_to_user_backup))
+        .route("/merge_backup", post(backup::merge_backup))
         .layer(ServiceBuilder::new().layer(middleware::from_fn_with_state(
             state.clone(),
             auth_middleware,
@@ -281,20 +404,9 @@ async fn setup_master_database(connection_string: &str) -> PgPool {
             connect_with_retry(connection_string).await.unwrap()
         }
     };
-    sqlx::query(
-        r#"
-        CREATE TABLE IF NOT EXISTS users (
-            id TEXT PRIMARY KEY,
-            username TEXT NOT NULL UNIQUE,
-            email TEXT NOT NULL UNIQUE,
-            password_hash TEXT NOT NULL,
-            connection_string TEXT NOT NULL
-            )
-        "#,
-    )
-    .execute(&pool)
-    .await
-    .unwrap();
+    migrations::migrate(&pool, migrations::DatabaseKind::Master)
+        .await
+        .unwrap();
     return pool;
 }
 
@@ -312,132 +424,343 @@ async fn get_multiple_items(
     Json(payload): Json<IdVectorRequest>,
 ) -> Result<Json<Vec<ContainedItem>>, StatusCode> {
     println!("in get multiple items");
-    let rows = sqlx::query("SELECT * FROM items WHERE id = ANY($1)")
-        .bind(payload.id_vec)
-        .fetch_all(&*user.db_pool)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let rows = sqlx::query(&format!(
+        "SELECT *, {} FROM items WHERE id = ANY($1)",
+        ITEM_QUANTITY_COLUMN
+    ))
+    .bind(payload.id_vec)
+    .fetch_all(&*user.db_pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
-    let items = rows
-        .into_iter()
-        .map(|row: PgRow| {
-            let mut item = ContainedItem::default();
-            row.columns().iter().for_each(|column| {
-                match (column.name(), &column.type_info().to_string() as &str) {
-                    ("id", "TEXT") => item.id = row.get("id"),
-                    ("name", "TEXT") => item.name = row.get("name"),
-                    ("image_type", "TEXT") => item.image_type = row.get("image_type"),
-                    (var, "TEXT") => item
-                        .string_vars
-                        .push(row.try_get(var).unwrap_or(String::default())),
-                    (var, "INT4") => item.int_vars.push(row.try_get(var).unwrap_or(0)),
-                    (var, "FLOAT4") => item.float_vars.push(row.try_get(var).unwrap_or(0.0)),
-                    _ => (),
-                }
-            });
-            return item;
-        })
-        .collect();
+    let items = rows.iter().map(pgrow_to_item).collect();
     Ok(Json(items))
 }
 
+//Field names can't have an underscore so the sum never clashes with a dynamic field
+const ITEM_QUANTITY_COLUMN: &str = r#"
+    (SELECT COALESCE(SUM(p.quantity), 0) FROM item_placements p WHERE p.item_id = items.id) AS total_quantity
+"#;
+
+//Dynamic fields are pushed in column order, dates are sent as text like the other string fields
+fn pgrow_to_item(row: &PgRow) -> ContainedItem {
+    let mut item = ContainedItem::default();
+    row.columns().iter().for_each(|column| {
+        match (column.name(), &column.type_info().to_string() as &str) {
+            ("id", "TEXT") => item.id = row.get("id"),
+            ("name", "TEXT") => item.name = row.get("name"),
+            ("image_type", "TEXT") => item.image_type = row.get("image_type"),
+            ("total_quantity", "INT8") => item.quantity = row.get("total_quantity"),
+            (var, "TEXT") => item
+                .string_vars
+                .push(row.try_get(var).unwrap_or(String::default())),
+            (var, "INT4") => item.int_vars.push(row.try_get(var).unwrap_or(0)),
+            (var, "FLOAT4") => item.float_vars.push(row.try_get(var).unwrap_or(0.0)),
+            (var, "DATE") => item.string_vars.push(
+                row.try_get::<Option<NaiveDate>, _>(var)
+                    .ok()
+                    .flatten()
+                    .map(|x| x.format("%Y-%m-%d").to_string())
+                    .unwrap_or_default(),
+            ),
+            (var, "TIMESTAMPTZ") => item.string_vars.push(
+                row.try_get::<Option<DateTime<Utc>>, _>(var)
+                    .ok()
+                    .flatten()
+                    .map(|x| x.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+                    .unwrap_or_default(),
+            ),
+            //Money is sent as text too, a float would lose cents
+            (var, "NUMERIC") => item.string_vars.push(
+                row.try_get::<Option<Decimal>, _>(var)
+                    .ok()
+                    .flatten()
+                    .map(|x| format!("{:.2}", x))
+                    .unwrap_or_default(),
+            ),
+            _ => (),
+        }
+    });
+    return item;
+}
+
+//The WHERE and ORDER BY of a search, shared by the items list and the export
+struct ItemSearch {
+    where_clause: String,
+    params: Vec<search::SearchParam>,
+    order_by: String,
+    matched_fields_column: String,
+}
+
+fn item_search(
+    mut payload: SearchItemsRequest,
+    fields: &[(String, DataType)],
+) -> Result<ItemSearch, (StatusCode, String)> {
+    //The searched column has to be the name or a field, the name is taken from dynamic_fields
+    payload.column_name = match search::find_field(&payload.column_name, fields) {
+        Some((column, _)) => column,
+        None => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Unknown column '{}'", payload.column_name),
+            ));
+        }
+    };
+    //Only columns that exist can be sorted on, the name is taken from dynamic_fields
+    let sort_column = match &payload.sort_column {
+        Some(sort_column) => match search::find_field(sort_column, fields) {
+            Some((column, _)) => Some(column),
+            None => {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("Unknown sort column '{}'", sort_column),
+                ));
+            }
+        },
+        None => None,
+    };
+    let sort_direction = match payload.sort_direction {
+        SortDirection::Ascending => "ASC",
+        SortDirection::Descending => "DESC",
+    };
+    let mut matched_fields_column = "ARRAY[]::text[]".to_string();
+    let mut rank_order = None;
+    //Filters like `Weight > 2.5 AND Checked` are compiled, plain text searches the selected column
+    let (where_clause, params) = if search::is_filter(&payload.search_string, fields) {
+        search::compile(&payload.search_string, fields)
+            .map_err(|e| (StatusCode::BAD_REQUEST, e))?
+    } else if payload.all_fields {
+        match search::prefix_tsquery(&payload.search_string) {
+            Some(tsquery) => {
+                matched_fields_column = search::matched_fields_sql(fields, 1);
+                rank_order = Some("ts_rank(search_vector, to_tsquery('simple', $1)) DESC");
+                (
+                    "search_vector @@ to_tsquery('simple', $1)".to_string(),
+                    vec![search::SearchParam::Text(tsquery)],
+                )
+            }
+            None => ("TRUE".to_string(), Vec::new()),
+        }
+    } else {
+        (
+            format!(
+                r#""{}"::text ILIKE '%'|| $1 || '%'"#,
+                payload.column_name
+            ),
+            vec![search::SearchParam::Text(payload.search_string)],
+        )
+    };
+    //Clicking a column sorts by it, otherwise the best matches of an all fields search come first
+    let order_by = match (sort_column, rank_order) {
+        (Some(column), _) => format!(r#""{}" {} NULLS LAST"#, column, sort_direction),
+        (None, Some(rank_order)) => format!("{}, name", rank_order),
+        (None, None) => format!("name {} NULLS LAST", sort_direction),
+    };
+    Ok(ItemSearch {
+        where_clause,
+        params,
+        order_by,
+        matched_fields_column,
+    })
+}
+
 async fn search_items(
     Extension(user): Extension<UserContext>,
-    Json(mut payload): Json<SearchItemsRequest>,
-) -> Result<Json<Vec<ContainedItem>>, StatusCode> {
-    if payload.column_name.chars().any(|x| !x.is_alphanumeric()) {
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
-    }
-    if payload.column_name == "Name" {
-        payload.column_name = "name".to_string();
+    Json(payload): Json<SearchItemsRequest>,
+) -> Result<Json<SearchItemsResponse>, (StatusCode, String)> {
+    let fields = match read_dynamic_fields(&*user.db_pool).await {
+        Ok(fields) => fields,
+        Err(e) => {
+            println!("Searching items fields error {}", e);
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to read fields".to_string(),
+            ));
+        }
+    };
+    let limit = payload.limit;
+    let offset = payload.offset;
+    //Postgres fails on a negative offset or limit, the request is wrong and not the search
+    if offset < 0 || limit.is_some_and(|x| x < 0) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "The offset and the limit can't be negative".to_string(),
+        ));
     }
-
+    let ItemSearch {
+        where_clause,
+        params,
+        order_by,
+        matched_fields_column,
+    } = item_search(payload, &fields)?;
+
+    let count_query = format!("SELECT COUNT(*) AS total FROM items WHERE {}", where_clause);
+    let total: i64 = match search::bind_params(sqlx::query(&count_query), &params)
+        .fetch_one(&*user.db_pool)
+        .await
+    {
+        Ok(row) => row.get("total"),
+        Err(e) => {
+            println!("Counting items error {}", e);
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Search failed".to_string(),
+            ));
+        }
+    };
+    //The id keeps the order stable between pages when the sorted values are equal
     let query = format!(
-        r#"SELECT * FROM items WHERE "{}"::text ILIKE '%'|| $2 || '%'"#,
-        payload.column_name
+        r#"SELECT *, {}, {} AS matched_fields FROM items WHERE {} ORDER BY {}, id LIMIT ${} OFFSET ${}"#,
+        ITEM_QUANTITY_COLUMN,
+        matched_fields_column,
+        where_clause,
+        order_by,
+        params.len() + 1,
+        params.len() + 2
     );
-    match sqlx::query(&query)
-        .bind(payload.column_name)
-        .bind(payload.search_string)
+    match search::bind_params(sqlx::query(&query), &params)
+        .bind(limit)
+        .bind(offset.max(0))
         .fetch_all(&*user.db_pool)
         .await
     {
         Ok(rows) => {
-            let items = rows
-                .into_iter()
-                .map(|row: PgRow| {
-                    let mut item = ContainedItem::default();
-                    row.columns().iter().for_each(|column| {
-                        match (column.name(), &column.type_info().to_string() as &str) {
-                            ("id", "TEXT") => item.id = row.get("id"),
-                            ("name", "TEXT") => item.name = row.get("name"),
-                            ("image_type", "TEXT") => item.image_type = row.get("image_type"),
-                            (var, "TEXT") => item
-                                .string_vars
-                                .push(row.try_get(var).unwrap_or(String::default())),
-                            (var, "INT4") => item.int_vars.push(row.try_get(var).unwrap_or(0)),
-                            (var, "FLOAT4") => {
-                                item.float_vars.push(row.try_get(var).unwrap_or(0.0))
-                            }
-
-                            _ => (),
-                        }
-                    });
-                    return item;
-                })
+            let matched_fields = rows
+                .iter()
+                .map(|row| row.try_get("matched_fields").unwrap_or_default())
                 .collect();
-            Ok(Json(items))
+            let items = rows.iter().map(pgrow_to_item).collect();
+            Ok(Json(SearchItemsResponse {
+                items,
+                total,
+                matched_fields,
+            }))
         }
         Err(e) => {
             println!("Searching items error {}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Search failed".to_string(),
+            ))
         }
     }
 }
 
+//The first container the item is placed in and its location, the location is the container right below the root
+//Kept for older clients, get_item_ancestors returns the whole path
 async fn get_item_container_location(
     Extension(user): Extension<UserContext>,
     Json(payload): Json<IdRequest>,
 ) -> Result<Json<Option<(Container, Container)>>, StatusCode> {
     println!("in get item container");
-    let row = sqlx::query("SELECT * FROM containers WHERE contained_items LIKE '%' || $1 || '%'")
-        .bind(payload.id)
-        .fetch_optional(&*user.db_pool)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    match row {
-        Some(container_row) => {
-            let container = pgrow_to_container(container_row);
-            let row = sqlx::query("SELECT * FROM containers WHERE slaves LIKE '%' || $1 || '%'")
-                .bind(&container.id)
-                .fetch_optional(&*user.db_pool)
-                .await
-                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let path = match item_container_path(&user.db_pool, &payload.id).await {
+        Ok(path) => path,
+        Err(e) => {
+            println!("Get item container location error {}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let mut path = path.into_iter();
+    let first = path.next();
+    let location = path.next();
+    //The item's container is the last one, or the location itself
+    match (first, location) {
+        (Some(_), Some(location)) => {
+            let container = path.last().unwrap_or_else(|| location.clone());
+            Ok(Json(Some((container, location))))
+        }
+        (Some(root), None) => Ok(Json(Some((root.clone(), root)))),
+        _ => Ok(Json(None)),
+    }
+}
 
-            match row {
-                Some(location_row) => {
-                    return Ok(Json(Some((container, pgrow_to_container(location_row)))));
-                }
-                None => return Ok(Json(None)),
-            }
+//Every container from the root down to the one the item is placed in, empty for items not in a container
+async fn get_item_ancestors(
+    Extension(user): Extension<UserContext>,
+    Json(payload): Json<IdRequest>,
+) -> Result<Json<Vec<Container>>, StatusCode> {
+    println!("in get item ancestors");
+    match item_container_path(&user.db_pool, &payload.id).await {
+        Ok(path) => Ok(Json(path)),
+        Err(e) => {
+            println!("Get item ancestors error {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
-        None => return Ok(Json(None)),
     }
 }
 
+//Items placed in more than one container use the first one by id
+async fn item_container_path(pool: &PgPool, item_id: &str) -> Result<Vec<Container>, sqlx::Error> {
+    let container_id: Option<String> = sqlx::query_scalar(
+        "SELECT container_id FROM item_placements WHERE item_id = $1 ORDER BY container_id LIMIT 1",
+    )
+    .bind(item_id)
+    .fetch_optional(pool)
+    .await?;
+    match container_id {
+        Some(container_id) => container_path(pool, &container_id).await,
+        None => Ok(Vec::new()),
+    }
+}
+
+//Every container from the root down to the given one, empty if it doesn't exist
+async fn container_path<'e, E: PgExecutor<'e>>(
+    executor: E,
+    container_id: &str,
+) -> Result<Vec<Container>, sqlx::Error> {
+    //The depth limit stops a parent_id loop from recursing forever
+    let query = format!(
+        r#"
+        WITH RECURSIVE chain AS (
+            SELECT id, parent_id, 0 AS depth FROM containers WHERE id = $1
+            UNION ALL
+            SELECT c.id, c.parent_id, ch.depth + 1 FROM containers c
+            JOIN chain ch ON c.id = ch.parent_id
+            WHERE ch.depth < 100
+        )
+        {} JOIN chain ON chain.id = c.id
+        ORDER BY chain.depth DESC
+        "#,
+        CONTAINER_SELECT
+    );
+    let rows = sqlx::query(&query)
+        .bind(container_id)
+        .fetch_all(executor)
+        .await?;
+    Ok(rows.into_iter().map(pgrow_to_container).collect())
+}
+
+//Containers keep their old json shape, master, slaves and contained_items are built from parent_id and item_placements
+const CONTAINER_SELECT: &str = r#"
+    SELECT c.id, c.name, COALESCE(c.parent_id, '') AS master, c.corners, c.image_type,
+    c.polygon, c.rotation,
+    ARRAY(SELECT s.id FROM containers s WHERE s.parent_id = c.id) AS slaves,
+    ARRAY(SELECT p.item_id FROM item_placements p WHERE p.container_id = c.id ORDER BY p.item_id) AS contained_items,
+    ARRAY(SELECT p.quantity FROM item_placements p WHERE p.container_id = c.id ORDER BY p.item_id) AS item_quantities
+    FROM containers c
+"#;
+
 fn pgrow_to_container(container_row: PgRow) -> Container {
-    let slaves: String = container_row.get("slaves");
+    let slaves: Vec<String> = container_row.get("slaves");
     let corners: String = container_row.get("corners");
-    let contained_items: String = container_row.get("contained_items");
+    let polygon: String = container_row.get("polygon");
+    let contained_items: Vec<String> = container_row.get("contained_items");
+    let item_quantities: Vec<i32> = container_row.get("item_quantities");
     let container = Container {
         id: container_row.get("id"),
         master: container_row.get("master"),
         image_type: container_row.get("image_type"),
         name: container_row.get("name"),
-        slaves: string_to_set(&slaves),
+        slaves: slaves.into_iter().collect(),
         corners: string_to_corners(&corners),
-        contained_items: string_to_set(&contained_items),
+        item_quantities: contained_items
+            .iter()
+            .cloned()
+            .zip(item_quantities)
+            .collect(),
+        contained_items: contained_items.into_iter().collect(),
+        polygon: string_to_polygon(&polygon),
+        rotation: container_row.get("rotation"),
     };
     return container;
 }
@@ -447,6 +770,41 @@ enum DynamicFieldValue {
     Integer(i32),
     Float(f32),
     Text(String),
+    Date(Option<NaiveDate>),
+    DateTime(Option<DateTime<Utc>>),
+    Money(Option<Decimal>),
+}
+
+//An empty string clears the date, anything else has to be YYYY-MM-DD
+fn parse_date(value: &str) -> Result<Option<NaiveDate>, StatusCode> {
+    if value.trim().is_empty() {
+        return Ok(None);
+    }
+    NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d")
+        .map(Some)
+        .map_err(|_| StatusCode::BAD_REQUEST)
+}
+
+//Amounts are sent as text like 12.50 and stored with 2 decimals
+fn parse_money(value: &str) -> Result<Option<Decimal>, StatusCode> {
+    if value.trim().is_empty() {
+        return Ok(None);
+    }
+    value
+        .trim()
+        .parse::<Decimal>()
+        .map(|x| Some(x.round_dp(2)))
+        .map_err(|_| StatusCode::BAD_REQUEST)
+}
+
+//Date-times are sent as RFC 3339 and stored in UTC
+fn parse_date_time(value: &str) -> Result<Option<DateTime<Utc>>, StatusCode> {
+    if value.trim().is_empty() {
+        return Ok(None);
+    }
+    DateTime::parse_from_rfc3339(value.trim())
+        .map(|x| Some(x.with_timezone(&Utc)))
+        .map_err(|_| StatusCode::BAD_REQUEST)
 }
 
 async fn insert_item(
@@ -483,6 +841,24 @@ async fn insert_item(
                 value_vec.push(DynamicFieldValue::Integer(payload.item.int_vars[i32_index]));
                 i32_index += 1;
             }
+            DataType::Date => {
+                value_vec.push(DynamicFieldValue::Date(parse_date(
+                    &payload.item.string_vars[string_index],
+                )?));
+                string_index += 1;
+            }
+            DataType::DateTime => {
+                value_vec.push(DynamicFieldValue::DateTime(parse_date_time(
+                    &payload.item.string_vars[string_index],
+                )?));
+                string_index += 1;
+            }
+            DataType::Money { .. } => {
+                value_vec.push(DynamicFieldValue::Money(parse_money(
+                    &payload.item.string_vars[string_index],
+                )?));
+                string_index += 1;
+            }
         }
     }
     // Create the query
@@ -497,25 +873,10 @@ async fn insert_item(
         column_clause, value_clause
     );
 
-    //Add item_id to containers containeditems
-    let container_query = sqlx::query(
-        r#"
-    UPDATE containers
-    SET contained_items = contained_items || $1 || '@'
-    WHERE id = $2
-    "#,
-    )
-    .bind(payload.item.id)
-    .bind(payload.container_id)
-    .execute(&*user.db_pool)
-    .await
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-    .rows_affected();
-
-    if container_query != 1 {
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
-    }
-
+    let mut tx = user.db_pool.begin().await.map_err(|e| {
+        println!("Insert item begin transaction error: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
     //Add the row to the items table
     let mut query = sqlx::query(&items_query);
     //Bind to values
@@ -524,13 +885,34 @@ async fn insert_item(
             DynamicFieldValue::Text(val) => query = query.bind(val),
             DynamicFieldValue::Integer(val) => query = query.bind(val),
             DynamicFieldValue::Float(val) => query = query.bind(val),
+            DynamicFieldValue::Date(val) => query = query.bind(val),
+            DynamicFieldValue::DateTime(val) => query = query.bind(val),
+            DynamicFieldValue::Money(val) => query = query.bind(val),
         };
     }
-    match query.execute(&*user.db_pool).await {
-        Ok(_) => Ok(StatusCode::CREATED),
+    if let Err(e) = query.execute(&mut *tx).await {
+        println!("Insert item error: {}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
 
+    //Place the item in its container
+    if let Err(e) = sqlx::query(
+        r#"
+    INSERT INTO item_placements (item_id, container_id) VALUES ($1, $2)
+    "#,
+    )
+    .bind(payload.item.id)
+    .bind(payload.container_id)
+    .execute(&mut *tx)
+    .await
+    {
+        println!("Insert item placement error: {}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    match tx.commit().await {
+        Ok(_) => Ok(StatusCode::CREATED),
         Err(e) => {
-            println!("Insert item error: {}", e);
+            println!("Insert item commit error: {}", e);
             Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
@@ -547,19 +929,46 @@ async fn update_item(
     column_names.push("name".into());
     value_vec.push(DynamicFieldValue::Text(payload.item.image_type));
     column_names.push("image_type".into());
+    let mut tx = user.db_pool.begin().await.map_err(|e| {
+        println!("Update item begin transaction error: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let previous_gallery_entries = match gallery::item_gallery_entries(
+        &mut tx,
+        std::slice::from_ref(&payload.item.id),
+    )
+    .await
+    {
+        Ok(entries) => entries,
+        Err(e) => {
+            println!("Update item gallery error: {}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
     let mut string_index: usize = 0;
     let mut i32_index: usize = 0;
     let mut f32_index: usize = 0;
+    //Gallery images still referenced after the update, the others are removed once it's committed
+    let mut kept_gallery_images: Vec<String> = Vec::new();
     //Adding each dynamic fields name and value
     for (column_name, column_type) in payload.item_columns_names_types {
         column_names.push(column_name.clone());
         match column_type {
-            DataType::String | DataType::List(_) | DataType::Text | DataType::Gallery => {
+            DataType::String | DataType::List(_) | DataType::Text => {
                 value_vec.push(DynamicFieldValue::Text(
                     payload.item.string_vars[string_index].clone(),
                 ));
                 string_index += 1;
             }
+            DataType::Gallery => {
+                let entries = gallery::owned_entries(
+                    &payload.item.string_vars[string_index],
+                    &previous_gallery_entries,
+                );
+                kept_gallery_images.extend(gallery::entry_ids(&entries));
+                value_vec.push(DynamicFieldValue::Text(entries));
+                string_index += 1;
+            }
             DataType::Float | DataType::Percentage => {
                 value_vec.push(DynamicFieldValue::Float(payload.item.float_vars[f32_index]));
                 f32_index += 1;
@@ -568,6 +977,24 @@ async fn update_item(
                 value_vec.push(DynamicFieldValue::Integer(payload.item.int_vars[i32_index]));
                 i32_index += 1;
             }
+            DataType::Date => {
+                value_vec.push(DynamicFieldValue::Date(parse_date(
+                    &payload.item.string_vars[string_index],
+                )?));
+                string_index += 1;
+            }
+            DataType::DateTime => {
+                value_vec.push(DynamicFieldValue::DateTime(parse_date_time(
+                    &payload.item.string_vars[string_index],
+                )?));
+                string_index += 1;
+            }
+            DataType::Money { .. } => {
+                value_vec.push(DynamicFieldValue::Money(parse_money(
+                    &payload.item.string_vars[string_index],
+                )?));
+                string_index += 1;
+            }
         }
     }
     // Create the query
@@ -590,15 +1017,25 @@ async fn update_item(
             DynamicFieldValue::Text(val) => query = query.bind(val),
             DynamicFieldValue::Integer(val) => query = query.bind(val),
             DynamicFieldValue::Float(val) => query = query.bind(val),
+            DynamicFieldValue::Date(val) => query = query.bind(val),
+            DynamicFieldValue::DateTime(val) => query = query.bind(val),
+            DynamicFieldValue::Money(val) => query = query.bind(val),
         };
     }
-    match query.bind(payload.item.id).execute(&*user.db_pool).await {
-        Ok(_) => Ok(StatusCode::OK),
-        Err(e) => {
-            println!("Update item error: {}", e);
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+    if let Err(e) = query.bind(payload.item.id).execute(&mut *tx).await {
+        println!("Update item error: {}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    if let Err(e) = tx.commit().await {
+        println!("Update item commit error: {}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    for image_id in gallery::entry_ids(&previous_gallery_entries.join("@")) {
+        if !kept_gallery_images.contains(&image_id) {
+            remove_image(&user.user_id, &image_id).await;
         }
     }
+    Ok(StatusCode::OK)
 }
 
 async fn delete_item(
@@ -606,159 +1043,383 @@ async fn delete_item(
     Json(payload): Json<DeleteItemRequest>,
 ) -> Result<StatusCode, StatusCode> {
     println!("in delete item");
-    //Remove the items image
-    remove_image(&user.user_id, &payload.item.id).await;
-    let container_query = sqlx::query(
+    let mut tx = user.db_pool.begin().await.map_err(|e| {
+        println!("Delete item begin transaction error: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    if let Err(e) = sqlx::query(
         r#"
-        UPDATE containers
-        SET
-        contained_items = REPLACE(contained_items, $1, '')
-        WHERE id = $2
+        DELETE FROM item_placements WHERE item_id = $1 AND container_id = $2
         "#,
     )
-    .bind(payload.item.id.clone() + "@")
+    .bind(&payload.item.id)
     .bind(&payload.container_id)
-    .execute(&*user.db_pool)
-    .await;
-
-    if container_query.is_err() {
-        println!("Delete item error 1: {}", container_query.unwrap_err());
+    .execute(&mut *tx)
+    .await
+    {
+        println!("Delete item error 1: {}", e);
         return Err(StatusCode::INTERNAL_SERVER_ERROR);
     }
+    let mut gallery_images = Vec::new();
     if payload.delete_from_items {
-        match sqlx::query(
+        gallery_images =
+            match gallery::item_gallery_images(&mut tx, std::slice::from_ref(&payload.item.id))
+                .await
+            {
+                Ok(images) => images,
+                Err(e) => {
+                    println!("Delete item gallery error: {}", e);
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR);
+                }
+            };
+        if let Err(e) = sqlx::query(
             r#"
             DELETE FROM items WHERE id = $1
             "#,
         )
-        .bind(payload.item.id)
-        .execute(&*user.db_pool)
+        .bind(&payload.item.id)
+        .execute(&mut *tx)
         .await
         {
-            Ok(_) => {
-                return Ok(StatusCode::OK);
-            }
-            Err(e) => {
-                println!("Update item error: {}", e);
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
-            }
+            println!("Delete item error 2: {}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
         }
     }
-
+    if let Err(e) = tx.commit().await {
+        println!("Delete item commit error: {}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    //The image is only removed once the row is gone, so a failed delete keeps it
+    if payload.delete_from_items {
+        remove_image(&user.user_id, &payload.item.id).await;
+    }
+    for image_id in gallery_images {
+        remove_image(&user.user_id, &image_id).await;
+    }
     Ok(StatusCode::OK)
 }
 
-async fn add_container(
+//Moves the placements in one transaction so a failed move never leaves an item unplaced
+//Quantities go along with the items and are added to what the target already holds
+async fn move_items(
     Extension(user): Extension<UserContext>,
-    Json(payload): Json<ContainerRequest>,
-) -> Result<StatusCode, StatusCode> {
-    println!("in add container");
-    let serialized_slaves = set_to_string(&payload.container.slaves);
-    let serialized_corners = corners_to_string(&payload.container.corners);
-    let serialized_items = set_to_string(&payload.container.contained_items);
-    match sqlx::query(
+    Json(payload): Json<MoveItemsRequest>,
+) -> Result<Json<MoveItemsResponse>, StatusCode> {
+    println!("in move items");
+    if payload.from_container_id == payload.container_id {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let mut tx = user.db_pool.begin().await.map_err(|e| {
+        println!("Move items begin transaction error: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    //Items can't be placed in the root, only in a location or a container inside one
+    match sqlx::query("SELECT parent_id FROM containers WHERE id = $1 FOR UPDATE")
+        .bind(&payload.container_id)
+        .fetch_optional(&mut *tx)
+        .await
+    {
+        Ok(Some(row)) if row.get::<Option<String>, _>("parent_id").is_some() => {}
+        Ok(Some(_)) => return Err(StatusCode::BAD_REQUEST),
+        Ok(None) => return Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            println!("Move items target error {}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+    let items_moved = match sqlx::query(
         r#"
-        UPDATE containers
-        SET slaves = slaves || $1 || '@'
-        WHERE id = $2
+        WITH moved AS (
+            DELETE FROM item_placements
+            WHERE item_id = ANY($1) AND container_id <> $3
+            AND (NULLIF($2, '') IS NULL OR container_id = $2)
+            RETURNING item_id, quantity
+        )
+        INSERT INTO item_placements (item_id, container_id, quantity)
+        SELECT item_id, $3, SUM(quantity) FROM moved GROUP BY item_id
+        ON CONFLICT (item_id, container_id)
+        DO UPDATE SET quantity = item_placements.quantity + EXCLUDED.quantity
         "#,
     )
-    .bind(&payload.container.id)
-    .bind(&payload.container.master)
-    .execute(&*user.db_pool)
+    .bind(&payload.id_vec)
+    .bind(&payload.from_container_id)
+    .bind(&payload.container_id)
+    .execute(&mut *tx)
     .await
     {
-        Ok(_) => {
-            match sqlx::query(
-                r#"
-                INSERT INTO containers (id,name,master,slaves,corners,image_type,contained_items) VALUES ($1, $2, $3, $4, $5, $6, $7) ON CONFLICT DO NOTHING"#,
-            ).bind(&payload.container.id)
-            .bind(payload.container.name)
-            .bind(payload.container.master)
-            .bind(serialized_slaves)
-            .bind(serialized_corners)
-            .bind(payload.container.image_type)
-            .bind(serialized_items)
-            .execute(&*user.db_pool)
-            .await
-            {
-                Ok(_)=>{
-                return Ok(StatusCode::OK);}
-                Err(e) =>{
-
-                    println!("Add container error {}", e);
-                    return Err(StatusCode::INTERNAL_SERVER_ERROR);
-                }
-            }
-        }
-        Err(e) =>{
-            println!("Adding container to master's slaves error {}", e);
+        Ok(result) => result.rows_affected(),
+        Err(e) => {
+            println!("Move items error {}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    //Items that weren't placed anywhere are placed in the target with one unit
+    let items_placed = match sqlx::query(
+        r#"
+        INSERT INTO item_placements (item_id, container_id, quantity)
+        SELECT i.id, $2, 1 FROM items i
+        WHERE i.id = ANY($1)
+        AND NOT EXISTS (SELECT 1 FROM item_placements p WHERE p.item_id = i.id)
+        "#,
+    )
+    .bind(&payload.id_vec)
+    .bind(&payload.container_id)
+    .execute(&mut *tx)
+    .await
+    {
+        Ok(result) => result.rows_affected(),
+        Err(e) => {
+            println!("Move items placing error {}", e);
             return Err(StatusCode::INTERNAL_SERVER_ERROR);
         }
+    };
+    match tx.commit().await {
+        Ok(_) => Ok(Json(MoveItemsResponse {
+            items_moved: items_moved + items_placed,
+        })),
+        Err(e) => {
+            println!("Move items commit error {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
     }
 }
 
-async fn delete_container(
+//The contained items with the quantity sent for each of them, None when the client didn't send one
+fn placement_quantities(
+    container: &Container,
+) -> Result<(Vec<String>, Vec<Option<i32>>), StatusCode> {
+    if container.item_quantities.values().any(|x| *x < 0) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let contained_items: Vec<String> = container.contained_items.iter().cloned().collect();
+    let quantities = contained_items
+        .iter()
+        .map(|x| container.item_quantities.get(x).copied())
+        .collect();
+    Ok((contained_items, quantities))
+}
+
+async fn add_container(
     Extension(user): Extension<UserContext>,
     Json(payload): Json<ContainerRequest>,
 ) -> Result<StatusCode, StatusCode> {
+    println!("in add container");
+    let serialized_corners = corners_to_string(&payload.container.corners);
+    let (contained_items, quantities) = placement_quantities(&payload.container)?;
+    let mut tx = user.db_pool.begin().await.map_err(|e| {
+        println!("Add container begin transaction error: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    if let Err(e) = sqlx::query(
+        r#"
+        INSERT INTO containers (id,name,parent_id,corners,image_type,polygon,rotation) VALUES ($1, $2, NULLIF($3, ''), $4, $5, $6, $7) ON CONFLICT DO NOTHING"#,
+    ).bind(&payload.container.id)
+    .bind(payload.container.name)
+    .bind(payload.container.master)
+    .bind(serialized_corners)
+    .bind(payload.container.image_type)
+    .bind(polygon_to_string(&payload.container.polygon))
+    .bind(payload.container.rotation)
+    .execute(&mut *tx)
+    .await
+    {
+        println!("Add container error {}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    if let Err(e) = sqlx::query(
+        r#"
+        INSERT INTO item_placements (item_id, container_id, quantity)
+        SELECT i.id, $1, COALESCE(q.quantity, 1)
+        FROM unnest($2::text[], $3::int4[]) AS q(item_id, quantity)
+        JOIN items i ON i.id = q.item_id
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(&payload.container.id)
+    .bind(&contained_items)
+    .bind(&quantities)
+    .execute(&mut *tx)
+    .await
+    {
+        println!("Adding container's items error {}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    match tx.commit().await {
+        Ok(_) => Ok(StatusCode::OK),
+        Err(e) => {
+            println!("Add container commit error {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn delete_container(
+    Extension(user): Extension<UserContext>,
+    Json(payload): Json<DeleteContainerRequest>,
+) -> Result<Json<DeleteContainerResponse>, StatusCode> {
     println!("in delete container");
-    //Remove the container from its master
-    match sqlx::query(
+    let mut response = DeleteContainerResponse::default();
+    let mut tx = user.db_pool.begin().await.map_err(|e| {
+        println!("Delete container begin transaction error: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    //The container and every container below it, however deep
+    let subtree = match sqlx::query(
         r#"
-        UPDATE containers
-        SET
-        slaves = REPLACE(slaves, $1, '')
-        WHERE id = $2
+        WITH RECURSIVE subtree AS (
+            SELECT id, parent_id, image_type FROM containers WHERE id = $1
+            UNION ALL
+            SELECT c.id, c.parent_id, c.image_type FROM containers c JOIN subtree s ON c.parent_id = s.id
+        )
+        SELECT id, COALESCE(parent_id, '') AS parent_id, COALESCE(image_type, '') AS image_type FROM subtree
         "#,
     )
-    .bind(payload.container.id.clone() + "@")
-    .bind(&payload.container.master)
-    .execute(&*user.db_pool)
+    .bind(&payload.container.id)
+    .fetch_all(&mut *tx)
     .await
     {
-        Ok(_) => {
-            //Remove the containers image
-            remove_image(&user.user_id, &payload.container.id).await;
-            //Remove the containers slaves
-            for slave_id in payload.container.slaves {
-                match sqlx::query(
-                    r#"
-                    DELETE FROM containers WHERE id = $1
-                    "#,
-                )
-                .bind(slave_id)
-                .execute(&*user.db_pool)
-                .await
-                {
-                    Ok(_) => (),
-                    Err(e) => println!("Delete container's slaves error {}", e),
+        Ok(rows) => rows,
+        Err(e) => {
+            println!("Delete container subtree error {}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let subtree_ids: Vec<String> = subtree.iter().map(|x| x.get("id")).collect();
+    //Ids whose image files are removed once the transaction commits
+    let mut images_to_remove: Vec<String> = subtree
+        .iter()
+        .filter(|x| !x.get::<String, _>("image_type").is_empty())
+        .map(|x| x.get("id"))
+        .collect();
+    let parent_id: String = match subtree
+        .iter()
+        .find(|x| x.get::<String, _>("id") == payload.container.id)
+    {
+        Some(row) => row.get("parent_id"),
+        None => String::new(),
+    };
+    //Items can't be placed in the root, so a location's items can't move to its parent
+    if payload.item_policy == ItemPolicy::MoveToParent {
+        match sqlx::query("SELECT parent_id FROM containers WHERE id = $1")
+            .bind(&parent_id)
+            .fetch_optional(&mut *tx)
+            .await
+        {
+            Ok(Some(row)) if row.get::<Option<String>, _>("parent_id").is_some() => {}
+            Ok(_) => return Err(StatusCode::BAD_REQUEST),
+            Err(e) => {
+                println!("Delete container parent error {}", e);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        }
+    }
+
+    //Items placed somewhere in the subtree that aren't also placed outside of it
+    let orphaned_items: Vec<String> = match sqlx::query(
+        r#"
+        SELECT DISTINCT p.item_id FROM item_placements p
+        WHERE p.container_id = ANY($1)
+        AND NOT EXISTS (
+            SELECT 1 FROM item_placements o WHERE o.item_id = p.item_id AND o.container_id <> ALL($1)
+        )
+        "#,
+    )
+    .bind(&subtree_ids)
+    .fetch_all(&mut *tx)
+    .await
+    {
+        Ok(rows) => rows.iter().map(|x| x.get("item_id")).collect(),
+        Err(e) => {
+            println!("Delete container orphaned items error {}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    match payload.item_policy {
+        ItemPolicy::DeleteItems => {
+            match gallery::item_gallery_images(&mut tx, &orphaned_items).await {
+                Ok(images) => images_to_remove.extend(images),
+                Err(e) => {
+                    println!("Delete container's items gallery error {}", e);
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR);
                 }
             }
-            //Remove the container row
+            //Items that are also placed outside the subtree are kept, they only lose these placements
             match sqlx::query(
                 r#"
-                DELETE FROM containers WHERE id = $1
+                DELETE FROM items WHERE id = ANY($1)
+                RETURNING id, COALESCE(image_type, '') AS image_type
                 "#,
             )
-            .bind(&payload.container.id)
-            .execute(&*user.db_pool)
+            .bind(&orphaned_items)
+            .fetch_all(&mut *tx)
             .await
             {
-                Ok(_) => {
-                    return Ok(StatusCode::OK);
+                Ok(rows) => {
+                    response.items_deleted = rows.len() as u64;
+                    for row in rows {
+                        if !row.get::<String, _>("image_type").is_empty() {
+                            images_to_remove.push(row.get("id"));
+                        }
+                    }
                 }
                 Err(e) => {
-                    println!("Delete container error {}", e);
+                    println!("Delete container's items error {}", e);
                     return Err(StatusCode::INTERNAL_SERVER_ERROR);
                 }
             }
         }
+        //The parent was checked above, it's a location or a container inside one
+        ItemPolicy::MoveToParent => {
+            match sqlx::query(
+                r#"
+                INSERT INTO item_placements (item_id, container_id, quantity)
+                SELECT p.item_id, $2, SUM(p.quantity) FROM item_placements p WHERE p.container_id = ANY($1)
+                GROUP BY p.item_id
+                ON CONFLICT (item_id, container_id)
+                DO UPDATE SET quantity = item_placements.quantity + EXCLUDED.quantity
+                "#,
+            )
+            .bind(&subtree_ids)
+            .bind(&parent_id)
+            .execute(&mut *tx)
+            .await
+            {
+                Ok(result) => response.items_moved = result.rows_affected(),
+                Err(e) => {
+                    println!("Move container's items to parent error {}", e);
+                    return Err(StatusCode::INTERNAL_SERVER_ERROR);
+                }
+            }
+        }
+        ItemPolicy::LeaveUnplaced => response.items_unplaced = orphaned_items.len() as u64,
+    }
+
+    //Removing the containers also removes their placements through the foreign keys
+    match sqlx::query(
+        r#"
+        DELETE FROM containers WHERE id = ANY($1)
+        "#,
+    )
+    .bind(&subtree_ids)
+    .execute(&mut *tx)
+    .await
+    {
+        Ok(result) => response.containers_deleted = result.rows_affected(),
         Err(e) => {
-            println!("Deleting container from master's slaves error {}", e);
+            println!("Delete container error {}", e);
             return Err(StatusCode::INTERNAL_SERVER_ERROR);
         }
     }
+    if let Err(e) = tx.commit().await {
+        println!("Delete container commit error {}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    for id in &images_to_remove {
+        remove_image(&user.user_id, id).await;
+    }
+    response.images_removed = images_to_remove.len() as u64;
+    Ok(Json(response))
 }
 
 async fn update_container(
@@ -766,30 +1427,93 @@ async fn update_container(
     Json(payload): Json<ContainerRequest>,
 ) -> Result<StatusCode, StatusCode> {
     println!("in update container");
-    let serialized_slaves = set_to_string(&payload.container.slaves);
     let serialized_corners = corners_to_string(&payload.container.corners);
-    let serialized_items = set_to_string(&payload.container.contained_items);
+    let (contained_items, quantities) = placement_quantities(&payload.container)?;
+    let mut tx = user.db_pool.begin().await.map_err(|e| {
+        println!("Update container begin transaction error: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    //Containers can be nested to any depth, but never inside themselves or one of their own containers
     match sqlx::query(
         r#"
-        UPDATE containers SET name=$1, master=$2, slaves=$3, corners=$4, image_type=$5, contained_items=$6 WHERE id=$7
+        WITH RECURSIVE subtree AS (
+            SELECT id FROM containers WHERE id = $1
+            UNION ALL
+            SELECT c.id FROM containers c JOIN subtree s ON c.parent_id = s.id
+        )
+        SELECT 1 FROM subtree WHERE id = $2
+        "#,
+    )
+    .bind(&payload.container.id)
+    .bind(&payload.container.master)
+    .fetch_optional(&mut *tx)
+    .await
+    {
+        Ok(Some(_)) => return Err(StatusCode::BAD_REQUEST),
+        Ok(None) => (),
+        Err(e) => {
+            println!("Update container parent check error {}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+    if let Err(e) = sqlx::query(
+        r#"
+        UPDATE containers SET name=$1, parent_id=NULLIF($2, ''), corners=$3, image_type=$4, polygon=$5, rotation=$6
+        WHERE id=$7
         "#,
     )
     .bind(&payload.container.name)
     .bind(&payload.container.master)
-    .bind(&serialized_slaves)
     .bind(&serialized_corners)
     .bind(&payload.container.image_type)
-    .bind(&serialized_items)
+    .bind(polygon_to_string(&payload.container.polygon))
+    .bind(payload.container.rotation)
     .bind(&payload.container.id)
-    .execute(&*user.db_pool)
+    .execute(&mut *tx)
     .await
     {
-        Ok(_) => {
-            return Ok(StatusCode::OK);
-        }
+        println!("Upadte container error {}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    //Make the container's placements match contained_items
+    if let Err(e) = sqlx::query(
+        r#"
+        DELETE FROM item_placements WHERE container_id = $1 AND item_id <> ALL($2)
+        "#,
+    )
+    .bind(&payload.container.id)
+    .bind(&contained_items)
+    .execute(&mut *tx)
+    .await
+    {
+        println!("Update container's removed items error {}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    //Items sent without a quantity keep the one they have, new placements start at 1
+    if let Err(e) = sqlx::query(
+        r#"
+        INSERT INTO item_placements (item_id, container_id, quantity)
+        SELECT i.id, $1, COALESCE(q.quantity, p.quantity, 1)
+        FROM unnest($2::text[], $3::int4[]) AS q(item_id, quantity)
+        JOIN items i ON i.id = q.item_id
+        LEFT JOIN item_placements p ON p.item_id = q.item_id AND p.container_id = $1
+        ON CONFLICT (item_id, container_id) DO UPDATE SET quantity = EXCLUDED.quantity
+        "#,
+    )
+    .bind(&payload.container.id)
+    .bind(&contained_items)
+    .bind(&quantities)
+    .execute(&mut *tx)
+    .await
+    {
+        println!("Update container's added items error {}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    match tx.commit().await {
+        Ok(_) => Ok(StatusCode::OK),
         Err(e) => {
-            println!("Upadte container error {}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            println!("Update container commit error {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
 }
@@ -798,42 +1522,68 @@ async fn get_all_item_ids_not_in_container(
     Extension(user): Extension<UserContext>,
 ) -> Result<Json<Vec<String>>, StatusCode> {
     println!("in get all item ids not in container");
-    //Get all item_ids in containers
-    let query = "SELECT contained_items FROM containers";
+    let query = r#"
+        SELECT id FROM items i
+        WHERE NOT EXISTS (SELECT 1 FROM item_placements p WHERE p.item_id = i.id)
+        "#;
     match sqlx::query(query).fetch_all(&*user.db_pool).await {
-        Ok(vec) => {
-            //Get all item_ids
-            let query = "SELECT id FROM items";
-            match sqlx::query(query).fetch_all(&*user.db_pool).await {
-                Ok(vec2) => {
-                    let in_container_ids: Vec<String> =
-                        vec.into_iter().map(|x| x.get("contained_items")).collect();
-                    let in_container_ids: Vec<String> = in_container_ids
-                        .join("")
-                        .split("@")
-                        .filter(|x| !x.is_empty())
-                        .map(|x| x.to_string())
-                        .collect();
-                    let all_item_ids: Vec<String> = vec2.into_iter().map(|x| x.get("id")).collect();
-                    let result: Vec<String> = all_item_ids
-                        .into_iter()
-                        .filter(|x| !in_container_ids.contains(x))
-                        .collect();
-                    return Ok(Json(result));
-                }
-                Err(e) => {
-                    println!("Get all item ids not in container error 1 : {}", e);
-                    return Err(StatusCode::INTERNAL_SERVER_ERROR);
-                }
-            }
+        Ok(rows) => {
+            let result: Vec<String> = rows.into_iter().map(|x| x.get("id")).collect();
+            return Ok(Json(result));
         }
         Err(e) => {
-            println!("Get all item ids not in container error 2 : {}", e);
+            println!("Get all item ids not in container error : {}", e);
             return Err(StatusCode::INTERNAL_SERVER_ERROR);
         }
     }
 }
 
+//The items column type and the dynamic_fields type of a field, None when the currency isn't a code
+fn column_definition(column_type: &DataType) -> Option<(&'static str, String)> {
+    Some(match column_type {
+        DataType::String => ("TEXT", "text".to_string()),
+        DataType::List(val) => ("TEXT", "list,".to_owned() + &val.join(",")),
+        DataType::Gallery => ("TEXT", "gallery".to_string()),
+        DataType::Text => ("TEXT", "paragraph".to_string()),
+        DataType::Integer => ("INT", "integer".to_string()),
+        DataType::Bool => ("INT", "bool".to_string()),
+        DataType::Float => ("REAL", "float".to_string()),
+        DataType::Percentage => ("REAL", "percentage".to_string()),
+        DataType::Date => ("DATE", "date".to_string()),
+        DataType::DateTime => ("TIMESTAMPTZ", "datetime".to_string()),
+        DataType::Money { currency } => {
+            if !is_currency_code(currency) {
+                return None;
+            }
+            ("NUMERIC(18, 2)", "money,".to_owned() + currency)
+        }
+    })
+}
+
+//Adds the items column and its dynamic_fields row, the search vector has to be dropped before and created after
+async fn add_item_column(
+    conn: &mut PgConnection,
+    column_name: &str,
+    (sql_type, type_string): &(&str, String),
+) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!(
+        r#"ALTER TABLE items ADD COLUMN "{}" {}"#,
+        column_name, sql_type
+    ))
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(
+        r#"
+        INSERT INTO dynamic_fields (name, type) VALUES ($1, $2)
+        "#,
+    )
+    .bind(column_name)
+    .bind(type_string)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
 async fn add_column_to_items(
     Extension(user): Extension<UserContext>,
     Json(payload): Json<ColumnRequest>,
@@ -842,89 +1592,31 @@ async fn add_column_to_items(
     if payload.column_name.chars().any(|x| !x.is_alphanumeric()) {
         return Err(StatusCode::INTERNAL_SERVER_ERROR);
     }
-    let list_val: String; //Need a let binding for the joined list to not be dropped early
-    let (item_query, column_type_string) = match payload.column_type {
-        DataType::String => (
-            format!(
-                r#"ALTER TABLE items ADD COLUMN "{}" TEXT"#,
-                payload.column_name,
-            ),
-            "text",
-        ),
-        DataType::List(val) => {
-            list_val = "list,".to_owned() + &val.join(",");
-            (
-                format!(
-                    r#"ALTER TABLE items ADD COLUMN "{}" TEXT"#,
-                    payload.column_name,
-                ),
-                list_val.as_str(),
-            )
-        }
-        DataType::Gallery => (
-            format!(
-                r#"ALTER TABLE items ADD COLUMN "{}" TEXT"#,
-                payload.column_name,
-            ),
-            "gallery",
-        ),
-        DataType::Text => (
-            format!(
-                r#"ALTER TABLE items ADD COLUMN "{}" TEXT"#,
-                payload.column_name,
-            ),
-            "paragraph",
-        ),
-        DataType::Integer => (
-            format!(
-                r#"ALTER TABLE items ADD COLUMN "{}" INT"#,
-                payload.column_name
-            ),
-            "integer",
-        ),
-        DataType::Bool => (
-            format!(
-                r#"ALTER TABLE items ADD COLUMN "{}" INT"#,
-                payload.column_name
-            ),
-            "bool",
-        ),
-        DataType::Float => (
-            format!(
-                r#"ALTER TABLE items ADD COLUMN "{}" REAL"#,
-                payload.column_name
-            ),
-            "float",
-        ),
-        DataType::Percentage => (
-            format!(
-                r#"ALTER TABLE items ADD COLUMN "{}" REAL"#,
-                payload.column_name
-            ),
-            "percentage",
-        ),
+    let Some(definition) = column_definition(&payload.column_type) else {
+        return Err(StatusCode::BAD_REQUEST);
     };
-    if let Err(e) = sqlx::query(&item_query).execute(&*user.db_pool).await {
+    let mut tx = user.db_pool.begin().await.map_err(|e| {
+        println!("Add column begin transaction error: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    //The search vector depends on the TEXT fields, it's created again once the fields changed
+    if let Err(e) = search::drop_search_vector(&mut tx).await {
+        println!("Drop search vector error {}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    if let Err(e) = add_item_column(&mut tx, &payload.column_name, &definition).await {
         println!("Add column to items error {}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
     }
-
-    //Add the dynamic_field to the dynamic_fields table
-    match sqlx::query(
-        r#"
-        INSERT INTO dynamic_fields (name, type) VALUES ($1, $2) ON CONFLICT DO NOTHING
-        "#,
-    )
-    .bind(payload.column_name)
-    .bind(column_type_string)
-    .execute(&*user.db_pool)
-    .await
-    {
-        Ok(_) => {
-            return Ok(StatusCode::OK);
-        }
+    if let Err(e) = search::create_search_vector(&mut tx).await {
+        println!("Create search vector error {}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    match tx.commit().await {
+        Ok(_) => Ok(StatusCode::OK),
         Err(e) => {
-            println!("Add column to dynamic_fields error {}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            println!("Add column commit error {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
 }
@@ -939,40 +1631,97 @@ async fn delete_column_from_items(
         return Err(StatusCode::INTERNAL_SERVER_ERROR);
     }
     let query = format!(r#"ALTER TABLE items DROP COLUMN "{}""#, payload.column_name);
-    if let Err(e) = sqlx::query(&query).execute(&*user.db_pool).await {
+    let mut tx = user.db_pool.begin().await.map_err(|e| {
+        println!("Delete column begin transaction error: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    //The search vector depends on the TEXT fields, it's created again once the fields changed
+    if let Err(e) = search::drop_search_vector(&mut tx).await {
+        println!("Drop search vector error {}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    //A gallery field takes its images with it
+    let gallery_images =
+        match gallery::column_gallery_images(&mut tx, &payload.column_name).await {
+            Ok(images) => images,
+            Err(e) => {
+                println!("Delete column gallery error {}", e);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        };
+    if let Err(e) = sqlx::query(&query).execute(&mut *tx).await {
         println!("Delete column from items error {}", e);
         return Err(StatusCode::INTERNAL_SERVER_ERROR);
     }
+    //Thresholds on the field go with it
+    if let Err(e) = sqlx::query(
+        r#"
+        DELETE FROM stock_thresholds WHERE field_name = $1
+        "#,
+    )
+    .bind(&payload.column_name)
+    .execute(&mut *tx)
+    .await
+    {
+        println!("Delete column's stock thresholds error {}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
     //Delete the field from dynamic_fields table
-    match sqlx::query(
+    if let Err(e) = sqlx::query(
         r#"
         DELETE FROM dynamic_fields WHERE name = $1
         "#,
     )
     .bind(payload.column_name)
-    .execute(&*user.db_pool)
+    .execute(&mut *tx)
     .await
     {
-        Ok(_) => {
-            return Ok(StatusCode::OK);
-        }
-        Err(e) => {
-            println!("Delete column from dynamic_fields error {}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
-        }
+        println!("Delete column from dynamic_fields error {}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
     }
+    if let Err(e) = search::create_search_vector(&mut tx).await {
+        println!("Create search vector error {}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    if let Err(e) = tx.commit().await {
+        println!("Delete column commit error {}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    for image_id in gallery_images {
+        remove_image(&user.user_id, &image_id).await;
+    }
+    Ok(StatusCode::OK)
 }
 
 async fn update_items_column(
     Extension(user): Extension<UserContext>,
     Json(payload): Json<UpdateColumnRequest>,
 ) -> Result<StatusCode, StatusCode> {
-    let query = format!(
-        r#"ALTER TABLE items RENAME COLUMN "{}" TO "{}""#,
-        payload.old_name, payload.new_column.0
-    );
-    if let Err(e) = sqlx::query(&query).execute(&*user.db_pool).await {
-        println!("Update column from items error {}", e);
+    println!("in update items column");
+    if payload.old_name.chars().any(|x| !x.is_alphanumeric())
+        || payload.new_column.0.chars().any(|x| !x.is_alphanumeric())
+    {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    let mut tx = user.db_pool.begin().await.map_err(|e| {
+        println!("Update column begin transaction error: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    //The search vector depends on the TEXT fields, it's created again once the fields changed
+    if let Err(e) = search::drop_search_vector(&mut tx).await {
+        println!("Drop search vector error {}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    //Only rename when the name changed, renaming to the same name is an error in postgres
+    if payload.old_name != payload.new_column.0 {
+        let query = format!(
+            r#"ALTER TABLE items RENAME COLUMN "{}" TO "{}""#,
+            payload.old_name, payload.new_column.0
+        );
+        if let Err(e) = sqlx::query(&query).execute(&mut *tx).await {
+            println!("Update column from items error {}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
     }
     //If the column is a list, update the list too
     match payload.new_column.1 {
@@ -986,74 +1735,84 @@ async fn update_items_column(
             )
             .bind(list_val)
             .bind(&payload.old_name)
-            .execute(&*user.db_pool)
+            .execute(&mut *tx)
             .await
             {
                 println!("Update field list in dynamic_fields error {}", e);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        }
+        //The currency can change, the amounts stay as they are
+        DataType::Money { currency } => {
+            if !is_currency_code(&currency) {
+                return Err(StatusCode::BAD_REQUEST);
+            }
+            if let Err(e) = sqlx::query(
+                r#"
+                UPDATE dynamic_fields SET type=$1 WHERE name=$2 AND type LIKE 'money,%'
+                "#,
+            )
+            .bind("money,".to_owned() + &currency)
+            .bind(&payload.old_name)
+            .execute(&mut *tx)
+            .await
+            {
+                println!("Update field currency in dynamic_fields error {}", e);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
             }
         }
         _ => (),
     }
+    if let Err(e) = sqlx::query(
+        r#"
+        UPDATE stock_thresholds SET field_name=$1 WHERE field_name=$2
+        "#,
+    )
+    .bind(&payload.new_column.0)
+    .bind(&payload.old_name)
+    .execute(&mut *tx)
+    .await
+    {
+        println!("Update field in stock_thresholds error {}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
     //Change the name in the dynamic_fields table
-    match sqlx::query(
+    if let Err(e) = sqlx::query(
         r#"
         UPDATE dynamic_fields SET name=$1 WHERE name=$2
         "#,
     )
     .bind(payload.new_column.0)
     .bind(payload.old_name)
-    .execute(&*user.db_pool)
+    .execute(&mut *tx)
     .await
     {
-        Ok(_) => {
-            return Ok(StatusCode::OK);
-        }
+        println!("Update field in dynamic_fields error {}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    if let Err(e) = search::create_search_vector(&mut tx).await {
+        println!("Create search vector error {}", e);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    match tx.commit().await {
+        Ok(_) => Ok(StatusCode::OK),
         Err(e) => {
-            println!("Update field in dynamic_fields error {}", e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            println!("Update column commit error {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
 }
 
+//ISO 4217 style codes like EUR or USD
+fn is_currency_code(currency: &str) -> bool {
+    currency.len() == 3 && currency.chars().all(|x| x.is_ascii_uppercase())
+}
+
 async fn get_dynamic_fields(
     Extension(user): Extension<UserContext>,
 ) -> Result<Json<Vec<(String, DataType)>>, StatusCode> {
-    match sqlx::query(r#"SELECT * FROM dynamic_fields"#)
-        .fetch_all(&*user.db_pool)
-        .await
-    {
-        Ok(vec) => {
-            return Ok(Json(
-                vec.into_iter()
-                    .map(|row: PgRow| {
-                        let column_name: String = row.get("name");
-                        let data_type: &str = row.get("type");
-                        match data_type {
-                            "text" => (column_name, DataType::String),
-                            "integer" => (column_name, DataType::Integer),
-                            "float" => (column_name, DataType::Float),
-                            "bool" => (column_name, DataType::Bool),
-                            "paragraph" => (column_name, DataType::Text),
-                            "percentage" => (column_name, DataType::Percentage),
-                            "gallery" => (column_name, DataType::Gallery),
-                            _ => {
-                                if data_type.starts_with("list,") {
-                                    let string_vec: Vec<String> = data_type
-                                        .trim()
-                                        .split(",")
-                                        .skip(1)
-                                        .map(|x| String::from(x))
-                                        .collect();
-                                    (column_name, DataType::List(string_vec))
-                                } else {
-                                    (column_name, DataType::String)
-                                }
-                            }
-                        }
-                    })
-                    .collect(),
-            ));
-        }
+    match read_dynamic_fields(&*user.db_pool).await {
+        Ok(fields) => Ok(Json(fields)),
         Err(e) => {
             println!("Get item column types error {}", e);
             return Err(StatusCode::INTERNAL_SERVER_ERROR);
@@ -1061,81 +1820,117 @@ async fn get_dynamic_fields(
     }
 }
 
+//The type of a field as stored in dynamic_fields.type, unknown types are read as text
+fn parse_data_type(data_type: &str) -> DataType {
+    match data_type {
+        "text" => DataType::String,
+        "integer" => DataType::Integer,
+        "float" => DataType::Float,
+        "bool" => DataType::Bool,
+        "paragraph" => DataType::Text,
+        "percentage" => DataType::Percentage,
+        "gallery" => DataType::Gallery,
+        "date" => DataType::Date,
+        "datetime" => DataType::DateTime,
+        _ => {
+            if let Some(currency) = data_type.strip_prefix("money,") {
+                DataType::Money {
+                    currency: currency.to_string(),
+                }
+            } else if data_type.starts_with("list,") {
+                let string_vec: Vec<String> = data_type
+                    .trim()
+                    .split(",")
+                    .skip(1)
+                    .map(String::from)
+                    .collect();
+                DataType::List(string_vec)
+            } else {
+                DataType::String
+            }
+        }
+    }
+}
+
+async fn read_dynamic_fields<'e, E: PgExecutor<'e>>(
+    executor: E,
+) -> Result<Vec<(String, DataType)>, sqlx::Error> {
+    let rows = sqlx::query(r#"SELECT * FROM dynamic_fields"#)
+        .fetch_all(executor)
+        .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row: PgRow| {
+            let column_name: String = row.get("name");
+            let data_type: &str = row.get("type");
+            (column_name, parse_data_type(data_type))
+        })
+        .collect())
+}
+
 async fn get_all_slaves(
     Extension(user): Extension<UserContext>,
     Json(payload): Json<IdRequest>,
 ) -> Result<Json<Vec<Container>>, StatusCode> {
     println!("in get all slaves");
-    match sqlx::query(
-        r#"
-        SELECT slaves FROM containers WHERE id=$1
-        "#,
-    )
-    .bind(payload.id)
-    .fetch_one(&*user.db_pool)
-    .await
+    let query = format!("{} WHERE c.parent_id = $1", CONTAINER_SELECT);
+    match sqlx::query(&query)
+        .bind(payload.id)
+        .fetch_all(&*user.db_pool)
+        .await
     {
-        Ok(slaves) => {
-            let slave_string: String = slaves.get("slaves");
-            let slave_ids: Vec<&str> = slave_string.split("@").filter(|x| !x.is_empty()).collect();
-            match sqlx::query(
-                r#"
-                SELECT *
-                FROM containers
-                WHERE id = ANY($1)
-                "#,
-            )
-            .bind(&slave_ids)
-            .fetch_all(&*user.db_pool)
-            .await
-            {
-                Ok(rows) => {
-                    return Ok(Json(
-                        rows.into_iter()
-                            .map(|row: PgRow| {
-                                let slaves: String = row.get("slaves");
-                                let corners: String = row.get("corners");
-                                let contained_items: String = row.get("contained_items");
-                                return Container {
-                                    id: row.get("id"),
-                                    master: row.get("master"),
-                                    slaves: string_to_set(&slaves),
-                                    name: row.get("name"),
-                                    corners: string_to_corners(&corners),
-                                    image_type: row.get("image_type"),
-                                    contained_items: string_to_set(&contained_items),
-                                };
-                            })
-                            .collect(),
-                    ));
-                }
-                Err(e) => {
-                    println!("Get all slaves error 2 {}", e);
-                    return Err(StatusCode::INTERNAL_SERVER_ERROR);
-                }
-            }
+        Ok(rows) => {
+            return Ok(Json(rows.into_iter().map(pgrow_to_container).collect()));
         }
         Err(e) => {
-            println!("Get all slaves error 1 {}", e);
+            println!("Get all slaves error {}", e);
             return Err(StatusCode::INTERNAL_SERVER_ERROR);
         }
     }
 }
 
-async fn remove_image(user_id: &str, item_id: &str) {
+//Every path an image with this id can be stored at, large, small and medium
+fn image_paths(user_id: &str, item_id: &str) -> Vec<String> {
     let file_extensions = ["jpeg", "jpg", "png", "webp", "gif"];
+    let mut paths = Vec::new();
     for ext in file_extensions {
-        let _ = fs::remove_file(format!("/app/users/{}/images/{}.{}", user_id, item_id, ext)).await;
-        let _ = fs::remove_file(format!(
+        paths.push(format!("/app/users/{}/images/{}.{}", user_id, item_id, ext));
+        paths.push(format!(
             "/app/users/{}/images/small/{}.{}",
             user_id, item_id, ext
-        ))
-        .await;
-        let _ = fs::remove_file(format!(
+        ));
+        paths.push(format!(
             "/app/users/{}/images/medium/{}.{}",
             user_id, item_id, ext
-        ))
-        .await;
+        ));
+    }
+    paths
+}
+
+async fn remove_image(user_id: &str, item_id: &str) {
+    for path in image_paths(user_id, item_id) {
+        let _ = fs::remove_file(path).await;
+    }
+}
+
+//Moves the current image files aside, so they can be put back if replacing them fails
+async fn stash_image(user_id: &str, item_id: &str) {
+    for path in image_paths(user_id, item_id) {
+        let _ = fs::rename(&path, format!("{}.old", path)).await;
+    }
+}
+
+//Removes any partially written files and puts the stashed ones back
+async fn restore_stashed_image(user_id: &str, item_id: &str) {
+    remove_image(user_id, item_id).await;
+    for path in image_paths(user_id, item_id) {
+        let _ = fs::rename(format!("{}.old", path), &path).await;
+    }
+}
+
+async fn discard_stashed_image(user_id: &str, item_id: &str) {
+    for path in image_paths(user_id, item_id) {
+        let _ = fs::remove_file(format!("{}.old", path)).await;
     }
 }
 
@@ -1281,6 +2076,13 @@ async fn add_image_to_database(
         && !item_id.is_empty()
     {
         println!("In upload image inner ok");
+        let mut tx = user.db_pool.begin().await.map_err(|e| {
+            println!("Upload image begin transaction error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to start transaction".to_string(),
+            )
+        })?;
         //Update the database, if the id doesn't exist in the containers, try the items
         let container_update = sqlx::query(
             r#"
@@ -1289,19 +2091,25 @@ async fn add_image_to_database(
         )
         .bind(&file_extension)
         .bind(&item_id)
-        .execute(&*user.db_pool)
+        .execute(&mut *tx)
         .await;
 
-        let mut container_found = false;
-        if let Ok(container_update) = container_update {
-            if container_update.rows_affected() == 1 {
+        let container_found = match container_update {
+            Ok(container_update) => {
                 println!(
                     "container rows affected: {}",
                     container_update.rows_affected()
                 );
-                container_found = true;
+                container_update.rows_affected() == 1
             }
-        }
+            Err(e) => {
+                println!("container table update error: {}", e);
+                return Err((
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Failed to update containers".to_string(),
+                ));
+            }
+        };
 
         if !container_found {
             let item_update = sqlx::query(
@@ -1311,11 +2119,16 @@ async fn add_image_to_database(
             )
             .bind(&file_extension)
             .bind(&item_id)
-            .execute(&*user.db_pool)
+            .execute(&mut *tx)
             .await;
             match item_update {
                 Ok(item_update) => {
-                    if item_update.rows_affected() != 1 {
+                    //Gallery images have no image_type to update, they only need their files
+                    let in_gallery = item_update.rows_affected() != 1
+                        && gallery::is_gallery_image(&mut tx, item_id)
+                            .await
+                            .unwrap_or(false);
+                    if item_update.rows_affected() != 1 && !in_gallery {
                         println!("item 1 rows affected: {}", item_update.rows_affected());
                         return Err((
                             StatusCode::INTERNAL_SERVER_ERROR,
@@ -1332,8 +2145,8 @@ async fn add_image_to_database(
                 }
             }
         }
-        //Check for an existing image with the same id and if so, delete it
-        remove_image(&user.user_id, &item_id).await;
+        //Move an existing image with the same id aside, it's put back if anything below fails
+        stash_image(&user.user_id, &item_id).await;
         //Now that the file is a valid image and the id is in the database, save it
         // Create the full path for saving the file
         let path = format!(
@@ -1341,7 +2154,7 @@ async fn add_image_to_database(
             user.user_id, item_id, file_extension
         );
         // Save the file
-        return match fs::write(&path, &data).await {
+        let saved = match fs::write(&path, &data).await {
             Ok(_) => match save_resized_images(&data, &user.user_id, &item_id, &file_extension) {
                 Ok(_) => Ok(()),
                 Err(e) => Err((
@@ -1351,12 +2164,27 @@ async fn add_image_to_database(
             },
             Err(e) => {
                 println!("Error when saving: {}", e);
-                return Err((
+                Err((
                     StatusCode::INTERNAL_SERVER_ERROR,
                     format!("Failed to save file: {}", e),
-                ));
+                ))
             }
         };
+        if let Err(e) = saved {
+            //Dropping tx rolls back the image_type change
+            restore_stashed_image(&user.user_id, &item_id).await;
+            return Err(e);
+        }
+        if let Err(e) = tx.commit().await {
+            println!("Upload image commit error: {}", e);
+            restore_stashed_image(&user.user_id, &item_id).await;
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to commit image".to_string(),
+            ));
+        }
+        discard_stashed_image(&user.user_id, &item_id).await;
+        return Ok(());
     }
     return Err((
         StatusCode::INTERNAL_SERVER_ERROR,
@@ -1384,7 +2212,7 @@ async fn upload_image(
                         Some(filename) => {
                             let filename: Vec<&str> = filename.split(".").collect();
                             if filename.len() == 2 {
-                                file_extension = Some(filename[1].to_owned());
+                                file_extension = Some(filename[1].to_lowercase());
                             } else {
                                 return Err((
                                     StatusCode::INTERNAL_SERVER_ERROR,
@@ -0,0 +1,100 @@
+//Where a set of items is, for showing search results on the location photos
+//For every location holding some of them, the number of them inside each container at any depth
+
+use std::collections::HashMap;
+
+use axum::{Extension, Json, http::StatusCode};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+
+use crate::{CONTAINER_SELECT, Container, pgrow_to_container, users::UserContext};
+
+#[derive(Deserialize)]
+pub struct ItemMapRequest {
+    id_vec: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct LocationMatches {
+    location: Container,
+    item_count: i64, //Items anywhere in the location, one placed twice is counted once
+    container_counts: HashMap<String, i64>, //Items inside each container of the location, including the ones below it
+}
+
+pub async fn get_item_map(
+    Extension(user): Extension<UserContext>,
+    Json(payload): Json<ItemMapRequest>,
+) -> Result<Json<Vec<LocationMatches>>, StatusCode> {
+    println!("in get item map");
+    //Walks up from every placement so each container on the way counts the items below it
+    //The location of a placement is the container on its way up whose parent is a root
+    let rows = match sqlx::query(
+        r#"
+        WITH RECURSIVE up AS (
+            SELECT p.item_id, p.container_id AS start, c.id, c.parent_id, 0 AS depth
+            FROM item_placements p JOIN containers c ON c.id = p.container_id
+            WHERE p.item_id = ANY($1)
+            UNION ALL
+            SELECT up.item_id, up.start, c.id, c.parent_id, up.depth + 1
+            FROM containers c JOIN up ON c.id = up.parent_id
+            WHERE up.depth < 100
+        ),
+        located AS (
+            SELECT DISTINCT up.start, up.id AS location_id FROM up
+            JOIN containers parent ON parent.id = up.parent_id
+            WHERE parent.parent_id IS NULL
+        )
+        SELECT l.location_id, up.id, COUNT(DISTINCT up.item_id) AS item_count
+        FROM up JOIN located l ON l.start = up.start
+        WHERE up.parent_id IS NOT NULL
+        GROUP BY l.location_id, up.id
+        "#,
+    )
+    .bind(&payload.id_vec)
+    .fetch_all(&*user.db_pool)
+    .await
+    {
+        Ok(rows) => rows,
+        Err(e) => {
+            println!("Get item map error {}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let mut counts: HashMap<String, HashMap<String, i64>> = HashMap::new();
+    for row in rows {
+        counts
+            .entry(row.get("location_id"))
+            .or_default()
+            .insert(row.get("id"), row.get("item_count"));
+    }
+    let location_ids: Vec<String> = counts.keys().cloned().collect();
+    let locations = match sqlx::query(&format!("{} WHERE c.id = ANY($1)", CONTAINER_SELECT))
+        .bind(&location_ids)
+        .fetch_all(&*user.db_pool)
+        .await
+    {
+        Ok(rows) => rows.into_iter().map(pgrow_to_container),
+        Err(e) => {
+            println!("Get item map locations error {}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let mut result: Vec<LocationMatches> = locations
+        .map(|location| {
+            let mut container_counts = counts.remove(&location.id).unwrap_or_default();
+            let item_count = container_counts.remove(&location.id).unwrap_or_default();
+            LocationMatches {
+                location,
+                item_count,
+                container_counts,
+            }
+        })
+        .collect();
+    //Locations with the most matches first
+    result.sort_by(|a, b| {
+        b.item_count
+            .cmp(&a.item_count)
+            .then_with(|| a.location.name.cmp(&b.location.name))
+    });
+    Ok(Json(result))
+}
@@ -0,0 +1,734 @@
+//Merging a backup adds its fields, containers, items and images to the user's data instead of replacing it
+//A field with the same name but another type, or a container or item whose id is already used, is a conflict
+//The policy picks what happens to all the conflicts:
+//  Skip       The current field or record stays as it is, the one in the backup is left out
+//  Overwrite  The backup's replaces it, a field keeps the values its new type can hold and is emptied otherwise
+//  Duplicate  The backup's is added next to it, records get new ids and fields a free name like Price2
+//The root of the backup is always the current root, only the locations below it and their containers follow the policy
+
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{PgPool, Row};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+use crate::{
+    DataType, DynamicFieldValue, add_item_column,
+    archive::{Archive, ArchiveContainer, field_value},
+    column_definition, corners_to_string, gallery, polygon_to_string, read_dynamic_fields, search,
+    stock::numeric_fields,
+};
+
+#[derive(Clone, Copy)]
+pub enum ConflictPolicy {
+    Skip,
+    Overwrite,
+    Duplicate,
+}
+
+#[derive(Serialize, Default)]
+pub struct MergeCounts {
+    added: usize,
+    overwritten: usize,
+    duplicated: usize,
+    skipped: usize,
+}
+
+impl MergeCounts {
+    fn count(&mut self, conflict: bool, policy: ConflictPolicy) {
+        match (conflict, policy) {
+            (false, _) => self.added += 1,
+            (true, ConflictPolicy::Skip) => self.skipped += 1,
+            (true, ConflictPolicy::Overwrite) => self.overwritten += 1,
+            (true, ConflictPolicy::Duplicate) => self.duplicated += 1,
+        }
+    }
+}
+
+//What a merge changes, the dry run fills it the same way without writing anything
+#[derive(Serialize, Default)]
+pub struct MergeReport {
+    pub dry_run: bool,
+    pub fields: MergeCounts,
+    pub containers: MergeCounts,
+    pub items: MergeCounts,
+    pub images: usize,
+    pub failed_images: Vec<String>,
+    pub notes: Vec<String>, //What happens to each conflicting field
+    pub from_dump: bool,
+}
+
+enum FieldMerge {
+    Add(String), //A new column named after the backup's field or its free name
+    //The values go into the current column, converted to column_type and with field_type as the new type when Some
+    Existing {
+        name: String,
+        column_type: Option<&'static str>,
+        field_type: Option<String>,
+    },
+    Replace(String), //The current column is dropped and added again with the backup's type
+    Skip,
+}
+
+impl FieldMerge {
+    fn column(&self) -> Option<&str> {
+        match self {
+            FieldMerge::Add(name)
+            | FieldMerge::Replace(name)
+            | FieldMerge::Existing { name, .. } => Some(name),
+            FieldMerge::Skip => None,
+        }
+    }
+}
+
+enum RecordMerge {
+    Insert(String), //The id it's inserted with, new for duplicates
+    Update,
+    Skip,
+}
+
+//What happens to each field, container and item of the archive, in the archive's order
+pub struct MergePlan {
+    fields: Vec<FieldMerge>,
+    containers: Vec<RecordMerge>,
+    items: Vec<RecordMerge>,
+    container_ids: HashMap<String, String>, //Id in the backup to the id in the database
+    image_ids: HashMap<String, String>,     //Image id in the backup to the id it's added under
+    pub report: MergeReport,
+}
+
+impl MergePlan {
+    //The images of the archive that are added, with the id each one is added under
+    pub fn images(&self, archive: &Archive) -> Vec<(String, String)> {
+        archive
+            .images
+            .iter()
+            .filter_map(|image| {
+                let (id, _) = image.file.split_once('.')?;
+                Some((image.file.clone(), self.image_ids.get(id)?.clone()))
+            })
+            .collect()
+    }
+}
+
+//Values the current column holds can be converted to the backup's type without losing them
+fn keeps_values(current: &str, backup: &str) -> bool {
+    current == backup
+        || backup == "TEXT"
+        || (current == "INT" && backup != "DATE" && backup != "TIMESTAMPTZ")
+}
+
+//The field name with the lowest number after it that no field uses, names are compared without case
+fn free_field_name(name: &str, taken: &HashSet<String>) -> String {
+    (2..)
+        .map(|number| format!("{}{}", name, number))
+        .find(|x| !taken.contains(&x.to_lowercase()))
+        .unwrap_or_default()
+}
+
+async fn current_ids(pool: &PgPool, table: &str) -> Result<HashSet<String>, String> {
+    Ok(sqlx::query(&format!("SELECT id FROM {}", table))
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("Reading {} error: {}", table, e))?
+        .iter()
+        .map(|row| row.get("id"))
+        .collect())
+}
+
+//What happens to each container, a container without a parent is the root and is mapped onto the current one
+//The root is never written so the merged locations end up below the root the user already has
+fn plan_containers(
+    archive_containers: &[ArchiveContainer],
+    current_containers: &HashSet<String>,
+    root_id: Option<&str>,
+    policy: ConflictPolicy,
+    counts: &mut MergeCounts,
+    container_ids: &mut HashMap<String, String>,
+    image_ids: &mut HashMap<String, String>,
+) -> Vec<RecordMerge> {
+    let mut containers = Vec::new();
+    for container in archive_containers {
+        if let (None, Some(root_id)) = (&container.parent_id, root_id) {
+            container_ids.insert(container.id.clone(), root_id.to_string());
+            containers.push(RecordMerge::Skip);
+            continue;
+        }
+        let conflict = current_containers.contains(&container.id);
+        let container_merge = match (conflict, policy) {
+            (false, _) => RecordMerge::Insert(container.id.clone()),
+            (true, ConflictPolicy::Skip) => RecordMerge::Skip,
+            (true, ConflictPolicy::Overwrite) => RecordMerge::Update,
+            (true, ConflictPolicy::Duplicate) => RecordMerge::Insert(Uuid::new_v4().to_string()),
+        };
+        counts.count(conflict, policy);
+        let id = match &container_merge {
+            RecordMerge::Insert(id) => id.clone(),
+            _ => container.id.clone(),
+        };
+        if !matches!(container_merge, RecordMerge::Skip) {
+            image_ids.insert(container.id.clone(), id.clone());
+        }
+        container_ids.insert(container.id.clone(), id);
+        containers.push(container_merge);
+    }
+    containers
+}
+
+//Decides what happens to everything in the archive, the archive has to be validated first
+pub async fn plan_merge(
+    pool: &PgPool,
+    archive: &Archive,
+    policy: ConflictPolicy,
+) -> Result<MergePlan, String> {
+    let mut report = MergeReport::default();
+    let current_fields = read_dynamic_fields(pool)
+        .await
+        .map_err(|e| format!("Reading fields error: {}", e))?;
+    let mut taken: HashSet<String> = current_fields
+        .iter()
+        .map(|(name, _)| name.to_lowercase())
+        .chain(archive.fields.iter().map(|x| x.name.to_lowercase()))
+        .collect();
+    let mut fields = Vec::new();
+    for field in &archive.fields {
+        //The archive was validated, every field has a definition
+        let Some((backup_column, backup_type)) = column_definition(&field.data_type) else {
+            fields.push(FieldMerge::Skip);
+            continue;
+        };
+        let Some((name, current_type)) = current_fields
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(&field.name))
+        else {
+            report.fields.added += 1;
+            fields.push(FieldMerge::Add(field.name.clone()));
+            continue;
+        };
+        let Some((current_column, current_type_string)) = column_definition(current_type) else {
+            fields.push(FieldMerge::Skip);
+            continue;
+        };
+        //Lists only differ by their options, the field gets the options of both
+        if let (DataType::List(options), DataType::List(backup_options)) =
+            (current_type, &field.data_type)
+        {
+            let mut options = options.clone();
+            for option in backup_options {
+                if !options.contains(option) {
+                    options.push(option.clone());
+                }
+            }
+            fields.push(FieldMerge::Existing {
+                name: name.clone(),
+                column_type: None,
+                field_type: column_definition(&DataType::List(options))
+                    .map(|(_, x)| x)
+                    .filter(|x| *x != current_type_string),
+            });
+            continue;
+        }
+        if current_type_string == backup_type {
+            fields.push(FieldMerge::Existing {
+                name: name.clone(),
+                column_type: None,
+                field_type: None,
+            });
+            continue;
+        }
+
+        report.fields.count(true, policy);
+        let galleries = matches!(current_type, DataType::Gallery)
+            || matches!(field.data_type, DataType::Gallery);
+        fields.push(match policy {
+            ConflictPolicy::Skip => {
+                report.notes.push(format!(
+                    "The field {} has another type in the backup, its values in the backup are left out",
+                    name
+                ));
+                FieldMerge::Skip
+            }
+            ConflictPolicy::Overwrite if !galleries && keeps_values(current_column, backup_column) => {
+                report.notes.push(format!(
+                    "The field {} gets the type of the backup, its current values are converted",
+                    name
+                ));
+                FieldMerge::Existing {
+                    name: name.clone(),
+                    column_type: Some(backup_column).filter(|x| *x != current_column),
+                    field_type: Some(backup_type),
+                }
+            }
+            ConflictPolicy::Overwrite => {
+                report.notes.push(format!(
+                    "The field {} gets the type of the backup, its current values are cleared",
+                    name
+                ));
+                FieldMerge::Replace(name.clone())
+            }
+            ConflictPolicy::Duplicate => {
+                let new_name = free_field_name(&field.name, &taken);
+                taken.insert(new_name.to_lowercase());
+                report.notes.push(format!(
+                    "The field {} of the backup is added as {}",
+                    field.name, new_name
+                ));
+                FieldMerge::Add(new_name)
+            }
+        });
+    }
+
+    let current_containers = current_ids(pool, "containers").await?;
+    let root_id: Option<String> =
+        sqlx::query_scalar(r#"SELECT id FROM containers WHERE parent_id IS NULL LIMIT 1"#)
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| format!("Reading root error: {}", e))?;
+    let mut container_ids: HashMap<String, String> = HashMap::new();
+    let mut image_ids: HashMap<String, String> = HashMap::new();
+    let containers = plan_containers(
+        &archive.containers,
+        &current_containers,
+        root_id.as_deref(),
+        policy,
+        &mut report.containers,
+        &mut container_ids,
+        &mut image_ids,
+    );
+
+    let current_items = current_ids(pool, "items").await?;
+    let mut items = Vec::new();
+    for item in &archive.items {
+        let conflict = current_items.contains(&item.id);
+        report.items.count(conflict, policy);
+        let item_merge = match (conflict, policy) {
+            (false, _) => RecordMerge::Insert(item.id.clone()),
+            (true, ConflictPolicy::Skip) => RecordMerge::Skip,
+            (true, ConflictPolicy::Overwrite) => RecordMerge::Update,
+            (true, ConflictPolicy::Duplicate) => RecordMerge::Insert(Uuid::new_v4().to_string()),
+        };
+        if let RecordMerge::Skip = item_merge {
+            items.push(item_merge);
+            continue;
+        }
+        let id = match &item_merge {
+            RecordMerge::Insert(id) => id.clone(),
+            _ => item.id.clone(),
+        };
+        let duplicate = id != item.id;
+        image_ids.insert(item.id.clone(), id);
+        //The gallery images of a duplicate are copies with their own ids, so deleting one keeps the other
+        for (field, field_merge) in archive.fields.iter().zip(&fields) {
+            if !matches!(field.data_type, DataType::Gallery) || field_merge.column().is_none() {
+                continue;
+            }
+            let Some(Value::Array(entries)) = item.values.get(&field.name) else {
+                continue;
+            };
+            for (image_id, _) in entries.iter().filter_map(|x| x.as_str()?.split_once('.')) {
+                let new_id = match duplicate {
+                    true => Uuid::new_v4().to_string(),
+                    false => image_id.to_string(),
+                };
+                image_ids.insert(image_id.to_string(), new_id);
+            }
+        }
+        items.push(item_merge);
+    }
+
+    let mut plan = MergePlan {
+        fields,
+        containers,
+        items,
+        container_ids,
+        image_ids,
+        report,
+    };
+    plan.report.images = plan.images(archive).len();
+    Ok(plan)
+}
+
+//A gallery value with the entries pointing to the ids their images are added under
+fn gallery_value(value: Option<&Value>, image_ids: &HashMap<String, String>) -> Option<Value> {
+    match value {
+        Some(Value::Array(entries)) => Some(Value::Array(
+            entries
+                .iter()
+                .map(
+                    |entry| match entry.as_str().and_then(|x| x.split_once('.')) {
+                        Some((id, extension)) => Value::String(format!(
+                            "{}.{}",
+                            image_ids.get(id).map_or(id, |x| x.as_str()),
+                            extension
+                        )),
+                        None => entry.clone(),
+                    },
+                )
+                .collect(),
+        )),
+        value => value.cloned(),
+    }
+}
+
+//Writes the plan in one transaction, nothing is changed if any part fails
+//Returns the image ids whose files are no longer used, they are removed once the merge is committed
+pub async fn apply_merge(
+    pool: &PgPool,
+    archive: &Archive,
+    plan: &MergePlan,
+) -> Result<Vec<String>, String> {
+    let error = |context: &str, e: sqlx::Error| format!("{} error: {}", context, e);
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| error("Merge begin transaction", e))?;
+    //An overwritten item gets the images of the backup, its current ones are read before any field changes
+    let overwritten_items: Vec<String> = archive
+        .items
+        .iter()
+        .zip(&plan.items)
+        .filter(|(_, item_merge)| matches!(item_merge, RecordMerge::Update))
+        .map(|(item, _)| item.id.clone())
+        .collect();
+    let mut unused_images = gallery::item_gallery_images(&mut tx, &overwritten_items)
+        .await
+        .map_err(|e| error("Read gallery images", e))?;
+    unused_images.extend(overwritten_items.iter().cloned());
+    //The search vector depends on the TEXT fields, it's created again once the fields changed
+    search::drop_search_vector(&mut tx)
+        .await
+        .map_err(|e| error("Drop search vector", e))?;
+    let threshold_fields = numeric_fields(
+        archive
+            .fields
+            .iter()
+            .map(|x| (x.name.clone(), x.data_type.clone()))
+            .collect(),
+    );
+    for (field, field_merge) in archive.fields.iter().zip(&plan.fields) {
+        let Some(definition) = column_definition(&field.data_type) else {
+            continue;
+        };
+        match field_merge {
+            FieldMerge::Add(name) => add_item_column(&mut tx, name, &definition)
+                .await
+                .map_err(|e| error("Add field", e))?,
+            FieldMerge::Existing {
+                name,
+                column_type,
+                field_type,
+            } => {
+                if let Some(column_type) = column_type {
+                    sqlx::query(&format!(
+                        r#"ALTER TABLE items ALTER COLUMN "{}" TYPE {} USING "{}"::{}"#,
+                        name, column_type, name, column_type
+                    ))
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| error("Convert field", e))?;
+                }
+                if let Some(field_type) = field_type {
+                    sqlx::query(r#"UPDATE dynamic_fields SET type=$1 WHERE name=$2"#)
+                        .bind(field_type)
+                        .bind(name)
+                        .execute(&mut *tx)
+                        .await
+                        .map_err(|e| error("Update field type", e))?;
+                }
+            }
+            FieldMerge::Replace(name) => {
+                sqlx::query(&format!(r#"ALTER TABLE items DROP COLUMN "{}""#, name))
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| error("Drop field", e))?;
+                sqlx::query(r#"DELETE FROM dynamic_fields WHERE name=$1"#)
+                    .bind(name)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| error("Drop field", e))?;
+                add_item_column(&mut tx, name, &definition)
+                    .await
+                    .map_err(|e| error("Add field", e))?;
+            }
+            FieldMerge::Skip => continue,
+        }
+        //Thresholds can only compare numbers, the ones on a field that no longer holds them are removed
+        if let Some(name) = field_merge
+            .column()
+            .filter(|_| !threshold_fields.contains(&field.name))
+        {
+            sqlx::query(r#"DELETE FROM stock_thresholds WHERE field_name=$1"#)
+                .bind(name)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| error("Remove stock thresholds", e))?;
+        }
+    }
+
+    for (container, container_merge) in archive.containers.iter().zip(&plan.containers) {
+        let parent_id = container
+            .parent_id
+            .as_ref()
+            .and_then(|x| plan.container_ids.get(x));
+        let query = match container_merge {
+            RecordMerge::Insert(id) => sqlx::query(
+                r#"
+                INSERT INTO containers (id, name, parent_id, corners, image_type, polygon, rotation)
+                VALUES ($1, $2, $3, $4, '', $5, $6)
+                "#,
+            )
+            .bind(id),
+            RecordMerge::Update => sqlx::query(
+                r#"
+                UPDATE containers SET name=$2, parent_id=$3, corners=$4, polygon=$5, rotation=$6
+                WHERE id=$1
+                "#,
+            )
+            .bind(&container.id),
+            RecordMerge::Skip => continue,
+        };
+        query
+            .bind(&container.name)
+            .bind(parent_id)
+            .bind(corners_to_string(&container.corners))
+            .bind(polygon_to_string(&container.polygon))
+            .bind(container.rotation)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| error("Write container", e))?;
+    }
+
+    //The values of the skipped fields are left out, the others go into the column the plan picked
+    let columns: Vec<(&DataType, &String, &str)> = archive
+        .fields
+        .iter()
+        .zip(&plan.fields)
+        .filter_map(|(field, field_merge)| {
+            Some((&field.data_type, &field.name, field_merge.column()?))
+        })
+        .collect();
+    let mut column_clause = vec!["id".to_string(), "name".to_string()];
+    column_clause.extend(columns.iter().map(|(_, _, column)| column.to_string()));
+    let value_clause: Vec<String> = (1..=column_clause.len())
+        .map(|x| format!("${}", x))
+        .collect();
+    let insert_query = format!(
+        r#"INSERT INTO items ("{}", image_type) VALUES ({}, '')"#,
+        column_clause.join(r#"", ""#),
+        value_clause.join(", ")
+    );
+    let update_query = format!(
+        r#"UPDATE items SET {}, image_type='' WHERE id=$1"#,
+        column_clause
+            .iter()
+            .zip(&value_clause)
+            .skip(1)
+            .map(|(column, value)| format!(r#""{}"={}"#, column, value))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    for (item, item_merge) in archive.items.iter().zip(&plan.items) {
+        let (mut query, id) = match item_merge {
+            RecordMerge::Insert(id) => (sqlx::query(&insert_query).bind(id), id),
+            RecordMerge::Update => (sqlx::query(&update_query).bind(&item.id), &item.id),
+            RecordMerge::Skip => continue,
+        };
+        query = query.bind(&item.name);
+        for (data_type, field_name, _) in &columns {
+            let value = gallery_value(item.values.get(*field_name), &plan.image_ids);
+            query = match field_value(value.as_ref(), data_type)? {
+                DynamicFieldValue::Text(val) => query.bind(val),
+                DynamicFieldValue::Integer(val) => query.bind(val),
+                DynamicFieldValue::Float(val) => query.bind(val),
+                DynamicFieldValue::Date(val) => query.bind(val),
+                DynamicFieldValue::DateTime(val) => query.bind(val),
+                DynamicFieldValue::Money(val) => query.bind(val),
+            };
+        }
+        query
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| error("Write item", e))?;
+        //An overwritten item is placed and tracked like in the backup
+        if let RecordMerge::Update = item_merge {
+            for table in ["item_placements", "stock_thresholds"] {
+                sqlx::query(&format!("DELETE FROM {} WHERE item_id=$1", table))
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| error("Overwrite item", e))?;
+            }
+        }
+        for placement in &item.placements {
+            sqlx::query(
+                r#"
+                INSERT INTO item_placements (item_id, container_id, quantity) VALUES ($1, $2, $3)
+                "#,
+            )
+            .bind(id)
+            .bind(plan.container_ids.get(&placement.container_id))
+            .bind(placement.quantity)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| error("Insert placement", e))?;
+        }
+        let Some(threshold) = &item.stock_threshold else {
+            continue;
+        };
+        //A threshold on a field that is left out is dropped
+        let field_name = match &threshold.field_name {
+            Some(field_name) => match columns.iter().find(|(_, name, _)| *name == field_name) {
+                Some((_, _, column)) => Some(*column),
+                None => continue,
+            },
+            None => None,
+        };
+        sqlx::query(
+            r#"
+            INSERT INTO stock_thresholds (item_id, field_name, minimum) VALUES ($1, $2, $3)
+            "#,
+        )
+        .bind(id)
+        .bind(field_name)
+        .bind(threshold.minimum)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| error("Insert stock threshold", e))?;
+    }
+    //Gallery images the backup's values still point to are kept
+    let merged_items: Vec<String> = plan
+        .items
+        .iter()
+        .filter_map(|item_merge| match item_merge {
+            RecordMerge::Insert(id) => Some(id.clone()),
+            _ => None,
+        })
+        .chain(overwritten_items)
+        .collect();
+    let used_images: HashSet<String> = gallery::item_gallery_images(&mut tx, &merged_items)
+        .await
+        .map_err(|e| error("Read gallery images", e))?
+        .into_iter()
+        .collect();
+    unused_images.retain(|x| !used_images.contains(x));
+    search::create_search_vector(&mut tx)
+        .await
+        .map_err(|e| error("Create search vector", e))?;
+    tx.commit().await.map_err(|e| error("Merge commit", e))?;
+    Ok(unused_images)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn keeps_values_it_can_convert() {
+        for (current, backup, keeps) in [
+            ("INT", "INT", true),
+            ("INT", "REAL", true),
+            ("INT", "NUMERIC(18, 2)", true),
+            ("INT", "TEXT", true),
+            ("INT", "DATE", false),
+            ("INT", "TIMESTAMPTZ", false),
+            ("REAL", "INT", false),
+            ("REAL", "TEXT", true),
+            ("NUMERIC(18, 2)", "INT", false),
+            ("TEXT", "INT", false),
+            ("DATE", "TIMESTAMPTZ", false),
+        ] {
+            assert_eq!(
+                keeps_values(current, backup),
+                keeps,
+                "{} to {}",
+                current,
+                backup
+            );
+        }
+    }
+
+    #[test]
+    fn finds_a_free_field_name() {
+        let taken: HashSet<String> = ["price", "price2", "weight"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(free_field_name("Price", &taken), "Price3");
+        assert_eq!(free_field_name("Weight", &taken), "Weight2");
+        assert_eq!(free_field_name("Count", &taken), "Count2");
+        let taken: HashSet<String> = ["price".to_string()].into();
+        assert_eq!(free_field_name("Price", &taken), "Price2");
+        assert_eq!(free_field_name("PRICE", &taken), "PRICE2");
+    }
+
+    fn container(id: &str, parent_id: Option<&str>) -> ArchiveContainer {
+        ArchiveContainer {
+            id: id.to_string(),
+            name: id.to_string(),
+            parent_id: parent_id.map(String::from),
+            corners: [0.0, 0.0, 1.0, 1.0],
+            polygon: Vec::new(),
+            rotation: 0.0,
+            image_type: String::new(),
+        }
+    }
+
+    #[test]
+    fn duplicates_below_the_current_root() {
+        let archive_containers = [
+            container("backup_root", None),
+            container("shed", Some("backup_root")),
+            container("shelf", Some("shed")),
+        ];
+        let current: HashSet<String> = ["root".to_string(), "shed".to_string()].into();
+        let mut counts = MergeCounts::default();
+        let mut container_ids = HashMap::new();
+        let mut image_ids = HashMap::new();
+        let containers = plan_containers(
+            &archive_containers,
+            &current,
+            Some("root"),
+            ConflictPolicy::Duplicate,
+            &mut counts,
+            &mut container_ids,
+            &mut image_ids,
+        );
+        assert!(matches!(containers[0], RecordMerge::Skip));
+        assert_eq!(container_ids["backup_root"], "root");
+        assert!(!image_ids.contains_key("backup_root"));
+        assert_eq!((counts.added, counts.duplicated), (1, 1));
+
+        //The current containers and the inserted ones with the parent they are written with
+        let mut parents: Vec<Option<&String>> = vec![None, Some(&container_ids["backup_root"])];
+        for (container, container_merge) in archive_containers.iter().zip(&containers) {
+            if let RecordMerge::Insert(id) = container_merge {
+                assert_ne!(id, "shed");
+                parents.push(
+                    container
+                        .parent_id
+                        .as_ref()
+                        .and_then(|x| container_ids.get(x)),
+                );
+            }
+        }
+        assert_eq!(parents.len(), 4);
+        assert_eq!(parents.iter().filter(|x| x.is_none()).count(), 1);
+        assert_eq!(parents[2], Some(&"root".to_string()));
+    }
+
+    #[test]
+    fn points_gallery_values_to_the_added_images() {
+        let image_ids: HashMap<String, String> = [("a1".to_string(), "b2".to_string())].into();
+        let value = json!(["a1.png", "c3.jpg"]);
+        assert_eq!(
+            gallery_value(Some(&value), &image_ids),
+            Some(json!(["b2.png", "c3.jpg"]))
+        );
+        assert_eq!(gallery_value(None, &image_ids), None);
+        assert_eq!(
+            gallery_value(Some(&json!("text")), &image_ids),
+            Some(json!("text"))
+        );
+    }
+}
@@ -0,0 +1,398 @@
+//Versioned schema migrations for the master database and every user database
+//Each database keeps a schema_version table with one row per applied migration
+//Migrations are applied in order, each one inside its own transaction together with its schema_version row
+//New migrations get appended with the next version number, already released ones are never edited
+
+use axum::{
+    Extension,
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
+use sqlx::{PgConnection, PgPool, Row};
+
+use crate::{AppState, search, users::UserContext};
+
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+}
+
+pub const MASTER_MIGRATIONS: &[Migration] = &[Migration {
+    version: 1,
+    name: "create users table",
+}];
+
+pub const USER_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "normalize container hierarchy",
+    },
+    Migration {
+        version: 2,
+        name: "index item names",
+    },
+    Migration {
+        version: 3,
+        name: "add item search vector",
+    },
+    Migration {
+        version: 4,
+        name: "add container shapes",
+    },
+    Migration {
+        version: 5,
+        name: "add placement quantities",
+    },
+    Migration {
+        version: 6,
+        name: "add stock thresholds",
+    },
+];
+
+#[derive(Clone, Copy)]
+pub enum DatabaseKind {
+    Master,
+    User,
+}
+
+impl DatabaseKind {
+    fn migrations(&self) -> &'static [Migration] {
+        match self {
+            DatabaseKind::Master => MASTER_MIGRATIONS,
+            DatabaseKind::User => USER_MIGRATIONS,
+        }
+    }
+}
+
+async fn apply_migration(
+    conn: &mut PgConnection,
+    kind: DatabaseKind,
+    version: i32,
+) -> Result<(), sqlx::Error> {
+    match (kind, version) {
+        (DatabaseKind::Master, 1) => create_users_table(conn).await,
+        (DatabaseKind::User, 1) => normalize_container_hierarchy(conn).await,
+        (DatabaseKind::User, 2) => index_item_names(conn).await,
+        (DatabaseKind::User, 3) => add_item_search_vector(conn).await,
+        (DatabaseKind::User, 4) => add_container_shapes(conn).await,
+        (DatabaseKind::User, 5) => add_placement_quantities(conn).await,
+        (DatabaseKind::User, 6) => add_stock_thresholds(conn).await,
+        //A listed migration without a function would be recorded as applied without running
+        _ => Err(sqlx::Error::Configuration(
+            format!("No migration function for version {}", version).into(),
+        )),
+    }
+}
+
+//Returns the latest applied version, 0 if the database has never been migrated
+//Doesn't create schema_version so it can be used by the dry run
+async fn current_version(conn: &mut PgConnection) -> Result<i32, sqlx::Error> {
+    let table_exists: bool = sqlx::query("SELECT to_regclass('schema_version') IS NOT NULL AS exists")
+        .fetch_one(&mut *conn)
+        .await?
+        .get("exists");
+    if !table_exists {
+        return Ok(0);
+    }
+    let row = sqlx::query("SELECT COALESCE(MAX(version), 0) AS version FROM schema_version")
+        .fetch_one(&mut *conn)
+        .await?;
+    Ok(row.get("version"))
+}
+
+pub async fn pending_migrations(
+    pool: &PgPool,
+    kind: DatabaseKind,
+) -> Result<Vec<&'static Migration>, sqlx::Error> {
+    let mut conn = pool.acquire().await?;
+    let version = current_version(&mut conn).await?;
+    Ok(kind
+        .migrations()
+        .iter()
+        .filter(|x| x.version > version)
+        .collect())
+}
+
+pub async fn migrate(pool: &PgPool, kind: DatabaseKind) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS schema_version (
+            version INT PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+            )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+    for migration in pending_migrations(pool, kind).await? {
+        let mut tx = pool.begin().await?;
+        //Two requests can open the same user database at once, the lock makes the second one wait
+        //and the version is read again so it doesn't apply the migration twice
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext('schema_version'))")
+            .execute(&mut *tx)
+            .await?;
+        if current_version(&mut tx).await? >= migration.version {
+            continue;
+        }
+        apply_migration(&mut tx, kind, migration.version).await?;
+        sqlx::query("INSERT INTO schema_version (version, name) VALUES ($1, $2)")
+            .bind(migration.version)
+            .bind(migration.name)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        println!("Applied migration {} {}", migration.version, migration.name);
+    }
+    Ok(())
+}
+
+//Migrates every user database listed in the master database, used at startup
+pub async fn migrate_all_user_databases(master_pool: &PgPool) {
+    let rows = match sqlx::query("SELECT id, connection_string FROM users")
+        .fetch_all(master_pool)
+        .await
+    {
+        Ok(rows) => rows,
+        Err(e) => {
+            println!("Reading users for migrations error: {}", e);
+            return;
+        }
+    };
+    for row in rows {
+        let user_id: String = row.get("id");
+        let connection_string: String = row.get("connection_string");
+        match PgPool::connect(&connection_string).await {
+            Ok(pool) => {
+                if let Err(e) = migrate(&pool, DatabaseKind::User).await {
+                    println!("Migrating database of user {} error: {}", user_id, e);
+                }
+                pool.close().await;
+            }
+            Err(e) => println!("Connecting to database of user {} error: {}", user_id, e),
+        }
+    }
+}
+
+//Prints the pending migrations of the master and every user database without applying them
+pub async fn dry_run(master_pool: &PgPool) {
+    match pending_migrations(master_pool, DatabaseKind::Master).await {
+        Ok(pending) => print_pending("master", &pending),
+        Err(e) => println!("master: error reading schema version: {}", e),
+    }
+    let rows = match sqlx::query("SELECT id, username, connection_string FROM users")
+        .fetch_all(master_pool)
+        .await
+    {
+        Ok(rows) => rows,
+        Err(e) => {
+            println!("Reading users error: {}", e);
+            return;
+        }
+    };
+    for row in rows {
+        let user_id: String = row.get("id");
+        let username: String = row.get("username");
+        let connection_string: String = row.get("connection_string");
+        let label = format!("user {} ({})", user_id, username);
+        match PgPool::connect(&connection_string).await {
+            Ok(pool) => {
+                match pending_migrations(&pool, DatabaseKind::User).await {
+                    Ok(pending) => print_pending(&label, &pending),
+                    Err(e) => println!("{}: error reading schema version: {}", label, e),
+                }
+                pool.close().await;
+            }
+            Err(e) => println!("{}: connection error: {}", label, e),
+        }
+    }
+}
+
+fn print_pending(label: &str, pending: &[&Migration]) {
+    if pending.is_empty() {
+        println!("{}: up to date", label);
+        return;
+    }
+    println!("{}: {} pending", label, pending.len());
+    for migration in pending {
+        println!("    {} {}", migration.version, migration.name);
+    }
+}
+
+//Runs after auth_middleware so user databases get migrated on their first request
+//This covers databases created after startup and pools reopened after cleanup_inactive_pools
+pub async fn migrate_user_database(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserContext>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    if !state.migrated_users.contains(&user.user_id) {
+        if let Err(e) = migrate(&user.db_pool, DatabaseKind::User).await {
+            println!("Migrating database of user {} error: {}", user.user_id, e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+        state.migrated_users.insert(user.user_id.clone());
+    }
+    Ok(next.run(request).await)
+}
+
+//=========================================
+//Master migrations
+//=========================================
+
+async fn create_users_table(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+    //IF NOT EXISTS because the table predates the migrations
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS users (
+            id TEXT PRIMARY KEY,
+            username TEXT NOT NULL UNIQUE,
+            email TEXT NOT NULL UNIQUE,
+            password_hash TEXT NOT NULL,
+            connection_string TEXT NOT NULL
+            )
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+//=========================================
+//User migrations
+//=========================================
+
+//Converts the '@'-joined strings (containers.master, containers.slaves, containers.contained_items)
+//to a parent_id column and an item_placements join table
+//Databases converted before versioning was added are detected and left untouched
+async fn normalize_container_hierarchy(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+    let old_schema = sqlx::query(
+        r#"
+        SELECT 1 FROM information_schema.columns
+        WHERE table_schema = 'public' AND table_name = 'containers' AND column_name = 'master'
+        "#,
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .is_some();
+    if !old_schema {
+        return Ok(());
+    }
+    sqlx::query(
+        r#"
+        ALTER TABLE containers ADD COLUMN parent_id TEXT REFERENCES containers(id) ON DELETE CASCADE
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
+    //Masters that point to containers which no longer exist become roots
+    sqlx::query(
+        r#"
+        UPDATE containers c SET parent_id = c.master
+        WHERE EXISTS (SELECT 1 FROM containers p WHERE p.id = c.master AND p.id <> c.id)
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(
+        r#"
+        CREATE TABLE item_placements (
+            item_id TEXT NOT NULL REFERENCES items(id) ON DELETE CASCADE,
+            container_id TEXT NOT NULL REFERENCES containers(id) ON DELETE CASCADE,
+            PRIMARY KEY (item_id, container_id)
+            )
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
+    //Only ids that still exist in items are carried over, dangling ids are dropped
+    sqlx::query(
+        r#"
+        INSERT INTO item_placements (item_id, container_id)
+        SELECT DISTINCT i.id, c.id
+        FROM containers c
+        CROSS JOIN LATERAL unnest(string_to_array(c.contained_items, '@')) AS placed(item_id)
+        JOIN items i ON i.id = placed.item_id
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query("CREATE INDEX containers_parent_id_idx ON containers (parent_id)")
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("CREATE INDEX item_placements_container_id_idx ON item_placements (container_id)")
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query(
+        r#"
+        ALTER TABLE containers
+        DROP COLUMN master,
+        DROP COLUMN slaves,
+        DROP COLUMN contained_items
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+//The home page sorts and pages by name by default
+async fn index_item_names(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+    sqlx::query("CREATE INDEX IF NOT EXISTS items_name_idx ON items (name, id)")
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+async fn add_item_search_vector(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+    search::drop_search_vector(conn).await?;
+    search::create_search_vector(conn).await?;
+    Ok(())
+}
+
+//Containers can be drawn as a polygon or a rotated rectangle, an empty polygon with no rotation is the old rectangle
+async fn add_container_shapes(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        ALTER TABLE containers
+        ADD COLUMN IF NOT EXISTS polygon TEXT NOT NULL DEFAULT '',
+        ADD COLUMN IF NOT EXISTS rotation REAL NOT NULL DEFAULT 0
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+//Units of the item kept in the container, existing placements become 1
+async fn add_placement_quantities(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        ALTER TABLE item_placements
+        ADD COLUMN IF NOT EXISTS quantity INTEGER NOT NULL DEFAULT 1 CHECK (quantity >= 0)
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+//Minimum of an item before it shows up as low stock, a NULL field_name is the quantity over all placements
+async fn add_stock_thresholds(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS stock_thresholds (
+            item_id TEXT PRIMARY KEY REFERENCES items(id) ON DELETE CASCADE,
+            field_name TEXT,
+            minimum DOUBLE PRECISION NOT NULL
+        )
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,200 @@
+//Scan to open, turns a scanned or typed code into the path of containers that leads to it
+//Codes are the "visualoc:container:{id}" and "visualoc:item:{id}" strings printed on the labels, or a bare id
+
+use axum::{Extension, Json, extract::Multipart, http::StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ContainedItem, Container, ITEM_QUANTITY_COLUMN, container_path, item_container_path,
+    pgrow_to_item, users::UserContext,
+};
+
+const MAX_SCAN_SIZE: u32 = 2000; //Photos are scaled down to this before looking for codes
+
+#[derive(Deserialize)]
+pub struct CodeRequest {
+    code: String,
+}
+
+#[derive(Serialize)]
+pub struct ResolvedCode {
+    path: Vec<Container>, //From the root down to the container itself, or the item's container
+    item: Option<ContainedItem>, //Set when the code points to an item
+}
+
+async fn resolve(user: &UserContext, code: &str) -> Result<Option<ResolvedCode>, sqlx::Error> {
+    let code = code.trim();
+    let (id, try_container, try_item) = if let Some(id) = code.strip_prefix("visualoc:container:") {
+        (id, true, false)
+    } else if let Some(id) = code.strip_prefix("visualoc:item:") {
+        (id, false, true)
+    } else {
+        (code, true, true)
+    };
+    if id.is_empty() {
+        return Ok(None);
+    }
+    if try_container {
+        let path = container_path(&*user.db_pool, id).await?;
+        if !path.is_empty() {
+            return Ok(Some(ResolvedCode { path, item: None }));
+        }
+    }
+    if try_item {
+        let row = sqlx::query(&format!(
+            "SELECT *, {} FROM items WHERE id = $1",
+            ITEM_QUANTITY_COLUMN
+        ))
+        .bind(id)
+        .fetch_optional(&*user.db_pool)
+        .await?;
+        if let Some(row) = row {
+            return Ok(Some(ResolvedCode {
+                path: item_container_path(&user.db_pool, id).await?,
+                item: Some(pgrow_to_item(&row)),
+            }));
+        }
+    }
+    Ok(None)
+}
+
+pub async fn resolve_code(
+    Extension(user): Extension<UserContext>,
+    Json(payload): Json<CodeRequest>,
+) -> Result<Json<Option<ResolvedCode>>, StatusCode> {
+    println!("in resolve code");
+    match resolve(&user, &payload.code).await {
+        Ok(resolved) => Ok(Json(resolved)),
+        Err(e) => {
+            println!("Resolve code error {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+//The text of the first QR code found in the image
+pub fn decode_qr(data: &[u8]) -> Option<String> {
+    let mut image = image::load_from_memory(data).ok()?;
+    if image.width().max(image.height()) > MAX_SCAN_SIZE {
+        image = image.resize(
+            MAX_SCAN_SIZE,
+            MAX_SCAN_SIZE,
+            image::imageops::FilterType::Triangle,
+        );
+    }
+    let luma = image.to_luma8();
+    let mut prepared = rqrr::PreparedImage::prepare_from_greyscale(
+        luma.width() as usize,
+        luma.height() as usize,
+        |x, y| luma.get_pixel(x as u32, y as u32).0[0],
+    );
+    prepared
+        .detect_grids()
+        .into_iter()
+        .find_map(|grid| grid.decode().ok().map(|(_, content)| content))
+}
+
+//Decodes the QR code in an uploaded photo and resolves it like a typed code
+pub async fn scan_code_image(
+    Extension(user): Extension<UserContext>,
+    mut multipart: Multipart,
+) -> Result<Json<Option<ResolvedCode>>, (StatusCode, String)> {
+    println!("in scan code image");
+    let mut data: Option<axum::body::Bytes> = None;
+    while let Some(field) = multipart.next_field().await.map_err(|e| {
+        println!("Scan code image error {}", e);
+        (StatusCode::BAD_REQUEST, e.to_string())
+    })? {
+        if field.name() == Some("image") {
+            data = Some(field.bytes().await.map_err(|e| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to read file data : {}", e),
+                )
+            })?);
+        }
+    }
+    let Some(data) = data else {
+        return Err((StatusCode::BAD_REQUEST, "Missing image".to_string()));
+    };
+    //Decoding a large photo takes a while, it runs off the async threads
+    let decoded = tokio::task::spawn_blocking(move || decode_qr(&data))
+        .await
+        .map_err(|e| {
+            println!("Scan code image decode error {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to read the image".to_string(),
+            )
+        })?;
+    let Some(code) = decoded else {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "No QR code found in the image".to_string(),
+        ));
+    };
+    match resolve(&user, &code).await {
+        Ok(resolved) => Ok(Json(resolved)),
+        Err(e) => {
+            println!("Scan code image resolve error {}", e);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to resolve the code".to_string(),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, GrayImage, ImageFormat, Luma};
+    use qrcode::{Color, QrCode};
+    use std::io::Cursor;
+
+    //A PNG of the code with 8 pixels per module and the quiet zone around it
+    fn qr_png(content: &str, rotate: bool) -> Vec<u8> {
+        let code = QrCode::new(content.as_bytes()).unwrap();
+        let width = code.width() as u32;
+        let colors = code.to_colors();
+        let image = GrayImage::from_fn((width + 8) * 8, (width + 8) * 8, |x, y| {
+            let (x, y) = (x / 8, y / 8);
+            let dark = (4..width + 4).contains(&x)
+                && (4..width + 4).contains(&y)
+                && colors[((y - 4) * width + x - 4) as usize] == Color::Dark;
+            Luma([if dark { 0 } else { 255 }])
+        });
+        let mut image = DynamicImage::ImageLuma8(image);
+        if rotate {
+            image = image.rotate90();
+        }
+        let mut png = Cursor::new(Vec::new());
+        image.write_to(&mut png, ImageFormat::Png).unwrap();
+        png.into_inner()
+    }
+
+    #[test]
+    fn decodes_a_code() {
+        let png = qr_png("visualoc:container:1234-abcd", false);
+        assert_eq!(
+            decode_qr(&png).as_deref(),
+            Some("visualoc:container:1234-abcd")
+        );
+    }
+
+    #[test]
+    fn decodes_a_rotated_code() {
+        let png = qr_png("visualoc:item:5678", true);
+        assert_eq!(decode_qr(&png).as_deref(), Some("visualoc:item:5678"));
+    }
+
+    #[test]
+    fn finds_no_code() {
+        let mut png = Cursor::new(Vec::new());
+        DynamicImage::ImageLuma8(GrayImage::from_pixel(200, 200, Luma([255])))
+            .write_to(&mut png, ImageFormat::Png)
+            .unwrap();
+        assert_eq!(decode_qr(&png.into_inner()), None);
+        assert_eq!(decode_qr(b"not an image"), None);
+    }
+}
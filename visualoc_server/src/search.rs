@@ -0,0 +1,630 @@
+//Filter language for the item search, compiled to a parameterized WHERE clause
+//
+//  query    = and ("OR" and)*
+//  and      = not ("AND" not)*
+//  not      = "NOT" not | primary
+//  primary  = "(" query ")" | field [operator value]
+//  operator = "=" | "!=" | "<" | "<=" | ">" | ">=" | "~"
+//  value    = "quoted text" | word
+//
+//Keywords are uppercase, field names are case insensitive
+//A field on its own is only allowed for Bool fields and means the field is checked
+//"~" is a case insensitive contains, it only works on text fields
+//Dates are written as 2024-12-31, date-times also accept RFC 3339 like 2024-12-31T18:00:00Z
+//Field names are only ever taken from dynamic_fields, values are always bound as parameters
+
+use chrono::{DateTime, NaiveDate};
+use rust_decimal::Decimal;
+use sqlx::{PgConnection, Postgres, postgres::PgArguments, query::Query};
+
+use crate::{DataType, read_dynamic_fields};
+
+#[derive(Clone)]
+pub enum SearchParam {
+    Text(String),
+    Number(f64),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Contains,
+}
+
+impl Operator {
+    fn as_sql(&self) -> &'static str {
+        match self {
+            Operator::Equal => "=",
+            Operator::NotEqual => "<>",
+            Operator::Less => "<",
+            Operator::LessOrEqual => "<=",
+            Operator::Greater => ">",
+            Operator::GreaterOrEqual => ">=",
+            Operator::Contains => "~",
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Operator(Operator),
+    OpenParen,
+    CloseParen,
+    And,
+    Or,
+    Not,
+}
+
+//Characters that can't be part of a word
+const SPECIAL_CHARACTERS: &str = "()=!<>~\"";
+
+//A query is a filter when it parses as one, or when it starts with a field and an operator so a mistake in it is shown
+//Anything else, like `screws (M4)` or `Salt AND Pepper`, is plain text searched in the selected column
+pub fn is_filter(query: &str, fields: &[(String, DataType)]) -> bool {
+    if compile(query, fields).is_ok() {
+        return true;
+    }
+    match tokenize(query).as_deref() {
+        Ok([(Token::Word(name), _), (Token::Operator(_), _), ..]) => {
+            find_field(name, fields).is_some()
+        }
+        _ => false,
+    }
+}
+
+//ILIKE treats % and _ as wildcards and \ as their escape, a contains search matches them literally
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+//Returns the WHERE clause and the parameters to bind in order, or a message for the user
+pub fn compile(
+    query: &str,
+    fields: &[(String, DataType)],
+) -> Result<(String, Vec<SearchParam>), String> {
+    let tokens = tokenize(query)?;
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+        fields,
+        params: Vec::new(),
+    };
+    let sql = parser.parse_or()?;
+    if let Some((_, pos)) = parser.tokens.get(parser.position) {
+        return Err(format!("Unexpected input at character {}", pos + 1));
+    }
+    Ok((sql, parser.params))
+}
+
+//Each token carries the index of the character it starts at, for the error messages
+fn tokenize(query: &str) -> Result<Vec<(Token, usize)>, String> {
+    let chars: Vec<char> = query.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        match chars[i] {
+            c if c.is_whitespace() => i += 1,
+            '(' => {
+                tokens.push((Token::OpenParen, start));
+                i += 1;
+            }
+            ')' => {
+                tokens.push((Token::CloseParen, start));
+                i += 1;
+            }
+            '=' => {
+                tokens.push((Token::Operator(Operator::Equal), start));
+                i += 1;
+            }
+            '~' => {
+                tokens.push((Token::Operator(Operator::Contains), start));
+                i += 1;
+            }
+            '!' => {
+                if chars.get(i + 1) != Some(&'=') {
+                    return Err(format!("Expected '!=' at character {}", start + 1));
+                }
+                tokens.push((Token::Operator(Operator::NotEqual), start));
+                i += 2;
+            }
+            '<' | '>' => {
+                let or_equal = chars.get(i + 1) == Some(&'=');
+                let operator = match (chars[i], or_equal) {
+                    ('<', false) => Operator::Less,
+                    ('<', true) => Operator::LessOrEqual,
+                    ('>', false) => Operator::Greater,
+                    _ => Operator::GreaterOrEqual,
+                };
+                tokens.push((Token::Operator(operator), start));
+                i += if or_equal { 2 } else { 1 };
+            }
+            '"' => {
+                i += 1;
+                let mut text = String::new();
+                while i < chars.len() && chars[i] != '"' {
+                    text.push(chars[i]);
+                    i += 1;
+                }
+                if i == chars.len() {
+                    return Err(format!("Unclosed quote at character {}", start + 1));
+                }
+                i += 1;
+                tokens.push((Token::Quoted(text), start));
+            }
+            _ => {
+                let mut word = String::new();
+                while i < chars.len()
+                    && !chars[i].is_whitespace()
+                    && !SPECIAL_CHARACTERS.contains(chars[i])
+                {
+                    word.push(chars[i]);
+                    i += 1;
+                }
+                let token = match word.as_str() {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    _ => Token::Word(word),
+                };
+                tokens.push((token, start));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+//Binds the params in the order compile returned them
+pub fn bind_params<'q>(
+    mut query: Query<'q, Postgres, PgArguments>,
+    params: &[SearchParam],
+) -> Query<'q, Postgres, PgArguments> {
+    for param in params {
+        match param {
+            SearchParam::Text(val) => query = query.bind(val.clone()),
+            SearchParam::Number(val) => query = query.bind(*val),
+        };
+    }
+    query
+}
+
+//Returns the column name as stored in the database and its type
+pub fn find_field(name: &str, fields: &[(String, DataType)]) -> Option<(String, DataType)> {
+    if name.eq_ignore_ascii_case("name") {
+        return Some(("name".to_string(), DataType::String));
+    }
+    fields
+        .iter()
+        .find(|(field_name, _)| field_name.eq_ignore_ascii_case(name))
+        .map(|(field_name, data_type)| (field_name.clone(), data_type.clone()))
+}
+
+struct Parser<'a> {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    fields: &'a [(String, DataType)],
+    params: Vec<SearchParam>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    //Position of the current token as shown to the user, the end of the query if there are no tokens left
+    fn character(&self) -> usize {
+        match self.tokens.get(self.position) {
+            Some((_, pos)) => pos + 1,
+            None => match self.tokens.last() {
+                Some((_, pos)) => pos + 2,
+                None => 1,
+            },
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<String, String> {
+        let mut sql = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.position += 1;
+            sql = format!("({} OR {})", sql, self.parse_and()?);
+        }
+        Ok(sql)
+    }
+
+    fn parse_and(&mut self) -> Result<String, String> {
+        let mut sql = self.parse_not()?;
+        while self.peek() == Some(&Token::And) {
+            self.position += 1;
+            sql = format!("({} AND {})", sql, self.parse_not()?);
+        }
+        Ok(sql)
+    }
+
+    fn parse_not(&mut self) -> Result<String, String> {
+        if self.peek() == Some(&Token::Not) {
+            self.position += 1;
+            return Ok(format!("(NOT {})", self.parse_not()?));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<String, String> {
+        let character = self.character();
+        match self.tokens.get(self.position).map(|(token, _)| token.clone()) {
+            Some(Token::OpenParen) => {
+                self.position += 1;
+                let sql = self.parse_or()?;
+                if self.peek() != Some(&Token::CloseParen) {
+                    return Err(format!("Expected ')' at character {}", self.character()));
+                }
+                self.position += 1;
+                Ok(sql)
+            }
+            Some(Token::Word(name)) => {
+                self.position += 1;
+                let (column, data_type) = match find_field(&name, self.fields) {
+                    Some(field) => field,
+                    None => {
+                        return Err(format!(
+                            "Unknown field '{}' at character {}",
+                            name, character
+                        ));
+                    }
+                };
+                let operator = match self.peek() {
+                    Some(Token::Operator(operator)) => *operator,
+                    _ => {
+                        //A Bool field on its own means it's checked
+                        if matches!(data_type, DataType::Bool) {
+                            return Ok(format!(r#"COALESCE("{}", 0) <> 0"#, column));
+                        }
+                        return Err(format!(
+                            "Expected an operator after '{}' at character {}",
+                            name,
+                            self.character()
+                        ));
+                    }
+                };
+                self.position += 1;
+                let value_character = self.character();
+                let value = match self.tokens.get(self.position).map(|(token, _)| token.clone()) {
+                    Some(Token::Word(value)) | Some(Token::Quoted(value)) => value,
+                    _ => {
+                        return Err(format!("Expected a value at character {}", value_character));
+                    }
+                };
+                self.position += 1;
+                self.comparison(&name, &column, &data_type, operator, value, value_character)
+            }
+            _ => Err(format!("Expected a field name at character {}", character)),
+        }
+    }
+
+    //Builds the comparison for the column's type, the value is added to the params
+    fn comparison(
+        &mut self,
+        name: &str,
+        column: &str,
+        data_type: &DataType,
+        operator: Operator,
+        value: String,
+        value_character: usize,
+    ) -> Result<String, String> {
+        match data_type {
+            DataType::String | DataType::Text | DataType::List(_) => {
+                self.params.push(SearchParam::Text(match operator {
+                    Operator::Contains => escape_like(&value),
+                    _ => value,
+                }));
+                let param = self.params.len();
+                match operator {
+                    Operator::Equal => Ok(format!(r#"lower("{}") = lower(${})"#, column, param)),
+                    Operator::NotEqual => Ok(format!(
+                        r#"lower(COALESCE("{}", '')) <> lower(${})"#,
+                        column, param
+                    )),
+                    Operator::Contains => {
+                        Ok(format!(r#""{}" ILIKE '%' || ${} || '%'"#, column, param))
+                    }
+                    _ => Err(format!(
+                        "'{}' is a text field, only =, != and ~ can be used",
+                        name
+                    )),
+                }
+            }
+            DataType::Integer | DataType::Float | DataType::Percentage => {
+                if operator == Operator::Contains {
+                    return Err(format!("'{}' is a number field, ~ can't be used", name));
+                }
+                let number: f64 = match value.parse() {
+                    Ok(number) => number,
+                    Err(_) => {
+                        return Err(format!(
+                            "'{}' is not a number at character {}",
+                            value, value_character
+                        ));
+                    }
+                };
+                self.params.push(SearchParam::Number(number));
+                Ok(format!(
+                    r#""{}" {} ${}"#,
+                    column,
+                    operator.as_sql(),
+                    self.params.len()
+                ))
+            }
+            DataType::Bool => {
+                let checked = match value.to_lowercase().as_str() {
+                    "true" | "yes" | "1" => true,
+                    "false" | "no" | "0" => false,
+                    _ => {
+                        return Err(format!(
+                            "'{}' is not true or false at character {}",
+                            value, value_character
+                        ));
+                    }
+                };
+                let checked = match operator {
+                    Operator::Equal => checked,
+                    Operator::NotEqual => !checked,
+                    _ => {
+                        return Err(format!(
+                            "'{}' is a checkbox field, only = and != can be used",
+                            name
+                        ));
+                    }
+                };
+                if checked {
+                    Ok(format!(r#"COALESCE("{}", 0) <> 0"#, column))
+                } else {
+                    Ok(format!(r#"COALESCE("{}", 0) = 0"#, column))
+                }
+            }
+            //Compared as numeric so amounts like 0.10 match exactly
+            DataType::Money { .. } => {
+                if operator == Operator::Contains {
+                    return Err(format!("'{}' is a money field, ~ can't be used", name));
+                }
+                if value.parse::<Decimal>().is_err() {
+                    return Err(format!(
+                        "'{}' is not an amount at character {}",
+                        value, value_character
+                    ));
+                }
+                self.params.push(SearchParam::Text(value));
+                Ok(format!(
+                    r#""{}" {} ${}::numeric"#,
+                    column,
+                    operator.as_sql(),
+                    self.params.len()
+                ))
+            }
+            DataType::Date | DataType::DateTime => {
+                if operator == Operator::Contains {
+                    return Err(format!("'{}' is a date field, ~ can't be used", name));
+                }
+                //A plain date compared to a date-time field means midnight of that day, in the server time zone
+                let (valid, cast) = match data_type {
+                    DataType::Date => (
+                        NaiveDate::parse_from_str(&value, "%Y-%m-%d").is_ok(),
+                        "date",
+                    ),
+                    _ => (
+                        DateTime::parse_from_rfc3339(&value).is_ok()
+                            || NaiveDate::parse_from_str(&value, "%Y-%m-%d").is_ok(),
+                        "timestamptz",
+                    ),
+                };
+                if !valid {
+                    return Err(format!(
+                        "'{}' is not a date like 2024-12-31 at character {}",
+                        value, value_character
+                    ));
+                }
+                self.params.push(SearchParam::Text(value));
+                Ok(format!(
+                    r#""{}" {} ${}::{}"#,
+                    column,
+                    operator.as_sql(),
+                    self.params.len(),
+                    cast
+                ))
+            }
+            DataType::Gallery => Err(format!("'{}' is a gallery field and can't be searched", name)),
+        }
+    }
+}
+
+//=========================================
+//Full text search
+//=========================================
+
+//items.search_vector is a generated tsvector over the name and every TEXT field, the name ranks higher
+//Postgres won't drop a column a generated column depends on, so it's dropped before the fields
+//change and created again after, in the same transaction
+
+//Fields whose values are words, galleries are TEXT too but only hold image ids
+fn text_fields(fields: &[(String, DataType)]) -> Vec<&String> {
+    fields
+        .iter()
+        .filter(|(_, data_type)| {
+            matches!(
+                data_type,
+                DataType::String | DataType::Text | DataType::List(_)
+            )
+        })
+        .map(|(name, _)| name)
+        .collect()
+}
+
+pub async fn drop_search_vector(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+    sqlx::query("ALTER TABLE items DROP COLUMN IF EXISTS search_vector")
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+pub async fn create_search_vector(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+    let fields = read_dynamic_fields(&mut *conn).await?;
+    let mut expression = "setweight(to_tsvector('simple', coalesce(name, '')), 'A')".to_string();
+    for field in text_fields(&fields) {
+        expression += &format!(
+            r#" || setweight(to_tsvector('simple', coalesce("{}", '')), 'B')"#,
+            field
+        );
+    }
+    sqlx::query(&format!(
+        "ALTER TABLE items ADD COLUMN search_vector tsvector GENERATED ALWAYS AS ({}) STORED",
+        expression
+    ))
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query("CREATE INDEX items_search_vector_idx ON items USING GIN (search_vector)")
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+//Turns what the user typed into a prefix query, so results show up while a word is still being typed
+//Only letters and digits are kept, everything else separates words
+pub fn prefix_tsquery(search_string: &str) -> Option<String> {
+    let words: Vec<String> = search_string
+        .split(|x: char| !x.is_alphanumeric())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_lowercase() + ":*")
+        .collect();
+    if words.is_empty() {
+        return None;
+    }
+    Some(words.join(" & "))
+}
+
+//Array of the field names that match the tsquery in the given param, for highlighting on the client
+pub fn matched_fields_sql(fields: &[(String, DataType)], param: usize) -> String {
+    let mut cases = vec![format!(
+        "CASE WHEN to_tsvector('simple', coalesce(name, '')) @@ to_tsquery('simple', ${}) THEN 'Name' END",
+        param
+    )];
+    for field in text_fields(fields) {
+        cases.push(format!(
+            r#"CASE WHEN to_tsvector('simple', coalesce("{}", '')) @@ to_tsquery('simple', ${}) THEN '{}' END"#,
+            field, param, field
+        ));
+    }
+    format!("ARRAY_REMOVE(ARRAY[{}], NULL)", cases.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields() -> Vec<(String, DataType)> {
+        vec![
+            ("Weight".to_string(), DataType::Float),
+            ("Checked".to_string(), DataType::Bool),
+            ("Notes".to_string(), DataType::Text),
+            (
+                "Price".to_string(),
+                DataType::Money {
+                    currency: "EUR".to_string(),
+                },
+            ),
+            ("Bought".to_string(), DataType::Date),
+        ]
+    }
+
+    fn params(params: &[SearchParam]) -> Vec<String> {
+        params
+            .iter()
+            .map(|x| match x {
+                SearchParam::Text(val) => val.clone(),
+                SearchParam::Number(val) => val.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tells_filters_from_text() {
+        let fields = fields();
+        assert!(is_filter("Weight > 2.5 AND Checked", &fields));
+        assert!(is_filter("checked", &fields));
+        assert!(is_filter("NOT (notes ~ box)", &fields));
+        //A field and an operator is a filter even with a mistake, so the mistake is shown
+        assert!(is_filter("Weight > heavy", &fields));
+        assert!(!is_filter("screws (M4)", &fields));
+        assert!(!is_filter("Salt AND Pepper", &fields));
+        assert!(!is_filter("M4 = 4mm", &fields));
+        assert!(!is_filter("screws", &fields));
+        assert!(!is_filter("", &fields));
+    }
+
+    #[test]
+    fn compiles_with_precedence() {
+        let (sql, values) = compile(
+            "Weight >= 2 OR NOT Checked AND name = \"Big box\"",
+            &fields(),
+        )
+        .unwrap();
+        assert_eq!(
+            sql,
+            r#"("Weight" >= $1 OR ((NOT COALESCE("Checked", 0) <> 0) AND lower("name") = lower($2)))"#
+        );
+        assert_eq!(params(&values), ["2", "Big box"]);
+    }
+
+    #[test]
+    fn compiles_each_type() {
+        let fields = fields();
+        let (sql, values) = compile("Checked = no", &fields).unwrap();
+        assert_eq!(sql, r#"COALESCE("Checked", 0) = 0"#);
+        assert!(values.is_empty());
+        let (sql, values) = compile("Price < 0.10", &fields).unwrap();
+        assert_eq!(sql, r#""Price" < $1::numeric"#);
+        assert_eq!(params(&values), ["0.10"]);
+        let (sql, _) = compile("(Bought != 2024-12-31)", &fields).unwrap();
+        assert_eq!(sql, r#""Bought" <> $1::date"#);
+    }
+
+    #[test]
+    fn escapes_contains_wildcards() {
+        let (sql, values) = compile(r#"Notes ~ "50%_off\""#, &fields()).unwrap();
+        assert_eq!(sql, r#""Notes" ILIKE '%' || $1 || '%'"#);
+        assert_eq!(params(&values), [r#"50\%\_off\\"#]);
+        let (_, values) = compile("Notes = 50%", &fields()).unwrap();
+        assert_eq!(params(&values), ["50%"]);
+    }
+
+    #[test]
+    fn reports_errors() {
+        let fields = fields();
+        let error = |query: &str| compile(query, &fields).err().unwrap_or_default();
+        assert_eq!(
+            error("Colour = red"),
+            "Unknown field 'Colour' at character 1"
+        );
+        assert_eq!(
+            error("Weight > heavy"),
+            "'heavy' is not a number at character 10"
+        );
+        assert_eq!(
+            error("(Checked OR Weight > 2"),
+            "Expected ')' at character 23"
+        );
+        assert_eq!(error("Notes = \"open"), "Unclosed quote at character 9");
+        assert_eq!(
+            error("Notes > 2"),
+            "'Notes' is a text field, only =, != and ~ can be used"
+        );
+        assert_eq!(error("Checked Checked"), "Unexpected input at character 9");
+    }
+}
@@ -0,0 +1,128 @@
+//Totals of the money fields and of the units for every location and container
+//A container's total includes every container below it, an item placed twice in the same subtree is counted once
+//Units are counted per placement, 2 of an item in one box and 3 in another make 5
+
+use axum::{Extension, Json, http::StatusCode};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use sqlx::Row;
+
+use crate::{DataType, read_dynamic_fields, users::UserContext};
+
+#[derive(Serialize)]
+pub struct ValueTotal {
+    container_id: String,
+    container_name: String,
+    parent_id: String, //Empty for locations
+    field_name: String,
+    currency: String,
+    total: String, //Text like the field values, a float would lose cents
+}
+
+pub async fn get_value_totals(
+    Extension(user): Extension<UserContext>,
+) -> Result<Json<Vec<ValueTotal>>, StatusCode> {
+    println!("in get value totals");
+    let fields = match read_dynamic_fields(&*user.db_pool).await {
+        Ok(fields) => fields,
+        Err(e) => {
+            println!("Value totals fields error {}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let mut totals = Vec::new();
+    for (field_name, data_type) in fields {
+        let DataType::Money { currency } = data_type else {
+            continue;
+        };
+        let query = format!(
+            r#"
+            WITH RECURSIVE tree AS (
+                SELECT id AS root_id, id FROM containers
+                UNION ALL
+                SELECT t.root_id, c.id FROM containers c JOIN tree t ON c.parent_id = t.id
+            ),
+            subtree_items AS (
+                SELECT DISTINCT t.root_id, p.item_id FROM tree t
+                JOIN item_placements p ON p.container_id = t.id
+            )
+            SELECT c.id, c.name, COALESCE(c.parent_id, '') AS parent_id,
+            COALESCE(SUM(i."{}"), 0) AS total
+            FROM containers c
+            LEFT JOIN subtree_items s ON s.root_id = c.id
+            LEFT JOIN items i ON i.id = s.item_id
+            GROUP BY c.id, c.name, c.parent_id
+            ORDER BY c.name, c.id
+            "#,
+            field_name
+        );
+        match sqlx::query(&query).fetch_all(&*user.db_pool).await {
+            Ok(rows) => {
+                for row in rows {
+                    let total: Decimal = row.get("total");
+                    totals.push(ValueTotal {
+                        container_id: row.get("id"),
+                        container_name: row.get("name"),
+                        parent_id: row.get("parent_id"),
+                        field_name: field_name.clone(),
+                        currency: currency.clone(),
+                        total: format!("{:.2}", total),
+                    });
+                }
+            }
+            Err(e) => {
+                println!("Value totals error {}", e);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        }
+    }
+    Ok(Json(totals))
+}
+
+#[derive(Serialize)]
+pub struct UnitTotal {
+    container_id: String,
+    container_name: String,
+    parent_id: String, //Empty for locations
+    units: i64,
+}
+
+pub async fn get_unit_totals(
+    Extension(user): Extension<UserContext>,
+) -> Result<Json<Vec<UnitTotal>>, StatusCode> {
+    println!("in get unit totals");
+    match sqlx::query(
+        r#"
+        WITH RECURSIVE tree AS (
+            SELECT id AS root_id, id FROM containers
+            UNION ALL
+            SELECT t.root_id, c.id FROM containers c JOIN tree t ON c.parent_id = t.id
+        )
+        SELECT c.id, c.name, COALESCE(c.parent_id, '') AS parent_id,
+        COALESCE(SUM(p.quantity), 0) AS units
+        FROM containers c
+        LEFT JOIN tree t ON t.root_id = c.id
+        LEFT JOIN item_placements p ON p.container_id = t.id
+        GROUP BY c.id, c.name, c.parent_id
+        ORDER BY c.name, c.id
+        "#,
+    )
+    .fetch_all(&*user.db_pool)
+    .await
+    {
+        Ok(rows) => Ok(Json(
+            rows.iter()
+                .map(|row| UnitTotal {
+                    container_id: row.get("id"),
+                    container_name: row.get("name"),
+                    parent_id: row.get("parent_id"),
+                    units: row.get("units"),
+                })
+                .collect(),
+        )),
+        Err(e) => {
+            println!("Unit totals error {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
@@ -0,0 +1,281 @@
+//Low stock warnings, an item can have a minimum for the total quantity or for one of its numeric fields
+//The report lists every item at or below its minimum under each location it's placed in
+//The shopping list is the same report as CSV with one line per item
+
+use axum::{
+    Extension, Json,
+    http::{StatusCode, header},
+    response::IntoResponse,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Row};
+
+use crate::{DataType, IdRequest, read_dynamic_fields, users::UserContext};
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StockThreshold {
+    field_name: Option<String>, //None compares the quantity over all placements
+    minimum: f64,
+}
+
+#[derive(Deserialize)]
+pub struct SetThresholdRequest {
+    item_id: String,
+    threshold: Option<StockThreshold>, //None removes the warning
+}
+
+#[derive(Serialize)]
+pub struct LowStockItem {
+    item_id: String,
+    item_name: String,
+    field_name: Option<String>,
+    value: f64,
+    minimum: f64,
+}
+
+#[derive(Serialize)]
+pub struct LowStockLocation {
+    location_id: String, //Empty for items not in a location
+    location_name: String,
+    items: Vec<LowStockItem>,
+}
+
+//Only fields holding a number can be compared, the names are checked so they can be put in the query
+pub fn numeric_fields(fields: Vec<(String, DataType)>) -> Vec<String> {
+    fields
+        .into_iter()
+        .filter(|(name, data_type)| {
+            matches!(
+                data_type,
+                DataType::Integer | DataType::Float | DataType::Percentage
+            ) && name.chars().all(|x| x.is_alphanumeric())
+        })
+        .map(|(name, _)| name)
+        .collect()
+}
+
+pub async fn get_stock_threshold(
+    Extension(user): Extension<UserContext>,
+    Json(payload): Json<IdRequest>,
+) -> Result<Json<Option<StockThreshold>>, StatusCode> {
+    println!("in get stock threshold");
+    match sqlx::query("SELECT field_name, minimum FROM stock_thresholds WHERE item_id = $1")
+        .bind(&payload.id)
+        .fetch_optional(&*user.db_pool)
+        .await
+    {
+        Ok(row) => Ok(Json(row.map(|row| StockThreshold {
+            field_name: row.get("field_name"),
+            minimum: row.get("minimum"),
+        }))),
+        Err(e) => {
+            println!("Get stock threshold error {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn set_stock_threshold(
+    Extension(user): Extension<UserContext>,
+    Json(payload): Json<SetThresholdRequest>,
+) -> Result<StatusCode, StatusCode> {
+    println!("in set stock threshold");
+    let Some(threshold) = payload.threshold else {
+        return match sqlx::query("DELETE FROM stock_thresholds WHERE item_id = $1")
+            .bind(&payload.item_id)
+            .execute(&*user.db_pool)
+            .await
+        {
+            Ok(_) => Ok(StatusCode::OK),
+            Err(e) => {
+                println!("Remove stock threshold error {}", e);
+                Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        };
+    };
+    if let Some(field_name) = &threshold.field_name {
+        let fields = match read_dynamic_fields(&*user.db_pool).await {
+            Ok(fields) => numeric_fields(fields),
+            Err(e) => {
+                println!("Set stock threshold fields error {}", e);
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        };
+        if !fields.contains(field_name) {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+    }
+    if !threshold.minimum.is_finite() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    match sqlx::query(
+        r#"
+        INSERT INTO stock_thresholds (item_id, field_name, minimum) VALUES ($1, $2, $3)
+        ON CONFLICT (item_id) DO UPDATE SET field_name = EXCLUDED.field_name, minimum = EXCLUDED.minimum
+        "#,
+    )
+    .bind(&payload.item_id)
+    .bind(&threshold.field_name)
+    .bind(threshold.minimum)
+    .execute(&*user.db_pool)
+    .await
+    {
+        Ok(_) => Ok(StatusCode::OK),
+        //The item has to exist
+        Err(sqlx::Error::Database(e)) if e.is_foreign_key_violation() => {
+            Err(StatusCode::NOT_FOUND)
+        }
+        Err(e) => {
+            println!("Set stock threshold error {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+//Locations sorted by name, the items that aren't in a location come last
+async fn low_stock(pool: &PgPool) -> Result<Vec<LowStockLocation>, sqlx::Error> {
+    let fields = numeric_fields(read_dynamic_fields(pool).await?);
+    //The value compared is picked per threshold, an item without placements has a quantity of 0
+    //A field without a value, or one that no longer exists, is NULL and never counts as low
+    let field_cases: String = fields
+        .iter()
+        .map(|x| format!(r#" WHEN t.field_name = '{}' THEN i."{}"::float8"#, x, x))
+        .collect();
+    let query = format!(
+        r#"
+        WITH RECURSIVE low AS (
+            SELECT i.id, i.name, t.field_name, t.minimum, CASE
+                WHEN t.field_name IS NULL THEN COALESCE(
+                    (SELECT SUM(p.quantity) FROM item_placements p WHERE p.item_id = i.id)::float8, 0
+                )
+                {}
+            END AS value
+            FROM stock_thresholds t JOIN items i ON i.id = t.item_id
+        ),
+        up AS (
+            SELECT p.item_id, c.id, c.parent_id, 0 AS depth
+            FROM item_placements p JOIN containers c ON c.id = p.container_id
+            WHERE p.item_id IN (SELECT id FROM low WHERE value <= minimum)
+            UNION ALL
+            SELECT up.item_id, c.id, c.parent_id, up.depth + 1
+            FROM containers c JOIN up ON c.id = up.parent_id
+            WHERE up.depth < 100
+        ),
+        located AS (
+            SELECT DISTINCT up.item_id, up.id AS location_id FROM up
+            JOIN containers parent ON parent.id = up.parent_id
+            WHERE parent.parent_id IS NULL
+        )
+        SELECT low.id, low.name, low.field_name, low.minimum, low.value,
+        COALESCE(l.location_id, '') AS location_id, COALESCE(c.name, '') AS location_name
+        FROM low
+        LEFT JOIN located l ON l.item_id = low.id
+        LEFT JOIN containers c ON c.id = l.location_id
+        WHERE low.value <= low.minimum
+        ORDER BY l.location_id IS NULL, c.name, l.location_id, low.name, low.id
+        "#,
+        field_cases
+    );
+    let rows = sqlx::query(&query).fetch_all(pool).await?;
+    let mut locations: Vec<LowStockLocation> = Vec::new();
+    for row in rows {
+        let location_id: String = row.get("location_id");
+        let item = LowStockItem {
+            item_id: row.get("id"),
+            item_name: row.get("name"),
+            field_name: row.get("field_name"),
+            value: row.get("value"),
+            minimum: row.get("minimum"),
+        };
+        //Rows come sorted by location, a new location starts a new group
+        match locations.last_mut() {
+            Some(location) if location.location_id == location_id => location.items.push(item),
+            _ => locations.push(LowStockLocation {
+                location_id,
+                location_name: row.get("location_name"),
+                items: vec![item],
+            }),
+        }
+    }
+    Ok(locations)
+}
+
+pub async fn get_low_stock(
+    Extension(user): Extension<UserContext>,
+) -> Result<Json<Vec<LowStockLocation>>, StatusCode> {
+    println!("in get low stock");
+    match low_stock(&user.db_pool).await {
+        Ok(locations) => Ok(Json(locations)),
+        Err(e) => {
+            println!("Get low stock error {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+//Text starting like a formula gets a ' in front so spreadsheets show it instead of running it
+pub fn formula_safe(value: &str) -> String {
+    if value.starts_with(['=', '+', '-', '@']) {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+//Written like the items export, text that starts like a formula is escaped
+fn shopping_list_csv(lines: Vec<(LowStockItem, Vec<String>)>) -> Result<Vec<u8>, csv::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record([
+        "Item",
+        "Locations",
+        "Field",
+        "Current",
+        "Minimum",
+        "Missing",
+    ])?;
+    for (item, location_names) in lines {
+        let field_name = item.field_name.unwrap_or_else(|| "Quantity".to_string());
+        writer.write_record([
+            formula_safe(&item.item_name),
+            formula_safe(&location_names.join("; ")),
+            formula_safe(&field_name),
+            item.value.to_string(),
+            item.minimum.to_string(),
+            (item.minimum - item.value).max(0.0).to_string(),
+        ])?;
+    }
+    writer
+        .into_inner()
+        .map_err(|e| csv::Error::from(e.into_error()))
+}
+
+//One line per item, an item placed in several locations lists all of them
+pub async fn get_low_stock_csv(
+    Extension(user): Extension<UserContext>,
+) -> Result<impl IntoResponse, StatusCode> {
+    println!("in get low stock csv");
+    let locations = match low_stock(&user.db_pool).await {
+        Ok(locations) => locations,
+        Err(e) => {
+            println!("Get low stock csv error {}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let mut lines: Vec<(LowStockItem, Vec<String>)> = Vec::new();
+    for location in locations {
+        for item in location.items {
+            match lines.iter_mut().find(|(x, _)| x.item_id == item.item_id) {
+                Some((_, names)) => names.push(location.location_name.clone()),
+                None => lines.push((item, vec![location.location_name.clone()])),
+            }
+        }
+    }
+    lines.sort_by(|a, b| a.0.item_name.cmp(&b.0.item_name));
+    match shopping_list_csv(lines) {
+        Ok(csv) => Ok(([(header::CONTENT_TYPE, "text/csv; charset=utf-8")], csv)),
+        Err(e) => {
+            println!("Write low stock csv error {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}